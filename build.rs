@@ -0,0 +1,28 @@
+//! Records the git commit the crate is built from, if it's built from a git checkout, so that a
+//! running server can report exactly what it was built from (see `BuildInfo`)
+//!
+//! Packagers building from elsewhere, e.g. a release tarball, may set `QOTD_GIT_HASH` themselves.
+
+use std::{path::Path, process::Command};
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=QOTD_GIT_HASH");
+    if std::env::var_os("QOTD_GIT_HASH").is_some() || !Path::new(".git").exists() {
+        return;
+    }
+    // HEAD's log changes with every commit and checkout, unlike HEAD itself when on a branch
+    for path in [".git/HEAD", ".git/logs/HEAD"] {
+        if Path::new(path).exists() {
+            println!("cargo:rerun-if-changed={path}");
+        }
+    }
+    let hash = Command::new("git")
+        .args(["rev-parse", "--short=12", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok());
+    if let Some(hash) = hash {
+        println!("cargo:rustc-env=QOTD_GIT_HASH={}", hash.trim());
+    }
+}
@@ -0,0 +1,76 @@
+//! This module decides which clients may be served, based on their IP address
+
+use std::{fmt, net::IpAddr, str::FromStr};
+
+use ipnet::IpNet;
+
+/// A network in CIDR notation, e.g. `192.0.2.0/24`; a bare address matches only itself
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Network(IpNet);
+
+impl Network {
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        self.0.contains(&addr)
+    }
+}
+
+impl From<IpNet> for Network {
+    fn from(net: IpNet) -> Self {
+        Self(net)
+    }
+}
+
+impl FromStr for Network {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse::<IpNet>()
+            .or_else(|_| s.parse::<IpAddr>().map(IpNet::from))
+            .map(Self)
+            .map_err(|_| format!("Invalid network \"{s}\", expected e.g. 192.0.2.0/24"))
+    }
+}
+
+impl fmt::Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Allow- and denylists of client networks
+///
+/// A client is denied if it is in any denied network, or if there are allowed networks and it is in
+/// none of them; all other clients are allowed.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct AccessControl {
+    allow: Vec<Network>,
+    deny: Vec<Network>,
+}
+
+impl AccessControl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn allow(mut self, network: Network) -> Self {
+        self.allow.push(network);
+        self
+    }
+
+    pub fn deny(mut self, network: Network) -> Self {
+        self.deny.push(network);
+        self
+    }
+
+    /// Whether every client is allowed
+    pub fn is_open(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    pub fn is_allowed(&self, addr: IpAddr) -> bool {
+        // Dual-stack sockets report IPv4 clients as IPv4-mapped IPv6 addresses
+        let addr = addr.to_canonical();
+        !self.deny.iter().any(|net| net.contains(addr))
+            && (self.allow.is_empty() || self.allow.iter().any(|net| net.contains(addr)))
+    }
+}
@@ -0,0 +1,477 @@
+//! This module contains the admin endpoint, which manages a running server over HTTP
+//!
+//! Over TCP, clients are authenticated by a bearer token, if one is set (see
+//! [`Server::admin_token`]); without one, the endpoint should only ever be bound to a trusted
+//! address (e.g. the loopback interface). Over a Unix socket, they're authenticated by their
+//! credentials instead (see [`Server::admin_socket`]).
+//!
+//! [`Server::admin_token`]: crate::Server::admin_token
+//! [`Server::admin_socket`]: crate::Server::admin_socket
+#![cfg(any(feature = "tcp", feature = "udp"))]
+
+use std::{fmt, path::PathBuf, sync::Arc};
+
+use anyhow::Context;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpListener,
+    sync::{mpsc::Sender, oneshot},
+};
+use tracing::{debug, info, warn};
+
+use crate::{
+    http::{Request, Response},
+    server::QuoteRequest,
+    BuildInfo, IndexOptions, IndexSummary, Quotes, Stats,
+};
+
+/// State shared by the admin endpoint's requests
+#[derive(Debug)]
+pub(crate) struct Admin {
+    pub stats: Arc<Stats>,
+    /// Whether quotes may be submitted, i.e. a submissions file was given
+    pub submissions: bool,
+    /// Options the served quotes were indexed with, which quotes loaded later are indexed with too
+    pub index_options: IndexOptions,
+    /// Token requests over TCP must bear, if any
+    pub token: Option<String>,
+    /// Who may make requests over the Unix socket
+    #[cfg(unix)]
+    pub peers: Peers,
+}
+
+/// Users and groups allowed on the admin socket, besides root and the user the server runs as
+#[cfg(unix)]
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Peers {
+    pub uids: Vec<u32>,
+    /// Users are allowed by their primary group only, as that's all a socket's peer reveals
+    pub gids: Vec<u32>,
+}
+
+#[cfg(unix)]
+impl Peers {
+    fn allows(&self, uid: u32, gid: u32) -> bool {
+        uid == 0
+            || uid == nix::unistd::geteuid().as_raw()
+            || self.uids.contains(&uid)
+            || self.gids.contains(&gid)
+    }
+}
+
+/// Bind the admin socket at `path`, replacing any left behind by an earlier server
+///
+/// On Linux, a path starting with '@' names a socket in the abstract namespace instead (see
+/// [`Server::admin_socket`](crate::Server::admin_socket)), which being no file, needs neither
+/// replacing nor permissions.
+#[cfg(unix)]
+pub(crate) fn bind_socket(path: &std::path::Path) -> anyhow::Result<tokio::net::UnixListener> {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+    #[cfg(target_os = "linux")]
+    if let Some(name) = abstract_name(path) {
+        use std::os::{linux::net::SocketAddrExt, unix::net::SocketAddr};
+
+        let address = SocketAddr::from_abstract_name(name)?;
+        let listener = std::os::unix::net::UnixListener::bind_addr(&address)
+            .with_context(|| format!("Failed to bind admin socket {}", path.display()))?;
+        listener.set_nonblocking(true)?;
+        return Ok(tokio::net::UnixListener::from_std(listener)?);
+    }
+
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path)
+            .with_context(|| format!("Failed to remove old admin socket {}", path.display()))?,
+        Ok(_) => anyhow::bail!("{} already exists, and isn't a socket", path.display()),
+        Err(_) => {}
+    }
+    let listener = tokio::net::UnixListener::bind(path)
+        .with_context(|| format!("Failed to bind admin socket {}", path.display()))?;
+    // Anyone may connect, so that allowed users needn't own the socket, but only they're served
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o666))
+        .with_context(|| format!("Failed to make admin socket {} connectable", path.display()))?;
+    Ok(listener)
+}
+
+/// The name in the abstract namespace that `path` gives, if it starts with '@'
+#[cfg(target_os = "linux")]
+fn abstract_name(path: &std::path::Path) -> Option<&[u8]> {
+    use std::os::unix::ffi::OsStrExt;
+
+    path.as_os_str().as_bytes().strip_prefix(b"@")
+}
+
+/// Accept admin requests until accepting fails
+pub(crate) async fn accept_loop(
+    listener: TcpListener,
+    quote_tx: Sender<QuoteRequest>,
+    admin: Arc<Admin>,
+) -> anyhow::Result<()> {
+    loop {
+        let (conn, peer) = crate::server::accept(&listener, "admin", &admin.stats)
+            .await
+            .context("Failed to connect admin client")?;
+        let quote_tx = quote_tx.clone();
+        let admin = Arc::clone(&admin);
+        tokio::spawn(async move {
+            let token = admin.token.as_deref();
+            if let Err(e) = handle(conn, peer, token, &quote_tx, &admin).await {
+                debug!("Admin request from {peer} failed: {e}");
+            }
+        });
+    }
+}
+
+/// Accept admin requests over a Unix socket until accepting fails, serving only allowed peers
+#[cfg(unix)]
+pub(crate) async fn unix_accept_loop(
+    listener: tokio::net::UnixListener,
+    quote_tx: Sender<QuoteRequest>,
+    admin: Arc<Admin>,
+) -> anyhow::Result<()> {
+    loop {
+        let (mut conn, _) = listener
+            .accept()
+            .await
+            .context("Failed to connect admin client")?;
+        let quote_tx = quote_tx.clone();
+        let admin = Arc::clone(&admin);
+        tokio::spawn(async move {
+            let credentials = match conn.peer_cred() {
+                Ok(credentials) => credentials,
+                Err(e) => {
+                    warn!("Refused admin client with unknown credentials: {e}");
+                    return;
+                }
+            };
+            let (uid, gid) = (credentials.uid(), credentials.gid());
+            let peer = format!("uid {uid}");
+            let result = if admin.peers.allows(uid, gid) {
+                handle(conn, &peer, None, &quote_tx, &admin).await
+            } else {
+                warn!("Refused admin client {peer} (gid {gid}), which isn't allowed");
+                // Closing the connection on an unread request would reset it, losing the answer
+                let _ = Request::read(&mut conn).await;
+                Response::text(403, "Forbidden")
+                    .write(&mut conn)
+                    .await
+                    .map_err(Into::into)
+            };
+            if let Err(e) = result {
+                debug!("Admin request from {peer} failed: {e}");
+            }
+        });
+    }
+}
+
+/// Answer the request on `conn`, which must bear `token` if one is given
+async fn handle<S, P>(
+    mut conn: S,
+    peer: P,
+    token: Option<&str>,
+    quote_tx: &Sender<QuoteRequest>,
+    admin: &Admin,
+) -> anyhow::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    P: fmt::Display,
+{
+    let response = match Request::read(&mut conn).await {
+        Ok(request) if !bears(&request, token) => {
+            warn!(
+                "Refused unauthorized admin request from {peer}: {} {}",
+                request.method, request.path
+            );
+            Response::text(401, "Unauthorized").header("WWW-Authenticate", "Bearer realm=\"qotd\"")
+        }
+        Ok(request) => {
+            info!(
+                "Admin request from {peer}: {} {}",
+                request.method, request.path
+            );
+            route(request, quote_tx, admin).await
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::InvalidData => Response::text(400, e),
+        Err(e) => return Err(e.into()),
+    };
+    response.write(&mut conn).await?;
+    Ok(())
+}
+
+/// Whether `request` bears `token` in its Authorization header, or needn't
+fn bears(request: &Request, token: Option<&str>) -> bool {
+    let Some(token) = token else {
+        return true;
+    };
+    let Some(bearer) = request
+        .header("authorization")
+        .and_then(|value| value.split_once(' '))
+        .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("bearer"))
+        .map(|(_, bearer)| bearer.trim())
+    else {
+        return false;
+    };
+    // Compared in constant time, so as not to reveal how much of a guess was right
+    bearer.len() == token.len()
+        && bearer
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+async fn route(request: Request, quote_tx: &Sender<QuoteRequest>, admin: &Admin) -> Response {
+    match request.path.as_str() {
+        #[cfg(feature = "dashboard")]
+        "/" if request.method == "GET" => dashboard(quote_tx, admin).await,
+        #[cfg(feature = "dashboard")]
+        "/admin/preview" if request.method == "GET" => preview(quote_tx).await,
+        "/admin/version" if request.method == "GET" => {
+            Response::text(200, env!("CARGO_PKG_VERSION"))
+        }
+        "/admin/info" if request.method == "GET" => info(admin),
+        "/admin/quotes" if request.method == "POST" => submit(request, quote_tx, admin).await,
+        "/admin/load" if request.method == "POST" => load(request, quote_tx, admin).await,
+        "/admin/activate" if request.method == "POST" => {
+            switch(quote_tx, QuoteRequest::Activate).await
+        }
+        "/admin/rollback" if request.method == "POST" => {
+            switch(quote_tx, QuoteRequest::Rollback).await
+        }
+        "/admin/reload" if request.method == "POST" => reload(request, quote_tx).await,
+        "/admin/override" if request.method == "PUT" => pin(request, quote_tx).await,
+        "/admin/override" if request.method == "DELETE" => unpin(quote_tx).await,
+        "/admin/override" => {
+            Response::text(405, "Method not allowed").header("Allow", "PUT, DELETE")
+        }
+        "/admin/quotes" | "/admin/load" | "/admin/activate" | "/admin/rollback"
+        | "/admin/reload" => Response::text(405, "Method not allowed").header("Allow", "POST"),
+        "/admin/version" | "/admin/info" => {
+            Response::text(405, "Method not allowed").header("Allow", "GET")
+        }
+        _ => Response::text(404, "Not found"),
+    }
+}
+
+/// Describe what the server was built from, and how long it's been up, e.g. for fleet tooling to
+/// check what's deployed
+fn info(admin: &Admin) -> Response {
+    let uptime = admin.stats.uptime().as_secs();
+    Response::text(200, format!("{}uptime: {uptime}", BuildInfo::current()))
+}
+
+/// Render the status page
+#[cfg(feature = "dashboard")]
+async fn dashboard(quote_tx: &Sender<QuoteRequest>, admin: &Admin) -> Response {
+    let (summary_tx, summary_rx) = oneshot::channel();
+    if quote_tx
+        .send(QuoteRequest::Summary(summary_tx))
+        .await
+        .is_err()
+    {
+        return Response::text(503, "Server is shutting down");
+    }
+    let Ok(summary) = summary_rx.await else {
+        return Response::text(503, "Server is shutting down");
+    };
+
+    let status = crate::dashboard::Status {
+        uptime: admin.stats.uptime(),
+        stats: admin.stats.snapshot(),
+        report: admin.stats.quote_report(0),
+        summary,
+    };
+    Response::new(200, "text/html; charset=utf-8", status.render())
+}
+
+/// Choose a quote as UDP clients would be sent it, i.e. never styled, without counting it as served
+#[cfg(feature = "dashboard")]
+async fn preview(quote_tx: &Sender<QuoteRequest>) -> Response {
+    let (reply_tx, quote_rx) = oneshot::channel();
+    if quote_tx
+        .send(QuoteRequest::Get(crate::format::Transport::Udp, reply_tx))
+        .await
+        .is_err()
+    {
+        return Response::text(503, "Server is shutting down");
+    }
+    match quote_rx.await {
+        Ok(Ok(quote)) => match quote.into_quote() {
+            Ok(quote) => Response::new(200, "text/plain; charset=utf-8", quote.text),
+            Err(e) => {
+                warn!("Failed to preview quote: {e:?}");
+                Response::text(500, "Failed to read quote")
+            }
+        },
+        Ok(Err(e)) => {
+            warn!("Failed to preview quote: {e:?}");
+            Response::text(500, "Failed to choose quote")
+        }
+        Err(_) => Response::text(503, "Server is shutting down"),
+    }
+}
+
+/// Append the request's body, as plain text, to the submissions file
+async fn submit(request: Request, quote_tx: &Sender<QuoteRequest>, admin: &Admin) -> Response {
+    if !admin.submissions {
+        return Response::text(404, "Quote submissions are not enabled");
+    }
+    let Ok(text) = String::from_utf8(request.body) else {
+        return Response::text(400, "Quote is not valid UTF-8");
+    };
+
+    let (id_tx, id_rx) = oneshot::channel();
+    if quote_tx
+        .send(QuoteRequest::Submit(text, id_tx))
+        .await
+        .is_err()
+    {
+        return Response::text(503, "Server is shutting down");
+    }
+    match id_rx.await {
+        Ok(Ok(id)) => {
+            info!("Quote {id} ({}) submitted", id.hash_hex());
+            admin.stats.register_quotes(std::iter::once(id.clone()));
+            let hash = id.hash_hex();
+            Response::text(201, id).header("X-Quote-Hash", hash)
+        }
+        Ok(Err(e)) => match e.downcast_ref::<std::io::Error>() {
+            Some(io) if io.kind() == std::io::ErrorKind::InvalidInput => Response::text(400, io),
+            _ => {
+                warn!("Failed to submit quote: {e:?}");
+                Response::text(500, "Failed to submit quote")
+            }
+        },
+        Err(_) => Response::text(503, "Server is shutting down"),
+    }
+}
+
+/// Index the quote directory named by the request's body, keeping it on standby until activated
+///
+/// Quotes continue to be served from the current directory while the new one is indexed.
+async fn load(request: Request, quote_tx: &Sender<QuoteRequest>, admin: &Admin) -> Response {
+    let Ok(dir) = String::from_utf8(request.body) else {
+        return Response::text(400, "Directory is not valid UTF-8");
+    };
+    let dir = PathBuf::from(dir.trim());
+    // The directory is indexed on a blocking thread, so quotes are still served meanwhile, however
+    // few threads the runtime has
+    let quotes = match Quotes::from_dir_with_options(dir.clone(), &admin.index_options).await {
+        Ok(quotes) => quotes,
+        Err(e) => {
+            warn!("Failed to load quotes from {}: {e}", dir.display());
+            return Response::text(400, format!("Unable to load quotes: {e}"));
+        }
+    };
+
+    let (summary_tx, summary_rx) = oneshot::channel();
+    if quote_tx
+        .send(QuoteRequest::Stage(Box::new(quotes), summary_tx))
+        .await
+        .is_err()
+    {
+        return Response::text(503, "Server is shutting down");
+    }
+    match summary_rx.await {
+        Ok(summary) => {
+            info!("Loaded quotes from {}", dir.display());
+            Response::new(200, "text/plain; charset=utf-8", summary.to_string())
+        }
+        Err(_) => Response::text(503, "Server is shutting down"),
+    }
+}
+
+/// Re-index the quote file named by the request's body, relative to the quote directory unless
+/// absolute, leaving the others as they are
+async fn reload(request: Request, quote_tx: &Sender<QuoteRequest>) -> Response {
+    let Ok(path) = String::from_utf8(request.body) else {
+        return Response::text(400, "Path is not valid UTF-8");
+    };
+    let path = PathBuf::from(path.trim());
+    let (summary_tx, summary_rx) = oneshot::channel();
+    if quote_tx
+        .send(QuoteRequest::Reindex(path.clone(), summary_tx))
+        .await
+        .is_err()
+    {
+        return Response::text(503, "Server is shutting down");
+    }
+    match summary_rx.await {
+        Ok(Ok(summary)) => {
+            info!("Reloaded quote file {}", path.display());
+            Response::new(200, "text/plain; charset=utf-8", summary.to_string())
+        }
+        Ok(Err(e)) => {
+            warn!("Failed to reload quote file {}: {e}", path.display());
+            Response::text(400, format!("Unable to reload quote file: {e}"))
+        }
+        Err(_) => Response::text(503, "Server is shutting down"),
+    }
+}
+
+/// Serve the request's body, as plain text, to everyone until the next rotation
+async fn pin(request: Request, quote_tx: &Sender<QuoteRequest>) -> Response {
+    let Ok(text) = String::from_utf8(request.body) else {
+        return Response::text(400, "Quote is not valid UTF-8");
+    };
+
+    let (pinned_tx, pinned_rx) = oneshot::channel();
+    if quote_tx
+        .send(QuoteRequest::Pin(text, pinned_tx))
+        .await
+        .is_err()
+    {
+        return Response::text(503, "Server is shutting down");
+    }
+    match pinned_rx.await {
+        Ok(Ok(quote)) => {
+            let hash = quote.id.hash_hex();
+            Response::text(200, "Quote pinned until the next rotation").header("X-Quote-Hash", hash)
+        }
+        Ok(Err(e)) => match e.downcast_ref::<std::io::Error>() {
+            Some(io) if io.kind() == std::io::ErrorKind::InvalidInput => Response::text(400, io),
+            _ => {
+                warn!("Failed to pin quote: {e:?}");
+                Response::text(500, "Failed to pin quote")
+            }
+        },
+        Err(_) => Response::text(503, "Server is shutting down"),
+    }
+}
+
+/// Stop serving the pinned quote before it expires
+async fn unpin(quote_tx: &Sender<QuoteRequest>) -> Response {
+    let (unpinned_tx, unpinned_rx) = oneshot::channel();
+    if quote_tx
+        .send(QuoteRequest::Unpin(unpinned_tx))
+        .await
+        .is_err()
+    {
+        return Response::text(503, "Server is shutting down");
+    }
+    match unpinned_rx.await {
+        Ok(Ok(true)) => Response::text(200, "Quote unpinned"),
+        Ok(Ok(false)) => Response::text(404, "No quote is pinned"),
+        Ok(Err(e)) => {
+            warn!("Failed to unpin quote: {e:?}");
+            Response::text(500, "Failed to unpin quote")
+        }
+        Err(_) => Response::text(503, "Server is shutting down"),
+    }
+}
+
+/// Swap the quotes being served with those on standby, i.e. activate or roll back
+async fn switch(
+    quote_tx: &Sender<QuoteRequest>,
+    request: fn(oneshot::Sender<anyhow::Result<IndexSummary>>) -> QuoteRequest,
+) -> Response {
+    let (summary_tx, summary_rx) = oneshot::channel();
+    if quote_tx.send(request(summary_tx)).await.is_err() {
+        return Response::text(503, "Server is shutting down");
+    }
+    match summary_rx.await {
+        Ok(Ok(summary)) => Response::new(200, "text/plain; charset=utf-8", summary.to_string()),
+        Ok(Err(e)) => Response::text(409, e),
+        Err(_) => Response::text(503, "Server is shutting down"),
+    }
+}
@@ -1,14 +1,20 @@
 //! Module for processing command-line arguments
 #![cfg(feature = "cli")]
 
-use std::path::PathBuf;
+use std::{ffi::OsString, io::Write, path::PathBuf, str::FromStr, time::Duration};
 
-use clap::{Parser, ValueEnum};
+use clap::{
+    parser::ValueSource, ArgAction, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum,
+};
 
-use crate::{AllowedCategories, QuoteCategory};
+use crate::{
+    AccessControl, AllowedCategories, CategorySchedule, Charset, ColorScheme, Decoration,
+    Keepalive, Network, QuoteCategory, QuoteLengths, ScheduleEntry, SelectionStrategy,
+    SimpleService, StorageMode, Template,
+};
 
 #[derive(Debug, ValueEnum, Clone, Copy, PartialEq, Eq)]
-enum FileLogLevel {
+enum LogLevel {
     Error,
     Warn,
     Info,
@@ -16,40 +22,242 @@ enum FileLogLevel {
     Trace,
 }
 
+impl From<LogLevel> for tracing::level_filters::LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Error => Self::ERROR,
+            LogLevel::Warn => Self::WARN,
+            LogLevel::Info => Self::INFO,
+            LogLevel::Debug => Self::DEBUG,
+            LogLevel::Trace => Self::TRACE,
+        }
+    }
+}
+
 /// A Quote of the Day Protocol (RFC 865) server
+///
+/// On Unix-like systems, SIGUSR1 logs a snapshot of the server's statistics (at the info level, so -v shows it), and
+/// SIGUSR2 upgrades the server in place by re-executing it without closing its sockets.
+///
+/// Exit status: 0 once shut down by SIGTERM or Ctrl+C, 1 for an error while serving, 2 for invalid
+/// arguments, 3 for an invalid configuration (e.g. unreadable quotes or template), 4 if the sockets
+/// can't be bound, and 5 if privileges can't be dropped.
 #[derive(Debug, Parser)]
 #[command(version, about, next_line_help = true)]
+#[command(group(clap::ArgGroup::new("admin").multiple(true)))]
 pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Serve the admin endpoint over HTTP on this address, e.g. "127.0.0.1:8017"
+    ///
+    /// Unless --admin-token is given, the endpoint is unauthenticated, so only bind it to a trusted
+    /// address. It accepts quote submissions (see --submissions-file), re-indexes the single quote
+    /// file named by the body of a "POST /admin/reload" request, pins a quote (see --override-file),
+    /// reports the version, git commit, features, and uptime at "GET /admin/info", and if built with
+    /// the "dashboard" feature serves a status page at "/".
+    #[arg(long, value_name = "ADDR", group = "admin")]
+    pub admin_address: Option<std::net::SocketAddr>,
+
+    /// Token that admin requests over TCP must bear, in an "Authorization: Bearer" header
+    ///
+    /// Prefer supplying this via the environment, where it won't be visible in the process list.
+    #[arg(
+        long,
+        env = "QOTD_ADMIN_TOKEN",
+        hide_env_values = true,
+        requires = "admin_address"
+    )]
+    pub admin_token: Option<String>,
+
+    /// Also serve the admin endpoint on a Unix socket at this path
+    ///
+    /// Clients are authenticated by their credentials rather than a token: only root, the user the
+    /// server runs as (see --user), and those allowed by --admin-user or --admin-group are served.
+    /// On Linux, a path starting with '@' (e.g. "@qotd") names an abstract socket instead, which
+    /// isn't a file at all.
+    ///
+    /// NOTE: This is currently only supported on Unix-like operating systems
+    #[arg(long, value_name = "PATH", value_hint = clap::ValueHint::FilePath, group = "admin")]
+    pub admin_socket: Option<PathBuf>,
+
+    /// User (name or UID) allowed on the admin socket; may be given more than once
+    #[arg(long, value_name = "USER", requires = "admin_socket")]
+    pub admin_user: Vec<String>,
+
+    /// Group (name or GID) whose members are allowed on the admin socket, if it's their primary
+    /// group; may be given more than once
+    #[arg(long, value_name = "GROUP", requires = "admin_socket")]
+    pub admin_group: Vec<String>,
+
     /// Choose from all available quotes, both offensive and not (see --categories)
     #[arg(long, short)]
     all: bool,
 
-    /// Allowed quote categories
+    /// Only serve clients within this network, e.g. "192.0.2.0/24"
     ///
+    /// May be given multiple times; if given at all, clients outside of all allowed networks are denied.
+    #[arg(long, value_name = "CIDR")]
+    allow: Vec<Network>,
+
+    /// Allowed quote categories, e.g. "kids,general", or "all"
+    ///
+    /// Categories are named by quote files' front-matter; files without one are "offensive" if named
+    /// as such (see --dir), and "decorous" otherwise.
     /// Short-form options are available as well: -a is equivalent to `--categories all`, while -o is equivalent to `--categories offensive`.
     /// If none are selected, the default is to only choose the decorous (i.e. inoffensive) quotes.
     /// This option, if provided, supersedes the others; otherwise, -a will supersede -o.
-    #[arg(long, short, value_enum)]
+    #[arg(long, short, value_name = "NAMES")]
     categories: Option<AllowedCategories>,
 
+    /// Style quotes sent over TCP with ANSI colors (see --color-scheme)
+    #[arg(long)]
+    pub color: bool,
+
+    /// Styles used by --color, e.g. "quote=bold+cyan,attribution=yellow"
+    ///
+    /// Parts are "quote", and "attribution" for lines beginning with "--". Styles are any of bold, dim,
+    /// italic, underline, and the colors black, red, green, yellow, blue, magenta, cyan, and white (each
+    /// optionally prefixed with "bright-"), joined by "+"; "none" leaves a part unstyled.
+    #[arg(long, value_name = "PART=STYLE,...", requires = "color")]
+    pub color_scheme: Option<ColorScheme>,
+
+    /// Maximum number of pending TCP connections
+    #[arg(long, default_value_t = crate::DEFAULT_BACKLOG)]
+    pub backlog: u32,
+
+    /// Size of the TCP and UDP sockets' receive buffers (SO_RCVBUF), in bytes
+    ///
+    /// A larger buffer lets the UDP socket absorb bursts of requests. The system may adjust the
+    /// size, e.g. Linux doubles it and caps it at net.core.rmem_max.
+    #[arg(long, value_name = "BYTES")]
+    pub recv_buffer: Option<usize>,
+
+    /// Size of the TCP and UDP sockets' send buffers (SO_SNDBUF), in bytes
+    ///
+    /// The system may adjust the size, e.g. Linux doubles it and caps it at net.core.wmem_max.
+    #[arg(long, value_name = "BYTES")]
+    pub send_buffer: Option<usize>,
+
+    /// Mark packets sent over TCP and UDP with this Differentiated Services class
+    ///
+    /// Either a codepoint from 0 to 63, or a class name: "cs0" to "cs7", "af11" to "af43", "ef",
+    /// or "le" (lower effort). For the "scavenger" class, use "cs1" or "le".
+    #[arg(long, value_name = "CLASS", value_parser = parse_dscp)]
+    pub dscp: Option<u8>,
+
+    /// TTL (IPv4) or hop limit (IPv6) of packets sent over TCP and UDP
+    #[arg(long, value_name = "HOPS", value_parser = clap::value_parser!(u32).range(1..=255))]
+    pub ttl: Option<u32>,
+
+    /// Only serve clients through this network interface, e.g. "eth1", whatever address they
+    /// connect to (SO_BINDTODEVICE)
+    ///
+    /// This matters on multi-homed hosts where the same addresses are reachable through several
+    /// interfaces. Only supported on Linux.
+    #[arg(long, value_name = "INTERFACE")]
+    pub bind_device: Option<String>,
+
+    /// Serve a small collection of quotes built into the server, rather than reading quote files
+    #[cfg(feature = "builtin-quotes")]
+    #[arg(long, conflicts_with = "dir")]
+    pub builtin_quotes: bool,
+
+    /// Check the configuration and quotes, print a summary, and exit without binding any sockets
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Print the configuration the server would run with, as TOML, and exit
+    ///
+    /// Every setting is printed, each annotated with where it came from: the command line, an
+    /// environment variable, or its default. Secrets, e.g. --admin-token, are redacted.
+    #[arg(long)]
+    pub print_config: bool,
+
+    /// Frame every quote in ASCII art
+    #[arg(long, value_enum)]
+    pub decorate: Option<Decoration>,
+
+    /// Never serve clients within this network, e.g. "198.51.100.0/24"
+    ///
+    /// May be given multiple times; denied networks take precedence over allowed ones (see --allow).
+    #[arg(long, value_name = "CIDR")]
+    deny: Vec<Network>,
+
     /// Directory to read quote files from
     ///
     /// Quote files are expected to be simple text files. Individual quotes may contain multiple lines;
     /// lines beginning with the '%' character are treated as the quote delimiters, and otherwise ignored.
-    /// If the file name ends with "-o" it is considered to contain offensive quotes, otherwise it is
+    /// If the file name ends with "-o", or the file is in a directory named "offensive" or "off" (as
+    /// fortune-mod installs them), it is considered to contain offensive quotes, otherwise it is
     /// assumed to only contain generally acceptable, "clean" quotes; see the --categories option.
     /// If the file contains the token "$SerrOFQ$", it is assumed that all alphabetic characters have been
-    /// rot-13 encoded; if this token is not present, or if the token "$FreeBSD$" is encountered first, the
+    /// rot-13 encoded; likewise "SuC66q$sS" declares that all printable characters have been rot-47 encoded,
+    /// and "$Keyed:KEY$" that each printable character has been shifted by the successive bytes of KEY.
+    /// If none of these tokens are present, or if the token "$FreeBSD$" is encountered first, the
     /// file is assumed to not be encoded.
-    #[arg(long, short, default_value = default_dir().into_os_string(), value_hint = clap::ValueHint::DirPath)]
-    pub dir: PathBuf,
+    /// A file may begin with a front-matter block delimited by "---" lines containing "key: value" lines
+    /// (category, language, tags, dates, not_before, not_after, weight, separator); these supersede the
+    /// file name conventions above.
+    /// Seasonal files are restricted to a window of days by their "dates" front-matter or a name ending in
+    /// e.g. "[12-20..12-27]"; individual quotes likewise by a window following the preceding separator.
+    /// Files stop being served after their "not_after" date (and start on their "not_before" date);
+    /// individual quotes likewise by dates like "[2026-05-01..2026-06-30]" or "[..2026-06-30]"
+    /// following the preceding separator.
+    ///
+    /// Defaults to the "data" directory beside the executable; if that is missing or contains no quotes,
+    /// the built-in quotes are served instead (see --builtin-quotes). Given as "-", quotes are instead
+    /// read from stdin as a single file, e.g. to pipe generated quotes into the server.
+    #[arg(long, short, value_hint = clap::ValueHint::DirPath)]
+    pub dir: Option<PathBuf>,
+
+    /// File containing age identities used to decrypt encrypted (*.age) quote files
+    #[cfg(feature = "encryption")]
+    #[arg(long, env = "QOTD_KEY_FILE", value_hint = clap::ValueHint::FilePath)]
+    pub key_file: Option<PathBuf>,
+
+    /// Passphrase used to decrypt passphrase-encrypted (*.age) quote files
+    ///
+    /// Prefer supplying this via the environment, where it won't be visible in the process list.
+    #[cfg(feature = "encryption")]
+    #[arg(long, env = "QOTD_PASSPHRASE", hide_env_values = true)]
+    pub passphrase: Option<String>,
+
+    /// Only serve quotes from files in these languages (e.g. "de,en")
+    ///
+    /// A file's language is declared in its front-matter, or by a language code before a ".txt"
+    /// extension (e.g. "quotes.de.txt"). Files that declare no language are always served.
+    #[arg(long, value_delimiter = ',')]
+    pub languages: Option<Vec<String>>,
 
     /// Log level for file
     ///
     /// If not provided, log file will default to the same level of output as the console.
     /// Must specify --log_file if providing this parameter.
     #[arg(long, short, value_enum, requires = "log_file")]
-    file_log_level: Option<FileLogLevel>,
+    file_log_level: Option<LogLevel>,
+
+    /// Also serve quotes over HTTP on this address, e.g. "0.0.0.0:8017"
+    ///
+    /// "GET /" is sent a quote as plain text or JSON (as its Accept header prefers), "GET /feed.xml"
+    /// an Atom feed of the most recent quotes of the day (see --feed-length), and "GET /calendar.ics"
+    /// an iCalendar feed of the upcoming ones (see --calendar-length). Quotes may be cached until the
+    /// next rotation (see --rotate). The same access control applies as to other clients.
+    #[arg(long, value_name = "ADDR")]
+    pub http_address: Option<std::net::SocketAddr>,
+
+    /// Number of days' quotes in the HTTP feed, ending with today's
+    #[arg(long, value_name = "DAYS", default_value_t = crate::DEFAULT_FEED_LENGTH, requires = "http_address")]
+    pub feed_length: usize,
+
+    /// Number of days' quotes in the HTTP calendar, starting with today's
+    #[arg(long, value_name = "DAYS", default_value_t = crate::DEFAULT_CALENDAR_LENGTH, requires = "http_address")]
+    pub calendar_length: usize,
+
+    /// Origin whose pages may fetch from the HTTP endpoint, e.g. "https://example.com", or "*" for
+    /// any; may be given more than once
+    #[arg(long, value_name = "ORIGIN", requires = "http_address")]
+    pub cors_origin: Vec<String>,
 
     /// Address to bind to
     #[arg(
@@ -60,10 +268,197 @@ pub struct Cli {
     )]
     pub host: String,
 
+    /// Probe TCP connections idle for this many seconds with keepalives
+    ///
+    /// Dead peers, e.g. those behind NAT middleboxes that forget idle connections, are then
+    /// eventually noticed and their connections closed.
+    #[arg(long, value_name = "SECONDS")]
+    pub keepalive: Option<u64>,
+
+    /// Seconds between unanswered keepalive probes (see --keepalive)
+    #[arg(long, value_name = "SECONDS", requires = "keepalive")]
+    pub keepalive_interval: Option<u64>,
+
+    /// Number of unanswered keepalive probes before a connection is dropped (see --keepalive)
+    ///
+    /// NOTE: This is not supported on Windows
+    #[arg(long, value_name = "COUNT", requires = "keepalive")]
+    pub keepalive_retries: Option<u32>,
+
+    /// Wait up to this many seconds for unsent data to be delivered when closing a TCP connection
+    ///
+    /// A value of 0 resets connections rather than closing them gracefully.
+    #[arg(long, value_name = "SECONDS")]
+    pub linger: Option<u64>,
+
+    /// Log a warning for every request denied by --allow or --deny
+    #[arg(long)]
+    pub log_denied: bool,
+
+    /// Log only this fraction of requests at info level, e.g. 0.01 for one in a hundred
+    ///
+    /// Each request is sampled at random; the rest are logged at debug level. Warnings and errors
+    /// are always logged, so that floods of requests don't flood the log while problems still show.
+    #[arg(long, value_name = "FRACTION", value_parser = parse_fraction)]
+    pub log_sample: Option<f64>,
+
+    /// Ignore UDP requests larger than --max-udp-request, rather than answering every datagram
+    ///
+    /// Genuine clients send empty or near-empty datagrams; larger ones are counted as suspected scan
+    /// or abuse traffic instead of being answered.
+    #[arg(long)]
+    pub strict_udp: bool,
+
+    /// Largest UDP request answered with --strict-udp, in bytes
+    #[arg(long, value_name = "BYTES", default_value_t = crate::DEFAULT_MAX_UDP_REQUEST, requires = "strict_udp")]
+    pub max_udp_request: usize,
+
+    /// Heed a hint in the first byte of UDP requests: 'o' for an offensive quote (if offensive
+    /// quotes are served), 's' for a short one
+    ///
+    /// Anything else, including an empty request, gets the usual quote, so RFC 865 clients are
+    /// unaffected. This gives clients without TCP (e.g. embedded ones) some control.
+    #[arg(long)]
+    pub udp_hints: bool,
+
+    /// Only serve quotes at most this many bytes long, like fortune -s, e.g. "160" or "udp=160"
+    ///
+    /// Prefixed with "tcp=" or "udp=", this only applies to quotes sent over that protocol (HTTP
+    /// quotes are chosen as for UDP); otherwise it applies to every quote, including those written
+    /// by the motd command. May be given once for each.
+    #[arg(long, value_name = "[PROTOCOL=]BYTES")]
+    pub short: Vec<LengthLimit>,
+
+    /// Only serve quotes at least this many bytes long, like fortune -l, e.g. "1000" or "tcp=1000"
+    ///
+    /// Prefixed like --short.
+    #[arg(long, value_name = "[PROTOCOL=]BYTES")]
+    pub long: Vec<LengthLimit>,
+
+    /// Shed requests while this many are being handled, closing new TCP connections at once,
+    /// sending HTTP clients a 503, and dropping UDP requests
+    ///
+    /// This keeps a flood of requests from growing the server's tasks and memory without bound,
+    /// degrading service predictably instead. Shed requests are counted in the statistics.
+    #[arg(long, value_name = "REQUESTS", value_parser = clap::value_parser!(u64).range(1..))]
+    pub max_in_flight: Option<u64>,
+
+    /// Send this line to TCP clients before closing their connections when shedding them, and to
+    /// HTTP clients as the body of their 503
+    #[arg(long, value_name = "TEXT", requires = "max_in_flight")]
+    pub busy_message: Option<String>,
+
+    /// Send each client its own quote of the day, chosen by its IP address and the date
+    ///
+    /// Clients see the same quote all day, while different clients see different quotes (see
+    /// --daily-seed).
+    #[arg(long, conflicts_with_all = ["udp_cache_ms", "rotate"])]
+    pub sticky_daily: bool,
+
+    /// Serve every client the same quote for each period of this length, e.g. 15m, 1h, or 24h
+    ///
+    /// Periods are aligned to server-local midnight, so that e.g. hourly quotes change on the hour,
+    /// and each change is logged. This is also how often quotes are posted to the webhook, published
+    /// to MQTT, and written by the motd command, which otherwise happens daily.
+    #[arg(long, value_name = "PERIOD")]
+    pub rotate: Option<crate::Rotation>,
+
+    /// Send every UDP client the same quote for this many milliseconds after one is chosen
+    ///
+    /// This absorbs bursts of UDP requests cheaply, as they're answered without choosing or reading
+    /// a quote; a window of 1000-5000 milliseconds is plenty to blunt a simple flood.
+    #[arg(long, value_name = "MILLISECONDS")]
+    pub udp_cache_ms: Option<u64>,
+
+    /// Log level for the console
+    ///
+    /// An alternative to --quiet and --verbose, which it conflicts with.
+    #[arg(long, value_enum, conflicts_with_all = ["quiet", "verbosity"])]
+    log_level: Option<LogLevel>,
+
     /// If present, log all output to the provided file
     #[arg(long, short, value_hint = clap::ValueHint::FilePath)]
     pub log_file: Option<PathBuf>,
 
+    /// What quote files are written in, unless their front-matter says otherwise
+    ///
+    /// Quotes are transcoded into UTF-8 when they're served, e.g. for pre-Unicode fortune files.
+    #[arg(long, value_enum, default_value_t)]
+    pub file_encoding: Charset,
+
+    /// Also treat lines in quote files starting with this (e.g. "#") as comments
+    ///
+    /// Lines starting with a doubled separator (e.g. "%%") are always comments, as with fortune.
+    /// Comments are never part of a quote.
+    #[arg(long, value_name = "PREFIX", value_parser = clap::builder::NonEmptyStringValueParser::new())]
+    pub comment_prefix: Option<String>,
+
+    /// Skip quotes matching this regular expression when indexing, e.g. "(?i)cheese"
+    ///
+    /// May be given multiple times; quotes matching any of the patterns are skipped.
+    #[arg(long, value_name = "REGEX")]
+    pub exclude_pattern: Vec<regex::Regex>,
+
+    /// Reclassify quotes in decorous files as offensive if they contain common profanity
+    ///
+    /// Not all quote files are reliably labeled. Reclassified quotes are only served if offensive
+    /// quotes are allowed.
+    #[arg(long)]
+    pub reclassify_offensive: bool,
+
+    /// Also reclassify quotes containing any of the words in this file, one per line
+    ///
+    /// Empty lines and lines starting with '#' are ignored. Implies --reclassify-offensive.
+    #[arg(long, value_name = "PATH", value_hint = clap::ValueHint::FilePath)]
+    pub offensive_words: Option<PathBuf>,
+
+    /// Never serve quotes whose hashes are listed in this file, one per line, wherever they're found
+    ///
+    /// Hashes are shown by the grep command. Empty lines and lines starting with '#' are ignored, as
+    /// is anything after the hash on its line, e.g. a note on why the quote was denied.
+    #[arg(long, value_name = "PATH", value_hint = clap::ValueHint::FilePath)]
+    pub deny_list: Option<PathBuf>,
+
+    /// Skip quote files larger than this many bytes, with a warning, e.g. a disk image left in the
+    /// quote directory by mistake
+    #[arg(long, value_name = "BYTES")]
+    pub max_file_size: Option<u64>,
+
+    /// Stop indexing once this many files have been read, leaving any further files unserved
+    ///
+    /// This bounds how long indexing takes should the quote directory be unexpectedly huge.
+    #[arg(long, value_name = "COUNT")]
+    pub max_files: Option<usize>,
+
+    /// Decode encoded (e.g. ROT13) quote files once when indexing them, holding them in memory
+    ///
+    /// By default each quote is read from its file and decoded every time it's served.
+    #[arg(long)]
+    pub preload_encoded: bool,
+
+    /// Ignore any text after a quote file's last separator
+    ///
+    /// By default it's served as a quote, as fortune does, so that a file whose last quote isn't
+    /// followed by a separator doesn't lose it, and a file with no separators at all is a single
+    /// quote.
+    #[arg(long)]
+    pub no_trailing_quote: bool,
+
+    /// How to hold quote files while serving their quotes
+    ///
+    /// "lazy" uses the least memory but reads each quote from disk as it's served; "preload" reads
+    /// every file into memory up front; "mmap" leaves the kernel to page files in, but a file
+    /// truncated while it's served may crash the server. Each file's storage is shown on the
+    /// dashboard.
+    #[arg(long, value_enum, default_value_t)]
+    pub storage: StorageMode,
+
+    /// Stop indexing once this many quotes have been indexed, leaving any further quotes unserved
+    ///
+    /// This bounds the memory used by the index should the quote directory be unexpectedly huge.
+    #[arg(long, value_name = "COUNT")]
+    pub max_quotes: Option<usize>,
+
     /// Choose only from offensive quotes (see --categories)
     #[arg(long, short)]
     offensive: bool,
@@ -72,12 +467,414 @@ pub struct Cli {
     #[arg(long, short, default_value_t = 17)]
     pub port: u16,
 
+    /// Also serve quotes on this address, with quotes of its own, e.g. "0.0.0.0:1717,categories=all"
+    ///
+    /// The address may be followed by comma-separated options: "dir=PATH", the quote directory
+    /// (default: as --dir), and "categories=NAMES", with names separated by '+' (e.g. "kids+general")
+    /// or "all" (default: as --categories). May be given multiple times, e.g. to serve different audiences different quotes from one process.
+    /// Each listener is served like the main one, but without the admin and HTTP endpoints.
+    #[arg(long, value_name = "ADDR[,KEY=VALUE...]")]
+    pub listener: Vec<ListenerSpec>,
+
+    /// Also serve only offensive quotes on this port, of the same address, while the main port
+    /// serves decorous quotes
+    ///
+    /// This is shorthand for `--listener HOST:PORT,categories=offensive`.
+    #[arg(long, value_name = "PORT", conflicts_with_all = ["all", "offensive"])]
+    offensive_port: Option<u16>,
+
+    /// Once bound, write a line describing the server to this file ("-" for stdout)
+    ///
+    /// The line is space-separated "key=value" pairs: "tcp", "udp", "admin", and "http" with the bound
+    /// addresses (each omitted if not served), "pid", and "version", e.g.
+    /// "tcp=127.0.0.1:40123 udp=127.0.0.1:40123 pid=4242 version=0.1.0". This reveals the port chosen
+    /// when binding to port 0.
+    #[arg(long, value_name = "PATH", value_hint = clap::ValueHint::FilePath)]
+    pub startup_info: Option<PathBuf>,
+
+    /// Seed for choosing quotes, making the sequence of quotes served reproducible
+    ///
+    /// The same quote files and requests get the same quotes, e.g. for demos and tests. Selection
+    /// that depends on time still varies: the schedule, seasonal quotes, --udp-cache-ms, and
+    /// --selection least-recently-served.
+    #[arg(long, value_name = "SEED")]
+    pub seed: Option<u64>,
+
+    /// Seed for choosing each day's quote, so that servers with the same quotes can differ
+    ///
+    /// Unlike --seed, this keeps the quotes of the day (e.g. in the HTTP feed and calendar) the
+    /// same across restarts.
+    #[arg(long, value_name = "SEED")]
+    pub daily_seed: Option<u64>,
+
+    /// How to choose a quote from the file selected to serve one
+    #[arg(long, value_enum, default_value_t)]
+    pub selection: SelectionStrategy,
+
+    /// Choose quotes from recently modified files more often, their extra weight halving every
+    /// PERIOD, e.g. 7d
+    ///
+    /// Newly added quotes then surface quickly, while older ones still appear. Quotes in a file
+    /// modified just now are --recency-boost times as likely to be chosen as those in a file modified
+    /// long ago. The quote of the day is chosen regardless.
+    #[arg(long, value_name = "PERIOD", value_parser = parse_period)]
+    pub recency_half_life: Option<std::time::Duration>,
+
+    /// How many times as likely quotes in a file modified just now are to be chosen (see
+    /// --recency-half-life)
+    #[arg(long, value_name = "FACTOR", default_value_t = crate::DEFAULT_RECENCY_BOOST, value_parser = parse_boost, requires = "recency_half_life")]
+    pub recency_boost: f64,
+
+    /// Log a warning for every request taking longer than this many milliseconds to serve
+    #[arg(long, value_name = "MILLISECONDS")]
+    pub slow_request_ms: Option<u64>,
+
+    /// Seconds requests in flight are given to finish when shutting down or upgrading, after which
+    /// they're abandoned
+    #[arg(long, value_name = "SECONDS", default_value_t = crate::DEFAULT_DRAIN_TIMEOUT.as_secs())]
+    pub drain_timeout: u64,
+
+    /// Don't set SO_REUSEADDR on the sockets
+    ///
+    /// By default it is set on Unix-like systems, allowing a restarted server to bind its port while
+    /// connections from the previous instance linger in TIME_WAIT.
+    #[arg(long)]
+    pub no_reuse_address: bool,
+
+    /// Set SO_REUSEPORT on the sockets, allowing multiple servers to share the same port
+    ///
+    /// NOTE: This is currently only supported on Unix-like operating systems
+    #[arg(long)]
+    pub reuse_port: bool,
+
+    /// Set TCP_NODELAY on TCP connections, sending quotes without waiting to coalesce packets
+    #[arg(long)]
+    pub tcp_nodelay: bool,
+
+    /// Only serve quotes over TCP, e.g. where UDP is blocked
+    #[arg(long, conflicts_with = "udp_only")]
+    pub tcp_only: bool,
+
+    /// Only serve quotes over UDP
+    #[arg(long)]
+    pub udp_only: bool,
+
+    /// Also serve one of the other simple services, e.g. "daytime", or "echo=7007" on a port other
+    /// than its usual one
+    ///
+    /// Services are echo (port 7), daytime (port 13), and chargen (port 19), served on the same
+    /// address as quotes, over TCP and UDP as quotes are, except that chargen is never served over
+    /// UDP. May be given multiple times.
+    #[arg(long, value_name = "SERVICE[=PORT]")]
+    pub simple_service: Vec<SimpleServiceArg>,
+
+    /// Reflow quotes to fit within this many columns, e.g. for narrow terminals
+    ///
+    /// Only paragraphs with lines too long to fit are reflowed, and line breaks that appear deliberate,
+    /// such as in verse or before an attribution, are preserved.
+    #[arg(long, value_name = "COLUMNS", value_parser = clap::value_parser!(u16).range(1..))]
+    pub wrap: Option<u16>,
+
+    /// Number of worker threads, each with its own sockets sharing the port via SO_REUSEPORT
+    ///
+    /// The kernel balances incoming requests between the workers' sockets.
+    /// NOTE: More than one worker is currently only supported on Unix-like operating systems
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+    pub workers: u16,
+
+    /// Confine the server's threads to these CPU cores, e.g. "0,1"
+    ///
+    /// Every thread, including those of all workers, may run on any of the listed cores.
+    /// NOTE: This is currently only supported on Linux
+    #[arg(long, value_name = "CPUS", value_delimiter = ',', num_args = 1..)]
+    pub cpu_affinity: Vec<usize>,
+
+    /// Raise the limit on open files to at least this many
+    ///
+    /// The server keeps every quote file open, as well as a file for each connection. When started
+    /// as root, the limit is raised to 65536 by default, before privileges are dropped; limits
+    /// already higher are left as they are.
+    /// NOTE: This is currently only supported on Unix-like operating systems
+    #[arg(long, value_name = "COUNT")]
+    pub open_files: Option<u64>,
+
+    /// Serve the quote in this file, whenever there is one, to every client in place of all others,
+    /// e.g. /var/lib/qotd/quote-of-the-day.override
+    ///
+    /// Pin an announcement by writing it to the file; it's served until the next rotation (see
+    /// --rotate; daily otherwise) after the file was written, and the file is then removed. The admin
+    /// endpoint pins quotes with "PUT /admin/override", writing them to this file, and unpins them
+    /// with "DELETE /admin/override".
+    #[arg(long, value_name = "PATH", value_hint = clap::ValueHint::FilePath)]
+    pub override_file: Option<PathBuf>,
+
+    /// Enable a category only within a daily window of server-local time, e.g. "offensive=18:00-06:00"
+    ///
+    /// May be given multiple times; a category is enabled while any of its windows is open. Scheduled
+    /// categories are implicitly allowed (see --categories), but only served during their windows.
+    #[arg(long, value_name = "CATEGORY=HH:MM-HH:MM")]
+    schedule: Vec<ScheduleEntry>,
+
+    /// Speak telnet to TCP clients
+    ///
+    /// Any options a client negotiates are refused, and quotes are sent with CRLF line endings and
+    /// 0xFF bytes escaped, so that they render correctly in raw telnet sessions.
+    #[arg(long)]
+    pub telnet: bool,
+
+    /// Prefix quotes sent to TCP clients with their length, as a 2-byte big-endian integer
+    ///
+    /// This lets clients read exactly one quote without waiting for the connection to close. Quotes
+    /// longer than 65535 bytes are truncated to fit in this mode.
+    #[arg(long, conflicts_with = "telnet")]
+    pub length_prefix: bool,
+
+    /// Comply strictly with RFC 865, for clients that rely on its recommendations
+    ///
+    /// Quotes are restricted to ASCII (typographic punctuation is replaced with ASCII equivalents,
+    /// and other characters with '?'), and TCP clients, like UDP clients, are only sent quotes
+    /// shorter than 512 characters. Colors are never applied in this mode.
+    #[arg(long = "strict-rfc865")]
+    pub strict_rfc865: bool,
+
+    /// Serve TCP clients over TLS, with this PEM certificate chain
+    #[cfg(feature = "tls")]
+    #[arg(long, requires = "tls_key", value_hint = clap::ValueHint::FilePath)]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM private key for --tls-cert
+    #[cfg(feature = "tls")]
+    #[arg(long, requires = "tls_cert", value_hint = clap::ValueHint::FilePath)]
+    pub tls_key: Option<PathBuf>,
+
+    /// Serve TLS clients asking for this hostname only these categories, e.g. "clean.example.com=decorous"
+    ///
+    /// Categories are given as for --categories; "all" means those of every quote file indexed at
+    /// startup.
+    /// The hostname is matched, ignoring case, against the one the client sends (i.e. its SNI).
+    /// Clients asking for any other hostname, or none at all, are served as --categories allows. May
+    /// be given multiple times.
+    #[cfg(feature = "tls")]
+    #[arg(long, value_name = "HOST=CATEGORIES", requires = "tls_cert")]
+    pub tls_host: Vec<TlsHost>,
+
+    /// Append a record of every offensive quote served to this file
+    ///
+    /// Each record is a line of tab-separated fields: the time, protocol, client address, quote ID,
+    /// quote hash (see the grep command), and request ID (as logged).
+    #[arg(long, value_name = "PATH", value_hint = clap::ValueHint::FilePath)]
+    pub audit_file: Option<PathBuf>,
+
+    /// File to persist statistics in across restarts, e.g. how often each quote has been served
+    #[arg(long, value_hint = clap::ValueHint::FilePath)]
+    pub state_file: Option<PathBuf>,
+
+    /// How often to save statistics to the state file, in seconds (see --state-file)
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        default_value_t = 300,
+        requires = "state_file"
+    )]
+    pub state_interval: u64,
+
+    /// Quote file that quotes submitted through the admin endpoint are appended to and served from
+    ///
+    /// Quotes are submitted as the plain text body of a "POST /admin/quotes" request. The file is
+    /// created if need be, and must be writable by the user the server runs as (see --user).
+    #[arg(long, value_name = "PATH", value_hint = clap::ValueHint::FilePath, requires = "admin")]
+    pub submissions_file: Option<PathBuf>,
+
+    /// Template every quote is wrapped in, e.g. "{quote}    -- from {file}, {date}"
+    ///
+    /// The placeholders {quote}, {file}, {date}, and {category} are substituted; literal braces must be
+    /// doubled, i.e. "{{" and "}}".
+    #[arg(long, conflicts_with = "template_file")]
+    pub template: Option<Template>,
+
+    /// File containing the template every quote is wrapped in (see --template)
+    #[arg(long, value_hint = clap::ValueHint::FilePath)]
+    pub template_file: Option<PathBuf>,
+
     /// User to run the server as
     ///
     /// NOTE: This is currently only supported on Unix-like operating systems
     #[arg(long, short, default_value = "nobody")]
     pub user: String,
 
+    /// Fail to start if privileges can't be dropped to --user, even when not started as root
+    ///
+    /// Otherwise failing to drop privileges is only fatal if the server would be left running as
+    /// root, e.g. when there's no such user; when started unprivileged, it's only logged.
+    #[arg(long, conflicts_with = "allow_root")]
+    pub strict_privileges: bool,
+
+    /// Keep serving as root if privileges can't be dropped to --user, rather than failing to start
+    #[arg(long)]
+    pub allow_root: bool,
+
+    /// Serve from an unprivileged child process, supervised by one that only binds the sockets
+    ///
+    /// The child runs as --user (failing if it can't), confined to --chroot if given, and is
+    /// restarted should it crash. Sockets other than the main ones (e.g. --admin-address and
+    /// --listener) are bound by the child, so must be on unprivileged ports. SIGUSR2 replaces the
+    /// child with a new one, rather than re-executing the supervisor.
+    ///
+    /// NOTE: This is currently only supported on Unix-like operating systems
+    #[arg(long)]
+    pub privsep: bool,
+
+    /// Directory to confine the child to (see --privsep)
+    ///
+    /// Quotes and any other files are then read from within it, e.g. "--chroot /srv/qotd --dir
+    /// /quotes" reads quotes from "/srv/qotd/quotes".
+    #[arg(long, value_name = "DIR", value_hint = clap::ValueHint::DirPath, requires = "privsep")]
+    pub chroot: Option<PathBuf>,
+
+    /// Restrict the server's access to the filesystem with Landlock
+    ///
+    /// The server may then only read its quotes and the other files it was given (plus /etc, for
+    /// looking up users, host names, and the time zone), and only write the files it was given (e.g.
+    /// --log-file and --state-file) or create and replace them in their directories. Upgrading on
+    /// SIGUSR2 is impossible, as no program may be executed, but is possible with --privsep, which
+    /// restricts only the child. Kernels without Landlock leave access unrestricted, with a warning.
+    ///
+    /// NOTE: This is only supported on Linux 5.13 and later
+    #[arg(long)]
+    pub landlock: bool,
+
+    /// Sandbox the server with Capsicum once its sockets are bound and its quotes are open
+    ///
+    /// The server may then only read the quote files it opened, and accept connections on the
+    /// sockets it bound; nothing else may be opened, nor may any connections be made. Quote files
+    /// therefore can't be reloaded (e.g. with "POST /admin/reload"), changes to them go unnoticed,
+    /// and upgrading on SIGUSR2 is impossible unless with --privsep, which sandboxes only the child. UDP can't be answered,
+    /// so only TCP is served, and files written by their paths (e.g. --state-file) can't be used,
+    /// though --audit-file and --log-file can, as they're opened beforehand.
+    ///
+    /// NOTE: This is only supported on FreeBSD
+    #[arg(
+        long,
+        requires = "tcp_only",
+        conflicts_with_all = ["state_file", "submissions_file", "override_file"]
+    )]
+    pub capsicum: bool,
+
+    /// MQTT broker to publish a quote to on every rotation, e.g. "localhost:1883"
+    ///
+    /// See --mqtt-topic and --rotate.
+    #[cfg(feature = "mqtt")]
+    #[arg(long, value_name = "HOST:PORT")]
+    pub mqtt_broker: Option<String>,
+
+    /// Topic to publish quotes to
+    #[cfg(feature = "mqtt")]
+    #[arg(long, value_name = "TOPIC", default_value = "qotd")]
+    pub mqtt_topic: String,
+
+    /// Quality of service to publish quotes with
+    #[cfg(feature = "mqtt")]
+    #[arg(long, value_enum, default_value_t)]
+    pub mqtt_qos: crate::Qos,
+
+    /// Have the broker retain the last quote published, so that new subscribers get it at once
+    #[cfg(feature = "mqtt")]
+    #[arg(long)]
+    pub mqtt_retain: bool,
+
+    /// Client identifier to connect to the MQTT broker with
+    #[cfg(feature = "mqtt")]
+    #[arg(long, value_name = "ID", default_value = "qotd")]
+    pub mqtt_client_id: String,
+
+    /// User name to connect to the MQTT broker as (see --mqtt-password)
+    #[cfg(feature = "mqtt")]
+    #[arg(long, value_name = "NAME", requires = "mqtt_password")]
+    pub mqtt_username: Option<String>,
+
+    /// Password to connect to the MQTT broker with
+    ///
+    /// Prefer supplying this via the environment, where it won't be visible in the process list.
+    #[cfg(feature = "mqtt")]
+    #[arg(
+        long,
+        env = "QOTD_MQTT_PASSWORD",
+        hide_env_values = true,
+        requires = "mqtt_username"
+    )]
+    pub mqtt_password: Option<String>,
+
+    /// Service registry to register the server with while it serves, deregistering it on shutdown
+    ///
+    /// See --registry-url and --service-name.
+    #[cfg(feature = "registry")]
+    #[arg(long, value_enum, value_name = "KIND")]
+    pub registry: Option<crate::RegistryKind>,
+
+    /// URL of the service registry's HTTP API [default: the registry's usual port on the local host]
+    #[cfg(feature = "registry")]
+    #[arg(long, value_name = "URL", requires = "registry")]
+    pub registry_url: Option<String>,
+
+    /// Name to register the service as
+    #[cfg(feature = "registry")]
+    #[arg(long, value_name = "NAME", default_value = crate::DEFAULT_SERVICE_NAME)]
+    pub service_name: String,
+
+    /// Identifier to register this instance of the service as [default: the name, address, and port]
+    #[cfg(feature = "registry")]
+    #[arg(long, value_name = "ID", requires = "registry")]
+    pub service_id: Option<String>,
+
+    /// Address to advertise to clients, if not the one the server is bound to
+    #[cfg(feature = "registry")]
+    #[arg(long, value_name = "IP", requires = "registry")]
+    pub service_address: Option<std::net::IpAddr>,
+
+    /// Seconds after which the registration lapses unless refreshed, e.g. if the server dies
+    #[cfg(feature = "registry")]
+    #[arg(long, value_name = "SECONDS", default_value_t = crate::DEFAULT_REGISTRATION_TTL.as_secs())]
+    pub registry_ttl: u64,
+
+    /// URL to POST a quote to, as JSON, on every rotation (see --rotate)
+    ///
+    /// Prefer supplying this via the environment, as webhook URLs usually embed a secret.
+    #[cfg(feature = "webhook")]
+    #[arg(
+        long,
+        value_name = "URL",
+        env = "QOTD_WEBHOOK_URL",
+        hide_env_values = true
+    )]
+    pub webhook_url: Option<String>,
+
+    /// Shape of the payload posted to the webhook
+    #[cfg(feature = "webhook")]
+    #[arg(long, value_enum, default_value_t)]
+    pub webhook_format: crate::WebhookFormat,
+
+    /// StatsD server (or relay) to push statistics to, e.g. "localhost:8125"
+    ///
+    /// See --statsd-prefix and --statsd-interval.
+    #[cfg(feature = "statsd")]
+    #[arg(long, value_name = "HOST:PORT")]
+    pub statsd: Option<String>,
+
+    /// Prefix for the names of metrics pushed to StatsD; may be empty
+    #[cfg(feature = "statsd")]
+    #[arg(long, value_name = "PREFIX", default_value = "qotd")]
+    pub statsd_prefix: String,
+
+    /// Seconds between pushes of statistics to StatsD
+    #[cfg(feature = "statsd")]
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        default_value_t = crate::DEFAULT_STATSD_INTERVAL.as_secs(),
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    pub statsd_interval: u64,
+
     /// Reduce output
     ///
     /// This option is ignored if any number of --verbose flags are present
@@ -87,24 +884,505 @@ pub struct Cli {
     /// Increase verbosity
     ///
     /// This flag may appear multiple times, each appearance (up to 3) increasing the level of verbosity
+    /// Directives in the RUST_LOG environment variable, e.g. "qotd::server=warn", take precedence over
+    /// this and the other log level options, for both the console and the log file.
     #[arg(short, long = "verbose", action = clap::ArgAction::Count)]
     verbosity: u8,
 }
 
+/// Auxiliary commands; if none is given, the server is run
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Write every quote that may be served, as selected by the other options, as structured records
+    #[cfg(feature = "export")]
+    Export {
+        #[arg(long, value_enum, default_value_t)]
+        format: crate::ExportFormat,
+        /// File to write, rather than writing to stdout
+        #[arg(long, short, value_hint = clap::ValueHint::FilePath)]
+        output: Option<PathBuf>,
+    },
+    /// Download a collection of quotes, by name or URL, into the quote directory (see --dir)
+    ///
+    /// Named packs are fortune-mod's data files: art, computers, cookie, definitions, fortunes,
+    /// literature, science, wisdom, and zippy.
+    #[cfg(feature = "fetch")]
+    Fetch {
+        /// Name or URL of the pack to fetch
+        pack: String,
+        /// Expected SHA-256 digest of the download, in hex
+        #[arg(long, value_name = "DIGEST")]
+        sha256: Option<String>,
+        /// Replace a quote file of the same name
+        #[arg(long)]
+        force: bool,
+    },
+    /// Print every quote that may be served matching a pattern, with its ID and hash
+    ///
+    /// Quotes are printed like `fortune -m` does, each preceded by its ID and hash (see --deny-list)
+    /// in parentheses.
+    Grep {
+        /// Text to search for, or a regular expression with --regex
+        pattern: String,
+        /// Treat the pattern as a regular expression
+        #[arg(long, short = 'E')]
+        regex: bool,
+        /// Ignore case when matching
+        #[arg(long, short = 'i')]
+        ignore_case: bool,
+    },
+    /// Convert a quote collection in another format into a quote file
+    #[cfg(feature = "import")]
+    Import {
+        /// File to convert
+        #[arg(value_hint = clap::ValueHint::FilePath)]
+        input: PathBuf,
+        /// Format of the file to convert, if not given by its extension
+        #[arg(long, value_enum)]
+        format: Option<crate::ImportFormat>,
+        /// Quote file to write, rather than writing to stdout
+        #[arg(long, short, value_hint = clap::ValueHint::FilePath)]
+        output: Option<PathBuf>,
+        /// Category to declare for the quotes
+        #[arg(long)]
+        category: Option<QuoteCategory>,
+        /// Language to declare for the quotes, e.g. "en"
+        #[arg(long)]
+        language: Option<String>,
+    },
+    /// Join an IRC channel and post quotes there, rather than serving quotes
+    ///
+    /// A quote is posted whenever someone says the command (by default "!qotd") in the channel, or
+    /// privately to the bot. Only plain-text connections are supported.
+    #[cfg(feature = "irc")]
+    Irc {
+        /// Server to connect to, e.g. "irc.libera.chat:6667"
+        server: String,
+        /// Channel to join, e.g. "#quotes"
+        #[arg(long)]
+        channel: String,
+        /// Nickname to connect as
+        #[arg(long, default_value = "qotd")]
+        nick: String,
+        /// Password to connect with
+        ///
+        /// Prefer supplying this via the environment, where it won't be visible in the process list.
+        #[arg(long, env = "QOTD_IRC_PASSWORD", hide_env_values = true)]
+        password: Option<String>,
+        /// Message asking for a quote
+        #[arg(long, default_value = "!qotd")]
+        command: String,
+        /// Also post a quote to the channel every day at this time, in server-local time (e.g. 09:00)
+        #[arg(long, value_name = "HH:MM", value_parser = parse_time)]
+        daily_at: Option<chrono::NaiveTime>,
+    },
+    /// Keep a file holding a quote, replaced with another on every rotation, rather than serving
+    /// quotes
+    ///
+    /// Quotes are replaced daily, or as often as --rotate says. The file is replaced atomically, so
+    /// it's safe to point sshd's Banner option at it, or to keep it among pam_motd's fragments (e.g.
+    /// /etc/motd.d/qotd). Quotes are wrapped, decorated, and templated as they would be for UDP
+    /// clients.
+    Motd {
+        /// File to keep a quote in
+        #[arg(value_hint = clap::ValueHint::FilePath)]
+        path: PathBuf,
+        /// Write a single quote and exit, e.g. when run from a timer
+        #[arg(long)]
+        once: bool,
+    },
+    /// Generate files derived from this command-line interface
+    #[command(subcommand)]
+    Generate(Generate),
+}
+
+/// An additional address to serve quotes on, with its own quote directory and categories
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListenerSpec {
+    /// Address to bind to, e.g. "0.0.0.0:1717"
+    pub address: String,
+    /// Directory to read quote files from, if not the main one
+    pub dir: Option<PathBuf>,
+    /// Categories to serve, if not those of the main listener
+    pub categories: Option<AllowedCategories>,
+}
+
+impl FromStr for ListenerSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(',');
+        let address = parts.next().unwrap_or_default().trim();
+        if address.is_empty() {
+            return Err(format!(
+                "Expected a listener like 0.0.0.0:1717,categories=all, got: {s}"
+            ));
+        }
+        let mut spec = Self {
+            address: address.to_string(),
+            dir: None,
+            categories: None,
+        };
+        for option in parts {
+            match option.split_once('=') {
+                Some(("dir", dir)) => spec.dir = Some(PathBuf::from(dir)),
+                Some(("categories", categories)) => {
+                    spec.categories = Some(categories.parse()?);
+                }
+                _ => return Err(format!("Unknown listener option \"{option}\"")),
+            }
+        }
+        Ok(spec)
+    }
+}
+
+/// A bound on the length of quotes served, see [`Cli::quote_lengths`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LengthLimit {
+    /// Whether the limit only applies over UDP (`Some(true)`) or TCP (`Some(false)`)
+    pub udp: Option<bool>,
+    pub bytes: usize,
+}
+
+impl FromStr for LengthLimit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (udp, bytes) = match s.split_once('=') {
+            Some((protocol, bytes)) => match protocol.trim().to_ascii_lowercase().as_str() {
+                "tcp" => (Some(false), bytes),
+                "udp" => (Some(true), bytes),
+                other => return Err(format!("Unknown protocol \"{other}\", expected tcp or udp")),
+            },
+            None => (None, s),
+        };
+        let bytes = bytes
+            .trim()
+            .parse()
+            .map_err(|e| format!("Invalid length \"{bytes}\": {e}"))?;
+        Ok(Self { udp, bytes })
+    }
+}
+
+/// A simple service to serve, and the port to serve it on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SimpleServiceArg {
+    pub service: SimpleService,
+    pub port: u16,
+}
+
+impl FromStr for SimpleServiceArg {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (service, port) = match s.split_once('=') {
+            Some((service, port)) => (service, Some(port)),
+            None => (s, None),
+        };
+        let service = SimpleService::from_str(service.trim(), true)?;
+        let port = match port {
+            Some(port) => port
+                .trim()
+                .parse()
+                .map_err(|e| format!("Invalid port \"{port}\": {e}"))?,
+            None => service.default_port(),
+        };
+        Ok(Self { service, port })
+    }
+}
+
+/// Categories served to TLS clients asking for a particular hostname
+#[cfg(feature = "tls")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsHost {
+    pub host: String,
+    pub categories: AllowedCategories,
+}
+
+#[cfg(feature = "tls")]
+impl FromStr for TlsHost {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((host, categories)) if !host.trim().is_empty() => Ok(Self {
+                host: host.trim().to_string(),
+                categories: categories.parse()?,
+            }),
+            _ => Err(format!(
+                "Expected a hostname like clean.example.com=decorous, got: {s}"
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Generate {
+    /// Generate a completion script for the given shell
+    Completions {
+        #[arg(value_enum)]
+        shell: clap_complete::Shell,
+    },
+    /// Generate a manual page in roff format
+    Man,
+    /// Generate an example configuration, as TOML, with every setting commented out at its default
+    ///
+    /// Settings are named and written as --print-config prints them, each described as in --help.
+    Config,
+}
+
+impl Generate {
+    /// Name of the server binary, which completions and the man page are generated for
+    const BIN_NAME: &'static str = "qotd-server";
+
+    /// Write the generated file to `out`
+    pub fn write<W: Write>(&self, out: &mut W) -> std::io::Result<()> {
+        let mut cmd = Cli::command().name(Self::BIN_NAME);
+        match *self {
+            Generate::Completions { shell } => {
+                clap_complete::generate(shell, &mut cmd, Self::BIN_NAME, out);
+                Ok(())
+            }
+            Generate::Man => clap_mangen::Man::new(cmd).render(out),
+            Generate::Config => out.write_all(Cli::example_config().as_bytes()),
+        }
+    }
+}
+
 impl Cli {
-    pub fn allowed_categories(&self) -> Vec<QuoteCategory> {
-        if let Some(categories) = self.categories {
+    /// The configuration resolved from the command line `args` (along with the environment and
+    /// defaults), as TOML annotated with where each setting came from, see --print-config
+    pub fn effective_config<I, T>(args: I) -> String
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        let cmd = Self::command();
+        let matches = cmd.clone().get_matches_from(args);
+        let mut config = String::new();
+        for arg in cmd.get_arguments() {
+            let id = arg.get_id().as_str();
+            let (Some(values), Some(source)) = (matches.get_raw(id), matches.value_source(id))
+            else {
+                continue;
+            };
+            if matches!(id, "help" | "version" | "print_config") {
+                continue;
+            }
+            let values: Vec<_> = values
+                .map(|value| {
+                    if arg.is_hide_env_values_set() {
+                        toml_string("<redacted>")
+                    } else {
+                        toml_value(&value.to_string_lossy(), arg.get_action())
+                    }
+                })
+                .collect();
+            let value = match arg.get_action() {
+                ArgAction::Append => format!("[{}]", values.join(", ")),
+                _ => values.join(", "),
+            };
+            let source = match (source, arg.get_env()) {
+                (ValueSource::EnvVariable, Some(env)) => {
+                    format!("from ${}", env.to_string_lossy())
+                }
+                (ValueSource::DefaultValue, _) => "default".to_string(),
+                _ => "command line".to_string(),
+            };
+            let key = arg.get_long().unwrap_or(id);
+            config.push_str(&format!("{key} = {value}  # {source}\n"));
+        }
+        // The quote directory's default isn't known until it's resolved
+        if let Ok(cli) = Self::from_arg_matches(&matches) {
+            if cli.dir.is_none() {
+                let dir = toml_string(&cli.quote_dir().to_string_lossy());
+                config.push_str(&format!("dir = {dir}  # default\n"));
+            }
+        }
+        config
+    }
+
+    /// Every setting, commented out at its default (or a placeholder if it has none), described as
+    /// in --help, as TOML like that of [`Cli::effective_config`]
+    pub fn example_config() -> String {
+        let mut config = format!(
+            "# Example configuration for {}, generated by `{0} generate config`\n\
+             #\n\
+             # Each setting is named after the command-line option that sets it.\n",
+            Generate::BIN_NAME
+        );
+        for arg in Self::command().get_arguments() {
+            let id = arg.get_id().as_str();
+            if arg.is_hide_set() || matches!(id, "help" | "version" | "print_config") {
+                continue;
+            }
+            config.push('\n');
+            let help = arg.get_long_help().or(arg.get_help());
+            for line in help.map(ToString::to_string).unwrap_or_default().lines() {
+                config.push_str(format!("# {line}").trim_end());
+                config.push('\n');
+            }
+            if let Some(env) = arg.get_env() {
+                config.push_str(&format!(
+                    "# May also be set by ${}\n",
+                    env.to_string_lossy()
+                ));
+            }
+            let action = arg.get_action();
+            let defaults: Vec<_> = arg
+                .get_default_values()
+                .iter()
+                .map(|value| toml_value(&value.to_string_lossy(), action))
+                .collect();
+            let value = match (action, &defaults[..]) {
+                (ArgAction::Append, _) => format!("[{}]", defaults.join(", ")),
+                (ArgAction::SetTrue, []) => "false".to_string(),
+                (ArgAction::SetFalse, []) => "true".to_string(),
+                (ArgAction::Count, []) => "0".to_string(),
+                (_, []) => {
+                    let names = arg.get_value_names().unwrap_or_default();
+                    let placeholder = names.first().map_or(id, |name| name.as_str());
+                    toml_string(placeholder)
+                }
+                _ => defaults.join(", "),
+            };
+            let key = arg.get_long().unwrap_or(id);
+            config.push_str(&format!("#{key} = {value}\n"));
+        }
+        config
+    }
+
+    /// The directory to read quote files from, whether given or defaulted
+    pub fn quote_dir(&self) -> PathBuf {
+        self.dir.clone().unwrap_or_else(default_dir)
+    }
+
+    /// Additional addresses to serve quotes on, including --offensive-port's
+    pub fn listeners(&self) -> Vec<ListenerSpec> {
+        let mut listeners = self.listener.clone();
+        if let Some(port) = self.offensive_port {
+            // IPv6 addresses must be bracketed to be followed by a port
+            let address = if self.host.contains(':') {
+                format!("[{}]:{port}", self.host)
+            } else {
+                format!("{}:{port}", self.host)
+            };
+            listeners.push(ListenerSpec {
+                address,
+                dir: None,
+                categories: Some(AllowedCategories::Only(vec![QuoteCategory::OFFENSIVE])),
+            });
+        }
+        listeners
+    }
+
+    /// The bounds on the length of quotes served over UDP (`Some(true)`), TCP (`Some(false)`), or
+    /// anything else (`None`), from --short and --long
+    ///
+    /// Limits for the protocol take precedence over those for every quote.
+    pub fn quote_lengths(&self, udp: Option<bool>) -> QuoteLengths {
+        let bound = |limits: &[LengthLimit]| {
+            let applies = |wanted: Option<bool>| {
+                limits
+                    .iter()
+                    .rev()
+                    .find(|limit| limit.udp == wanted)
+                    .map(|limit| limit.bytes)
+            };
+            udp.and_then(|udp| applies(Some(udp)))
+                .or_else(|| applies(None))
+        };
+        QuoteLengths {
+            shortest: bound(&self.long),
+            longest: bound(&self.short),
+        }
+    }
+
+    /// The categories allowed, or `None` if all are
+    pub fn allowed_categories(&self) -> Option<Vec<QuoteCategory>> {
+        self.allowed_categories_with(None)
+    }
+
+    /// The categories allowed for `listener`, which supersede --categories, -a, and -o if given
+    pub fn listener_categories(&self, listener: &ListenerSpec) -> Option<Vec<QuoteCategory>> {
+        self.allowed_categories_with(listener.categories.as_ref())
+    }
+
+    /// The categories to index: those allowed, plus any served only to some TLS hostnames, or
+    /// `None` if all are
+    pub fn indexed_categories(&self) -> Option<Vec<QuoteCategory>> {
+        #[allow(unused_mut)]
+        let mut categories = self.allowed_categories();
+        #[cfg(feature = "tls")]
+        for host in &self.tls_host {
+            let (Some(categories), Some(host_categories)) =
+                (&mut categories, host.categories.as_category_vec())
+            else {
+                return None;
+            };
+            for category in host_categories {
+                if !categories.contains(&category) {
+                    categories.push(category);
+                }
+            }
+        }
+        categories
+    }
+
+    fn allowed_categories_with(
+        &self,
+        categories: Option<&AllowedCategories>,
+    ) -> Option<Vec<QuoteCategory>> {
+        let mut categories = categories.map_or_else(
+            || self.unscheduled_categories(),
+            AllowedCategories::as_category_vec,
+        )?;
+        for category in self.schedule.iter().map(|entry| entry.category) {
+            if !categories.contains(&category) {
+                categories.push(category);
+            }
+        }
+        Some(categories)
+    }
+
+    pub fn access_control(&self) -> AccessControl {
+        let access = self
+            .allow
+            .iter()
+            .fold(AccessControl::new(), |access, &net| access.allow(net));
+        self.deny
+            .iter()
+            .fold(access, |access, &net| access.deny(net))
+    }
+
+    pub fn keepalive(&self) -> Option<Keepalive> {
+        self.keepalive.map(|idle| Keepalive {
+            idle: Duration::from_secs(idle),
+            interval: self.keepalive_interval.map(Duration::from_secs),
+            retries: self.keepalive_retries,
+        })
+    }
+
+    pub fn schedule(&self) -> CategorySchedule {
+        self.schedule.iter().copied().collect()
+    }
+
+    fn unscheduled_categories(&self) -> Option<Vec<QuoteCategory>> {
+        if let Some(categories) = &self.categories {
             categories.as_category_vec()
         } else if self.all {
-            AllowedCategories::All.as_category_vec()
+            None
         } else if self.offensive {
-            AllowedCategories::Offensive.as_category_vec()
+            Some(vec![QuoteCategory::OFFENSIVE])
         } else {
-            AllowedCategories::Decorous.as_category_vec()
+            AllowedCategories::default().as_category_vec()
         }
     }
 
     pub fn verbosity(&self) -> tracing::level_filters::LevelFilter {
+        if let Some(level) = self.log_level {
+            return level.into();
+        }
         match self.verbosity {
             0 => {
                 if self.quiet {
@@ -122,18 +1400,39 @@ impl Cli {
 
     pub fn file_verbosity(&self) -> tracing::level_filters::LevelFilter {
         match self.file_log_level {
-            Some(level) => match level {
-                FileLogLevel::Error => tracing::level_filters::LevelFilter::ERROR,
-                FileLogLevel::Warn => tracing::level_filters::LevelFilter::WARN,
-                FileLogLevel::Info => tracing::level_filters::LevelFilter::INFO,
-                FileLogLevel::Debug => tracing::level_filters::LevelFilter::DEBUG,
-                FileLogLevel::Trace => tracing::level_filters::LevelFilter::TRACE,
-            },
+            Some(level) => level.into(),
             None => self.verbosity(),
         }
     }
 }
 
+/// `value` as a TOML value: flags and numbers as they are, anything else as a string
+fn toml_value(value: &str, action: &ArgAction) -> String {
+    let is_number = value.parse::<i64>().is_ok() || value.parse::<f64>().is_ok_and(f64::is_finite);
+    match action {
+        ArgAction::SetTrue | ArgAction::SetFalse | ArgAction::Count => value.to_string(),
+        _ if is_number => value.to_string(),
+        _ => toml_string(value),
+    }
+}
+
+/// `s` as a quoted TOML string
+fn toml_string(s: &str) -> String {
+    let mut quoted = String::from('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04X}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
 fn default_dir() -> PathBuf {
     let mut path = std::env::var("CARGO_MANIFEST_DIR")
         .map(|p| p.into())
@@ -146,3 +1445,56 @@ fn default_dir() -> PathBuf {
 
     path
 }
+
+/// Parse a Differentiated Services codepoint, e.g. "46" or "ef"
+fn parse_dscp(s: &str) -> Result<u8, String> {
+    let s = s.trim().to_ascii_lowercase();
+    let invalid = || format!("Invalid DSCP \"{s}\"; expected 0-63, cs0-cs7, af11-af43, ef, or le");
+    let digit = |c: u8, max: u8| {
+        c.checked_sub(b'0')
+            .filter(|&d| d <= max)
+            .ok_or_else(invalid)
+    };
+    match s.as_bytes() {
+        b"ef" => Ok(46),
+        b"le" => Ok(1),
+        [b'c', b's', class] => Ok(digit(*class, 7)? << 3),
+        [b'a', b'f', class, drop] if *class != b'0' && *drop != b'0' => {
+            Ok(digit(*class, 4)? << 3 | digit(*drop, 3)? << 1)
+        }
+        _ => match s.parse::<u8>() {
+            Ok(dscp) if dscp < 64 => Ok(dscp),
+            _ => Err(invalid()),
+        },
+    }
+}
+
+/// Parse a period written like a rotation, e.g. "15m" or "7d"
+fn parse_period(s: &str) -> Result<std::time::Duration, String> {
+    s.parse::<crate::Rotation>()
+        .map(|rotation| rotation.period())
+}
+
+/// Parse a factor of at least 1, e.g. "2.5"
+fn parse_boost(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(boost) if boost.is_finite() && boost >= 1.0 => Ok(boost),
+        _ => Err(format!("Expected a factor of at least 1, got: {s}")),
+    }
+}
+
+/// Parse a fraction between 0 and 1, e.g. "0.25"
+fn parse_fraction(s: &str) -> Result<f64, String> {
+    match s.trim().parse::<f64>() {
+        Ok(fraction) if (0.0..=1.0).contains(&fraction) => Ok(fraction),
+        Ok(_) => Err(format!("{s} is not between 0 and 1")),
+        Err(e) => Err(format!("Invalid fraction \"{s}\": {e}")),
+    }
+}
+
+/// Parse a time of day like "09:00"
+#[cfg(feature = "irc")]
+fn parse_time(s: &str) -> Result<chrono::NaiveTime, String> {
+    chrono::NaiveTime::parse_from_str(s.trim(), "%H:%M")
+        .map_err(|e| format!("Invalid time \"{s}\": {e}"))
+}
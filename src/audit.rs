@@ -0,0 +1,72 @@
+//! This module keeps an audit log of offensive quotes served, so that exactly what was sent to whom
+//! can be answered for later
+//!
+//! Each record is a line of tab-separated fields: an RFC 3339 timestamp, the protocol, the client's
+//! address, the quote's ID and hash (see [`Quote::hash`](crate::Quote::hash)), and the request's ID
+//! (as logged). Records are only ever appended, and written as soon as the quote is served.
+#![cfg(any(feature = "tcp", feature = "udp"))]
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    net::SocketAddr,
+    path::Path,
+    sync::Mutex,
+};
+
+use tracing::warn;
+
+#[cfg(feature = "tcp")]
+use crate::ChosenQuote;
+use crate::{server::RequestId, Quote, QuoteCategory, QuoteId};
+
+#[derive(Debug)]
+pub(crate) struct AuditLog {
+    file: Mutex<File>,
+}
+
+impl AuditLog {
+    /// Open the audit log at `path`, creating it if need be
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Record `quote` as served to `peer`, if it's offensive
+    ///
+    /// Failing to write is logged rather than failing the request, which has already been served.
+    pub fn record(&self, protocol: &str, peer: SocketAddr, request: RequestId, quote: &Quote) {
+        if quote.category == QuoteCategory::OFFENSIVE {
+            self.write(protocol, peer, request, &quote.id);
+        }
+    }
+
+    /// Record `quote` as served to `peer`, if it's offensive, like [`AuditLog::record`]
+    #[cfg(feature = "tcp")]
+    pub fn record_chosen(
+        &self,
+        protocol: &str,
+        peer: SocketAddr,
+        request: RequestId,
+        quote: &ChosenQuote,
+    ) {
+        if quote.category() == QuoteCategory::OFFENSIVE {
+            self.write(protocol, peer, request, quote.id());
+        }
+    }
+
+    fn write(&self, protocol: &str, peer: SocketAddr, request: RequestId, id: &QuoteId) {
+        let record = format!(
+            "{}\t{protocol}\t{peer}\t{id}\t{}\t{request}\n",
+            chrono::Local::now().to_rfc3339(),
+            id.hash_hex()
+        );
+        // Each record is written at once, so that concurrent records don't interleave
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        if let Err(e) = file.write_all(record.as_bytes()) {
+            warn!("Failed to write audit record of quote {id}: {e}");
+        }
+    }
+}
@@ -0,0 +1,449 @@
+//! Client program for QotD Protocol service
+
+mod srv;
+
+use std::{
+    fmt,
+    io::{ErrorKind, Read},
+    net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs, UdpSocket},
+    path::PathBuf,
+    process::ExitCode,
+    sync::mpsc,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use clap::Parser;
+use socket2::{Domain, Protocol, Socket, Type};
+
+/// How long to wait for a TCP connection to an address before also trying the next one
+const CONNECTION_STAGGER: Duration = Duration::from_millis(250);
+
+/// Length of UDP replies that may have been truncated to fit in a datagram
+///
+/// Servers truncate quotes to less than the 512 bytes RFC 865 limits them to, cutting them short by
+/// up to 3 more bytes so as not to split a UTF-8 character.
+const TRUNCATED_UDP_LENGTH: usize = 508;
+
+/// How long to wait for a UDP reply before asking again, doubling with each retry
+const INITIAL_UDP_WAIT: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Parser)]
+struct Args {
+    /// IP or hostname to connect to
+    #[arg(value_name = "IP or HOSTNAME", required_unless_present_any = ["servers", "srv"])]
+    pub host: Option<String>,
+
+    /// Port number to connect to, unless a server's own port is given
+    #[arg(default_value_t = 17)]
+    pub port: u16,
+
+    /// Also try the servers listed in this file, one per line as HOST or HOST:PORT
+    ///
+    /// Servers are tried in turn until one sends a quote: first the one given on the command line,
+    /// if any, then those in this file in order, then those found with --srv. Empty lines and
+    /// lines starting with '#' are ignored.
+    #[arg(long, value_name = "PATH", value_hint = clap::ValueHint::FilePath)]
+    pub servers: Option<PathBuf>,
+
+    /// Also try the servers found in the "_qotd._tcp" SRV records of this domain
+    ///
+    /// Servers are tried in order of priority, then of weight (heaviest first).
+    #[arg(long, value_name = "DOMAIN")]
+    pub srv: Option<String>,
+
+    /// Use TCP instead of UDP
+    ///
+    /// Otherwise TCP is only used if no UDP reply comes, or the reply looks truncated.
+    #[arg(long)]
+    pub tcp: bool,
+
+    /// Never fall back to TCP when fetching a quote over UDP
+    #[arg(long, conflicts_with_all = ["tcp", "length_prefix"])]
+    pub no_fallback: bool,
+
+    /// With --check, only check UDP
+    #[arg(long, conflicts_with_all = ["tcp", "length_prefix"], requires = "check")]
+    pub udp: bool,
+
+    /// Expect the quote to be prefixed with its length, as sent by a server run with
+    /// --length-prefix; implies --tcp
+    #[arg(long)]
+    pub length_prefix: bool,
+
+    /// Give up on the server if it hasn't answered within this many milliseconds
+    #[arg(long, value_name = "MS", default_value_t = 5000, value_parser = clap::value_parser!(u64).range(1..))]
+    pub timeout_ms: u64,
+
+    /// Ask again up to this many times if no UDP reply comes, waiting twice as long each time
+    ///
+    /// Either the request or the reply may be lost, since UDP doesn't guarantee delivery. The first
+    /// retry is after 500 milliseconds; no reply within --timeout-ms is still an error.
+    #[arg(long, value_name = "COUNT", default_value_t = 3)]
+    pub retries: u32,
+
+    /// Send requests from this local address, e.g. to test the server's access control
+    ///
+    /// Only the server's addresses of the same family (IPv4 or IPv6) are tried.
+    #[arg(long, value_name = "ADDRESS")]
+    pub source: Option<IpAddr>,
+
+    /// Send requests through this network interface, e.g. "eth1", whatever the routing table says
+    ///
+    /// NOTE: This is currently only supported on Linux, and usually requires root
+    #[arg(long, value_name = "NAME")]
+    pub interface: Option<String>,
+
+    /// Check the server is serving quotes over both TCP and UDP (or only --tcp or --udp)
+    ///
+    /// Nothing is printed if every check passes, otherwise what failed is. Exits with 0 if the
+    /// server is up, 1 if it's degraded (some but not all checks failed), or 2 if it's down, as
+    /// e.g. Nagios expects.
+    #[arg(long)]
+    pub check: bool,
+}
+
+/// A server to fetch quotes from
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Server {
+    host: String,
+    port: u16,
+}
+
+impl Server {
+    /// Parse `s` as HOST or HOST:PORT, using `default_port` for the former
+    fn parse(s: &str, default_port: u16) -> anyhow::Result<Self> {
+        if let Ok(addr) = s.parse::<SocketAddr>() {
+            return Ok(Self {
+                host: addr.ip().to_string(),
+                port: addr.port(),
+            });
+        }
+        // A bare IPv6 address is full of colons, none of which start a port
+        if s.parse::<IpAddr>().is_ok() {
+            return Ok(Self {
+                host: s.to_string(),
+                port: default_port,
+            });
+        }
+        match s.rsplit_once(':') {
+            Some((host, port)) => Ok(Self {
+                host: host.to_string(),
+                port: port
+                    .parse()
+                    .with_context(|| format!("Invalid port in {s}"))?,
+            }),
+            None => Ok(Self {
+                host: s.to_string(),
+                port: default_port,
+            }),
+        }
+    }
+}
+
+impl fmt::Display for Server {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.host.parse::<IpAddr>() {
+            Ok(IpAddr::V6(_)) => write!(f, "[{}]:{}", self.host, self.port),
+            _ => write!(f, "{}:{}", self.host, self.port),
+        }
+    }
+}
+
+/// Every server to try, in order
+fn servers(args: &Args) -> anyhow::Result<Vec<Server>> {
+    let mut servers = Vec::new();
+    if let Some(host) = &args.host {
+        servers.push(Server {
+            host: host.clone(),
+            port: args.port,
+        });
+    }
+    if let Some(path) = &args.servers {
+        let list = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read servers from {}", path.display()))?;
+        for line in list.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            servers.push(Server::parse(line, args.port)?);
+        }
+    }
+    if let Some(domain) = &args.srv {
+        let name = format!("_qotd._tcp.{}", domain.trim_end_matches('.'));
+        let timeout = Duration::from_millis(args.timeout_ms);
+        servers.extend(
+            srv::lookup(&name, timeout)
+                .with_context(|| format!("Failed to look up SRV records for {name}"))?,
+        );
+    }
+    if servers.is_empty() {
+        anyhow::bail!("No servers to try");
+    }
+    Ok(servers)
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+
+    let servers = match servers(&args) {
+        Ok(servers) => servers,
+        Err(e) => {
+            eprintln!("Error: {e:?}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if args.check {
+        return check(&args, &servers);
+    }
+
+    let mut quote = Err(anyhow::anyhow!("No servers to try"));
+    for server in &servers {
+        quote =
+            fetch_quote(&args, server).with_context(|| format!("Failed to fetch from {server}"));
+        if quote.is_ok() {
+            break;
+        }
+    }
+    match quote {
+        Ok(quote) => {
+            println!("{}", quote.trim_end());
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("Error: {e:?}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Get a quote from `server` over whichever transport gets one
+fn fetch_quote(args: &Args, server: &Server) -> anyhow::Result<String> {
+    if args.tcp || args.length_prefix {
+        return fetch(args, server, true);
+    }
+    match fetch(args, server, false) {
+        Ok(quote) if quote.len() < TRUNCATED_UDP_LENGTH || args.no_fallback => Ok(quote),
+        Err(e) if args.no_fallback => Err(e),
+        // Either way TCP can do better, though it'll likely be a different quote
+        _ => fetch(args, server, true),
+    }
+}
+
+/// Check each transport of each server in turn, reporting any that fail
+fn check(args: &Args, servers: &[Server]) -> ExitCode {
+    let mut transports = Vec::new();
+    if !args.udp {
+        transports.push(("TCP", true));
+    }
+    if !(args.tcp || args.length_prefix) {
+        transports.push(("UDP", false));
+    }
+
+    let mut failed = 0;
+    for server in servers {
+        for &(name, tcp) in &transports {
+            if let Err(e) = fetch(args, server, tcp) {
+                match servers.len() {
+                    1 => println!("{name}: {e:#}"),
+                    _ => println!("{server} {name}: {e:#}"),
+                }
+                failed += 1;
+            }
+        }
+    }
+
+    match failed {
+        0 => ExitCode::SUCCESS,
+        _ if failed < servers.len() * transports.len() => ExitCode::from(1),
+        _ => ExitCode::from(2),
+    }
+}
+
+/// Get a quote from `server`, over TCP if `tcp` or else UDP
+fn fetch(args: &Args, server: &Server, tcp: bool) -> anyhow::Result<String> {
+    let bytes = if tcp {
+        do_tcp(args, server)?
+    } else {
+        do_udp(args, server)?
+    };
+    if bytes.is_empty() {
+        anyhow::bail!("Empty response");
+    }
+
+    // Convert into a string, propogating any conversion errors
+    String::from_utf8(bytes).context("Response is not valid UTF-8")
+}
+
+fn do_tcp(args: &Args, server: &Server) -> anyhow::Result<Vec<u8>> {
+    let timeout = Duration::from_millis(args.timeout_ms);
+
+    let mut stream = connect(args, server, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+
+    if args.length_prefix {
+        // Read exactly as much as the server says it's sending
+        let mut len = [0; 2];
+        stream.read_exact(&mut len)?;
+        let mut buf = vec![0; u16::from_be_bytes(len) as usize];
+        stream.read_exact(&mut buf)?;
+        return Ok(buf);
+    }
+
+    // Read all data sent to us into a bytes Vec
+    // The server will close the connection once it's sent us one quote, so this is all we need
+    let mut buf = Vec::new();
+    stream.read_to_end(&mut buf)?;
+
+    Ok(buf)
+}
+
+/// Connect to the server over TCP, racing its addresses as "Happy Eyeballs" (RFC 8305) does
+///
+/// Addresses are tried alternating between IPv6 and IPv4, starting with IPv6; each attempt gets
+/// [`CONNECTION_STAGGER`] to succeed before the next one starts alongside it, and the first
+/// connection made is used.
+fn connect(args: &Args, server: &Server, timeout: Duration) -> anyhow::Result<TcpStream> {
+    let (ipv6, ipv4): (Vec<_>, Vec<_>) = server_addrs(args, server)?.partition(SocketAddr::is_ipv6);
+    let (mut ipv6, mut ipv4) = (ipv6.into_iter(), ipv4.into_iter());
+    let mut interleaved = Vec::new();
+    loop {
+        match (ipv6.next(), ipv4.next()) {
+            (None, None) => break,
+            (first, second) => interleaved.extend(first.into_iter().chain(second)),
+        }
+    }
+    let mut addrs = interleaved.into_iter();
+
+    let deadline = Instant::now() + timeout;
+    let (tx, rx) = mpsc::channel();
+    let mut pending = 0;
+    let mut error = None;
+    loop {
+        if let Some(addr) = addrs.next() {
+            let socket = match local_socket(args, addr, Type::STREAM, Protocol::TCP) {
+                Ok(socket) => socket,
+                Err(e) => {
+                    error = Some(e);
+                    continue;
+                }
+            };
+            let tx = tx.clone();
+            std::thread::spawn(move || {
+                let connected = socket
+                    .connect_timeout(&addr.into(), timeout)
+                    .map(|()| TcpStream::from(socket))
+                    .with_context(|| format!("Failed to connect to {addr}"));
+                // Should another attempt have won, this connection is simply dropped
+                let _ = tx.send(connected);
+            });
+            pending += 1;
+        } else if pending == 0 {
+            break;
+        }
+
+        // With more addresses left to try, only wait for the stagger before trying the next
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let wait = match addrs.len() {
+            0 => remaining,
+            _ => remaining.min(CONNECTION_STAGGER),
+        };
+        match rx.recv_timeout(wait) {
+            Ok(Ok(stream)) => return Ok(stream),
+            Ok(Err(e)) => {
+                pending -= 1;
+                error = Some(e);
+            }
+            Err(_) if Instant::now() >= deadline => break,
+            Err(_) => {}
+        }
+    }
+
+    match error {
+        _ if pending > 0 => anyhow::bail!("Failed to connect within {}ms", timeout.as_millis()),
+        Some(e) => Err(e),
+        None => anyhow::bail!("No addresses found for {}", server.host),
+    }
+}
+
+/// The addresses of `server` that can be reached from --source
+fn server_addrs<'a>(
+    args: &'a Args,
+    server: &Server,
+) -> anyhow::Result<impl Iterator<Item = SocketAddr> + 'a> {
+    let addrs = (server.host.as_str(), server.port)
+        .to_socket_addrs()
+        .with_context(|| format!("Failed to resolve {}", server.host))?;
+    Ok(addrs.filter(|addr| {
+        args.source
+            .is_none_or(|source| source.is_ipv4() == addr.is_ipv4())
+    }))
+}
+
+/// Create a socket to reach `addr` with, bound to --source and --interface if given
+fn local_socket(
+    args: &Args,
+    addr: SocketAddr,
+    ty: Type,
+    protocol: Protocol,
+) -> anyhow::Result<Socket> {
+    let socket = Socket::new(Domain::for_address(addr), ty, Some(protocol))?;
+    if let Some(interface) = &args.interface {
+        #[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+        socket
+            .bind_device(Some(interface.as_bytes()))
+            .with_context(|| format!("Failed to bind to interface {interface}"))?;
+        #[cfg(not(any(target_os = "android", target_os = "fuchsia", target_os = "linux")))]
+        anyhow::bail!("Binding to interface {interface} is not supported on this platform");
+    }
+    if let Some(source) = args.source {
+        // Any port will do
+        socket
+            .bind(&SocketAddr::new(source, 0).into())
+            .with_context(|| format!("Failed to bind to {source}"))?;
+    }
+    Ok(socket)
+}
+
+fn do_udp(args: &Args, server: &Server) -> anyhow::Result<Vec<u8>> {
+    // Bind to a UDP socket; we don't care about the local address/port, any will do
+    let addr = server_addrs(args, server)?
+        .next()
+        .with_context(|| format!("No addresses found for {}", server.host))?;
+    let socket = local_socket(args, addr, Type::DGRAM, Protocol::UDP)?;
+    // "Connect" to our server
+    socket.connect(&addr.into())?;
+    let socket = UdpSocket::from(socket);
+
+    let deadline = Instant::now() + Duration::from_millis(args.timeout_ms);
+    let mut wait = INITIAL_UDP_WAIT;
+    for attempt in 0..=args.retries {
+        // Send an empty packet; anything we send is ignored, but since there's no handshake we have to start with something
+        let _ = socket.send(&[0; 0])?;
+
+        // Wait for a reply until it's time to ask again, or to give up
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if attempt == args.retries || remaining <= wait {
+            socket.set_read_timeout(Some(remaining.max(Duration::from_millis(1))))?;
+        } else {
+            socket.set_read_timeout(Some(wait))?;
+        }
+
+        // Receive up to 512 bytes in the response - the max our server sends via UDP
+        let mut buf = [0; 512];
+        match socket.recv(&mut buf) {
+            // Convert the buffer into a Vec
+            Ok(len) => return Ok(buf[..len].to_vec()),
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                if Instant::now() >= deadline {
+                    break;
+                }
+            }
+            Err(e) => return Err(e.into()),
+        }
+        wait = wait.saturating_mul(2);
+    }
+
+    anyhow::bail!("No reply within {}ms", args.timeout_ms)
+}
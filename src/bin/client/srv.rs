@@ -0,0 +1,204 @@
+//! Just enough of DNS to look up SRV records (RFC 2782), so that servers can be discovered without
+//! a resolver library
+//!
+//! A single query is sent over UDP to each nameserver in `/etc/resolv.conf` in turn. Answers too
+//! long for a datagram are not retried over TCP, but a few dozen records fit in one comfortably.
+
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket},
+    time::Duration,
+};
+
+use anyhow::Context;
+
+use crate::Server;
+
+/// Record type of SRV records
+const SRV: u16 = 33;
+/// Class of Internet records
+const IN: u16 = 1;
+/// Longest answer accepted, as limited by DNS over UDP without EDNS
+const MAX_ANSWER_LENGTH: usize = 512;
+
+/// Look up the SRV records of `name`, returning their servers in the order to try them
+pub(crate) fn lookup(name: &str, timeout: Duration) -> anyhow::Result<Vec<Server>> {
+    let query = query(name, rand::random())?;
+    let mut error = None;
+    for nameserver in nameservers() {
+        match ask(nameserver, &query, timeout).and_then(|answer| parse(&answer, &query)) {
+            Ok(mut records) => {
+                records.sort_by_key(|record| (record.priority, std::cmp::Reverse(record.weight)));
+                return Ok(records.into_iter().map(|record| record.server).collect());
+            }
+            Err(e) => error = Some(e.context(format!("Nameserver {nameserver} failed"))),
+        }
+    }
+    Err(error.unwrap_or_else(|| anyhow::anyhow!("No nameservers")))
+}
+
+/// A server found in an SRV record
+struct Record {
+    priority: u16,
+    weight: u16,
+    server: Server,
+}
+
+/// The nameservers listed in `/etc/resolv.conf`, or else the local host
+fn nameservers() -> Vec<SocketAddr> {
+    let nameservers: Vec<_> = std::fs::read_to_string("/etc/resolv.conf")
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("nameserver"))
+        .filter_map(|address| address.trim().parse::<IpAddr>().ok())
+        .map(|ip| SocketAddr::new(ip, 53))
+        .collect();
+    if nameservers.is_empty() {
+        vec![SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 53)]
+    } else {
+        nameservers
+    }
+}
+
+/// A recursive query for the SRV records of `name`
+fn query(name: &str, id: u16) -> anyhow::Result<Vec<u8>> {
+    let mut query = Vec::with_capacity(name.len() + 18);
+    query.extend_from_slice(&id.to_be_bytes());
+    // Recursion desired, with one question
+    query.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            anyhow::bail!("Invalid domain name {name}");
+        }
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    query.extend_from_slice(&SRV.to_be_bytes());
+    query.extend_from_slice(&IN.to_be_bytes());
+    Ok(query)
+}
+
+/// Send `query` to `nameserver`, returning its answer
+fn ask(nameserver: SocketAddr, query: &[u8], timeout: Duration) -> anyhow::Result<Vec<u8>> {
+    let local: IpAddr = match nameserver {
+        SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+        SocketAddr::V6(_) => std::net::Ipv6Addr::UNSPECIFIED.into(),
+    };
+    let socket = UdpSocket::bind((local, 0))?;
+    socket.set_read_timeout(Some(timeout))?;
+    socket.connect(nameserver)?;
+    socket.send(query)?;
+
+    let mut answer = vec![0; MAX_ANSWER_LENGTH];
+    loop {
+        let len = socket.recv(&mut answer)?;
+        // Ignore anything that isn't the answer to our query, e.g. a late answer to another
+        if len >= 2 && answer[..2] == query[..2] {
+            answer.truncate(len);
+            return Ok(answer);
+        }
+    }
+}
+
+/// Parse the SRV records out of `answer`, the answer to `query`
+fn parse(answer: &[u8], query: &[u8]) -> anyhow::Result<Vec<Record>> {
+    let mut reader = Reader { answer, pos: 0 };
+    let header = reader.take(12)?;
+    let rcode = header[3] & 0x0F;
+    match rcode {
+        0 => {}
+        // The name doesn't exist, so there are no servers
+        3 => return Ok(Vec::new()),
+        _ => anyhow::bail!("Query failed with response code {rcode}"),
+    }
+    if header[2] & 0x02 != 0 {
+        anyhow::bail!("Answer truncated");
+    }
+    let questions = u16::from_be_bytes([header[4], header[5]]);
+    let answers = u16::from_be_bytes([header[6], header[7]]);
+    if questions != 1 || reader.take(query.len() - 12)? != &query[12..] {
+        anyhow::bail!("Answer is to a different question");
+    }
+
+    let mut records = Vec::new();
+    for _ in 0..answers {
+        reader.name()?;
+        let fields = reader.take(10)?;
+        let ty = u16::from_be_bytes([fields[0], fields[1]]);
+        let length = usize::from(u16::from_be_bytes([fields[8], fields[9]]));
+        if ty != SRV {
+            // e.g. a CNAME the name is an alias by
+            reader.take(length)?;
+            continue;
+        }
+        let fields = reader.take(6)?;
+        let target = reader.name()?;
+        // A target of "." means the service is deliberately unavailable
+        if target.is_empty() {
+            continue;
+        }
+        records.push(Record {
+            priority: u16::from_be_bytes([fields[0], fields[1]]),
+            weight: u16::from_be_bytes([fields[2], fields[3]]),
+            server: Server {
+                host: target,
+                port: u16::from_be_bytes([fields[4], fields[5]]),
+            },
+        });
+    }
+    Ok(records)
+}
+
+/// Reads fields out of a DNS message in turn
+struct Reader<'a> {
+    answer: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    /// Take the next `n` bytes
+    fn take(&mut self, n: usize) -> anyhow::Result<&'a [u8]> {
+        let bytes = self
+            .answer
+            .get(self.pos..self.pos + n)
+            .context("Answer ended early")?;
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    /// Take the next domain name, following any pointers into the rest of the message
+    fn name(&mut self) -> anyhow::Result<String> {
+        let mut labels = Vec::new();
+        let mut pos = self.pos;
+        let mut end = None;
+        // Every pointer must point backwards, so the message's length bounds how many there can be
+        for _ in 0..self.answer.len() {
+            let &len = self.answer.get(pos).context("Answer ended early")?;
+            match len {
+                0 => {
+                    self.pos = end.unwrap_or(pos + 1);
+                    return Ok(labels.join("."));
+                }
+                len if len & 0xC0 == 0xC0 => {
+                    let &low = self.answer.get(pos + 1).context("Answer ended early")?;
+                    end.get_or_insert(pos + 2);
+                    let target = usize::from(u16::from_be_bytes([len & 0x3F, low]));
+                    if target >= pos {
+                        anyhow::bail!("Invalid name in answer");
+                    }
+                    pos = target;
+                }
+                len if len < 0x40 => {
+                    let label = self
+                        .answer
+                        .get(pos + 1..pos + 1 + usize::from(len))
+                        .context("Answer ended early")?;
+                    labels.push(String::from_utf8_lossy(label).into_owned());
+                    pos += 1 + usize::from(len);
+                }
+                _ => anyhow::bail!("Invalid name in answer"),
+            }
+        }
+        anyhow::bail!("Invalid name in answer")
+    }
+}
@@ -1,47 +1,1030 @@
-use std::{fs::File, sync::Mutex};
+use std::{fmt, fs::File, io::Write, path::Path, process::ExitCode, sync::Mutex, time::Duration};
 
 use anyhow::Context;
 use clap::Parser;
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, Layer};
+use rand::{rngs::StdRng, SeedableRng};
+use tracing_subscriber::{
+    filter::LevelFilter, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer,
+};
 
-#[tokio::main(flavor = "current_thread")]
-async fn main() -> anyhow::Result<()> {
+/// Stages of starting up that have their own exit codes, as documented on [`qotd::Cli`]
+///
+/// Errors are tagged with these as context; any other error, e.g. one while serving, exits with 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Failure {
+    Config = 3,
+    Bind = 4,
+    Privileges = 5,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Failure::Config => "Invalid configuration",
+            Failure::Bind => "Unable to bind sockets",
+            Failure::Privileges => "Unable to drop privileges",
+        })
+    }
+}
+
+fn main() -> ExitCode {
+    match try_main() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {e:?}");
+            match e.downcast_ref::<Failure>() {
+                Some(&failure) => ExitCode::from(failure as u8),
+                None => ExitCode::FAILURE,
+            }
+        }
+    }
+}
+
+fn try_main() -> anyhow::Result<()> {
     let args = qotd::Cli::parse();
+    if args.print_config {
+        print!("{}", qotd::Cli::effective_config(std::env::args_os()));
+        return Ok(());
+    }
+
+    if let Some(qotd::Command::Generate(generate)) = &args.command {
+        return generate
+            .write(&mut std::io::stdout().lock())
+            .context("Failed to write generated output");
+    }
 
     // Set up our logging
     let registry = tracing_subscriber::registry()
-        .with(tracing_subscriber::fmt::layer().with_filter(args.verbosity()));
+        .with(tracing_subscriber::fmt::layer().with_filter(log_filter(args.verbosity())));
     if let Some(log_path) = &args.log_file {
-        let log_file = File::create(log_path).context("Unable to create log file")?;
+        let log_file = File::create(log_path)
+            .context("Unable to create log file")
+            .context(Failure::Config)?;
         registry
             .with(
                 tracing_subscriber::fmt::layer()
                     .with_ansi(false)
                     .with_writer(Mutex::new(log_file))
-                    .with_filter(args.file_verbosity()),
+                    .with_filter(log_filter(args.file_verbosity())),
             )
             .init();
     } else {
         registry.init();
     }
 
-    let ret = run(args).await;
+    #[cfg(feature = "fetch")]
+    if let Some(qotd::Command::Fetch {
+        pack,
+        sha256,
+        force,
+    }) = &args.command
+    {
+        let path = qotd::fetch(pack, &args.quote_dir(), sha256.as_deref(), *force)?;
+        println!("Fetched {pack} into {}", path.display());
+        return Ok(());
+    }
+
+    #[cfg(feature = "import")]
+    if let Some(qotd::Command::Import {
+        input,
+        format,
+        output,
+        category,
+        language,
+    }) = &args.command
+    {
+        return import(
+            input,
+            *format,
+            output.as_deref(),
+            *category,
+            language.clone(),
+        );
+    }
+
+    // Threads inherit their affinity, so setting it before the runtime starts covers all of them
+    if !args.cpu_affinity.is_empty() {
+        set_cpu_affinity(&args.cpu_affinity).context(Failure::Config)?;
+    }
+    raise_open_files_limit(args.open_files).context(Failure::Config)?;
+    // Landlock restricts only threads started after it, so must come before the runtime; the
+    // supervisor of a privilege-separated server must still execute its children
+    if args.landlock && args.command.is_none() && (is_supervised() || !args.privsep) {
+        restrict_filesystem(&args).context(Failure::Config)?;
+    }
+
+    // A single worker has no use for more than one thread
+    let runtime = if args.workers > 1 {
+        tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(args.workers.into())
+            .enable_all()
+            .build()
+    } else {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+    }
+    .context("Failed to start async runtime")?;
+
+    let ret = runtime.block_on(run(args));
     if let Err(e) = &ret {
         tracing::error!("{e:?}");
     }
-    ret.context("Server exited with fatal error")
+    ret
+}
+
+/// Confine this thread, and so all threads it starts, to the CPU cores `cpus`
+#[cfg(target_os = "linux")]
+fn set_cpu_affinity(cpus: &[usize]) -> anyhow::Result<()> {
+    use nix::{sched::CpuSet, unistd::Pid};
+
+    let mut cpu_set = CpuSet::new();
+    for &cpu in cpus {
+        cpu_set
+            .set(cpu)
+            .with_context(|| format!("Invalid CPU {cpu}"))?;
+    }
+    nix::sched::sched_setaffinity(Pid::from_raw(0), &cpu_set)
+        .with_context(|| format!("Failed to set CPU affinity to {cpus:?}"))?;
+    tracing::info!("Confined to CPUs {cpus:?}");
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_cpu_affinity(_cpus: &[usize]) -> anyhow::Result<()> {
+    anyhow::bail!("Setting CPU affinity is only supported on Linux")
+}
+
+/// Restrict the server to reading and writing the files it was given (see --landlock)
+#[cfg(target_os = "linux")]
+fn restrict_filesystem(args: &qotd::Cli) -> anyhow::Result<()> {
+    use std::{os::unix::ffi::OsStrExt, path::PathBuf};
+
+    // A supervised server confines itself to a new root once restricted, so its paths are within it
+    let root = args.chroot.as_deref().filter(|_| is_supervised());
+    let within = |path: &Path| match root {
+        Some(root) => root.join(path.strip_prefix("/").unwrap_or(path)),
+        None => path.to_path_buf(),
+    };
+    // Files are created and replaced by renaming, so their directories must be writable
+    let dir_of = |path: &PathBuf| match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => within(dir),
+        _ => within(Path::new(".")),
+    };
+
+    let mut reads: Vec<PathBuf> = vec![args.quote_dir()];
+    reads.extend(
+        args.listeners()
+            .into_iter()
+            .filter_map(|listener| listener.dir),
+    );
+    reads.extend(args.template_file.iter().cloned());
+    reads.extend(args.offensive_words.iter().cloned());
+    reads.extend(args.deny_list.iter().cloned());
+    #[cfg(feature = "tls")]
+    reads.extend(args.tls_cert.iter().chain(&args.tls_key).cloned());
+    #[cfg(feature = "encryption")]
+    reads.extend(args.key_file.iter().cloned());
+    for path in ["/etc", "/usr/share/zoneinfo"] {
+        reads.push(PathBuf::from(path));
+    }
+    // An abstract admin socket (e.g. "@qotd") isn't a file, so needs no access to one
+    let admin_socket = args
+        .admin_socket
+        .clone()
+        .filter(|path| !path.as_os_str().as_bytes().starts_with(b"@"));
+    let writes = [
+        &args.state_file,
+        &args.submissions_file,
+        &args.override_file,
+        &args.audit_file,
+        &admin_socket,
+        &args.startup_info,
+    ];
+
+    let mut landlock = qotd::Landlock::new();
+    for path in &reads {
+        landlock = landlock.allow_read(within(path));
+    }
+    // Users are looked up before confining to the new root
+    if root.is_some() {
+        landlock = landlock.allow_read("/etc");
+    }
+    for path in writes.into_iter().flatten() {
+        if path != Path::new("-") {
+            landlock = landlock.allow_write(dir_of(path));
+        }
+    }
+    // Already open, but allowed should it be reopened
+    if let Some(path) = &args.log_file {
+        landlock = landlock.allow_write(path);
+    }
+    if landlock.enforce()? == qotd::LandlockStatus::Unsupported {
+        tracing::warn!(
+            "Landlock is unsupported by this kernel, so filesystem access is unrestricted"
+        );
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn restrict_filesystem(_args: &qotd::Cli) -> anyhow::Result<()> {
+    anyhow::bail!("Landlock is only supported on Linux")
+}
+
+/// Limit `servers` to their sockets and quote files, then sandbox the process (see --capsicum)
+#[cfg(target_os = "freebsd")]
+fn enter_capability_mode<'a>(
+    servers: impl Iterator<Item = (&'a qotd::Server, &'a mut qotd::Quotes)>,
+) -> anyhow::Result<()> {
+    for (server, quotes) in servers {
+        server.limit_capabilities()?;
+        quotes
+            .limit_capabilities()
+            .context("Failed to limit quote files")?;
+    }
+    qotd::enter_capability_mode().context("Failed to enter capability mode")
+}
+
+#[cfg(not(target_os = "freebsd"))]
+fn enter_capability_mode<'a>(
+    _servers: impl Iterator<Item = (&'a qotd::Server, &'a mut qotd::Quotes)>,
+) -> anyhow::Result<()> {
+    anyhow::bail!("Capsicum is only supported on FreeBSD")
+}
+
+/// Open files limit to raise to by default when started as root
+#[cfg(unix)]
+const DEFAULT_OPEN_FILES: u64 = 65536;
+
+/// Raise the limit on open files to at least `limit`, or when root to [`DEFAULT_OPEN_FILES`]
+#[cfg(unix)]
+fn raise_open_files_limit(limit: Option<u64>) -> anyhow::Result<()> {
+    use nix::sys::resource::{getrlimit, setrlimit, Resource};
+
+    let is_default = limit.is_none();
+    let Some(mut limit) = limit.or_else(|| {
+        nix::unistd::Uid::effective()
+            .is_root()
+            .then_some(DEFAULT_OPEN_FILES)
+    }) else {
+        return Ok(());
+    };
+    let (soft, hard) =
+        getrlimit(Resource::RLIMIT_NOFILE).context("Failed to get open files limit")?;
+    if soft >= limit {
+        return Ok(());
+    }
+    // Raising the hard limit takes CAP_SYS_RESOURCE, which even root may lack, e.g. in a container
+    if let Err(e) = setrlimit(Resource::RLIMIT_NOFILE, limit, hard.max(limit)) {
+        if !is_default {
+            return Err(e).with_context(|| {
+                format!("Failed to raise open files limit from {soft} to {limit}")
+            });
+        }
+        tracing::warn!("Failed to raise open files limit to {limit} ({e}), using {hard} instead");
+        if soft == hard {
+            return Ok(());
+        }
+        limit = hard;
+        setrlimit(Resource::RLIMIT_NOFILE, limit, hard)
+            .with_context(|| format!("Failed to raise open files limit from {soft} to {limit}"))?;
+    }
+    tracing::info!("Raised open files limit from {soft} to {limit}");
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn raise_open_files_limit(limit: Option<u64>) -> anyhow::Result<()> {
+    if limit.is_some() {
+        anyhow::bail!("Setting the open files limit is only supported on Unix-like systems");
+    }
+    Ok(())
+}
+
+/// Log at `level`, except as overridden by directives in `RUST_LOG`, e.g. "qotd::server=warn"
+fn log_filter(level: LevelFilter) -> EnvFilter {
+    let directives = std::env::var(EnvFilter::DEFAULT_ENV).unwrap_or_default();
+    EnvFilter::builder().parse_lossy(format!("{level},{directives}"))
 }
 
 async fn run(args: qotd::Cli) -> anyhow::Result<()> {
-    // Get our quotes
-    let categories = args.allowed_categories();
-    let quotes = qotd::Quotes::from_dir(args.dir, &categories).await?;
-
-    // Start the server
-    qotd::Server::new()
-        .bind((args.host, args.port))
-        .await?
-        .drop_privileges(args.user)?
-        .serve(quotes)
+    if args.privsep && args.command.is_none() {
+        if !is_supervised() {
+            return supervise(&args).await;
+        }
+        #[cfg(unix)]
+        qotd::confine(&args.user, args.chroot.as_ref()).context(Failure::Privileges)?;
+    }
+    let mut quotes = load_quotes(&args).await.context(Failure::Config)?;
+    if let Some(qotd::Command::Grep {
+        pattern,
+        regex,
+        ignore_case,
+    }) = &args.command
+    {
+        return grep(&mut quotes, pattern, *regex, *ignore_case).await;
+    }
+    #[cfg(feature = "export")]
+    if let Some(qotd::Command::Export { format, output }) = &args.command {
+        let count = match output {
+            Some(path) => {
+                let out = File::create(path).context("Unable to create export file")?;
+                qotd::export(&mut quotes, *format, std::io::BufWriter::new(out)).await?
+            }
+            None => qotd::export(&mut quotes, *format, std::io::stdout().lock()).await?,
+        };
+        eprintln!("Exported {count} quote(s)");
+        return Ok(());
+    }
+    #[cfg(feature = "irc")]
+    if let Some(qotd::Command::Irc {
+        server,
+        channel,
+        nick,
+        password,
+        command,
+        daily_at,
+    }) = &args.command
+    {
+        let mut bot = qotd::IrcBot::new(server, nick, channel).command(command);
+        if let Some(password) = password {
+            bot = bot.password(password);
+        }
+        if let Some(time) = daily_at {
+            bot = bot.daily_at(*time);
+        }
+        return bot.run_until(quotes, shutdown_requested()).await;
+    }
+    if let Some(qotd::Command::Motd { path, once }) = &args.command {
+        let writer = configure_motd(&args, path).context(Failure::Config)?;
+        if *once {
+            writer.write(&mut quotes).await?;
+            return Ok(());
+        }
+        return writer
+            .run_until(quotes, shutdown_requested())
+            .await
+            .context("Failed to write quote");
+    }
+    let server = configure_server(&args, &quotes).context(Failure::Config)?;
+    let specs = args.listeners();
+    let mut listeners = Vec::new();
+    for listener in &specs {
+        let quotes = load_listener_quotes(&args, listener)
+            .await
+            .context(Failure::Config)?;
+        let server = configure_listener(&args).context(Failure::Config)?;
+        listeners.push((listener, server, quotes));
+    }
+    if args.dry_run {
+        return dry_run(&args, &quotes, &listeners)
+            .await
+            .context(Failure::Config);
+    }
+    #[cfg(unix)]
+    tokio::spawn(report_stats_on_signal(server.stats()));
+
+    let server = server
+        .bind((args.host.as_str(), args.port))
+        .await
+        .context(Failure::Bind)?;
+    let mut others = Vec::new();
+    for (listener, server, quotes) in listeners {
+        let server = server
+            .bind(listener.address.as_str())
+            .await
+            .with_context(|| format!("Failed to bind listener {}", listener.address))
+            .context(Failure::Bind)?;
+        others.push((server, quotes));
+    }
+    if let Some(path) = &args.startup_info {
+        write_startup_info(&server, path).context(Failure::Config)?;
+    }
+    // A supervised server already confined itself
+    let server = if is_supervised() {
+        server
+    } else {
+        server
+            .drop_privileges(&args.user)
+            .context(Failure::Privileges)?
+    };
+    if args.capsicum {
+        let servers = std::iter::once((&server, &mut quotes))
+            .chain(others.iter_mut().map(|(server, quotes)| (&*server, quotes)));
+        enter_capability_mode(servers).context(Failure::Privileges)?;
+    }
+    let others = others
+        .into_iter()
+        .map(|(server, quotes)| server.serve_with_shutdown(quotes, shutdown_requested()));
+    let main = server.serve_with_shutdown(quotes, shutdown_requested());
+    futures::future::try_join(main, futures::future::try_join_all(others))
         .await
+        .context("Server exited with fatal error")?;
+    Ok(())
+}
+
+async fn load_quotes(args: &qotd::Cli) -> anyhow::Result<qotd::Quotes> {
+    let options = index_options(args, args.indexed_categories())?;
+    let mut quotes = configure_quotes(args, read_quotes(args, &options).await?);
+    // Serve earlier submissions even if they're kept outside of the quote directory
+    if let Some(path) = args.submissions_file.as_ref().filter(|path| path.exists()) {
+        quotes
+            .add_file(path)
+            .await
+            .with_context(|| format!("Unable to read submitted quotes from {}", path.display()))?;
+    }
+    Ok(quotes)
+}
+
+/// Load the quotes served on an additional listener (see --listener)
+async fn load_listener_quotes(
+    args: &qotd::Cli,
+    listener: &qotd::ListenerSpec,
+) -> anyhow::Result<qotd::Quotes> {
+    let options = index_options(args, args.listener_categories(listener))?;
+    let quotes = match &listener.dir {
+        Some(dir) => qotd::Quotes::from_dir_with_options(dir.clone(), &options)
+            .await
+            .with_context(|| format!("Unable to read quotes for listener {}", listener.address))?,
+        None => read_quotes(args, &options).await?,
+    };
+    Ok(configure_quotes(args, quotes))
+}
+
+fn index_options(
+    args: &qotd::Cli,
+    categories: Option<Vec<qotd::QuoteCategory>>,
+) -> anyhow::Result<qotd::IndexOptions> {
+    let mut options = match categories {
+        Some(categories) => qotd::IndexOptions::new().categories(&categories),
+        None => qotd::IndexOptions::new().all_categories(),
+    };
+    if let Some(languages) = &args.languages {
+        options = options.languages(languages);
+    }
+    options = options.file_encoding(args.file_encoding);
+    if let Some(prefix) = &args.comment_prefix {
+        options = options.comment_prefix(prefix);
+    }
+    for pattern in &args.exclude_pattern {
+        options = options.exclude_pattern(pattern.clone());
+    }
+    if args.reclassify_offensive || args.offensive_words.is_some() {
+        options = options.offensive_words(qotd::BUILTIN_OFFENSIVE_WORDS);
+    }
+    if let Some(path) = &args.offensive_words {
+        let words = std::fs::read_to_string(path)
+            .with_context(|| format!("Unable to read offensive words from {}", path.display()))?;
+        options = options.offensive_words(&words);
+    }
+    if let Some(path) = &args.deny_list {
+        let list = std::fs::read_to_string(path)
+            .with_context(|| format!("Unable to read deny-list from {}", path.display()))?;
+        options = options.deny_hashes(&list);
+    }
+    if let Some(max) = args.max_quotes {
+        options = options.max_quotes(max);
+    }
+    if let Some(max) = args.max_file_size {
+        options = options.max_file_size(max);
+    }
+    if let Some(max) = args.max_files {
+        options = options.max_files(max);
+    }
+    options = options
+        .preload_encoded(args.preload_encoded)
+        .trailing_quote(!args.no_trailing_quote)
+        .storage(args.storage);
+    #[cfg(feature = "encryption")]
+    let options = options.decryption_keys(decryption_keys(args)?);
+    Ok(options)
+}
+
+/// Apply the options choosing which of the indexed quotes is served
+fn configure_quotes(args: &qotd::Cli, quotes: qotd::Quotes) -> qotd::Quotes {
+    let mut quotes = quotes
+        .with_schedule(args.schedule())
+        .with_strategy(args.selection)
+        .with_lengths(args.quote_lengths(None));
+    if let Some(seed) = args.seed {
+        quotes = quotes.with_rng(StdRng::seed_from_u64(seed));
+    }
+    if let Some(seed) = args.daily_seed {
+        quotes = quotes.with_daily_seed(seed);
+    }
+    if let Some(half_life) = args.recency_half_life {
+        quotes = quotes.with_recency(qotd::Recency::new(half_life).boost(args.recency_boost));
+    }
+    quotes
+}
+
+async fn read_quotes(
+    args: &qotd::Cli,
+    options: &qotd::IndexOptions,
+) -> anyhow::Result<qotd::Quotes> {
+    #[cfg(feature = "builtin-quotes")]
+    if args.builtin_quotes {
+        return Ok(qotd::Quotes::builtin().await?);
+    }
+
+    if args.dir.as_deref() == Some(std::path::Path::new("-")) {
+        return qotd::Quotes::from_reader("stdin", std::io::stdin().lock(), options)
+            .context("Unable to read quotes from stdin");
+    }
+
+    match qotd::Quotes::from_dir_with_options(args.quote_dir(), options).await {
+        Ok(quotes) => Ok(quotes),
+        // The default directory is missing or without quotes; one that was asked for is an error
+        #[cfg(feature = "builtin-quotes")]
+        Err(e) if args.dir.is_none() && e.kind() == std::io::ErrorKind::NotFound => {
+            tracing::warn!(
+                "No quotes in {}: {e}; serving built-in quotes",
+                args.quote_dir().display()
+            );
+            Ok(qotd::Quotes::builtin().await?)
+        }
+        Err(e) => {
+            let no_quotes = e
+                .get_ref()
+                .is_some_and(|inner| inner.is::<qotd::NoQuotesError>());
+            let e = anyhow::Error::new(e);
+            Err(if no_quotes {
+                e.context("Check that --dir contains quote files, and that --categories and --languages allow them")
+            } else {
+                e.context("Unable to read quotes")
+            })
+        }
+    }
+}
+
+#[cfg_attr(not(feature = "tls"), allow(unused_variables))]
+fn configure_server(args: &qotd::Cli, quotes: &qotd::Quotes) -> anyhow::Result<qotd::Server> {
+    let mut server = configure_listener(args)?.graceful_upgrade(true);
+    if args.strict_privileges {
+        server = server.privilege_drop(qotd::PrivilegeDrop::Strict);
+    } else if args.allow_root {
+        server = server.privilege_drop(qotd::PrivilegeDrop::AllowRoot);
+    }
+    if let Some(path) = &args.state_file {
+        server = server.state_file(path, Duration::from_secs(args.state_interval));
+    }
+    if let Some(address) = args.http_address {
+        server = server
+            .http_address(address)
+            .feed_length(args.feed_length)
+            .calendar_length(args.calendar_length);
+        for origin in &args.cors_origin {
+            server = server.cors_origin(origin);
+        }
+    }
+    if let Some(address) = args.admin_address {
+        server = server.admin_address(address);
+    }
+    if let Some(token) = &args.admin_token {
+        server = server.admin_token(token);
+    }
+    if let Some(path) = &args.admin_socket {
+        server = admin_socket(server, path, &args.admin_user, &args.admin_group)?;
+    }
+    if let Some(path) = &args.submissions_file {
+        server = server.submissions_file(path);
+    }
+    #[cfg(feature = "webhook")]
+    if let Some(url) = &args.webhook_url {
+        server = server.webhook(qotd::Webhook::new(url).format(args.webhook_format));
+    }
+    #[cfg(feature = "mqtt")]
+    if let Some(broker) = &args.mqtt_broker {
+        let mut publisher = qotd::MqttPublisher::new(broker, &args.mqtt_topic)
+            .qos(args.mqtt_qos)
+            .retain(args.mqtt_retain)
+            .client_id(&args.mqtt_client_id);
+        if let (Some(username), Some(password)) = (&args.mqtt_username, &args.mqtt_password) {
+            publisher = publisher.credentials(username, password);
+        }
+        server = server.mqtt(publisher);
+    }
+    for simple in &args.simple_service {
+        server = server.simple_service(simple.service, simple.port);
+    }
+    #[cfg(feature = "statsd")]
+    if let Some(address) = &args.statsd {
+        server = server.statsd(
+            qotd::StatsdExporter::new(address)
+                .prefix(&args.statsd_prefix)
+                .interval(Duration::from_secs(args.statsd_interval)),
+        );
+    }
+    #[cfg(feature = "registry")]
+    if let Some(kind) = args.registry {
+        let url = args.registry_url.as_deref().unwrap_or(kind.default_url());
+        let mut registration = qotd::Registration::new(kind, url)
+            .name(&args.service_name)
+            .ttl(Duration::from_secs(args.registry_ttl));
+        if let Some(id) = &args.service_id {
+            registration = registration.id(id);
+        }
+        if let Some(address) = args.service_address {
+            registration = registration.address(address);
+        }
+        server = server.register(registration);
+    }
+    #[cfg(feature = "tls")]
+    if let (Some(cert), Some(key)) = (&args.tls_cert, &args.tls_key) {
+        let mut tls = qotd::Tls::from_pem_files(cert, key)?;
+        for host in &args.tls_host {
+            let categories = host
+                .categories
+                .as_category_vec()
+                .unwrap_or_else(|| quotes.categories());
+            tls = tls.host(&host.host, &categories);
+        }
+        server = server.tls(tls);
+    }
+    // Hosts may be served categories that other clients mustn't be
+    if let Some(allowed) = args.allowed_categories() {
+        if args.indexed_categories().as_ref() != Some(&allowed) {
+            server = server.categories(&allowed);
+        }
+    }
+    Ok(server)
+}
+
+/// Configure how quotes are served to clients, for the main listener and any others
+fn configure_listener(args: &qotd::Cli) -> anyhow::Result<qotd::Server> {
+    let mut server = qotd::Server::new()
+        .backlog(args.backlog)
+        .reuse_address(!args.no_reuse_address)
+        .reuse_port(args.reuse_port)
+        .workers(args.workers.into())
+        .tcp(!args.udp_only)
+        .udp(!args.tcp_only)
+        .access_control(args.access_control())
+        .log_denied(args.log_denied)
+        .tcp_nodelay(args.tcp_nodelay)
+        .telnet(args.telnet)
+        .length_prefix(args.length_prefix)
+        .strict_rfc865(args.strict_rfc865)
+        .sticky_daily(args.sticky_daily)
+        .udp_hints(args.udp_hints)
+        .tcp_quote_lengths(args.quote_lengths(Some(false)))
+        .udp_quote_lengths(args.quote_lengths(Some(true)));
+    if let Some(bytes) = args.recv_buffer {
+        server = server.recv_buffer_size(bytes);
+    }
+    if let Some(bytes) = args.send_buffer {
+        server = server.send_buffer_size(bytes);
+    }
+    if let Some(dscp) = args.dscp {
+        server = server.dscp(dscp);
+    }
+    if let Some(hops) = args.ttl {
+        server = server.ttl(hops);
+    }
+    if let Some(device) = &args.bind_device {
+        server = server.bind_device(device);
+    }
+    if let Some(path) = &args.audit_file {
+        server = server.audit_file(path);
+    }
+    if args.strict_udp {
+        server = server.max_udp_request(args.max_udp_request);
+    }
+    if let Some(max) = args.max_in_flight {
+        server = server.max_in_flight(max as usize);
+    }
+    if let Some(message) = &args.busy_message {
+        server = server.busy_message(message);
+    }
+    if let Some(rotation) = args.rotate {
+        server = server.rotate(rotation);
+    }
+    if let Some(path) = &args.override_file {
+        server = server.override_file(path);
+    }
+    if let Some(ms) = args.udp_cache_ms {
+        server = server.udp_cache_window(Duration::from_millis(ms));
+    }
+    if let Some(rate) = args.log_sample {
+        server = server.log_sample_rate(rate);
+    }
+    if let Some(ms) = args.slow_request_ms {
+        server = server.slow_request_threshold(Duration::from_millis(ms));
+    }
+    server = server.drain_timeout(Duration::from_secs(args.drain_timeout));
+    if let Some(width) = args.wrap {
+        server = server.wrap(width.into());
+    }
+    if args.color {
+        server = server.color(args.color_scheme.clone().unwrap_or_default());
+    }
+    if let Some(decoration) = args.decorate {
+        server = server.decorate(decoration);
+    }
+    if let Some(template) = template(args)? {
+        server = server.template(template);
+    }
+    if let Some(keepalive) = args.keepalive() {
+        server = server.tcp_keepalive(keepalive);
+    }
+    if let Some(secs) = args.linger {
+        server = server.tcp_linger(Duration::from_secs(secs));
+    }
+    Ok(server)
+}
+
+fn configure_motd(args: &qotd::Cli, path: &std::path::Path) -> anyhow::Result<qotd::MotdWriter> {
+    let mut writer = qotd::MotdWriter::new(path, args.rotate.unwrap_or_default());
+    if let Some(width) = args.wrap {
+        writer = writer.wrap(width.into());
+    }
+    if let Some(decoration) = args.decorate {
+        writer = writer.decorate(decoration);
+    }
+    if let Some(template) = template(args)? {
+        writer = writer.template(template);
+    }
+    Ok(writer)
+}
+
+/// Print what would be served, and where, without serving it
+async fn dry_run(
+    args: &qotd::Cli,
+    quotes: &qotd::Quotes,
+    listeners: &[(&qotd::ListenerSpec, qotd::Server, qotd::Quotes)],
+) -> anyhow::Result<()> {
+    let addrs: Vec<_> = tokio::net::lookup_host((args.host.as_str(), args.port))
+        .await
+        .context("Failed to resolve bind address")?
+        .map(|addr| addr.to_string())
+        .collect();
+    let protocols = match (args.tcp_only, args.udp_only) {
+        (true, _) => "TCP",
+        (_, true) => "UDP",
+        _ => "TCP/UDP",
+    };
+
+    print!("{}", quotes.summary());
+    println!("Would listen on {protocols} {}", addrs.join(", "));
+    for (listener, _, quotes) in listeners {
+        print!("{}", quotes.summary());
+        println!("Would also listen on {protocols} {}", listener.address);
+    }
+    Ok(())
+}
+
+#[cfg(feature = "import")]
+fn import(
+    input: &Path,
+    format: Option<qotd::ImportFormat>,
+    output: Option<&Path>,
+    category: Option<qotd::QuoteCategory>,
+    language: Option<String>,
+) -> anyhow::Result<()> {
+    let format = format
+        .or_else(|| {
+            input
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .and_then(qotd::ImportFormat::from_extension)
+        })
+        .context("Unable to tell the format of the file to import; use --format")?;
+    let options = qotd::ImportOptions { category, language };
+    let input = File::open(input).context("Unable to open file to import")?;
+    let count = match output {
+        Some(path) => {
+            let out = File::create(path).context("Unable to create quote file")?;
+            qotd::import(input, format, &options, std::io::BufWriter::new(out))?
+        }
+        None => qotd::import(input, format, &options, std::io::stdout().lock())?,
+    };
+    eprintln!("Imported {count} quote(s)");
+    Ok(())
+}
+
+/// Print the quotes matching `pattern` in the format of `fortune -m`
+async fn grep(
+    quotes: &mut qotd::Quotes,
+    pattern: &str,
+    regex: bool,
+    ignore_case: bool,
+) -> anyhow::Result<()> {
+    let pattern = if regex {
+        pattern.to_string()
+    } else {
+        regex::escape(pattern)
+    };
+    let pattern = regex::RegexBuilder::new(&pattern)
+        .case_insensitive(ignore_case)
+        .build()
+        .context("Invalid pattern")?;
+
+    let found = quotes.search_regex(&pattern).await?;
+    let mut stdout = std::io::stdout().lock();
+    for quote in &found {
+        writeln!(stdout, "({}, hash {})\n%", quote.id, quote.hash())?;
+        stdout.write_all(&quote.text)?;
+        if !quote.text.ends_with(b"\n") {
+            writeln!(stdout)?;
+        }
+        writeln!(stdout, "%")?;
+    }
+    if found.is_empty() {
+        anyhow::bail!("No quotes match");
+    }
+    Ok(())
+}
+
+/// Describe the bound server for wrapper scripts, in the format documented on `--startup-info`
+fn write_startup_info(server: &qotd::Server, path: &Path) -> anyhow::Result<()> {
+    let mut info = String::new();
+    if let Some(addr) = server.tcp_local_addr() {
+        info.push_str(&format!("tcp={addr} "));
+    }
+    if let Some(addr) = server.udp_local_addr() {
+        info.push_str(&format!("udp={addr} "));
+    }
+    if let Some(addr) = server.admin_local_addr() {
+        info.push_str(&format!("admin={addr} "));
+    }
+    if let Some(addr) = server.http_local_addr() {
+        info.push_str(&format!("http={addr} "));
+    }
+    info.push_str(&format!(
+        "pid={} version={}\n",
+        std::process::id(),
+        env!("CARGO_PKG_VERSION")
+    ));
+
+    if path == Path::new("-") {
+        let mut stdout = std::io::stdout().lock();
+        stdout.write_all(info.as_bytes())?;
+        stdout.flush()?;
+    } else {
+        std::fs::write(path, info).context("Unable to write startup info")?;
+    }
+    Ok(())
+}
+
+/// Bind the main sockets, and serve from unprivileged children (see --privsep)
+#[cfg(unix)]
+async fn supervise(args: &qotd::Cli) -> anyhow::Result<()> {
+    configure_listener(args)
+        .context(Failure::Config)?
+        .bind((args.host.as_str(), args.port))
+        .await
+        .context(Failure::Bind)?
+        .supervise()
+        .await
+}
+
+#[cfg(not(unix))]
+async fn supervise(_args: &qotd::Cli) -> anyhow::Result<()> {
+    anyhow::bail!("Privilege separation is only supported on Unix-like systems")
+}
+
+#[cfg(unix)]
+fn is_supervised() -> bool {
+    qotd::is_supervised()
+}
+
+#[cfg(not(unix))]
+fn is_supervised() -> bool {
+    false
+}
+
+/// Serve the admin endpoint on the Unix socket at `path`, to root, ourselves, `users`, and `groups`
+#[cfg(unix)]
+fn admin_socket(
+    server: qotd::Server,
+    path: &Path,
+    users: &[String],
+    groups: &[String],
+) -> anyhow::Result<qotd::Server> {
+    use nix::unistd::{Group, User};
+
+    let mut server = server.admin_socket(path);
+    for user in users {
+        let uid = match user.parse() {
+            Ok(uid) => uid,
+            Err(_) => User::from_name(user)
+                .with_context(|| format!("Failed to get user: {user}"))?
+                .with_context(|| format!("No such user: {user}"))?
+                .uid
+                .as_raw(),
+        };
+        server = server.admin_user(uid);
+    }
+    for group in groups {
+        let gid = match group.parse() {
+            Ok(gid) => gid,
+            Err(_) => Group::from_name(group)
+                .with_context(|| format!("Failed to get group: {group}"))?
+                .with_context(|| format!("No such group: {group}"))?
+                .gid
+                .as_raw(),
+        };
+        server = server.admin_group(gid);
+    }
+    Ok(server)
+}
+
+#[cfg(not(unix))]
+fn admin_socket(
+    _server: qotd::Server,
+    _path: &Path,
+    _users: &[String],
+    _groups: &[String],
+) -> anyhow::Result<qotd::Server> {
+    anyhow::bail!("The admin socket is only supported on Unix-like systems")
+}
+
+/// Wait for Ctrl+C, or SIGTERM on Unix-like systems
+async fn shutdown_requested() {
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(e) => {
+                tracing::warn!("Failed to listen for SIGTERM: {e}");
+                std::future::pending().await
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => {
+            if let Err(e) = result {
+                tracing::warn!("Failed to listen for Ctrl+C: {e}");
+                std::future::pending().await
+            }
+        }
+        () = terminate => {}
+    }
+}
+
+/// Log a snapshot of the server's statistics whenever SIGUSR1 is received
+#[cfg(unix)]
+async fn report_stats_on_signal(stats: std::sync::Arc<qotd::Stats>) -> anyhow::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let build = qotd::BuildInfo::current();
+    let mut report_requested = signal(SignalKind::user_defined1())?;
+    while report_requested.recv().await.is_some() {
+        let snapshot = stats.snapshot();
+        let report = stats.quote_report(0);
+        tracing::info!(
+            uptime_secs = snapshot.uptime.as_secs(),
+            version = build.version,
+            git_hash = build.git_hash,
+            requests = snapshot.service_time.count,
+            tcp_serves = snapshot.tcp_serves,
+            udp_serves = snapshot.udp_serves,
+            quotes = report.quotes,
+            quotes_served = report.served,
+            failed_requests = snapshot.failed_requests,
+            denied_requests = snapshot.denied_requests,
+            slow_requests = snapshot.slow_requests,
+            ignored_datagrams = snapshot.ignored_datagrams,
+            shed_connections = snapshot.shed_connections,
+            shed_datagrams = snapshot.shed_datagrams,
+            accept_pauses = snapshot.accept_pauses,
+            "Statistics"
+        );
+        for (file, serves) in &report.by_file {
+            tracing::info!(file, serves, "Statistics for file");
+        }
+    }
+    Ok(())
+}
+
+fn template(args: &qotd::Cli) -> anyhow::Result<Option<qotd::Template>> {
+    match &args.template_file {
+        Some(path) => {
+            let template = std::fs::read_to_string(path).context("Unable to read template file")?;
+            let template = template.parse().map_err(anyhow::Error::msg)?;
+            Ok(Some(template))
+        }
+        None => Ok(args.template.clone()),
+    }
+}
+
+#[cfg(feature = "encryption")]
+fn decryption_keys(args: &qotd::Cli) -> anyhow::Result<qotd::DecryptionKeys> {
+    let mut keys = qotd::DecryptionKeys::new();
+    if let Some(key_file) = &args.key_file {
+        keys =
+            keys.identities(std::fs::read_to_string(key_file).context("Unable to read key file")?);
+    }
+    if let Some(passphrase) = &args.passphrase {
+        keys = keys.passphrase(passphrase);
+    }
+    Ok(keys)
 }
@@ -0,0 +1,120 @@
+//! This module offers a synchronous API, for programs that don't run an async runtime of their own
+//!
+//! Quotes are read with blocking I/O anyway, so their futures are simply run to completion on the
+//! calling thread. Indexing, which the async API moves off the runtime's threads, is simply done on
+//! the calling thread too.
+#![cfg(feature = "blocking")]
+
+use std::{io, path::Path};
+#[cfg(any(feature = "tcp", feature = "udp"))]
+use std::{
+    net::ToSocketAddrs,
+    sync::{mpsc, Arc, Mutex},
+    thread,
+};
+
+use futures::executor::block_on;
+#[cfg(any(feature = "tcp", feature = "udp"))]
+use tracing::info;
+
+use crate::{IndexOptions, Quote, QuoteCategory, Quotes};
+
+impl Quotes {
+    /// Index the quote files in `dir` and its subdirectories, like [`Quotes::from_dir`]
+    pub fn from_dir_blocking<P: AsRef<Path> + Send + std::fmt::Debug + 'static>(
+        dir: P,
+        allowed_categories: &[QuoteCategory],
+    ) -> io::Result<Self> {
+        Self::from_dir_with_options_blocking(
+            dir,
+            &IndexOptions::new().categories(allowed_categories),
+        )
+    }
+
+    /// Index the quote files in `dir` and its subdirectories, like [`Quotes::from_dir_with_options`]
+    pub fn from_dir_with_options_blocking<P: AsRef<Path> + Send + std::fmt::Debug + 'static>(
+        dir: P,
+        options: &IndexOptions,
+    ) -> io::Result<Self> {
+        Self::index_dir_blocking(dir.as_ref().to_path_buf(), options.clone())
+    }
+
+    /// Choose and read a quote, like [`Quotes::random_quote`]
+    pub fn random_quote_blocking(&mut self) -> io::Result<Quote> {
+        block_on(self.random_quote())
+    }
+}
+
+/// Serve quotes on `address` until a fatal error occurs, with a thread per protocol and per TCP
+/// connection
+///
+/// This is a minimal server without any of [`Server`](crate::Server)'s options: quotes are sent
+/// as they are, and UDP clients are only sent quotes that fit in a single datagram.
+#[cfg(any(feature = "tcp", feature = "udp"))]
+pub fn serve_blocking<A: ToSocketAddrs>(address: A, quotes: Quotes) -> io::Result<()> {
+    #[allow(unused_mut)]
+    let mut address = address
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "No address to bind to"))?;
+    let quotes = Arc::new(Mutex::new(quotes));
+    // Workers only ever finish on failure, which is fatal to the whole server
+    let (failed_tx, failed_rx) = mpsc::channel::<io::Result<()>>();
+
+    #[cfg(feature = "tcp")]
+    {
+        use std::io::Write;
+
+        let tcp = std::net::TcpListener::bind(address)?;
+        // Binding to port 0 chooses a port, which UDP is then served on too
+        address = tcp.local_addr()?;
+        info!("Now listening on TCP {address}");
+        let quotes = Arc::clone(&quotes);
+        let failed_tx = failed_tx.clone();
+        thread::spawn(move || {
+            let accept_loop = || loop {
+                let (mut conn, peer) = tcp.accept()?;
+                let quotes = Arc::clone(&quotes);
+                thread::spawn(move || {
+                    let quote = quotes.lock().unwrap().random_quote_blocking();
+                    match quote.and_then(|quote| conn.write_all(&quote.text)) {
+                        Ok(()) => info!("Sent quote to TCP client {peer}"),
+                        Err(e) => tracing::warn!("Failed to send quote to TCP client {peer}: {e}"),
+                    }
+                });
+            };
+            let _ = failed_tx.send(accept_loop());
+        });
+    }
+
+    #[cfg(feature = "udp")]
+    {
+        let udp = std::net::UdpSocket::bind(address)?;
+        info!("Now listening on UDP {}", udp.local_addr()?);
+        let quotes = Arc::clone(&quotes);
+        let failed_tx = failed_tx.clone();
+        thread::spawn(move || {
+            let accept_loop = || {
+                let mut buf = [0_u8; 0];
+                loop {
+                    let (_, peer) = udp.recv_from(&mut buf)?;
+                    // Quotes are chosen until one fits in a datagram
+                    let quote = loop {
+                        let quote = quotes.lock().unwrap().random_quote_blocking()?;
+                        if quote.text.len() < 512 {
+                            break quote;
+                        }
+                    };
+                    udp.send_to(&quote.text, peer)?;
+                    info!("Sent quote to UDP client {peer}");
+                }
+            };
+            let _ = failed_tx.send(accept_loop());
+        });
+    }
+
+    drop(failed_tx);
+    failed_rx
+        .recv()
+        .map_err(|_| io::Error::other("Server thread panicked"))?
+}
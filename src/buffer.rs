@@ -0,0 +1,38 @@
+//! This module recycles the buffers quotes are read into and responses assembled in, so that a busy
+//! server isn't forever allocating and freeing them
+//!
+//! Buffers are taken with [`take`] and, once their contents have been sent, handed back with
+//! [`recycle`]; buffers that aren't handed back are simply freed as usual.
+
+use std::sync::Mutex;
+
+/// Most buffers kept for reuse; any more handed back are freed
+const MAX_POOLED: usize = 64;
+/// Largest buffer kept for reuse, so that one huge quote doesn't pin its memory forever
+const MAX_POOLED_CAPACITY: usize = 64 * 1024;
+
+static POOL: Mutex<Vec<Vec<u8>>> = Mutex::new(Vec::new());
+
+/// An empty buffer with room for at least `capacity` bytes, reused if one is to hand
+pub(crate) fn take(capacity: usize) -> Vec<u8> {
+    let buffer = POOL.lock().unwrap_or_else(|e| e.into_inner()).pop();
+    match buffer {
+        Some(mut buffer) => {
+            buffer.reserve(capacity);
+            buffer
+        }
+        None => Vec::with_capacity(capacity),
+    }
+}
+
+/// Hand `buffer` back for reuse, its contents no longer needed
+pub(crate) fn recycle(mut buffer: Vec<u8>) {
+    if buffer.capacity() == 0 || buffer.capacity() > MAX_POOLED_CAPACITY {
+        return;
+    }
+    buffer.clear();
+    let mut pool = POOL.lock().unwrap_or_else(|e| e.into_inner());
+    if pool.len() < MAX_POOLED {
+        pool.push(buffer);
+    }
+}
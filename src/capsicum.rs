@@ -0,0 +1,71 @@
+//! This module sandboxes the server with Capsicum (FreeBSD), so that a compromised server can do no
+//! more with the sockets and files it has open than serve quotes, and can open nothing new at all
+//!
+//! Once in capability mode, nothing may be reached by its path, nor may sockets be bound or
+//! connected, so everything the server needs must be open beforehand: its quote files, and its
+//! sockets, bound. Those are limited to the rights serving needs (see [`Quotes::limit_capabilities`]
+//! and [`Server::limit_capabilities`]) before entering capability mode with
+//! [`enter_capability_mode`]; connections accepted from then on inherit their listener's rights.
+//!
+//! [`Quotes::limit_capabilities`]: crate::Quotes::limit_capabilities
+//! [`Server::limit_capabilities`]: crate::Server::limit_capabilities
+#![cfg(target_os = "freebsd")]
+
+use std::{
+    io,
+    os::fd::{AsFd, AsRawFd},
+};
+
+use tracing::info;
+
+/// Rights quote files are limited to: reading from any offset, and checking their size
+pub(crate) const FILE_RIGHTS: &[u64] = &[libc::CAP_READ, libc::CAP_SEEK, libc::CAP_FSTAT];
+/// Rights listening sockets are limited to, which connections accepted from them inherit: waiting
+/// for and accepting connections, and talking over them
+pub(crate) const LISTENER_RIGHTS: &[u64] = &[
+    libc::CAP_SOCK_SERVER,
+    libc::CAP_EVENT,
+    libc::CAP_FCNTL,
+    libc::CAP_FSTAT,
+];
+
+/// Limit `fd` to `rights`, for good
+///
+/// Each of `rights` is a single right or a union of those in the same word, e.g.
+/// `CAP_SOCK_SERVER`, as `cap_rights_set(3)` takes them.
+pub(crate) fn limit<F: AsFd>(fd: F, rights: &[u64]) -> io::Result<()> {
+    // SAFETY: `cap_rights_t` is plain old data, which `__cap_rights_init` overwrites anyway
+    let mut limited: libc::cap_rights_t = unsafe { std::mem::zeroed() };
+    // SAFETY: `limited` is a valid `cap_rights_t`, and the list of rights is terminated by 0
+    unsafe { libc::__cap_rights_init(libc::CAP_RIGHTS_VERSION, &mut limited, 0u64) };
+    for &right in rights {
+        // SAFETY: As above
+        unsafe { libc::__cap_rights_set(&mut limited, right, 0u64) };
+    }
+    // SAFETY: `limited` is a valid, initialized `cap_rights_t`, and the fd is open
+    if unsafe { libc::cap_rights_limit(fd.as_fd().as_raw_fd(), &limited) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Enter capability mode, for good, after which nothing may be opened by its path, nor may
+/// sockets be bound or connected
+///
+/// Limit the files and sockets to be kept, with [`Quotes::limit_capabilities`] and
+/// [`Server::limit_capabilities`], beforehand. Capability mode is inherited by threads and child
+/// processes alike, so quotes can't be reloaded, nor can the server be upgraded; UDP can't be
+/// answered either, as datagrams can't be sent to an address.
+///
+/// [`Quotes::limit_capabilities`]: crate::Quotes::limit_capabilities
+/// [`Server::limit_capabilities`]: crate::Server::limit_capabilities
+pub fn enter_capability_mode() -> io::Result<()> {
+    // The local time zone is read from /etc/localtime, which is out of reach from now on
+    let _ = chrono::Local::now();
+    // SAFETY: Entering capability mode takes no arguments
+    if unsafe { libc::cap_enter() } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    info!("Entered Capsicum capability mode");
+    Ok(())
+}
@@ -0,0 +1,92 @@
+//! This module transcodes quote files written before Unicode into UTF-8, so that e.g. the classic
+//! fortune packs' accented characters survive being served as JSON or HTML
+
+use std::{borrow::Cow, io, str::FromStr};
+
+use crate::buffer;
+
+/// The character set a quote file is written in
+///
+/// Every character set here agrees with ASCII, so separators, encoding tokens, and front-matter
+/// are found the same way in all of them.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Charset {
+    #[default]
+    Utf8,
+    /// ISO 8859-1, as most pre-Unicode fortune files on Unix were written in
+    Latin1,
+    /// The original IBM PC character set, as fortune files from DOS were written in
+    Cp437,
+}
+
+impl FromStr for Charset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "utf8" | "utf-8" => Ok(Charset::Utf8),
+            "latin1" | "latin-1" | "iso-8859-1" => Ok(Charset::Latin1),
+            "cp437" | "ibm437" => Ok(Charset::Cp437),
+            other => Err(format!("Unknown file encoding \"{other}\"")),
+        }
+    }
+}
+
+impl Charset {
+    /// Decode `bytes` as text, failing if they aren't valid UTF-8 when they should be
+    pub(crate) fn decode(self, bytes: &[u8]) -> io::Result<Cow<'_, str>> {
+        match self {
+            Charset::Utf8 => std::str::from_utf8(bytes).map(Cow::Borrowed).map_err(|e| {
+                let message = format!("Not valid UTF-8 ({e}), so its encoding must be given");
+                io::Error::new(io::ErrorKind::InvalidData, message)
+            }),
+            // ASCII is the same in every character set, so needn't be copied
+            _ if bytes.is_ascii() => Charset::Utf8.decode(bytes),
+            Charset::Latin1 => Ok(Cow::Owned(bytes.iter().map(|&c| char::from(c)).collect())),
+            Charset::Cp437 => Ok(Cow::Owned(bytes.iter().map(|&c| cp437(c)).collect())),
+        }
+    }
+
+    /// Transcode `text` into UTF-8 in place
+    ///
+    /// Text that should already be UTF-8 is left as it is, valid or not.
+    pub(crate) fn transcode(self, text: &mut Vec<u8>) {
+        if self == Charset::Utf8 || text.is_ascii() {
+            return;
+        }
+        let mut transcoded = buffer::take(text.len() * 2);
+        let mut char_buf = [0; 4];
+        for &c in text.iter() {
+            let c = match self {
+                Charset::Latin1 => char::from(c),
+                _ => cp437(c),
+            };
+            transcoded.extend_from_slice(c.encode_utf8(&mut char_buf).as_bytes());
+        }
+        buffer::recycle(std::mem::replace(text, transcoded));
+    }
+}
+
+/// The character `c` is in code page 437
+///
+/// Only the upper half is translated; control characters are left as they are, rather than
+/// becoming the symbols the IBM PC drew for them.
+fn cp437(c: u8) -> char {
+    match c.checked_sub(0x80) {
+        Some(i) => CP437_UPPER[usize::from(i)],
+        None => char::from(c),
+    }
+}
+
+#[rustfmt::skip]
+const CP437_UPPER: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{A0}',
+];
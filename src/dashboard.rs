@@ -0,0 +1,155 @@
+//! This module renders the status page served on the admin endpoint
+#![cfg(feature = "dashboard")]
+
+use std::{fmt::Write, time::Duration};
+
+use crate::{IndexSummary, QuoteReport, StatsSnapshot};
+
+/// Everything shown on the status page
+#[derive(Debug)]
+pub(crate) struct Status {
+    pub uptime: Duration,
+    pub stats: StatsSnapshot,
+    pub report: QuoteReport,
+    pub summary: IndexSummary,
+}
+
+/// Fetches a quote from the preview endpoint without leaving the page
+const SCRIPT: &str = r#"
+document.getElementById("preview").addEventListener("click", async () => {
+    const response = await fetch("/admin/preview");
+    document.getElementById("quote").textContent = await response.text();
+});
+"#;
+
+const STYLE: &str = r#"
+body { font-family: sans-serif; max-width: 50em; margin: 2em auto; padding: 0 1em; }
+table { border-collapse: collapse; }
+th, td { text-align: left; padding: 0.2em 1em 0.2em 0; }
+td.count { text-align: right; }
+pre { background: #eee; padding: 1em; white-space: pre-wrap; }
+"#;
+
+impl Status {
+    pub fn render(&self) -> String {
+        let mut html = String::new();
+        // Writing to a String can't fail
+        let _ = self.write(&mut html);
+        html
+    }
+
+    fn write(&self, html: &mut String) -> std::fmt::Result {
+        writeln!(html, "<!DOCTYPE html>")?;
+        writeln!(html, "<html><head><meta charset=\"utf-8\">")?;
+        writeln!(
+            html,
+            "<title>qotd-server</title><style>{STYLE}</style></head>"
+        )?;
+        writeln!(
+            html,
+            "<body><h1>qotd-server {}</h1>",
+            env!("CARGO_PKG_VERSION")
+        )?;
+
+        writeln!(html, "<h2>Status</h2><table>")?;
+        let row = |html: &mut String, name: &str, value: &dyn std::fmt::Display| {
+            writeln!(html, "<tr><th>{name}</th><td>{value}</td></tr>")
+        };
+        row(html, "Uptime", &format_uptime(self.uptime))?;
+        row(html, "Requests served", &self.stats.service_time.count)?;
+        row(html, "Requests denied", &self.stats.denied_requests)?;
+        row(html, "Datagrams ignored", &self.stats.ignored_datagrams)?;
+        row(html, "Accepts paused", &self.stats.accept_pauses)?;
+        row(html, "Connections shed", &self.stats.shed_connections)?;
+        row(html, "Datagrams shed", &self.stats.shed_datagrams)?;
+        row(html, "Requests failed", &self.stats.failed_requests)?;
+        row(html, "Slow requests", &self.stats.slow_requests)?;
+        if let Some(mean) = self.stats.service_time.mean() {
+            row(html, "Mean service time", &format!("{mean:?}"))?;
+        }
+        row(
+            html,
+            "Quotes served",
+            &format!(
+                "{} of {} at least once",
+                self.report.served, self.report.quotes
+            ),
+        )?;
+        row(
+            html,
+            "By protocol",
+            &format!(
+                "TCP {}, UDP {}",
+                self.report.tcp_serves, self.report.udp_serves
+            ),
+        )?;
+        for (category, count) in &self.report.by_category {
+            row(html, &format!("Served {category}"), count)?;
+        }
+        writeln!(html, "</table>")?;
+
+        writeln!(
+            html,
+            "<h2>Quote files</h2><p>{} quote(s) in {} file(s)</p>",
+            self.summary.quotes(),
+            self.summary.files.len()
+        )?;
+        writeln!(html, "<table><tr><th>File</th><th>Category</th><th>Language</th><th>Encoding</th><th>Storage</th><th>Quotes</th><th>Served</th></tr>")?;
+        for file in &self.summary.files {
+            let served = self
+                .report
+                .by_file
+                .iter()
+                .find(|(name, _)| *name == file.name)
+                .map_or(0, |(_, count)| *count);
+            writeln!(
+                html,
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td class=\"count\">{}</td><td class=\"count\">{served}</td></tr>",
+                escape(&file.name),
+                file.category,
+                escape(file.language.as_deref().unwrap_or("")),
+                file.encoding,
+                file.storage,
+                file.quotes
+            )?;
+        }
+        writeln!(html, "</table>")?;
+
+        writeln!(html, "<h2>Preview</h2>")?;
+        writeln!(
+            html,
+            "<button id=\"preview\">Random quote</button><pre id=\"quote\"></pre>"
+        )?;
+        writeln!(html, "<script>{SCRIPT}</script></body></html>")
+    }
+}
+
+/// Uptime as e.g. "3d 4h 5m 6s"
+fn format_uptime(uptime: Duration) -> String {
+    let secs = uptime.as_secs();
+    let (days, hours, minutes, secs) =
+        (secs / 86_400, secs / 3_600 % 24, secs / 60 % 60, secs % 60);
+    if days > 0 {
+        format!("{days}d {hours}h {minutes}m {secs}s")
+    } else if hours > 0 {
+        format!("{hours}h {minutes}m {secs}s")
+    } else {
+        format!("{minutes}m {secs}s")
+    }
+}
+
+/// Escape text for inclusion in HTML
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,79 @@
+//! This module exports indexed quotes as structured records, e.g. for auditing what is served
+#![cfg(feature = "export")]
+
+use std::io::Write;
+
+use serde::Serialize;
+
+use crate::Quotes;
+
+/// Formats quotes can be exported to
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum ExportFormat {
+    /// An array of objects
+    #[default]
+    Json,
+    /// A header row followed by a row per quote
+    Csv,
+}
+
+/// A quote as exported
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ExportRecord {
+    /// The quote's ID, as used in statistics
+    pub id: String,
+    /// The hash of the quote's text, which stays the same however its file is edited (see
+    /// [`Quote::hash`](crate::Quote::hash))
+    pub hash: String,
+    pub file: String,
+    pub category: String,
+    /// How the quote's file is encoded on disk; the text is always decoded
+    pub encoding: &'static str,
+    pub text: String,
+    /// Who the quote is attributed to, if anyone (see [`Quote::author`](crate::Quote::author))
+    pub author: Option<String>,
+}
+
+/// Write every quote that may be served to `out`, returning the number of quotes written
+///
+/// Quotes are written as they are read from their files, i.e. without any formatting applied.
+pub async fn export<W: Write>(
+    quotes: &mut Quotes,
+    format: ExportFormat,
+    mut out: W,
+) -> anyhow::Result<usize> {
+    let records: Vec<ExportRecord> = quotes
+        .read_all()
+        .await?
+        .into_iter()
+        .flat_map(|(file, quotes)| {
+            quotes.into_iter().map(move |quote| ExportRecord {
+                id: quote.id.to_string(),
+                hash: quote.hash(),
+                category: quote.category.to_string(),
+                encoding: file.encoding,
+                text: quote.text_str().into_owned(),
+                author: quote.author(),
+                file: quote.file,
+            })
+        })
+        .collect();
+
+    match format {
+        ExportFormat::Json => {
+            serde_json::to_writer_pretty(&mut out, &records)?;
+            writeln!(out)?;
+        }
+        ExportFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(&mut out);
+            for record in &records {
+                writer.serialize(record)?;
+            }
+            writer.flush()?;
+        }
+    }
+    out.flush()?;
+
+    Ok(records.len())
+}
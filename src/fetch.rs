@@ -0,0 +1,141 @@
+//! This module downloads collections of quotes ("packs") into a quote directory
+#![cfg(feature = "fetch")]
+
+use std::{
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use sha2::{Digest, Sha256};
+use tracing::info;
+
+/// Largest pack we're willing to download, far larger than any known fortune file
+const MAX_PACK_SIZE: u64 = 16 * 1024 * 1024;
+
+/// A well-known collection of quotes, fetched by name
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pack {
+    pub name: &'static str,
+    pub url: &'static str,
+    /// Expected SHA-256 digest of the download, in hex, if pinned
+    pub sha256: Option<&'static str>,
+}
+
+macro_rules! fortune_mod_pack {
+    ($name:literal) => {
+        Pack {
+            name: $name,
+            url: concat!(
+                "https://raw.githubusercontent.com/shlomif/fortune-mod/fortune-mod-3.20.0/fortune-mod/datfiles/",
+                $name
+            ),
+            sha256: None,
+        }
+    };
+}
+
+/// Packs that can be fetched by name
+///
+/// These are the data files of fortune-mod, pinned to a release. Its releases don't publish
+/// checksums for them, so their digests must be given when fetching until they're pinned here.
+pub const KNOWN_PACKS: &[Pack] = &[
+    fortune_mod_pack!("art"),
+    fortune_mod_pack!("computers"),
+    fortune_mod_pack!("cookie"),
+    fortune_mod_pack!("definitions"),
+    fortune_mod_pack!("fortunes"),
+    fortune_mod_pack!("literature"),
+    fortune_mod_pack!("science"),
+    fortune_mod_pack!("wisdom"),
+    fortune_mod_pack!("zippy"),
+];
+
+impl Pack {
+    /// Look up a well-known pack by name
+    pub fn named(name: &str) -> Option<Self> {
+        KNOWN_PACKS.iter().find(|pack| pack.name == name).copied()
+    }
+}
+
+/// Download a pack, given by name or URL, into `dir`, returning the path it was written to
+///
+/// The download must match `sha256` (a hex digest) if given, or otherwise the pack's pinned
+/// digest; if neither is known, nothing is written and the error reports the download's digest so
+/// that it can be checked and given. An existing file is only replaced if `force` is set.
+pub fn fetch(pack: &str, dir: &Path, sha256: Option<&str>, force: bool) -> anyhow::Result<PathBuf> {
+    let (name, url, pinned) = match Pack::named(pack) {
+        Some(pack) => (pack.name.to_string(), pack.url, pack.sha256),
+        None if pack.starts_with("https://") || pack.starts_with("http://") => {
+            let name = pack
+                .rsplit('/')
+                .next()
+                .filter(|name| !name.is_empty() && *name != "." && *name != "..")
+                .with_context(|| format!("No file name in URL: {pack}"))?;
+            (name.to_string(), pack, None)
+        }
+        None => anyhow::bail!(
+            "Unknown pack \"{pack}\"; give a URL or one of: {}",
+            KNOWN_PACKS
+                .iter()
+                .map(|pack| pack.name)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    };
+    let path = dir.join(&name);
+    if path.exists() && !force {
+        anyhow::bail!(
+            "{} already exists; use --force to replace it",
+            path.display()
+        );
+    }
+
+    info!("Fetching {url}");
+    let mut contents = Vec::new();
+    ureq::get(url)
+        .call()
+        .with_context(|| format!("Failed to fetch {url}"))?
+        .into_reader()
+        .take(MAX_PACK_SIZE + 1)
+        .read_to_end(&mut contents)
+        .with_context(|| format!("Failed to fetch {url}"))?;
+    if contents.len() as u64 > MAX_PACK_SIZE {
+        anyhow::bail!("{url} is larger than {MAX_PACK_SIZE} bytes");
+    }
+
+    let digest: String = Sha256::digest(&contents)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    match sha256.or(pinned) {
+        Some(expected) if expected.eq_ignore_ascii_case(&digest) => {}
+        Some(expected) => {
+            anyhow::bail!("Checksum mismatch for {url}: expected {expected}, got {digest}")
+        }
+        None => anyhow::bail!(
+            "No checksum known for {url}; if its SHA-256 digest {digest} is as expected, \
+            fetch it again with --sha256 {digest}"
+        ),
+    }
+
+    // The last quote is only recognized if a separator follows it
+    if !contents.ends_with(b"%\n") {
+        if !contents.ends_with(b"\n") {
+            contents.push(b'\n');
+        }
+        contents.extend_from_slice(b"%\n");
+    }
+
+    std::fs::create_dir_all(dir).with_context(|| format!("Unable to create {}", dir.display()))?;
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let mut file = std::fs::File::create(&tmp_path)
+        .with_context(|| format!("Unable to create {}", path.display()))?;
+    file.write_all(&contents)?;
+    file.sync_all()?;
+    std::fs::rename(&tmp_path, &path)
+        .with_context(|| format!("Unable to write {}", path.display()))?;
+
+    Ok(path)
+}
@@ -0,0 +1,124 @@
+//! This module offers a C interface, so that programs in other languages can choose quotes without
+//! running a server, or this program, of their own
+//!
+//! Built with the `ffi` feature, the crate's cdylib (e.g. `libqotd.so`) exports these functions:
+//!
+//! ```c
+//! typedef struct qotd_quotes qotd_quotes;
+//!
+//! /* Index the quote files in `dir`; NULL on failure */
+//! qotd_quotes *qotd_load_dir(const char *dir);
+//! /* Choose a quote, which the caller must free with qotd_free; NULL on failure */
+//! char *qotd_random_quote(qotd_quotes *quotes);
+//! /* Free a quote returned by qotd_random_quote */
+//! void qotd_free(char *quote);
+//! /* Free quotes returned by qotd_load_dir */
+//! void qotd_free_quotes(qotd_quotes *quotes);
+//! ```
+//!
+//! Quotes are indexed and chosen as by [`Quotes::from_dir_blocking`] and
+//! [`Quotes::random_quote_blocking`], so only decorous quotes are chosen. A `qotd_quotes` may be
+//! used from any thread, but only from one at a time.
+#![cfg(feature = "ffi")]
+
+use std::{
+    ffi::{c_char, CStr, CString},
+    path::PathBuf,
+    ptr,
+};
+
+use tracing::warn;
+
+use crate::{QuoteCategory, Quotes};
+
+/// Index the quote files in the directory `dir`, returning null on failure
+///
+/// # Safety
+///
+/// `dir` must be a valid, NUL-terminated string. The quotes returned must be freed with
+/// [`qotd_free_quotes`], and by no other means.
+#[no_mangle]
+pub unsafe extern "C" fn qotd_load_dir(dir: *const c_char) -> *mut Quotes {
+    if dir.is_null() {
+        return ptr::null_mut();
+    }
+    // SAFETY: The caller promises `dir` is a valid string
+    let Some(dir) = path(unsafe { CStr::from_ptr(dir) }) else {
+        warn!("Failed to load quotes: invalid directory name");
+        return ptr::null_mut();
+    };
+    match Quotes::from_dir_blocking(dir, &[QuoteCategory::DECOROUS]) {
+        Ok(quotes) => Box::into_raw(Box::new(quotes)),
+        Err(e) => {
+            warn!("Failed to load quotes: {e}");
+            ptr::null_mut()
+        }
+    }
+}
+
+/// The path named by `s`, which must be UTF-8 but on Unix-like systems
+fn path(s: &CStr) -> Option<PathBuf> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+
+        Some(std::ffi::OsStr::from_bytes(s.to_bytes()).into())
+    }
+    #[cfg(not(unix))]
+    {
+        s.to_str().ok().map(PathBuf::from)
+    }
+}
+
+/// Choose a quote from `quotes`, returning it as a NUL-terminated string, or null on failure
+///
+/// # Safety
+///
+/// `quotes` must have been returned by [`qotd_load_dir`], and not yet freed, nor be in use by
+/// another thread. The quote returned must be freed with [`qotd_free`], and by no other means.
+#[no_mangle]
+pub unsafe extern "C" fn qotd_random_quote(quotes: *mut Quotes) -> *mut c_char {
+    // SAFETY: The caller promises `quotes` is valid, and used by no one else meanwhile
+    let Some(quotes) = (unsafe { quotes.as_mut() }) else {
+        return ptr::null_mut();
+    };
+    match quotes.random_quote_blocking() {
+        Ok(quote) => {
+            let mut text = quote.text;
+            // C strings end at the first NUL, which no quote should contain anyway
+            text.retain(|&b| b != 0);
+            CString::new(text).map_or(ptr::null_mut(), CString::into_raw)
+        }
+        Err(e) => {
+            warn!("Failed to read quote: {e}");
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Free a quote returned by [`qotd_random_quote`]; does nothing if `quote` is null
+///
+/// # Safety
+///
+/// `quote` must have been returned by [`qotd_random_quote`], and not yet freed.
+#[no_mangle]
+pub unsafe extern "C" fn qotd_free(quote: *mut c_char) {
+    if !quote.is_null() {
+        // SAFETY: The caller promises `quote` came from `CString::into_raw`, and is freed only once
+        drop(unsafe { CString::from_raw(quote) });
+    }
+}
+
+/// Free quotes returned by [`qotd_load_dir`]; does nothing if `quotes` is null
+///
+/// # Safety
+///
+/// `quotes` must have been returned by [`qotd_load_dir`], and not yet freed, nor be in use by
+/// another thread.
+#[no_mangle]
+pub unsafe extern "C" fn qotd_free_quotes(quotes: *mut Quotes) {
+    if !quotes.is_null() {
+        // SAFETY: The caller promises `quotes` came from `Box::into_raw`, and is freed only once
+        drop(unsafe { Box::from_raw(quotes) });
+    }
+}
@@ -0,0 +1,441 @@
+//! This module formats quotes for sending to clients
+
+use std::{fmt, str::FromStr};
+
+use chrono::Local;
+#[cfg(feature = "cli")]
+use clap::ValueEnum;
+use unicode_width::UnicodeWidthStr;
+
+use crate::{buffer, Quote};
+
+/// Tab stops are assumed every this many columns when measuring text
+const TAB_WIDTH: usize = 8;
+
+/// The cow, which is drawn beneath its speech bubble
+const COW: &str = r"        \   ^__^
+         \  (oo)\_______
+            (__)\       )\/\
+                ||----w |
+                ||     ||
+";
+
+/// What a formatted quote will be sent over, which decides which transforms apply to it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(not(all(feature = "tcp", feature = "udp")), allow(dead_code))]
+pub(crate) enum Transport {
+    Tcp,
+    Udp,
+}
+
+/// Transforms applied to every quote before sending it
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct Formatter {
+    pub wrap: Option<usize>,
+    /// Only applied to quotes sent over TCP, as UDP clients get no chance to opt out
+    pub color: Option<ColorScheme>,
+    pub decoration: Option<Decoration>,
+    pub template: Option<Template>,
+    /// Restrict quotes to ASCII printing characters, spaces, and line breaks, as RFC 865 suggests
+    pub ascii: bool,
+}
+
+impl Formatter {
+    /// Whether quotes sent over `transport` are sent as they are, i.e. no transforms apply to them
+    #[cfg(any(feature = "tcp", feature = "udp"))]
+    pub fn is_identity(&self, transport: Transport) -> bool {
+        self.wrap.is_none()
+            && (self.color.is_none() || transport != Transport::Tcp)
+            && self.decoration.is_none()
+            && self.template.is_none()
+            && !self.ascii
+    }
+
+    /// Replace the quote's text with its formatted text
+    pub fn format(&self, mut quote: Quote, transport: Transport) -> Quote {
+        if let Some(width) = self.wrap {
+            quote.text = rewrap(&quote.text_str(), width).into_bytes();
+        }
+        // Escape sequences aren't printing characters, so colors are never applied to ASCII quotes
+        if let Some(color) = self
+            .color
+            .as_ref()
+            .filter(|_| transport == Transport::Tcp && !self.ascii)
+        {
+            quote.text = color.apply(&quote.text_str()).into_bytes();
+        }
+        if let Some(decoration) = self.decoration {
+            quote.text = decoration.apply(&quote.text);
+        }
+        if let Some(template) = &self.template {
+            let rendered = template.render(&quote);
+            buffer::recycle(std::mem::replace(&mut quote.text, rendered));
+        }
+        if self.ascii {
+            quote.text = to_ascii(&quote.text_str()).into_bytes();
+        }
+        quote
+    }
+}
+
+/// ANSI text styles for the parts of a quote
+///
+/// Written as comma-separated `PART=STYLE` pairs, e.g. `quote=bold+cyan,attribution=yellow`, where
+/// the parts are `quote` and `attribution` (lines beginning with "--"), and styles are any of `bold`,
+/// `dim`, `italic`, `underline`, and the colors `black`, `red`, `green`, `yellow`, `blue`, `magenta`,
+/// `cyan`, and `white`, each optionally prefixed with `bright-`, joined by '+'; `none` is unstyled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColorScheme {
+    /// SGR parameters of each part's style, e.g. "1;36"
+    quote: String,
+    attribution: String,
+}
+
+impl Default for ColorScheme {
+    fn default() -> Self {
+        Self {
+            quote: "36".to_string(),
+            attribution: "2;33".to_string(),
+        }
+    }
+}
+
+impl ColorScheme {
+    const COLORS: [&'static str; 8] = [
+        "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+    ];
+
+    /// Style every line of `text` as either quote or attribution
+    pub fn apply(&self, text: &str) -> String {
+        let mut styled = String::with_capacity(text.len() * 2);
+        for line in text.lines() {
+            let style = if line.trim_start().starts_with("--") {
+                &self.attribution
+            } else {
+                &self.quote
+            };
+            if style.is_empty() || line.trim().is_empty() {
+                styled.push_str(line);
+            } else {
+                // Reset at the end of every line, so that nothing added around it is styled
+                styled.push_str(&format!("\x1b[{style}m{line}\x1b[0m"));
+            }
+            styled.push('\n');
+        }
+        styled
+    }
+
+    fn parse_style(style: &str) -> Result<String, String> {
+        let mut params = Vec::new();
+        for name in style.split('+').map(str::trim) {
+            let (bright, color) = match name.strip_prefix("bright-") {
+                Some(color) => (true, color),
+                None => (false, name),
+            };
+            let param = match (bright, color) {
+                (false, "none") => continue,
+                (false, "bold") => 1,
+                (false, "dim") => 2,
+                (false, "italic") => 3,
+                (false, "underline") => 4,
+                (bright, color) => match Self::COLORS.iter().position(|&c| c == color) {
+                    Some(i) if bright => 90 + i,
+                    Some(i) => 30 + i,
+                    None => return Err(format!("Unknown style: {name}")),
+                },
+            };
+            params.push(param.to_string());
+        }
+        Ok(params.join(";"))
+    }
+}
+
+impl FromStr for ColorScheme {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut scheme = Self::default();
+        for pair in s.split(',').filter(|pair| !pair.trim().is_empty()) {
+            let (part, style) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("Expected a style like quote=bold+cyan, got: {pair}"))?;
+            let style = Self::parse_style(style)?;
+            match part.trim() {
+                "quote" => scheme.quote = style,
+                "attribution" => scheme.attribution = style,
+                other => return Err(format!("Unknown part of a quote: {other}")),
+            }
+        }
+        Ok(scheme)
+    }
+}
+
+/// ASCII art quotes can be framed in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(ValueEnum))]
+pub enum Decoration {
+    /// A speech bubble, spoken by a cow
+    Cowsay,
+    /// A simple box
+    Box,
+}
+
+impl Decoration {
+    pub fn apply(&self, text: &[u8]) -> Vec<u8> {
+        let text = String::from_utf8_lossy(text);
+        let lines: Vec<String> = text.trim_end().lines().map(expand_tabs).collect();
+        let width = lines
+            .iter()
+            .map(|line| display_width(line))
+            .max()
+            .unwrap_or(0);
+        let pad = |line: &str| " ".repeat(width - display_width(line));
+
+        let mut framed = String::new();
+        match self {
+            Decoration::Cowsay => {
+                framed.push_str(&format!(" {}\n", "_".repeat(width + 2)));
+                for (i, line) in lines.iter().enumerate() {
+                    let (left, right) = match (i, lines.len()) {
+                        (_, 1) => ('<', '>'),
+                        (0, _) => ('/', '\\'),
+                        (i, len) if i == len - 1 => ('\\', '/'),
+                        _ => ('|', '|'),
+                    };
+                    framed.push_str(&format!("{left} {line}{} {right}\n", pad(line)));
+                }
+                framed.push_str(&format!(" {}\n", "-".repeat(width + 2)));
+                framed.push_str(COW);
+            }
+            Decoration::Box => {
+                let border = format!("+{}+\n", "-".repeat(width + 2));
+                framed.push_str(&border);
+                for line in &lines {
+                    framed.push_str(&format!("| {line}{} |\n", pad(line)));
+                }
+                framed.push_str(&border);
+            }
+        }
+        framed.into_bytes()
+    }
+}
+
+/// Reflow text to fit within `width` columns
+///
+/// Only paragraphs (separated by blank lines) with lines too long to fit are reflowed. Within them,
+/// a line break is kept if the next line is indented (e.g. an attribution), or if the line had room
+/// left for the next line's first word, as then it must have been broken deliberately (e.g. verse).
+pub fn rewrap(text: &str, width: usize) -> String {
+    let lines: Vec<String> = text.lines().map(expand_tabs).collect();
+    let mut wrapped = String::with_capacity(text.len());
+    for paragraph in lines.split(|line| line.is_empty()) {
+        if paragraph.iter().all(|line| line.width() <= width) {
+            for line in paragraph {
+                wrapped.push_str(line);
+                wrapped.push('\n');
+            }
+        } else {
+            let fill_width = paragraph.iter().map(|line| line.width()).max().unwrap_or(0);
+            let mut start = 0;
+            for end in 1..=paragraph.len() {
+                let is_hard_break = paragraph.get(end).is_none_or(|next| {
+                    let first_word = next.split_whitespace().next().unwrap_or_default();
+                    next.starts_with(' ')
+                        || paragraph[end - 1].width() + 1 + first_word.width() <= fill_width
+                });
+                if is_hard_break {
+                    fill(&paragraph[start..end], width, &mut wrapped);
+                    start = end;
+                }
+            }
+        }
+        wrapped.push('\n');
+    }
+    // Splitting on blank lines leaves one too many paragraph breaks at the end
+    wrapped.pop();
+    wrapped
+}
+
+/// Greedily fill the words of `lines` into lines of at most `width` columns, keeping the first
+/// line's indentation
+fn fill(lines: &[String], width: usize, out: &mut String) {
+    let indent = &lines[0][..lines[0].len() - lines[0].trim_start().len()];
+    let mut line_width = 0;
+    for word in lines.iter().flat_map(|line| line.split_whitespace()) {
+        if line_width > 0 && line_width + 1 + word.width() <= width {
+            out.push(' ');
+            line_width += 1;
+        } else {
+            if line_width > 0 {
+                out.push('\n');
+            }
+            out.push_str(indent);
+            line_width = indent.width();
+        }
+        out.push_str(word);
+        line_width += word.width();
+    }
+    out.push('\n');
+}
+
+/// Reduce text to ASCII printing characters, spaces, and line breaks
+///
+/// Typographic punctuation is replaced with its nearest ASCII equivalent (e.g. curly quotes become
+/// straight ones), tabs are expanded, other control characters are dropped, and any other
+/// characters become '?'.
+pub fn to_ascii(text: &str) -> String {
+    let mut ascii = String::with_capacity(text.len());
+    for (i, line) in text.split('\n').enumerate() {
+        if i > 0 {
+            ascii.push('\n');
+        }
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        for c in expand_tabs(line).chars() {
+            match c {
+                ' '..='~' => ascii.push(c),
+                '\u{2018}' | '\u{2019}' | '\u{201A}' | '\u{2032}' => ascii.push('\''),
+                '\u{201C}' | '\u{201D}' | '\u{201E}' | '\u{2033}' | '\u{00AB}' | '\u{00BB}' => {
+                    ascii.push('"')
+                }
+                '\u{2010}'..='\u{2015}' | '\u{2212}' => ascii.push('-'),
+                '\u{2026}' => ascii.push_str("..."),
+                '\u{2022}' | '\u{00B7}' => ascii.push('*'),
+                '\u{00A0}' | '\u{2000}'..='\u{200A}' | '\u{202F}' => ascii.push(' '),
+                // Zero-width characters, e.g. joiners and byte order marks
+                '\u{200B}'..='\u{200D}' | '\u{FEFF}' => {}
+                c if c.is_control() => {}
+                _ => ascii.push('?'),
+            }
+        }
+    }
+    ascii
+}
+
+/// Replace tabs with spaces up to the next tab stop, and strip trailing whitespace
+fn expand_tabs(line: &str) -> String {
+    let mut expanded = String::with_capacity(line.len());
+    for c in line.trim_end().chars() {
+        if c == '\t' {
+            let spaces = TAB_WIDTH - display_width(&expanded) % TAB_WIDTH;
+            expanded.extend(std::iter::repeat_n(' ', spaces));
+        } else {
+            expanded.push(c);
+        }
+    }
+    expanded
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    Quote,
+    File,
+    Date,
+    Category,
+}
+
+/// A template quotes are wrapped in, e.g. to add a banner or footer
+///
+/// The placeholders `{quote}`, `{file}`, `{date}` (the current date, in server-local time), and
+/// `{category}` are substituted; literal braces are written as `{{` and `}}`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+impl Template {
+    pub fn render(&self, quote: &Quote) -> Vec<u8> {
+        let mut rendered = buffer::take(quote.text.len());
+        for part in &self.parts {
+            match part {
+                Part::Literal(text) => rendered.extend_from_slice(text.as_bytes()),
+                Part::Quote => rendered.extend_from_slice(&quote.text),
+                Part::File => rendered.extend_from_slice(quote.file.as_bytes()),
+                Part::Date => rendered
+                    .extend_from_slice(Local::now().format("%Y-%m-%d").to_string().as_bytes()),
+                Part::Category => rendered.extend_from_slice(quote.category.to_string().as_bytes()),
+            }
+        }
+        rendered
+    }
+}
+
+impl FromStr for Template {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let (name, rest) = chars
+                        .as_str()
+                        .split_once('}')
+                        .ok_or_else(|| format!("Unclosed placeholder in template: {s}"))?;
+                    let part = match name {
+                        "quote" => Part::Quote,
+                        "file" => Part::File,
+                        "date" => Part::Date,
+                        "category" => Part::Category,
+                        other => return Err(format!("Unknown template placeholder: {{{other}}}")),
+                    };
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(part);
+                    chars = rest.chars();
+                }
+                '}' => return Err(format!("Unmatched '}}' in template: {s}")),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+
+        Ok(Self { parts })
+    }
+}
+
+impl fmt::Display for Template {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for part in &self.parts {
+            match part {
+                Part::Literal(text) => f.write_str(&text.replace('{', "{{").replace('}', "}}"))?,
+                Part::Quote => f.write_str("{quote}")?,
+                Part::File => f.write_str("{file}")?,
+                Part::Date => f.write_str("{date}")?,
+                Part::Category => f.write_str("{category}")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The number of columns text takes up on a terminal, ignoring ANSI escape sequences
+fn display_width(text: &str) -> usize {
+    let mut width = 0;
+    let mut rest = text;
+    while let Some(start) = rest.find('\x1b') {
+        width += rest[..start].width();
+        // Skip the sequence up to and including its final byte, in the range '@' to '~'
+        let sequence = &rest[start + 1..];
+        let end = sequence
+            .char_indices()
+            .skip(1)
+            .find(|&(_, c)| ('@'..='~').contains(&c))
+            .map_or(sequence.len(), |(i, c)| i + c.len_utf8());
+        rest = &sequence[end..];
+    }
+    width + rest.width()
+}
@@ -0,0 +1,198 @@
+//! This module speaks just enough HTTP/1.1 for the admin endpoint: one request per connection, with
+//! bodies only delimited by Content-Length
+#![cfg(any(feature = "tcp", feature = "udp"))]
+
+use std::{fmt, io, time::Duration};
+
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+
+/// Longest request line or header line accepted
+const MAX_LINE: usize = 8 * 1024;
+/// Most headers accepted in a request
+const MAX_HEADERS: usize = 64;
+/// Largest request body accepted
+const MAX_BODY: usize = 64 * 1024;
+/// How long a client may take to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Request {
+    pub method: String,
+    /// The request target, without any query string
+    pub path: String,
+    pub query: Option<String>,
+    /// Header names are lowercased
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    /// Read a request from `conn`, failing with `InvalidData` if it's malformed or too large
+    pub async fn read<S: AsyncRead + Unpin>(conn: &mut S) -> io::Result<Self> {
+        tokio::time::timeout(REQUEST_TIMEOUT, Self::read_inner(conn))
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "Request timed out"))?
+    }
+
+    async fn read_inner<S: AsyncRead + Unpin>(conn: &mut S) -> io::Result<Self> {
+        let mut reader = BufReader::new(conn);
+        let request_line = read_line(&mut reader).await?;
+        let mut parts = request_line.split(' ');
+        let (Some(method), Some(target), Some(version), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid("Malformed request line"));
+        };
+        if !version.starts_with("HTTP/1.") {
+            return Err(invalid("Unsupported HTTP version"));
+        }
+        let (path, query) = match target.split_once('?') {
+            Some((path, query)) => (path.to_string(), Some(query.to_string())),
+            None => (target.to_string(), None),
+        };
+
+        let mut headers = Vec::new();
+        loop {
+            let line = read_line(&mut reader).await?;
+            if line.is_empty() {
+                break;
+            }
+            if headers.len() == MAX_HEADERS {
+                return Err(invalid("Too many headers"));
+            }
+            let (name, value) = line
+                .split_once(':')
+                .ok_or_else(|| invalid("Malformed header"))?;
+            headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+        }
+
+        let mut request = Self {
+            method: method.to_string(),
+            path,
+            query,
+            headers,
+            body: Vec::new(),
+        };
+        if request.header("transfer-encoding").is_some() {
+            return Err(invalid("Transfer-Encoding is not supported"));
+        }
+        let length = match request.header("content-length") {
+            Some(length) => length
+                .parse::<usize>()
+                .map_err(|_| invalid("Malformed Content-Length"))?,
+            None => 0,
+        };
+        if length > MAX_BODY {
+            return Err(invalid("Request body too large"));
+        }
+        request.body = vec![0; length];
+        reader.read_exact(&mut request.body).await?;
+
+        Ok(request)
+    }
+
+    /// The value of the first header with the given (lowercase) name
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Read a CRLF- (or LF-) terminated line, without its terminator
+async fn read_line<R: AsyncBufReadExt + Unpin>(reader: &mut R) -> io::Result<String> {
+    let mut line = Vec::new();
+    (&mut *reader)
+        .take(MAX_LINE as u64 + 1)
+        .read_until(b'\n', &mut line)
+        .await?;
+    if line.len() > MAX_LINE {
+        return Err(invalid("Line too long"));
+    }
+    if line.pop() != Some(b'\n') {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "Connection closed mid-request",
+        ));
+    }
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    String::from_utf8(line).map_err(|_| invalid("Request is not valid UTF-8"))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Response {
+    pub status: u16,
+    pub headers: Vec<(&'static str, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new<B: Into<Vec<u8>>>(status: u16, content_type: &str, body: B) -> Self {
+        Self {
+            status,
+            headers: vec![("Content-Type", content_type.to_string())],
+            body: body.into(),
+        }
+    }
+
+    /// A response without a body, e.g. to a preflight or conditional request
+    pub fn empty(status: u16) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    /// A plain text response, e.g. an error message
+    pub fn text<S: fmt::Display>(status: u16, text: S) -> Self {
+        Self::new(status, "text/plain; charset=utf-8", format!("{text}\n"))
+    }
+
+    pub fn header<V: Into<String>>(mut self, name: &'static str, value: V) -> Self {
+        self.headers.push((name, value.into()));
+        self
+    }
+
+    pub async fn write<S: AsyncWrite + Unpin>(&self, conn: &mut S) -> io::Result<()> {
+        let mut head = format!("HTTP/1.1 {} {}\r\n", self.status, reason(self.status));
+        for (name, value) in &self.headers {
+            head.push_str(&format!("{name}: {value}\r\n"));
+        }
+        // Neither has a body, and a 304's length would be that of the body it stands in for
+        if !matches!(self.status, 204 | 304) {
+            head.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+        }
+        head.push_str("Connection: close\r\n\r\n");
+
+        conn.write_all(head.as_bytes()).await?;
+        conn.write_all(&self.body).await?;
+        conn.shutdown().await
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        304 => "Not Modified",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        406 => "Not Acceptable",
+        413 => "Payload Too Large",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
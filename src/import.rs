@@ -0,0 +1,187 @@
+//! This module converts quote collections in other formats into quote files
+#![cfg(feature = "import")]
+
+use std::io::{Read, Write};
+
+use anyhow::Context;
+use serde::Deserialize;
+
+use crate::{quotes::PLAIN_TOKEN, QuoteCategory, Validity};
+
+/// Formats quote collections can be imported from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum ImportFormat {
+    /// An array of strings, or of objects with "text" and optionally "attribution", "not_before",
+    /// and "not_after" fields
+    Json,
+    /// A header row naming a "text" column and optionally "attribution", "not_before", and
+    /// "not_after" columns
+    Csv,
+    /// A list of strings, or of mappings with "text" and optionally "attribution", "not_before",
+    /// and "not_after" keys
+    Yaml,
+}
+
+impl ImportFormat {
+    /// Guess the format from a file extension
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension.to_ascii_lowercase().as_str() {
+            "json" => Some(ImportFormat::Json),
+            "csv" => Some(ImportFormat::Csv),
+            "yaml" | "yml" => Some(ImportFormat::Yaml),
+            _ => None,
+        }
+    }
+}
+
+/// Metadata to declare in the front-matter of the imported quote file
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ImportOptions {
+    pub category: Option<QuoteCategory>,
+    pub language: Option<String>,
+}
+
+/// A quote to import, with its attribution if it has one
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+enum Record {
+    Text(String),
+    Quote {
+        #[serde(alias = "quote")]
+        text: String,
+        #[serde(default, alias = "author", alias = "source")]
+        attribution: Option<String>,
+        /// First day the quote may be served, as YYYY-MM-DD
+        #[serde(default)]
+        not_before: Option<String>,
+        /// Last day the quote may be served, as YYYY-MM-DD
+        #[serde(default)]
+        not_after: Option<String>,
+    },
+}
+
+impl Record {
+    /// The quote as it appears in a quote file, with its attribution on a line of its own, and the
+    /// dates it may be served between if they're limited, or `None` if it's empty
+    fn into_text(self) -> anyhow::Result<Option<(String, Option<Validity>)>> {
+        let (text, attribution, not_before, not_after) = match self {
+            Record::Text(text) => (text, None, None, None),
+            Record::Quote {
+                text,
+                attribution,
+                not_before,
+                not_after,
+            } => (text, attribution, not_before, not_after),
+        };
+        let mut text = text.replace("\r\n", "\n").trim_end().to_string();
+        if text.trim().is_empty() {
+            return Ok(None);
+        }
+        if let Some(attribution) = attribution.filter(|a| !a.trim().is_empty()) {
+            text.push_str("\n\t\t-- ");
+            text.push_str(attribution.trim());
+        }
+        let validity = Validity {
+            not_before: Validity::parse_date(not_before.as_deref().unwrap_or_default())
+                .map_err(anyhow::Error::msg)?,
+            not_after: Validity::parse_date(not_after.as_deref().unwrap_or_default())
+                .map_err(anyhow::Error::msg)?,
+        };
+        let validity = (validity != Validity::default()).then_some(validity);
+        Ok(Some((text, validity)))
+    }
+}
+
+/// Convert a quote collection read from `input` into a quote file written to `out`, returning the
+/// number of quotes written
+///
+/// Empty quotes are skipped. If any quote has a line beginning with the usual separator, a longer
+/// separator is declared in the front-matter instead.
+pub fn import<R: Read, W: Write>(
+    input: R,
+    format: ImportFormat,
+    options: &ImportOptions,
+    mut out: W,
+) -> anyhow::Result<usize> {
+    let records = match format {
+        ImportFormat::Json => serde_json::from_reader(input).context("Invalid JSON")?,
+        ImportFormat::Yaml => serde_yaml::from_reader(input).context("Invalid YAML")?,
+        ImportFormat::Csv => csv_records(input)?,
+    };
+    let quotes = records
+        .into_iter()
+        .filter_map(|record| record.into_text().transpose())
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    // Lines beginning with the separator end a quote, so pick one that none do
+    let separator = (1..)
+        .map(|len| "%".repeat(len))
+        .find(|separator| {
+            !quotes
+                .iter()
+                .flat_map(|(quote, _)| quote.lines())
+                .any(|line| line.starts_with(separator.as_str()))
+        })
+        .expect("some separator is longer than every line");
+
+    if separator != "%" || options.category.is_some() || options.language.is_some() {
+        writeln!(out, "---")?;
+        if let Some(category) = options.category {
+            writeln!(out, "category: {category}")?;
+        }
+        if let Some(language) = &options.language {
+            writeln!(out, "language: {language}")?;
+        }
+        if separator != "%" {
+            writeln!(out, "separator: {separator}")?;
+        }
+        writeln!(out, "---")?;
+    }
+    // Declares the file unencoded, lest a quote mention an encoding's token, on a comment line
+    // that also keeps the first quote from being taken for front-matter
+    writeln!(out, "{separator}{separator}{PLAIN_TOKEN}")?;
+    // Every quote follows a separator, which declares the dates it may be served between if
+    // they're limited
+    for (quote, validity) in &quotes {
+        match validity {
+            Some(validity) => writeln!(out, "{separator} [{validity}]")?,
+            None => writeln!(out, "{separator}")?,
+        }
+        writeln!(out, "{quote}")?;
+    }
+    writeln!(out, "{separator}")?;
+    out.flush()?;
+
+    Ok(quotes.len())
+}
+
+/// Read quotes from a CSV file, whose columns are found by their (case-insensitive) headers
+fn csv_records<R: Read>(input: R) -> anyhow::Result<Vec<Record>> {
+    let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(input);
+    let headers = reader.headers().context("Invalid CSV")?.clone();
+    let column = |names: &[&str]| {
+        headers.iter().position(|header| {
+            names
+                .iter()
+                .any(|name| header.trim().eq_ignore_ascii_case(name))
+        })
+    };
+    let text = column(&["text", "quote"]).context("No \"text\" or \"quote\" column in CSV")?;
+    let attribution = column(&["attribution", "author", "source"]);
+    let not_before = column(&["not_before", "not-before"]);
+    let not_after = column(&["not_after", "not-after"]);
+
+    reader
+        .records()
+        .map(|record| {
+            let record = record.context("Invalid CSV")?;
+            Ok(Record::Quote {
+                text: record.get(text).unwrap_or_default().to_string(),
+                attribution: attribution.and_then(|i| record.get(i)).map(str::to_string),
+                not_before: not_before.and_then(|i| record.get(i)).map(str::to_string),
+                not_after: not_after.and_then(|i| record.get(i)).map(str::to_string),
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,230 @@
+//! This module contains the IRC bot, which serves quotes in a channel rather than over RFC 865
+//!
+//! Only plain-text connections are supported; use a TLS tunnel (e.g. stunnel) for networks that
+//! require TLS.
+#![cfg(feature = "irc")]
+
+use std::time::Duration;
+
+use anyhow::Context;
+use chrono::{Local, NaiveTime};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{tcp::OwnedWriteHalf, TcpStream},
+};
+use tracing::{debug, info, warn};
+
+use crate::Quotes;
+
+/// Most lines of a quote sent to a channel, so that long quotes don't flood it
+const MAX_LINES: usize = 10;
+/// Longest line sent, in bytes, leaving room within IRC's 512-byte limit for the message's prefix
+const MAX_LINE_LENGTH: usize = 400;
+/// Pause between lines, as servers disconnect clients that send too quickly
+const LINE_DELAY: Duration = Duration::from_millis(500);
+
+/// Joins an IRC channel and posts quotes there, when asked or daily
+#[derive(Debug, Clone)]
+pub struct IrcBot {
+    address: String,
+    nick: String,
+    channel: String,
+    password: Option<String>,
+    command: String,
+    daily_at: Option<NaiveTime>,
+}
+
+impl IrcBot {
+    /// Connect to the server at `address` (e.g. "irc.libera.chat:6667") as `nick`, joining `channel`
+    pub fn new<A: Into<String>, N: Into<String>, C: Into<String>>(
+        address: A,
+        nick: N,
+        channel: C,
+    ) -> Self {
+        Self {
+            address: address.into(),
+            nick: nick.into(),
+            channel: channel.into(),
+            password: None,
+            command: "!qotd".to_string(),
+            daily_at: None,
+        }
+    }
+
+    /// Send the server this password on connecting
+    pub fn password<S: Into<String>>(mut self, password: S) -> Self {
+        self.password = Some(password.into());
+        self
+    }
+
+    /// Reply with a quote to messages consisting of `command`, rather than "!qotd"
+    pub fn command<S: Into<String>>(mut self, command: S) -> Self {
+        self.command = command.into();
+        self
+    }
+
+    /// Also post a quote to the channel every day at `time`, in server-local time
+    pub fn daily_at(mut self, time: NaiveTime) -> Self {
+        self.daily_at = Some(time);
+        self
+    }
+
+    /// Stay in the channel until `signal` completes, failing if the connection is lost
+    pub async fn run_until<F: std::future::Future<Output = ()>>(
+        self,
+        mut quotes: Quotes,
+        signal: F,
+    ) -> anyhow::Result<()> {
+        let conn = TcpStream::connect(&self.address)
+            .await
+            .with_context(|| format!("Failed to connect to IRC server {}", self.address))?;
+        info!("Connected to IRC server {}", self.address);
+        let (reader, mut writer) = conn.into_split();
+        let mut lines = BufReader::new(reader).lines();
+
+        let mut nick = self.nick.clone();
+        if let Some(password) = &self.password {
+            send(&mut writer, &format!("PASS {password}")).await?;
+        }
+        send(&mut writer, &format!("NICK {nick}")).await?;
+        send(&mut writer, &format!("USER {nick} 0 * :qotd-rs")).await?;
+
+        tokio::pin!(signal);
+        loop {
+            let until_daily = self.daily_at.map(until).unwrap_or(Duration::MAX);
+            tokio::select! {
+                line = lines.next_line() => {
+                    let line = line
+                        .context("Failed to read from IRC server")?
+                        .context("IRC server closed the connection")?;
+                    debug!("IRC: {line}");
+                    let message = Message::parse(&line);
+                    match message.command {
+                        "PING" => {
+                            send(&mut writer, &format!("PONG :{}", message.trailing())).await?;
+                        }
+                        // Welcome, i.e. registered
+                        "001" => {
+                            send(&mut writer, &format!("JOIN {}", self.channel)).await?;
+                            info!("Joining IRC channel {}", self.channel);
+                        }
+                        // Nickname in use
+                        "433" => {
+                            nick.push('_');
+                            send(&mut writer, &format!("NICK {nick}")).await?;
+                        }
+                        "PRIVMSG" if message.trailing().trim() == self.command => {
+                            // Reply in the channel asked in, or privately to whoever asked privately
+                            let target = match message.params.first() {
+                                Some(target) if *target != nick => target.to_string(),
+                                _ => message.sender().to_string(),
+                            };
+                            info!("Quote requested in {target} by {}", message.sender());
+                            post(&mut writer, &mut quotes, &target).await?;
+                        }
+                        _ => {}
+                    }
+                }
+                _ = tokio::time::sleep(until_daily) => {
+                    info!("Posting daily quote to {}", self.channel);
+                    post(&mut writer, &mut quotes, &self.channel).await?;
+                }
+                _ = &mut signal => {
+                    let _ = send(&mut writer, "QUIT :Shutting down").await;
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+/// A message received from the server, e.g. `:nick!user@host PRIVMSG #channel :!qotd`
+#[derive(Debug)]
+struct Message<'a> {
+    prefix: Option<&'a str>,
+    command: &'a str,
+    /// Parameters, the last of which may have contained spaces
+    params: Vec<&'a str>,
+}
+
+impl<'a> Message<'a> {
+    fn parse(line: &'a str) -> Self {
+        let mut rest = line;
+        let prefix = rest.strip_prefix(':').map(|prefixed| {
+            let (prefix, after) = prefixed.split_once(' ').unwrap_or((prefixed, ""));
+            rest = after;
+            prefix
+        });
+        let (params, trailing) = match rest.split_once(" :") {
+            Some((params, trailing)) => (params, Some(trailing)),
+            None => (rest, None),
+        };
+        let mut params = params.split_whitespace();
+        let command = params.next().unwrap_or_default();
+        Self {
+            prefix,
+            command,
+            params: params.chain(trailing).collect(),
+        }
+    }
+
+    fn trailing(&self) -> &'a str {
+        self.params.last().copied().unwrap_or_default()
+    }
+
+    /// The nickname of the message's sender
+    fn sender(&self) -> &'a str {
+        let prefix = self.prefix.unwrap_or_default();
+        prefix.split_once('!').map_or(prefix, |(nick, _)| nick)
+    }
+}
+
+async fn send(writer: &mut OwnedWriteHalf, line: &str) -> anyhow::Result<()> {
+    writer
+        .write_all(format!("{line}\r\n").as_bytes())
+        .await
+        .context("Failed to write to IRC server")
+}
+
+/// Post a quote to `target`, a line at a time
+async fn post(
+    writer: &mut OwnedWriteHalf,
+    quotes: &mut Quotes,
+    target: &str,
+) -> anyhow::Result<()> {
+    let quote = quotes.random_quote().await?;
+    let text = quote.text_str().replace('\t', "    ");
+    let lines: Vec<&str> = text.trim_end().lines().collect();
+    if lines.len() > MAX_LINES {
+        warn!(
+            "Only posting the first {MAX_LINES} of {} lines of quote {}",
+            lines.len(),
+            quote.id
+        );
+    }
+    for line in lines.into_iter().take(MAX_LINES) {
+        let mut end = line.len().min(MAX_LINE_LENGTH);
+        while !line.is_char_boundary(end) {
+            end -= 1;
+        }
+        // Blank lines can't be sent, but a single space keeps the quote's paragraphs apart
+        let line = if line.trim().is_empty() {
+            " "
+        } else {
+            &line[..end]
+        };
+        send(writer, &format!("PRIVMSG {target} :{line}")).await?;
+        tokio::time::sleep(LINE_DELAY).await;
+    }
+    Ok(())
+}
+
+/// Time left until `time` next comes around, in server-local time
+fn until(time: NaiveTime) -> Duration {
+    let now = Local::now().naive_local();
+    let mut next = now.date().and_time(time);
+    if next <= now {
+        next += chrono::Duration::days(1);
+    }
+    (next - now).to_std().unwrap_or_default()
+}
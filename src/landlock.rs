@@ -0,0 +1,210 @@
+//! This module restricts the process's access to the filesystem with Landlock (Linux 5.13 and
+//! later), so that a compromised server can read no more than its quotes, and write no more than
+//! its own files
+//!
+//! Landlock only restricts the thread that enforces it and the threads it starts afterwards, so it
+//! should be enforced before any others are started, e.g. before an async runtime.
+#![cfg(target_os = "linux")]
+
+use std::{
+    io,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    path::{Path, PathBuf},
+};
+
+use nix::{fcntl::OFlag, sys::stat::Mode};
+use tracing::{debug, info};
+
+/// `landlock_create_ruleset` flag asking for the ABI version rather than creating a ruleset
+const CREATE_RULESET_VERSION: u32 = 1;
+/// `landlock_add_rule` rule type of a path and everything beneath it
+const RULE_PATH_BENEATH: libc::c_int = 1;
+
+// Filesystem access rights, see landlock(7); those never allowed, e.g. to make directories or
+// devices, are handled (and so denied) all the same
+const ACCESS_EXECUTE: u64 = 1 << 0;
+const ACCESS_WRITE_FILE: u64 = 1 << 1;
+const ACCESS_READ_FILE: u64 = 1 << 2;
+const ACCESS_READ_DIR: u64 = 1 << 3;
+const ACCESS_REMOVE_FILE: u64 = 1 << 5;
+const ACCESS_MAKE_REG: u64 = 1 << 8;
+const ACCESS_MAKE_SOCK: u64 = 1 << 9;
+/// Since ABI 2
+const ACCESS_REFER: u64 = 1 << 13;
+/// Since ABI 3
+const ACCESS_TRUNCATE: u64 = 1 << 14;
+/// Every right of ABI 1, i.e. the thirteen below refer
+const ACCESS_ABI_1: u64 = ACCESS_REFER - 1;
+/// Rights that apply to files, as opposed to directories
+const ACCESS_FILE: u64 = ACCESS_EXECUTE | ACCESS_WRITE_FILE | ACCESS_READ_FILE | ACCESS_TRUNCATE;
+const ACCESS_READ: u64 = ACCESS_READ_FILE | ACCESS_READ_DIR;
+const ACCESS_WRITE: u64 = ACCESS_READ
+    | ACCESS_WRITE_FILE
+    | ACCESS_TRUNCATE
+    | ACCESS_REMOVE_FILE
+    | ACCESS_MAKE_REG
+    | ACCESS_MAKE_SOCK;
+
+#[repr(C)]
+struct RulesetAttr {
+    handled_access_fs: u64,
+}
+
+#[repr(C, packed)]
+struct PathBeneathAttr {
+    allowed_access: u64,
+    parent_fd: i32,
+}
+
+/// How far the filesystem was restricted, see [`Landlock::enforce`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LandlockStatus {
+    /// Access is restricted, by the given version of the Landlock ABI
+    Enforced(u32),
+    /// The kernel doesn't support Landlock (or it's disabled), so access is unrestricted
+    Unsupported,
+}
+
+/// Restricts the process to reading and writing the given paths, see [`Landlock::enforce`]
+#[derive(Debug, Clone, Default)]
+pub struct Landlock {
+    read: Vec<PathBuf>,
+    write: Vec<PathBuf>,
+}
+
+impl Landlock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allow reading `path`, and if it's a directory everything beneath it
+    pub fn allow_read<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.read.push(path.into());
+        self
+    }
+
+    /// Allow reading and writing `path`, and if it's a directory creating and removing files (and
+    /// sockets) beneath it
+    ///
+    /// Files replaced by renaming a temporary file over them, e.g. state files, need their
+    /// directory to be writable.
+    pub fn allow_write<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.write.push(path.into());
+        self
+    }
+
+    /// Restrict this thread, and threads it starts from now on, to the allowed paths, for good
+    ///
+    /// Paths that don't exist are skipped. Executing programs is denied, as are the other rights of
+    /// the kernel's Landlock ABI that aren't allowed; rights added by later ABIs are left
+    /// unrestricted. Also sets `PR_SET_NO_NEW_PRIVS`, so that programs executed anyway can't
+    /// regain privileges.
+    pub fn enforce(&self) -> io::Result<LandlockStatus> {
+        // SAFETY: Asking for the ABI version takes no attributes
+        let abi = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                0,
+                0,
+                CREATE_RULESET_VERSION,
+            )
+        };
+        if abi < 0 {
+            let e = io::Error::last_os_error();
+            return match e.raw_os_error() {
+                Some(libc::ENOSYS | libc::EOPNOTSUPP) => Ok(LandlockStatus::Unsupported),
+                _ => Err(e),
+            };
+        }
+        let abi = abi as u32;
+        let mut handled = ACCESS_ABI_1;
+        if abi >= 2 {
+            handled |= ACCESS_REFER;
+        }
+        if abi >= 3 {
+            handled |= ACCESS_TRUNCATE;
+        }
+
+        let attr = RulesetAttr {
+            handled_access_fs: handled,
+        };
+        // SAFETY: `attr` is a valid ruleset attribute of the size given
+        let ruleset = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                &attr as *const RulesetAttr,
+                std::mem::size_of::<RulesetAttr>(),
+                0,
+            )
+        };
+        if ruleset < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: The syscall returned a new fd, which nothing else owns
+        let ruleset = unsafe { OwnedFd::from_raw_fd(ruleset as i32) };
+
+        let rules = self
+            .read
+            .iter()
+            .map(|path| (path, ACCESS_READ))
+            .chain(self.write.iter().map(|path| (path, ACCESS_WRITE)));
+        for (path, access) in rules {
+            add_rule(&ruleset, path, access & handled)?;
+        }
+
+        // SAFETY: Setting no_new_privs takes no pointers
+        if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: `ruleset` is a valid ruleset fd
+        if unsafe { libc::syscall(libc::SYS_landlock_restrict_self, ruleset.as_raw_fd(), 0) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        info!("Restricted filesystem access with Landlock ABI {abi}");
+        Ok(LandlockStatus::Enforced(abi))
+    }
+}
+
+/// Allow `access` beneath `path`, if it exists
+fn add_rule(ruleset: &OwnedFd, path: &Path, mut access: u64) -> io::Result<()> {
+    let fd = match nix::fcntl::open(path, OFlag::O_PATH | OFlag::O_CLOEXEC, Mode::empty()) {
+        Ok(fd) => fd,
+        Err(nix::errno::Errno::ENOENT) => {
+            debug!(
+                "Not allowing access to {}, which doesn't exist",
+                path.display()
+            );
+            return Ok(());
+        }
+        Err(e) => return Err(e.into()),
+    };
+    // SAFETY: `open` returned a new fd, which nothing else owns
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+    // Directory rights can't be granted on anything else
+    if !path.is_dir() {
+        access &= ACCESS_FILE;
+    }
+    let attr = PathBeneathAttr {
+        allowed_access: access,
+        parent_fd: fd.as_raw_fd(),
+    };
+    // SAFETY: `attr` is a valid rule of the given type, whose fd outlives the call
+    let result = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_add_rule,
+            ruleset.as_raw_fd(),
+            RULE_PATH_BENEATH,
+            &attr as *const PathBeneathAttr,
+            0,
+        )
+    };
+    if result != 0 {
+        let e = io::Error::last_os_error();
+        return Err(io::Error::new(
+            e.kind(),
+            format!("Failed to allow access to {}: {e}", path.display()),
+        ));
+    }
+    debug!("Allowed access to {}", path.display());
+    Ok(())
+}
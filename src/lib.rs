@@ -1,16 +1,102 @@
 //! Core library for qotd-rs
 
+#[cfg(any(feature = "tcp", feature = "udp"))]
 use std::path::Path;
 
+#[cfg(any(feature = "tcp", feature = "udp"))]
+use tokio::net::ToSocketAddrs;
+
+mod access;
+pub use access::*;
+mod admin;
 mod args;
 #[cfg(feature = "cli")]
 pub use args::*;
+mod audit;
+mod blocking;
+mod buffer;
+#[cfg(all(feature = "blocking", any(feature = "tcp", feature = "udp")))]
+pub use blocking::*;
+mod capsicum;
+#[cfg(target_os = "freebsd")]
+pub use capsicum::enter_capability_mode;
+mod charset;
+pub use charset::*;
+mod dashboard;
+mod export;
+#[cfg(feature = "export")]
+pub use export::*;
+mod fetch;
+#[cfg(feature = "fetch")]
+pub use fetch::*;
+mod ffi;
+#[cfg(feature = "ffi")]
+pub use ffi::*;
+mod format;
+pub use format::*;
+mod http;
+mod import;
+#[cfg(feature = "import")]
+pub use import::*;
+mod irc;
+#[cfg(feature = "irc")]
+pub use irc::*;
+mod landlock;
+#[cfg(target_os = "linux")]
+pub use landlock::*;
+mod mapped;
+mod motd;
+pub use motd::*;
+mod mqtt;
+#[cfg(feature = "mqtt")]
+pub use mqtt::*;
+mod pin;
+mod privsep;
+#[cfg(all(unix, any(feature = "tcp", feature = "udp")))]
+pub use privsep::{confine, is_supervised};
 mod quotes;
 pub use quotes::*;
+mod registry;
+#[cfg(feature = "registry")]
+pub use registry::*;
+mod schedule;
+pub use schedule::*;
 mod server;
+#[cfg(any(feature = "tcp", feature = "udp"))]
 pub use server::*;
-use tokio::net::ToSocketAddrs;
+mod simple;
+#[cfg(any(feature = "tcp", feature = "udp"))]
+pub use simple::SimpleService;
+mod socket;
+#[cfg(any(feature = "tcp", feature = "udp"))]
+pub use socket::{Keepalive, DEFAULT_BACKLOG};
+mod stats;
+pub use stats::*;
+mod statsd;
+#[cfg(feature = "statsd")]
+pub use statsd::*;
+mod telnet;
+mod testing;
+#[cfg(feature = "test-util")]
+pub use testing::*;
+mod tls;
+#[cfg(feature = "tls")]
+pub use tls::*;
+mod udp_batch;
+mod upgrade;
+mod web;
+#[cfg(any(feature = "tcp", feature = "udp"))]
+pub use web::{DEFAULT_CALENDAR_LENGTH, DEFAULT_FEED_LENGTH};
+mod webhook;
+#[cfg(feature = "webhook")]
+pub use webhook::*;
+
+#[cfg(not(any(feature = "tcp", feature = "udp", feature = "blocking")))]
+compile_error!(
+    "At least one of the \"tcp\" and \"udp\" features must be enabled, or \"blocking\" for quotes alone"
+);
 
+#[cfg(any(feature = "tcp", feature = "udp"))]
 pub async fn serve_dir<
     A: ToSocketAddrs + std::fmt::Debug,
     P: AsRef<Path> + Send + std::fmt::Debug + 'static,
@@ -18,7 +104,7 @@ pub async fn serve_dir<
     addr: A,
     dir: P,
 ) -> anyhow::Result<()> {
-    let quotes = Quotes::from_dir(dir, &[QuoteCategory::Decorous]).await?;
+    let quotes = Quotes::from_dir(dir, &[QuoteCategory::DECOROUS]).await?;
     Server::new()
         .bind(addr)
         .await?
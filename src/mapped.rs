@@ -0,0 +1,63 @@
+//! This module maps quote files into memory, so that quotes are read by the kernel paging them in
+//! rather than by a syscall each, see [`StorageMode::Mmap`](crate::StorageMode::Mmap)
+
+use std::{ffi::c_void, fs::File, io, num::NonZeroUsize, ops::Deref, ptr::NonNull};
+
+use nix::sys::mman::{mmap, munmap, MapFlags, ProtFlags};
+
+/// A read-only, private mapping of a whole file
+#[derive(Debug)]
+pub(crate) struct Mapping {
+    ptr: NonNull<c_void>,
+    len: usize,
+}
+
+// SAFETY: The mapping is never written to, and is unmapped only when dropped, so it may be read
+// from any thread
+unsafe impl Send for Mapping {}
+// SAFETY: As above
+unsafe impl Sync for Mapping {}
+
+impl Mapping {
+    /// Map the whole of `file`, which mustn't be empty
+    pub fn of(file: &File) -> io::Result<Self> {
+        let len = usize::try_from(file.metadata()?.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "File too large to map"))?;
+        let length = NonZeroUsize::new(len)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Cannot map empty file"))?;
+        // SAFETY: A new private mapping of an open file aliases no memory of ours. The file being
+        // truncated while mapped would fault reads beyond its new end; quote files are checked for
+        // changes before each read (see `QuoteFile::is_changed`), which narrows but can't close that
+        // window, as the documentation of `StorageMode::Mmap` warns
+        let ptr = unsafe {
+            mmap(
+                None,
+                length,
+                ProtFlags::PROT_READ,
+                MapFlags::MAP_PRIVATE,
+                file,
+                0,
+            )
+        }?;
+        Ok(Self { ptr, len })
+    }
+}
+
+impl Deref for Mapping {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // SAFETY: The mapping is `len` readable bytes, and lives as long as `self`
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr().cast(), self.len) }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        // SAFETY: The mapping was made by `Mapping::of` with this length, and no slices of it
+        // outlive `self`
+        if let Err(e) = unsafe { munmap(self.ptr, self.len) } {
+            tracing::warn!("Failed to unmap quote file: {e}");
+        }
+    }
+}
@@ -0,0 +1,117 @@
+//! This module keeps a file holding a quote, e.g. an SSH banner or a pam_motd fragment, replaced
+//! with another quote on every rotation
+//!
+//! Files are replaced atomically (written in full alongside, then renamed into place), so readers
+//! never see a partially written quote.
+
+use std::{
+    fs::{self, File},
+    future::Future,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use chrono::Local;
+use tracing::{info, warn};
+
+use crate::{
+    format::{Formatter, Transport},
+    Decoration, QuoteId, Quotes, Rotation, Template,
+};
+
+/// Writes a quote to a file on every rotation
+#[derive(Debug, Clone)]
+pub struct MotdWriter {
+    path: PathBuf,
+    rotation: Rotation,
+    formatter: Formatter,
+}
+
+impl MotdWriter {
+    pub fn new<P: Into<PathBuf>>(path: P, rotation: Rotation) -> Self {
+        Self {
+            path: path.into(),
+            rotation,
+            formatter: Formatter::default(),
+        }
+    }
+
+    /// Reflow quotes to fit within `width` columns (see [`crate::rewrap`])
+    pub fn wrap(mut self, width: usize) -> Self {
+        self.formatter.wrap = Some(width);
+        self
+    }
+
+    /// Frame every quote in `decoration` before writing it (and before applying any template)
+    pub fn decorate(mut self, decoration: Decoration) -> Self {
+        self.formatter.decoration = Some(decoration);
+        self
+    }
+
+    /// Wrap every quote in `template` before writing it
+    pub fn template(mut self, template: Template) -> Self {
+        self.formatter.template = Some(template);
+        self
+    }
+
+    /// Replace the file with the current period's quote (see [`Quotes::rotating_quote`]),
+    /// returning its ID
+    pub async fn write(&self, quotes: &mut Quotes) -> io::Result<QuoteId> {
+        let quote = quotes.rotating_quote(&self.rotation, Local::now()).await?;
+        let quote = self.formatter.format(quote, Transport::Udp);
+        replace(&self.path, &quote.text)?;
+        info!("Wrote quote {} to \"{}\"", quote.id, self.path.display());
+        Ok(quote.id)
+    }
+
+    /// Write a quote at once and then on every rotation, until `signal` completes
+    ///
+    /// Failing to write a quote is only fatal the first time, as e.g. a full disk may well recover.
+    pub async fn run_until<F: Future<Output = ()>>(
+        self,
+        mut quotes: Quotes,
+        signal: F,
+    ) -> io::Result<()> {
+        self.write(&mut quotes).await?;
+        tokio::pin!(signal);
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(self.rotation.until_next()) => {
+                    if let Err(e) = self.write(&mut quotes).await {
+                        warn!("Failed to write quote to \"{}\": {e}", self.path.display());
+                    }
+                }
+                _ = &mut signal => return Ok(()),
+            }
+        }
+    }
+}
+
+/// Atomically replace the file at `path` with `contents`, keeping its permissions
+pub(crate) fn replace(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Not a file path"))?;
+    // The temporary file must be on the same filesystem for the rename to be atomic
+    let mut temp_name = std::ffi::OsString::from(".");
+    temp_name.push(name);
+    temp_name.push(".tmp");
+    let temp_path = path.with_file_name(temp_name);
+
+    let result = (|| {
+        let mut temp = File::create(&temp_path)?;
+        temp.write_all(contents)?;
+        if !contents.ends_with(b"\n") {
+            temp.write_all(b"\n")?;
+        }
+        if let Ok(metadata) = fs::metadata(path) {
+            temp.set_permissions(metadata.permissions())?;
+        }
+        temp.sync_all()?;
+        fs::rename(&temp_path, path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
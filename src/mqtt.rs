@@ -0,0 +1,228 @@
+//! This module publishes a quote to an MQTT broker on every rotation, e.g. for home automation
+//! dashboards
+//!
+//! Just enough of MQTT 3.1.1 is spoken to publish: a connection is made for each quote, and closed
+//! once the broker has acknowledged it as the QoS requires. Only plain-text connections are
+//! supported.
+#![cfg(feature = "mqtt")]
+
+use std::time::Duration;
+
+use anyhow::Context;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    sync::broadcast::{self, error::RecvError},
+};
+use tracing::{info, warn};
+
+use crate::Quote;
+
+/// How long the broker may take to respond
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Keep-alive interval declared on connecting, in seconds; never reached, as connections are brief
+const KEEP_ALIVE: u16 = 60;
+/// Packet identifier of the (only) message published on each connection
+const PACKET_ID: u16 = 1;
+
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const PUBACK: u8 = 0x40;
+const PUBREC: u8 = 0x50;
+/// PUBREL has reserved flags that must be set
+const PUBREL: u8 = 0x62;
+const PUBCOMP: u8 = 0x70;
+const DISCONNECT: u8 = 0xE0;
+
+/// Delivery guarantees for published quotes
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Qos {
+    /// At most once
+    #[default]
+    #[cfg_attr(feature = "cli", value(name = "0"))]
+    AtMostOnce = 0,
+    /// At least once
+    #[cfg_attr(feature = "cli", value(name = "1"))]
+    AtLeastOnce = 1,
+    /// Exactly once
+    #[cfg_attr(feature = "cli", value(name = "2"))]
+    ExactlyOnce = 2,
+}
+
+/// Publishes a quote to an MQTT topic on every rotation (see
+/// [`Server::rotate`](crate::Server::rotate))
+#[derive(Debug, Clone)]
+pub struct MqttPublisher {
+    broker: String,
+    topic: String,
+    qos: Qos,
+    retain: bool,
+    client_id: String,
+    credentials: Option<(String, String)>,
+}
+
+impl MqttPublisher {
+    /// Publish to `topic` on the broker at `broker` (e.g. "localhost:1883")
+    pub fn new<B: Into<String>, T: Into<String>>(broker: B, topic: T) -> Self {
+        Self {
+            broker: broker.into(),
+            topic: topic.into(),
+            qos: Qos::default(),
+            retain: false,
+            client_id: "qotd".to_string(),
+            credentials: None,
+        }
+    }
+
+    pub fn qos(mut self, qos: Qos) -> Self {
+        self.qos = qos;
+        self
+    }
+
+    /// Have the broker retain the last quote, so that new subscribers get it at once
+    pub fn retain(mut self, retain: bool) -> Self {
+        self.retain = retain;
+        self
+    }
+
+    pub fn client_id<S: Into<String>>(mut self, client_id: S) -> Self {
+        self.client_id = client_id.into();
+        self
+    }
+
+    pub fn credentials<U: Into<String>, P: Into<String>>(
+        mut self,
+        username: U,
+        password: P,
+    ) -> Self {
+        self.credentials = Some((username.into(), password.into()));
+        self
+    }
+
+    /// Publish each quote rotated to, until rotation ends
+    ///
+    /// Failing to publish is never fatal, as the broker may well be back by the next rotation.
+    pub(crate) async fn run(self, mut rotations: broadcast::Receiver<Quote>) -> anyhow::Result<()> {
+        loop {
+            let quote = match rotations.recv().await {
+                Ok(quote) => quote,
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return Ok(()),
+            };
+            if let Err(e) = self.publish(&quote).await {
+                warn!(
+                    "Failed to publish quote to MQTT broker {}: {e:?}",
+                    self.broker
+                );
+            }
+        }
+    }
+
+    async fn publish(&self, quote: &Quote) -> anyhow::Result<()> {
+        tokio::time::timeout(RESPONSE_TIMEOUT, self.send(&quote.text))
+            .await
+            .context("MQTT broker timed out")??;
+        info!("Published quote {} to MQTT topic {}", quote.id, self.topic);
+        Ok(())
+    }
+
+    async fn send(&self, payload: &[u8]) -> anyhow::Result<()> {
+        let mut conn = TcpStream::connect(&self.broker)
+            .await
+            .context("Failed to connect")?;
+
+        let mut connect = Vec::new();
+        put_str(&mut connect, "MQTT");
+        // Protocol level 4, i.e. 3.1.1
+        connect.push(4);
+        // Clean session, plus whether credentials follow
+        connect.push(if self.credentials.is_some() {
+            0xC2
+        } else {
+            0x02
+        });
+        connect.extend_from_slice(&KEEP_ALIVE.to_be_bytes());
+        put_str(&mut connect, &self.client_id);
+        if let Some((username, password)) = &self.credentials {
+            put_str(&mut connect, username);
+            put_str(&mut connect, password);
+        }
+        write_packet(&mut conn, CONNECT, &connect).await?;
+        let connack = read_packet(&mut conn, CONNACK).await?;
+        match connack.get(1) {
+            Some(0) => {}
+            Some(code) => anyhow::bail!("Connection refused with return code {code}"),
+            None => anyhow::bail!("Malformed CONNACK"),
+        }
+
+        let mut publish = Vec::with_capacity(self.topic.len() + payload.len() + 4);
+        put_str(&mut publish, &self.topic);
+        if self.qos != Qos::AtMostOnce {
+            publish.extend_from_slice(&PACKET_ID.to_be_bytes());
+        }
+        publish.extend_from_slice(payload);
+        let flags = (self.qos as u8) << 1 | u8::from(self.retain);
+        write_packet(&mut conn, PUBLISH | flags, &publish).await?;
+        match self.qos {
+            Qos::AtMostOnce => {}
+            Qos::AtLeastOnce => {
+                read_packet(&mut conn, PUBACK).await?;
+            }
+            Qos::ExactlyOnce => {
+                read_packet(&mut conn, PUBREC).await?;
+                write_packet(&mut conn, PUBREL, &PACKET_ID.to_be_bytes()).await?;
+                read_packet(&mut conn, PUBCOMP).await?;
+            }
+        }
+
+        write_packet(&mut conn, DISCONNECT, &[]).await?;
+        conn.shutdown().await?;
+        Ok(())
+    }
+}
+
+/// Append a length-prefixed UTF-8 string
+fn put_str(buf: &mut Vec<u8>, s: &str) {
+    buf.extend_from_slice(&(s.len() as u16).to_be_bytes());
+    buf.extend_from_slice(s.as_bytes());
+}
+
+async fn write_packet(conn: &mut TcpStream, header: u8, body: &[u8]) -> anyhow::Result<()> {
+    let mut packet = Vec::with_capacity(body.len() + 5);
+    packet.push(header);
+    // The remaining length, 7 bits at a time with the top bit marking continuation
+    let mut length = body.len();
+    loop {
+        let byte = (length % 128) as u8;
+        length /= 128;
+        if length == 0 {
+            packet.push(byte);
+            break;
+        }
+        packet.push(byte | 0x80);
+    }
+    packet.extend_from_slice(body);
+    conn.write_all(&packet).await?;
+    Ok(())
+}
+
+/// Read a packet, failing unless it's of the expected type
+async fn read_packet(conn: &mut TcpStream, expected: u8) -> anyhow::Result<Vec<u8>> {
+    let header = conn.read_u8().await?;
+    let mut length = 0_usize;
+    for shift in (0..4).map(|i| i * 7) {
+        let byte = conn.read_u8().await?;
+        length |= usize::from(byte & 0x7F) << shift;
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+    let mut body = vec![0; length];
+    conn.read_exact(&mut body).await?;
+    if header & 0xF0 != expected & 0xF0 {
+        anyhow::bail!("Expected packet type {expected:#04x}, got {header:#04x}");
+    }
+    Ok(body)
+}
@@ -0,0 +1,163 @@
+//! This module pins a quote, e.g. an announcement or a memorial, to be served to everyone in place
+//! of those otherwise chosen, until the next rotation
+//!
+//! A quote is pinned by writing it to the override file (see [`Server::override_file`]), or through
+//! the admin endpoint. It expires once the rotation period it was pinned in is over, at which point
+//! the override file is removed.
+//!
+//! [`Server::override_file`]: crate::Server::override_file
+#![cfg(any(feature = "tcp", feature = "udp"))]
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use chrono::{DateTime, Local, NaiveDateTime};
+use tracing::{info, warn};
+
+use crate::{Quote, Rotation};
+
+/// Name pinned quotes are served as coming from
+const PINNED_FILE_NAME: &str = "override";
+
+/// The pinned quote, if any
+#[derive(Debug)]
+pub(crate) struct Pin {
+    /// File quotes are pinned by, if any; otherwise quotes are only pinned in memory
+    path: Option<PathBuf>,
+    rotation: Rotation,
+    pinned: Option<Pinned>,
+}
+
+#[derive(Debug)]
+struct Pinned {
+    quote: Quote,
+    /// Start of the rotation period the quote was pinned in
+    period: NaiveDateTime,
+    /// When the override file was last modified, if the quote was read from it
+    modified: Option<SystemTime>,
+}
+
+impl Pin {
+    pub fn new(path: Option<PathBuf>, rotation: Rotation) -> Self {
+        Self {
+            path,
+            rotation,
+            pinned: None,
+        }
+    }
+
+    /// The quote pinned at `now`, unpinning it if it has expired
+    pub fn quote(&mut self, now: DateTime<Local>) -> Option<Quote> {
+        self.refresh();
+        let pinned = self.pinned.as_ref()?;
+        if pinned.period < self.rotation.period_start_at(now) {
+            info!("Pinned quote {} expired", pinned.quote.id);
+            if let Err(e) = self.unpin() {
+                warn!("Failed to remove expired override file: {e}");
+            }
+            return None;
+        }
+        Some(pinned.quote.clone())
+    }
+
+    /// Pin `text` until the next rotation, writing it to the override file if there is one
+    pub fn pin(&mut self, text: &str, now: DateTime<Local>) -> io::Result<Quote> {
+        let text = text.trim();
+        if text.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Quote is empty",
+            ));
+        }
+        match &self.path {
+            Some(path) => {
+                crate::motd::replace(path, text.as_bytes())?;
+                self.pinned = None;
+                self.refresh();
+            }
+            None => {
+                self.pinned = Some(Pinned {
+                    quote: Self::pinned_quote(text.as_bytes()),
+                    period: self.rotation.period_start_at(now),
+                    modified: None,
+                });
+            }
+        }
+        let quote = self
+            .pinned
+            .as_ref()
+            .map(|pinned| pinned.quote.clone())
+            .ok_or_else(|| io::Error::other("Override file could not be read back"))?;
+        info!("Pinned quote {} ({})", quote.id, quote.id.hash_hex());
+        Ok(quote)
+    }
+
+    /// Unpin the pinned quote, removing the override file; returns whether one was pinned
+    pub fn unpin(&mut self) -> io::Result<bool> {
+        self.refresh();
+        let was_pinned = self.pinned.take().is_some();
+        if let Some(path) = &self.path {
+            match fs::remove_file(path) {
+                Ok(()) => info!("Removed override file \"{}\"", path.display()),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(was_pinned)
+    }
+
+    /// Re-read the override file if it changed since it was last read
+    fn refresh(&mut self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let modified = match fs::metadata(path).and_then(|metadata| metadata.modified()) {
+            Ok(modified) => modified,
+            Err(e) => {
+                if e.kind() != io::ErrorKind::NotFound {
+                    warn!("Failed to check override file \"{}\": {e}", path.display());
+                }
+                self.pinned = None;
+                return;
+            }
+        };
+        if self
+            .pinned
+            .as_ref()
+            .is_some_and(|pinned| pinned.modified == Some(modified))
+        {
+            return;
+        }
+        self.pinned = Self::read(path).map(|quote| {
+            info!("Pinned quote {} from \"{}\"", quote.id, path.display());
+            Pinned {
+                quote,
+                period: self.rotation.period_start_at(modified.into()),
+                modified: Some(modified),
+            }
+        });
+    }
+
+    /// Read the quote in the override file, if it has one
+    fn read(path: &Path) -> Option<Quote> {
+        match fs::read(path) {
+            Ok(text) if !text.trim_ascii().is_empty() => {
+                Some(Self::pinned_quote(text.trim_ascii()))
+            }
+            Ok(_) => None,
+            Err(e) => {
+                warn!("Failed to read override file \"{}\": {e}", path.display());
+                None
+            }
+        }
+    }
+
+    fn pinned_quote(text: &[u8]) -> Quote {
+        let mut text = text.to_vec();
+        text.push(b'\n');
+        Quote::standalone(PINNED_FILE_NAME, text)
+    }
+}
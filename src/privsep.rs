@@ -0,0 +1,175 @@
+//! This module separates the server's privileges: a small supervisor keeps those it was started
+//! with only to bind the sockets, while quotes are served by an unprivileged child, which is
+//! restarted should it crash
+//!
+//! The child is this program run again with the same arguments, and handed the sockets as an
+//! upgraded server is (see [`Server::graceful_upgrade`](crate::Server::graceful_upgrade)). It
+//! confines itself (see [`confine`]) before reading anything else, e.g. quotes, so a compromised
+//! child can neither regain privileges nor reach outside its root. The supervisor forwards SIGUSR1
+//! to the child, and on SIGUSR2 replaces it with a new one, once that is serving.
+#![cfg(all(unix, any(feature = "tcp", feature = "udp")))]
+
+use std::{
+    io,
+    path::Path,
+    process::{Child, ExitStatus},
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use nix::{
+    sys::signal::{kill, Signal},
+    unistd::{chdir, chroot, setgid, setuid, Pid, Uid, User},
+};
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    task::JoinHandle,
+};
+use tracing::{error, info, warn};
+
+use crate::upgrade::FdPair;
+
+/// Environment variable set for children of a supervisor
+const SUPERVISED_VAR: &str = "QOTD_SUPERVISED";
+/// How long to wait before restarting a crashed child at first
+const MIN_RESTART_DELAY: Duration = Duration::from_secs(1);
+/// Longest to wait before restarting a child that keeps crashing
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(60);
+/// How long a child must have served for to be restarted without delay should it crash
+const STABLE_UPTIME: Duration = Duration::from_secs(60);
+
+/// Whether this process is the unprivileged child of a supervisor (see [`Server::supervise`])
+///
+/// [`Server::supervise`]: crate::Server::supervise
+pub fn is_supervised() -> bool {
+    std::env::var_os(SUPERVISED_VAR).is_some()
+}
+
+/// Confine this process to `root`, if given, then drop privileges to those of `user`
+///
+/// Unlike [`Server::drop_privileges`](crate::Server::drop_privileges), failing to drop privileges
+/// is an error. Paths are resolved within `root` from then on.
+pub fn confine<P: AsRef<Path>>(user: &str, root: Option<P>) -> anyhow::Result<()> {
+    // The user must be looked up before the user database is out of reach
+    let user = User::from_name(user)
+        .with_context(|| format!("Failed to get user: {user}"))?
+        .with_context(|| format!("No such user: {user}"))?;
+    // As must the groups they're in
+    init_groups(&user)?;
+    if let Some(root) = root {
+        let root = root.as_ref();
+        chroot(root).with_context(|| format!("Failed to chroot to {}", root.display()))?;
+        chdir("/").context("Failed to change to new root")?;
+        info!("Confined to {}", root.display());
+    }
+    // Must drop gid first: dropping uid first robs us of our permissions to change our gid!
+    setgid(user.gid).with_context(|| format!("Failed to set gid: {}", user.gid))?;
+    setuid(user.uid).with_context(|| format!("Failed to set uid: {}", user.uid))?;
+    if !user.uid.is_root() && setuid(Uid::from_raw(0)).is_ok() {
+        anyhow::bail!("Privileges were regained after dropping them");
+    }
+    info!("Dropped privileges to {}", user.name);
+    Ok(())
+}
+
+/// Set the supplementary groups to those `user` is in, which would otherwise be kept from whoever
+/// started us, e.g. root's
+///
+/// This is a no-op where supplementary groups can't be initialized, e.g. on macOS.
+pub(crate) fn init_groups(user: &User) -> anyhow::Result<()> {
+    #[cfg(not(any(target_vendor = "apple", target_os = "redox", target_os = "haiku")))]
+    {
+        let name = std::ffi::CString::new(user.name.as_str())?;
+        nix::unistd::initgroups(&name, user.gid)
+            .with_context(|| format!("Failed to set supplementary groups of {}", user.name))?;
+    }
+    #[cfg(any(target_vendor = "apple", target_os = "redox", target_os = "haiku"))]
+    let _ = user;
+    Ok(())
+}
+
+/// Serve from unprivileged children with `sockets`, restarting them should they crash, until
+/// SIGTERM or SIGINT
+pub(crate) async fn supervise(sockets: Vec<FdPair>) -> anyhow::Result<()> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let mut report = signal(SignalKind::user_defined1())?;
+    let mut upgrade = signal(SignalKind::user_defined2())?;
+
+    let mut child = Supervised::spawn(&sockets)
+        .await
+        .context("Failed to start server")?;
+    let mut delay = MIN_RESTART_DELAY;
+    loop {
+        tokio::select! {
+            status = &mut child.exited => {
+                let status = status??;
+                if status.success() {
+                    info!("Server exited");
+                    return Ok(());
+                }
+                if child.started.elapsed() >= STABLE_UPTIME {
+                    delay = MIN_RESTART_DELAY;
+                }
+                error!("Server {status}; restarting it in {delay:?}");
+                child = loop {
+                    tokio::time::sleep(delay).await;
+                    delay = (delay * 2).min(MAX_RESTART_DELAY);
+                    match Supervised::spawn(&sockets).await {
+                        Ok(child) => break child,
+                        Err(e) => error!("Failed to restart server, retrying in {delay:?}: {e}"),
+                    }
+                };
+            }
+            _ = terminate.recv() => return child.stop().await,
+            _ = interrupt.recv() => return child.stop().await,
+            _ = report.recv() => child.signal(Signal::SIGUSR1),
+            _ = upgrade.recv() => {
+                info!("Upgrade requested");
+                match Supervised::spawn(&sockets).await {
+                    Ok(successor) => {
+                        let predecessor = std::mem::replace(&mut child, successor);
+                        tokio::spawn(predecessor.stop());
+                    }
+                    Err(e) => error!("Upgrade failed, continuing to serve: {e}"),
+                }
+            }
+        }
+    }
+}
+
+/// A child serving quotes
+struct Supervised {
+    pid: Pid,
+    started: Instant,
+    exited: JoinHandle<io::Result<ExitStatus>>,
+}
+
+impl Supervised {
+    /// Spawn a child, returning once it's serving
+    async fn spawn(sockets: &[FdPair]) -> io::Result<Self> {
+        let mut child: Child =
+            crate::upgrade::spawn_serving(sockets, &[(SUPERVISED_VAR, "1")]).await?;
+        let pid = Pid::from_raw(child.id() as i32);
+        info!("Serving from process {pid}");
+        Ok(Self {
+            pid,
+            started: Instant::now(),
+            exited: tokio::task::spawn_blocking(move || child.wait()),
+        })
+    }
+
+    fn signal(&self, signal: Signal) {
+        if let Err(e) = kill(self.pid, signal) {
+            warn!("Failed to send {signal} to process {}: {e}", self.pid);
+        }
+    }
+
+    /// Have the child finish the requests in flight and exit, and wait until it has
+    async fn stop(self) -> anyhow::Result<()> {
+        self.signal(Signal::SIGTERM);
+        let status = self.exited.await??;
+        info!("Process {} {status}", self.pid);
+        Ok(())
+    }
+}
@@ -1,198 +1,3263 @@
 //! This module is responsible for parsing quote files
+//!
+//! Quote files are read with blocking I/O, so that quotes can be used with any async runtime (or
+//! none). Reads are small and local, and files are only read in full when they're indexed.
 
-use std::path::Path;
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    fs::{read_dir, File},
+    io::{self, BufRead, BufReader, Read, Seek, Write},
+    net::IpAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, Mutex, OnceLock},
+    time::{Duration, Instant, SystemTime},
+};
 
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, NaiveTime};
 use futures::{future::BoxFuture, FutureExt};
-use rand::{thread_rng, Rng};
-use rand_distr::{Distribution, WeightedAliasIndex};
-use tokio::{
-    fs::{read_dir, File},
-    io::{self, AsyncBufReadExt, AsyncReadExt, AsyncSeekExt, BufReader},
+use rand::{
+    distributions::WeightedIndex,
+    rngs::StdRng,
+    seq::{IteratorRandom, SliceRandom},
+    Rng, RngCore, SeedableRng,
 };
-use tracing::{info, instrument};
+use rand_distr::{Distribution, WeightedAliasIndex};
+use regex::Regex;
+use tokio::task::spawn_blocking;
+use tracing::{debug, info, instrument, warn};
+
+use crate::{buffer, mapped::Mapping, CategorySchedule, Charset, DateWindow, Rotation, Validity};
+
+/// A named category of quotes, e.g. "decorous" or "offensive"
+///
+/// Any other name may be used as well, simply by declaring it (e.g. in a file's front-matter, or
+/// with [`IndexOptions::categories`]), so adding a category never changes this type. Categories
+/// are interned IDs, cheap to copy and compare however many quotes they're attached to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct QuoteCategory(u32);
+
+/// Names of the categories named so far, and their IDs
+///
+/// Each name is leaked the first time it's seen, and never again, so this only grows with the
+/// number of distinct categories named, not with the number of times they're named.
+#[derive(Default)]
+struct CategoryNames {
+    ids: HashMap<&'static str, u32>,
+    names: Vec<&'static str>,
+}
+
+static CATEGORY_NAMES: OnceLock<Mutex<CategoryNames>> = OnceLock::new();
+
+impl QuoteCategory {
+    /// Quotes inoffensive enough to serve to anyone, which is where files are put by default
+    pub const DECOROUS: Self = Self(0);
+    /// Quotes only served when asked for, as files named with the usual `-o` suffix are
+    pub const OFFENSIVE: Self = Self(1);
+    const BUILTIN: [&'static str; 2] = ["decorous", "offensive"];
+
+    /// The category named `name`, ignoring case
+    ///
+    /// Names may contain letters, digits, '-', and '_'.
+    pub fn named(name: &str) -> Result<Self, String> {
+        let name = name.trim().to_ascii_lowercase();
+        if name.is_empty()
+            || !name
+                .bytes()
+                .all(|c| c.is_ascii_alphanumeric() || c == b'-' || c == b'_')
+        {
+            return Err(format!("Invalid quote category \"{name}\""));
+        }
+        let mut categories = Self::names().lock().unwrap();
+        if let Some(&id) = categories.ids.get(name.as_str()) {
+            return Ok(Self(id));
+        }
+        let name: &'static str = Box::leak(name.into_boxed_str());
+        let id = categories.names.len() as u32;
+        categories.ids.insert(name, id);
+        categories.names.push(name);
+        Ok(Self(id))
+    }
+
+    /// The category's name, in lowercase
+    pub fn name(&self) -> &'static str {
+        match Self::BUILTIN.get(self.0 as usize) {
+            Some(name) => name,
+            None => Self::names().lock().unwrap().names[self.0 as usize],
+        }
+    }
+
+    fn names() -> &'static Mutex<CategoryNames> {
+        CATEGORY_NAMES.get_or_init(|| {
+            let mut categories = CategoryNames::default();
+            for (id, name) in Self::BUILTIN.into_iter().enumerate() {
+                categories.ids.insert(name, id as u32);
+                categories.names.push(name);
+            }
+            Mutex::new(categories)
+        })
+    }
+}
+
+impl Default for QuoteCategory {
+    fn default() -> Self {
+        Self::DECOROUS
+    }
+}
+
+impl FromStr for QuoteCategory {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::named(s)
+    }
+}
+
+impl std::fmt::Display for QuoteCategory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for QuoteCategory {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.name())
+    }
+}
+
+/// Weighs files by how recently they were modified, so that newly added quotes are chosen more
+/// often for a while without crowding out the rest, see [`Quotes::with_recency`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Recency {
+    /// How long it takes for a file's extra weight to halve
+    pub half_life: Duration,
+    /// How many times as likely each quote of a file modified just now is to be chosen, as one of
+    /// a file modified long ago
+    pub boost: f64,
+}
+
+impl Recency {
+    pub fn new(half_life: Duration) -> Self {
+        Self {
+            half_life,
+            boost: DEFAULT_RECENCY_BOOST,
+        }
+    }
+
+    pub fn boost(mut self, boost: f64) -> Self {
+        self.boost = boost;
+        self
+    }
+
+    /// What the weight of a file last modified at `modified` is multiplied by at `now`
+    fn factor(&self, modified: Option<SystemTime>, now: SystemTime) -> f64 {
+        let Some(modified) = modified else {
+            return 1.0;
+        };
+        let age = now.duration_since(modified).unwrap_or_default();
+        let half_lives = age.as_secs_f64() / self.half_life.as_secs_f64().max(1.0);
+        1.0 + (self.boost - 1.0) * 0.5_f64.powf(half_lives)
+    }
+}
+
+/// How a quote is chosen from the file selected to serve one
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum SelectionStrategy {
+    /// Every quote is equally likely to be chosen
+    #[default]
+    Random,
+    /// Quotes never served are chosen first; after that, quotes are more likely to be chosen the
+    /// longer ago they were last served
+    LeastRecentlyServed,
+}
+
+/// Bounds on the length of quotes chosen, as with fortune's `-s` and `-l`
+///
+/// Lengths are in bytes, of quotes as they're stored in their files.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct QuoteLengths {
+    /// Only choose quotes at least this long
+    pub shortest: Option<usize>,
+    /// Only choose quotes at most this long
+    pub longest: Option<usize>,
+}
+
+impl QuoteLengths {
+    pub fn contains(&self, length: usize) -> bool {
+        self.shortest.is_none_or(|shortest| length >= shortest)
+            && self.longest.is_none_or(|longest| length <= longest)
+    }
+
+    fn is_unbounded(&self) -> bool {
+        self.shortest.is_none() && self.longest.is_none()
+    }
+
+    /// The lengths within both these bounds and `other`
+    fn within(&self, other: QuoteLengths) -> Self {
+        Self {
+            shortest: self.shortest.max(other.shortest),
+            longest: match (self.longest, other.longest) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            },
+        }
+    }
+}
+
+/// Identifies a quote by its file and position within it, and by its text
+///
+/// The position is only stable for as long as the file's quotes aren't added to, removed, or
+/// reordered; the hash of the text (see [`Quote::hash`]) is stable however the file is edited, and
+/// wherever the quote is moved to.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct QuoteId {
+    pub file: String,
+    pub index: usize,
+    /// The FNV-1a hash of the quote's text, less surrounding whitespace
+    pub hash: u64,
+}
+
+impl QuoteId {
+    /// The hash of the quote's text as 16 hex digits, which is how it's shown everywhere
+    pub fn hash_hex(&self) -> String {
+        format!("{:016x}", self.hash)
+    }
+}
+
+impl std::fmt::Display for QuoteId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}#{}", self.file, self.index)
+    }
+}
+
+/// Serialized as displayed, e.g. "fortunes#42", as it's written everywhere else
+#[cfg(feature = "serde")]
+impl serde::Serialize for QuoteId {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// A quote, along with where it came from
+///
+/// The text is kept as the bytes sent to clients (quote files needn't be UTF-8); its parts are
+/// available as strings with [`Quote::text_str`], [`Quote::body`], and [`Quote::author`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Quote {
+    pub id: QuoteId,
+    pub text: Vec<u8>,
+    /// Name of the file the quote was read from
+    pub file: String,
+    pub category: QuoteCategory,
+}
+
+impl Quote {
+    /// The quote sent when no other can be, e.g. because every quote file failed to be read
+    pub fn fallback() -> Self {
+        Self::standalone(FALLBACK_FILE_NAME, FALLBACK_QUOTE.as_bytes().to_vec())
+    }
+
+    /// A decorous quote that wasn't read from any quote file, identified as the only quote of a
+    /// file named `name`
+    pub(crate) fn standalone(name: &str, text: Vec<u8>) -> Self {
+        Quote {
+            id: QuoteId {
+                file: name.to_string(),
+                index: 0,
+                hash: text_hash(&String::from_utf8_lossy(&text)),
+            },
+            text,
+            file: name.to_string(),
+            category: QuoteCategory::DECOROUS,
+        }
+    }
+
+    /// A hash of the quote's text, which identifies it wherever it's found (e.g. to deny it, see
+    /// [`IndexOptions::deny_hashes`]), and however its file is edited
+    ///
+    /// Surrounding whitespace is ignored, so the hash doesn't depend on how the file is laid out.
+    /// The hash is of the quote as it's stored, so is unaffected by any formatting or truncation
+    /// before it's sent.
+    pub fn hash(&self) -> String {
+        self.id.hash_hex()
+    }
+
+    /// The quote's text, with any invalid UTF-8 replaced
+    pub fn text_str(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.text)
+    }
+
+    /// The quote's text without its attribution (see [`Quote::author`]) or trailing whitespace
+    pub fn body(&self) -> String {
+        let text = self.text_str();
+        split_attribution(&text).0.to_string()
+    }
+
+    /// Who the quote is attributed to, if anyone, from its last line beginning with "--" (e.g.
+    /// "\t\t-- Mark Twain", as the import command writes) and any lines following it
+    pub fn author(&self) -> Option<String> {
+        let text = self.text_str();
+        let (_, attribution) = split_attribution(&text);
+        let author = attribution?
+            .trim_start()
+            .trim_start_matches('-')
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ");
+        (!author.is_empty()).then_some(author)
+    }
+
+    /// Shorten the quote's text to at most `max` bytes, never splitting a UTF-8 character
+    pub fn truncate(&mut self, max: usize) {
+        if self.text.len() <= max {
+            return;
+        }
+        let mut end = max;
+        // Continuation bytes (0b10xxxxxx) are never the start of a character
+        while end > 0 && self.text[end] & 0xC0 == 0x80 {
+            end -= 1;
+        }
+        self.text.truncate(end);
+    }
+}
+
+/// Split `text` into its body and attribution, if it has one
+///
+/// The attribution starts at the last line beginning with "--", as long as a line that doesn't
+/// precedes it; quotes made up only of such lines (e.g. dialogue) have no attribution.
+fn split_attribution(text: &str) -> (&str, Option<&str>) {
+    let text = text.trim_end();
+    let mut offset = 0;
+    let mut split = None;
+    let mut has_body = false;
+    for line in text.split_inclusive('\n') {
+        if !line.trim_start().starts_with("--") {
+            has_body = true;
+        } else if has_body {
+            split = Some(offset);
+        }
+        offset += line.len();
+    }
+    match split {
+        Some(at) => (text[..at].trim_end(), Some(&text[at..])),
+        None => (text, None),
+    }
+}
+
+/// The FNV-1a hash of `text` less surrounding whitespace
+///
+/// The hash must be the same across restarts and versions, so it's computed here rather than with
+/// the standard library's hasher.
+fn text_hash(text: &str) -> u64 {
+    text.trim()
+        .bytes()
+        .fold(0xCBF2_9CE4_8422_2325_u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01B3)
+        })
+}
+
+/// Remove the carriage return from every "\r\n" in `text`, leaving lone ones be
+fn strip_carriage_returns(text: &mut Vec<u8>) {
+    let mut write = 0;
+    for read in 0..text.len() {
+        if text[read] == b'\r' && text.get(read + 1) == Some(&b'\n') {
+            continue;
+        }
+        text[write] = text[read];
+        write += 1;
+    }
+    text.truncate(write);
+}
+
+/// Finds a file's comment lines: those starting with a doubled separator (e.g. "%%"), as with
+/// fortune, or with the prefix given to [`IndexOptions::comment_prefix`]
+#[derive(Debug, Clone)]
+struct Comments {
+    separator: String,
+    prefix: Option<String>,
+}
+
+impl Comments {
+    fn is_comment_line(line: &[u8], separator: &str, prefix: Option<&str>) -> bool {
+        line.strip_prefix(separator.as_bytes())
+            .is_some_and(|rest| rest.starts_with(separator.as_bytes()))
+            || prefix.is_some_and(|prefix| line.starts_with(prefix.as_bytes()))
+    }
+
+    fn is_comment(&self, line: &[u8]) -> bool {
+        Self::is_comment_line(line, &self.separator, self.prefix.as_deref())
+    }
+
+    /// Decode the lines of a quote that aren't comments, which are never encoded
+    fn decode(&self, encoding: &FileEncoding, text: &mut [u8]) {
+        let mut printable = 0;
+        for line in text.split_inclusive_mut(|&c| c == b'\n') {
+            if !self.is_comment(line) {
+                printable += encoding.decode_chunk(line, printable);
+            }
+        }
+    }
+
+    /// Drop the comment lines from a quote
+    fn strip(&self, text: &mut Vec<u8>) {
+        let mut stripped = buffer::take(text.len());
+        for line in text.split_inclusive(|&c| c == b'\n') {
+            if !self.is_comment(line) {
+                stripped.extend_from_slice(line);
+            }
+        }
+        buffer::recycle(std::mem::replace(text, stripped));
+    }
+}
+
+/// A chosen quote, either read into memory or to be streamed from its file by [`QuoteStream`]
+#[derive(Debug)]
+pub enum ChosenQuote {
+    Read(Quote),
+    Streamed(QuoteStream),
+}
+
+impl ChosenQuote {
+    pub fn id(&self) -> &QuoteId {
+        match self {
+            ChosenQuote::Read(quote) => &quote.id,
+            ChosenQuote::Streamed(stream) => &stream.id,
+        }
+    }
+
+    pub fn category(&self) -> QuoteCategory {
+        match self {
+            ChosenQuote::Read(quote) => quote.category,
+            ChosenQuote::Streamed(stream) => stream.category,
+        }
+    }
+
+    /// Length of the quote's text in bytes, i.e. of what will be sent
+    pub fn length(&self) -> usize {
+        match self {
+            ChosenQuote::Read(quote) => quote.text.len(),
+            ChosenQuote::Streamed(stream) => stream.length,
+        }
+    }
+
+    /// A hash of the quote's text (see [`Quote::hash`])
+    pub fn hash(&self) -> String {
+        self.id().hash_hex()
+    }
+
+    /// Hand the quote's text back for reuse once it has been sent (see [`buffer::recycle`])
+    #[cfg(feature = "tcp")]
+    pub(crate) fn recycle(self) {
+        if let ChosenQuote::Read(quote) = self {
+            buffer::recycle(quote.text);
+        }
+    }
+
+    /// Read the quote into memory, if it isn't already
+    pub fn into_quote(self) -> io::Result<Quote> {
+        match self {
+            ChosenQuote::Read(quote) => Ok(quote),
+            ChosenQuote::Streamed(stream) => {
+                let mut text = buffer::take(stream.length);
+                for chunk in stream.chunks()? {
+                    let chunk = chunk?;
+                    text.extend_from_slice(&chunk);
+                    buffer::recycle(chunk);
+                }
+                Ok(Quote {
+                    id: stream.id,
+                    text,
+                    file: stream.file,
+                    category: stream.category,
+                })
+            }
+        }
+    }
+}
+
+/// A quote too long to read into memory at once, which is instead read in bounded chunks
+///
+/// The quote file is opened anew for every stream, so that streaming doesn't hold up choosing
+/// other quotes.
+#[derive(Debug)]
+pub struct QuoteStream {
+    pub id: QuoteId,
+    /// Name of the file the quote is read from
+    pub file: String,
+    pub category: QuoteCategory,
+    /// Length of the quote, in bytes
+    pub length: usize,
+    path: PathBuf,
+    offset: u64,
+    encoding: FileEncoding,
+}
+
+impl QuoteStream {
+    /// Open the quote's file to read the quote in decoded chunks of at most [`STREAM_CHUNK_SIZE`]
+    /// bytes
+    pub fn chunks(&self) -> io::Result<QuoteChunks> {
+        let mut file = File::open(&self.path)?;
+        file.seek(io::SeekFrom::Start(self.offset))?;
+        Ok(QuoteChunks {
+            file,
+            remaining: self.length,
+            printable: 0,
+            encoding: self.encoding.clone(),
+        })
+    }
+}
+
+/// The chunks of a [`QuoteStream`], read as they're iterated over
+#[derive(Debug)]
+pub struct QuoteChunks {
+    file: File,
+    remaining: usize,
+    /// Printable characters read so far, which keyed substitutions depend on
+    printable: usize,
+    encoding: FileEncoding,
+}
+
+impl Iterator for QuoteChunks {
+    type Item = io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let mut chunk = buffer::take(STREAM_CHUNK_SIZE);
+        chunk.resize(self.remaining.min(STREAM_CHUNK_SIZE), 0);
+        if let Err(e) = self.file.read_exact(&mut chunk) {
+            self.remaining = 0;
+            return Some(Err(e));
+        }
+        self.printable += self.encoding.decode_chunk(&mut chunk, self.printable);
+        self.remaining -= chunk.len();
+        Some(Ok(chunk))
+    }
+}
+
+/// Files sampled again at most when choosing a quote, should they have none of an allowed length
+/// (see [`QuoteLengths`])
+const LENGTH_ATTEMPTS: usize = 16;
+/// Quotes chosen again at most when choosing a quote, should reading the chosen one fail (e.g. its
+/// file was deleted, or is on a network filesystem that's unavailable)
+const READ_ATTEMPTS: usize = 3;
+/// Text of [`Quote::fallback`]
+const FALLBACK_QUOTE: &str = "No quote could be found today; please try again later.\n";
+/// Name of the pseudo-file [`Quote::fallback`] comes from, as seen in its [`QuoteId`]
+const FALLBACK_FILE_NAME: &str = "fallback";
+
+/// Default for [`Recency::boost`]
+pub const DEFAULT_RECENCY_BOOST: f64 = 4.0;
+/// How often files are weighed anew by their recency, see [`Quotes::with_recency`]
+const RECENCY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Quotes longer than this are streamed in chunks of this size, rather than read into memory at
+/// once, when they may be
+pub const STREAM_CHUNK_SIZE: usize = 4 * 1024;
+
+const SEPARATOR: &str = "%";
+/// Delimits the optional metadata block at the very top of a quote file
+const FRONT_MATTER_DELIMITER: &str = "---";
+/// Some editors begin UTF-8 files with this byte-order mark, which is never part of the first quote
+const BYTE_ORDER_MARK: &[u8] = b"\xEF\xBB\xBF";
+const ROT31_TOKEN: &str = "$SerrOFQ$";
+/// The plain token, ROT47-encoded
+const ROT47_TOKEN: &str = "SuC66q$sS";
+/// Prefix of the token declaring a keyed substitution; the key follows, terminated by '$'
+const KEYED_TOKEN: &str = "$Keyed:";
+pub(crate) const PLAIN_TOKEN: &str = "$FreeBSD$";
+/// Quotes served by [`Quotes::builtin`], in the usual quote file format
+#[cfg(feature = "builtin-quotes")]
+const BUILTIN_QUOTES: &str = include_str!("builtin_quotes");
+/// Name of the pseudo-file built-in quotes are served from, as seen in their [`QuoteId`]s
+#[cfg(feature = "builtin-quotes")]
+const BUILTIN_FILE_NAME: &str = "builtin";
+const OFFENSIVE_SUFFIX: &str = "-o";
+/// Names of directories whose files are offensive, as fortune-mod installs its offensive packs
+const OFFENSIVE_DIRS: [&str; 2] = ["offensive", "off"];
+/// Words that get quotes reclassified as offensive, see [`IndexOptions::offensive_words`]
+///
+/// One word per line; empty lines and those starting with '#' are ignored.
+pub const BUILTIN_OFFENSIVE_WORDS: &str = include_str!("offensive_words");
+/// Extension of age-encrypted quote files
+const ENCRYPTED_EXTENSION: &str = "age";
+/// Optional extension of plain text quote files, which may be preceded by a language code
+const TEXT_EXTENSION: &str = "txt";
+
+/// The first and last characters affected by ROT47 and the keyed substitution ('!' through '~')
+const PRINTABLE_FIRST: u8 = b'!';
+const PRINTABLE_LAST: u8 = b'~';
+const PRINTABLE_COUNT: u8 = PRINTABLE_LAST - PRINTABLE_FIRST + 1;
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+enum FileEncoding {
+    #[default]
+    Plain,
+    Rot13,
+    /// Rotates all printable, non-space ASCII characters
+    Rot47,
+    /// Shifts all printable, non-space ASCII characters by the successive bytes of the key,
+    /// restarting from the start of the key for every quote
+    Keyed(Arc<[u8]>),
+}
+
+impl FileEncoding {
+    /// Name of this encoding, as reported in a [`FileSummary`]
+    fn name(&self) -> &'static str {
+        match self {
+            FileEncoding::Plain => "plain",
+            FileEncoding::Rot13 => "rot13",
+            FileEncoding::Rot47 => "rot47",
+            FileEncoding::Keyed(_) => "keyed",
+        }
+    }
+
+    /// Detect an encoding token within the given line
+    fn detect(line: &str) -> Option<Self> {
+        if line.contains(ROT31_TOKEN) {
+            Some(FileEncoding::Rot13)
+        } else if line.contains(ROT47_TOKEN) {
+            Some(FileEncoding::Rot47)
+        } else if let Some(start) = line.find(KEYED_TOKEN) {
+            let key = &line[start + KEYED_TOKEN.len()..];
+            let key = &key[..key.find('$')?];
+            if key.is_empty() {
+                warn!("Ignoring keyed encoding token with an empty key");
+                None
+            } else {
+                Some(FileEncoding::Keyed(key.as_bytes().into()))
+            }
+        } else if line.contains(PLAIN_TOKEN) {
+            Some(FileEncoding::Plain)
+        } else {
+            None
+        }
+    }
+
+    /// Whether the given line, which isn't a comment, is a quote separator
+    ///
+    /// Encodings that rotate punctuation may produce lines beginning with the separator
+    /// character, so for them only a bare separator counts.
+    fn is_separator(&self, line: &str, separator: &str) -> bool {
+        match self {
+            FileEncoding::Plain | FileEncoding::Rot13 => line.starts_with(separator),
+            FileEncoding::Rot47 | FileEncoding::Keyed(_) => line.trim_end() == separator,
+        }
+    }
+
+    fn decode(&self, text: &mut [u8]) {
+        self.decode_chunk(text, 0);
+    }
+
+    /// Decode part of a quote, given how many printable characters of it precede this part, returning
+    /// how many printable characters this part contains
+    ///
+    /// Every encoding only substitutes ASCII characters for ASCII characters, and no byte of a
+    /// multibyte UTF-8 character is ASCII, so decoding leaves those characters intact wherever
+    /// the quote is split into parts.
+    fn decode_chunk(&self, text: &mut [u8], preceding: usize) -> usize {
+        let printable = text
+            .iter()
+            .filter(|c| (PRINTABLE_FIRST..=PRINTABLE_LAST).contains(*c))
+            .count();
+        match self {
+            FileEncoding::Plain => {}
+            FileEncoding::Rot13 => text.iter_mut().for_each(|c| match c {
+                b'A'..=b'M' | b'a'..=b'm' => *c += 13,
+                b'N'..=b'Z' | b'n'..=b'z' => *c -= 13,
+                _ => {}
+            }),
+            FileEncoding::Rot47 => text
+                .iter_mut()
+                .filter(|c| (PRINTABLE_FIRST..=PRINTABLE_LAST).contains(*c))
+                .for_each(|c| *c = Self::shift_back(*c, 47)),
+            FileEncoding::Keyed(key) => text
+                .iter_mut()
+                .filter(|c| (PRINTABLE_FIRST..=PRINTABLE_LAST).contains(*c))
+                .zip(key.iter().cycle().skip(preceding % key.len()))
+                .for_each(|(c, k)| *c = Self::shift_back(*c, *k)),
+        }
+        printable
+    }
+
+    /// Undo a rotation by `amount` within the printable range
+    fn shift_back(c: u8, amount: u8) -> u8 {
+        let amount = amount % PRINTABLE_COUNT;
+        PRINTABLE_FIRST + (c - PRINTABLE_FIRST + PRINTABLE_COUNT - amount) % PRINTABLE_COUNT
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct QuoteIndex {
+    offset: u64,
+    length: usize,
+    /// Days of the year this quote is restricted to, declared on its preceding separator line
+    dates: Option<DateWindow>,
+    /// Dates this quote may be served between, declared on its preceding separator line
+    validity: Option<Validity>,
+    /// Hash of the quote's text, as decoded (see [`text_hash`])
+    hash: u64,
+}
+
+impl QuoteIndex {
+    fn is_in_season(&self, today: NaiveDate) -> bool {
+        self.dates.is_none_or(|dates| dates.contains(today))
+            && self
+                .validity
+                .is_none_or(|validity| validity.contains(today))
+    }
+
+    /// Whether this quote may only be served on certain days
+    fn is_dated(&self) -> bool {
+        self.dates.is_some() || self.validity.is_some()
+    }
+}
+
+/// Metadata declared in a quote file's front-matter
+///
+/// The front-matter is an optional block at the very top of the file, delimited by lines
+/// consisting of `---`, containing `key: value` lines:
+///
+/// ```text
+/// ---
+/// category: offensive
+/// language: en
+/// tags: unix, computers
+/// dates: 12-20..12-27
+/// not_before: 2026-05-01
+/// not_after: 2026-06-30
+/// weight: 0.5
+/// separator: %
+/// ---
+/// ```
+///
+/// Anything declared here supersedes what would otherwise be inferred from the file name.
+#[derive(Debug, Default, Clone, PartialEq)]
+struct FileMetadata {
+    category: Option<QuoteCategory>,
+    language: Option<String>,
+    tags: Vec<String>,
+    dates: Option<DateWindow>,
+    /// Dates the file's quotes may be served between, e.g. for those about an event
+    validity: Validity,
+    /// Multiplier applied to the file's share of selections
+    weight: Option<f64>,
+    separator: Option<String>,
+    charset: Option<Charset>,
+}
+
+impl FileMetadata {
+    fn parse_line(&mut self, line: &str) {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return;
+        }
+        let Some((key, value)) = line.split_once(':') else {
+            warn!("Ignoring malformed front-matter line: {line}");
+            return;
+        };
+        let value = value.trim();
+
+        match key.trim().to_ascii_lowercase().as_str() {
+            "category" => match value.parse() {
+                Ok(category) => self.category = Some(category),
+                Err(e) => warn!("Ignoring front-matter category: {e}"),
+            },
+            "language" => self.language = Some(value.to_ascii_lowercase()),
+            "tags" => {
+                self.tags = value
+                    .split(',')
+                    .map(|tag| tag.trim().to_ascii_lowercase())
+                    .filter(|tag| !tag.is_empty())
+                    .collect()
+            }
+            "dates" => match value.parse() {
+                Ok(dates) => self.dates = Some(dates),
+                Err(e) => warn!("Ignoring front-matter dates: {e}"),
+            },
+            "not_before" | "not-before" => match Validity::parse_date(value) {
+                Ok(date) => self.validity.not_before = date,
+                Err(e) => warn!("Ignoring front-matter not_before: {e}"),
+            },
+            "not_after" | "not-after" => match Validity::parse_date(value) {
+                Ok(date) => self.validity.not_after = date,
+                Err(e) => warn!("Ignoring front-matter not_after: {e}"),
+            },
+            "weight" => match value.parse::<f64>() {
+                Ok(weight) if weight.is_finite() && weight >= 0.0 => self.weight = Some(weight),
+                _ => warn!("Ignoring invalid front-matter weight: {value}"),
+            },
+            "separator" if !value.is_empty() => self.separator = Some(value.to_string()),
+            "encoding" => match value.parse() {
+                Ok(charset) => self.charset = Some(charset),
+                Err(e) => warn!("Ignoring front-matter encoding: {e}"),
+            },
+            other => warn!("Ignoring unknown front-matter key: {other}"),
+        }
+    }
+
+    fn separator(&self) -> &str {
+        self.separator.as_deref().unwrap_or(SEPARATOR)
+    }
+
+    fn validity(&self) -> Option<Validity> {
+        (self.validity != Validity::default()).then_some(self.validity)
+    }
+}
+
+/// The result of scanning a quote file
+#[derive(Debug, Default)]
+struct IndexedQuotes {
+    quotes: Vec<QuoteIndex>,
+    encoding: FileEncoding,
+    /// Whether any line ends with "\r\n" rather than "\n"
+    crlf: bool,
+    /// How to find comment lines, if any quotes contain them
+    comments: Option<Comments>,
+    charset: Charset,
+    metadata: FileMetadata,
+    /// Whether indexing stopped short of the end, having indexed as many quotes as allowed
+    truncated: bool,
+    /// Whether an encoding token was ignored for coming after the first quote
+    late_encoding: bool,
+    /// Number of quotes skipped for matching an exclusion pattern
+    excluded: usize,
+    /// Number of quotes skipped for being on the deny-list
+    denied: usize,
+    /// Indices (into `quotes`) of quotes containing a word flagged as offensive
+    flagged: Vec<usize>,
+    /// Whether the file has no separators, so its whole text was indexed as a single quote
+    unseparated: bool,
+}
+
+/// Where a file's quotes are read from when serving
+#[derive(Debug)]
+enum QuoteStorage {
+    /// Quotes are read from the open file on demand
+    File(File),
+    /// The file's contents are held in memory, e.g. because they had to be decrypted or were read
+    /// from a stream
+    Memory(Vec<u8>),
+    /// The file's contents are held in memory with its quotes already decoded, see
+    /// [`IndexOptions::preload_encoded`]
+    Decoded(Vec<u8>),
+    /// The file is mapped into memory, see [`StorageMode::Mmap`]
+    Mapped(Arc<Mapping>),
+}
+
+impl QuoteStorage {
+    /// Name of this storage, as reported in a [`FileSummary`]
+    fn name(&self) -> &'static str {
+        match self {
+            QuoteStorage::File(_) => "file",
+            QuoteStorage::Memory(_) => "memory",
+            QuoteStorage::Decoded(_) => "decoded",
+            QuoteStorage::Mapped(_) => "mapped",
+        }
+    }
+}
+
+/// How quote files on disk are held while their quotes are served
+///
+/// Files that must be held in memory regardless, e.g. encrypted files, are unaffected, as are
+/// encoded files preloaded with [`IndexOptions::preload_encoded`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum StorageMode {
+    /// Keep each file open, reading each quote from it as it's served
+    ///
+    /// This uses the least memory, as only the index is held, at the cost of a seek and read for
+    /// every quote; the page cache usually makes those cheap.
+    #[default]
+    Lazy,
+    /// Read each file into memory when indexing it
+    ///
+    /// Quotes are served without any I/O, at the cost of memory for the whole of every file, and
+    /// changes to files go unnoticed until they're indexed again.
+    Preload,
+    /// Map each file into memory, leaving the kernel to page quotes in as they're served
+    ///
+    /// Quotes are served without a syscall each, and memory is shared with the page cache, but a
+    /// file truncated while it's served may crash the server.
+    Mmap,
+}
+
+/// When a quote file on disk was last modified, and its size, to tell whether it has changed since
+/// it was indexed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FileFingerprint {
+    modified: Option<SystemTime>,
+    size: u64,
+}
+
+impl FileFingerprint {
+    fn of(metadata: &std::fs::Metadata) -> Self {
+        Self {
+            modified: metadata.modified().ok(),
+            size: metadata.len(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct QuoteFile {
+    /// The file's name, stripped of any suffixes that carry meaning (e.g. language or dates)
+    name: String,
+    /// Where the file was read from
+    path: PathBuf,
+    storage: QuoteStorage,
+    quotes: Vec<QuoteIndex>,
+    encoding: FileEncoding,
+    /// Whether the file has Windows line endings, which are served as plain "\n"
+    crlf: bool,
+    /// How to find comment lines, if any quotes contain them, which are dropped when they're read
+    comments: Option<Comments>,
+    /// What the file is written in, which its quotes are transcoded from into UTF-8 when read
+    charset: Charset,
+    category: QuoteCategory,
+    language: Option<String>,
+    /// Days of the year this file's quotes are restricted to
+    dates: Option<DateWindow>,
+    /// Whether any individual quotes are restricted to certain days
+    has_dated_quotes: bool,
+    metadata: FileMetadata,
+    /// The file as it was indexed, if quotes are read from it on demand
+    fingerprint: Option<FileFingerprint>,
+    /// When each quote was last chosen, only tracked for [`SelectionStrategy::LeastRecentlyServed`]
+    last_served: Vec<Option<Instant>>,
+    /// When the file was last modified, if it was read from disk
+    modified: Option<SystemTime>,
+    /// What the file's weight is multiplied by for its recency, see [`Quotes::with_recency`]
+    recency_factor: f64,
+}
+
+impl QuoteFile {
+    /// Read and decode the quote at `index`
+    fn read(&mut self, index: usize) -> io::Result<Quote> {
+        let quote_index = self.quotes[index];
+
+        let copy = |contents: &[u8]| {
+            let start = quote_index.offset as usize;
+            let mut quote = buffer::take(quote_index.length);
+            quote.extend_from_slice(&contents[start..start + quote_index.length]);
+            quote
+        };
+        let mut quote = match &mut self.storage {
+            QuoteStorage::File(fh) => {
+                fh.seek(io::SeekFrom::Start(quote_index.offset))?;
+                let mut quote = buffer::take(quote_index.length);
+                quote.resize(quote_index.length, 0);
+                fh.read_exact(&mut quote)?;
+                quote
+            }
+            QuoteStorage::Memory(contents) | QuoteStorage::Decoded(contents) => copy(contents),
+            QuoteStorage::Mapped(mapping) => copy(mapping),
+        };
+
+        if !matches!(self.storage, QuoteStorage::Decoded(_)) {
+            match &self.comments {
+                Some(comments) => comments.decode(&self.encoding, &mut quote),
+                None => self.encoding.decode(&mut quote),
+            }
+        }
+        if let Some(comments) = &self.comments {
+            comments.strip(&mut quote);
+        }
+        if self.crlf {
+            strip_carriage_returns(&mut quote);
+        }
+        self.charset.transcode(&mut quote);
+
+        Ok(Quote {
+            id: QuoteId {
+                file: self.name.clone(),
+                index,
+                hash: quote_index.hash,
+            },
+            text: quote,
+            file: self.name.clone(),
+            category: self.category,
+        })
+    }
+
+    /// Prepare to stream the quote at `index` from its file, if it's too long to read into memory
+    /// at once and its file is on disk (and checked for changes, so may be reopened)
+    ///
+    /// Quotes with Windows line endings or comments, or that aren't UTF-8, are never streamed, as
+    /// how long they are once stripped and transcoded isn't known until they're read.
+    fn stream(&self, index: usize) -> Option<QuoteStream> {
+        let quote_index = self.quotes[index];
+        if quote_index.length <= STREAM_CHUNK_SIZE
+            || self.crlf
+            || self.comments.is_some()
+            || self.charset != Charset::Utf8
+            || !matches!(self.storage, QuoteStorage::File(_))
+            || self.fingerprint.is_none()
+        {
+            return None;
+        }
+
+        Some(QuoteStream {
+            id: QuoteId {
+                file: self.name.clone(),
+                index,
+                hash: quote_index.hash,
+            },
+            file: self.name.clone(),
+            category: self.category,
+            length: quote_index.length,
+            path: self.path.clone(),
+            offset: quote_index.offset,
+            encoding: self.encoding.clone(),
+        })
+    }
+
+    /// Whether the file on disk has changed (or gone) since it was indexed, in which case its
+    /// quotes' offsets can't be trusted
+    fn is_changed(&self) -> bool {
+        self.fingerprint.is_some_and(|fingerprint| {
+            std::fs::metadata(&self.path).map_or(true, |metadata| {
+                FileFingerprint::of(&metadata) != fingerprint
+            })
+        })
+    }
+
+    /// Whether this file's quotes, or any of them, may only be served on certain days
+    fn is_seasonal(&self) -> bool {
+        self.dates.is_some() || self.has_dated_quotes || self.metadata.validity().is_some()
+    }
+
+    /// This file's weight for selection purposes
+    fn weight(&self) -> f64 {
+        self.base_weight() * self.recency_factor
+    }
+
+    /// This file's weight, regardless of its recency
+    fn base_weight(&self) -> f64 {
+        self.quotes.len() as f64 * self.metadata.weight.unwrap_or(1.0)
+    }
+
+    /// This file's weight for selection purposes on the given day, regardless of its recency
+    fn seasonal_weight(&self, today: NaiveDate) -> f64 {
+        if self.dates.is_some_and(|dates| !dates.contains(today))
+            || self
+                .metadata
+                .validity()
+                .is_some_and(|validity| !validity.contains(today))
+        {
+            0.0
+        } else if self.has_dated_quotes {
+            let in_season = self.quotes.iter().filter(|q| q.is_in_season(today)).count();
+            in_season as f64 * self.metadata.weight.unwrap_or(1.0)
+        } else {
+            self.base_weight()
+        }
+    }
+}
+
+/// Keys used to decrypt age-encrypted quote files
+#[cfg(feature = "encryption")]
+#[derive(Clone, Default)]
+pub struct DecryptionKeys {
+    identities: Option<String>,
+    passphrase: Option<age::secrecy::SecretString>,
+}
+
+#[cfg(feature = "encryption")]
+impl DecryptionKeys {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Use the identities (e.g. `AGE-SECRET-KEY-...` lines) from the contents of an age identity file
+    pub fn identities<S: Into<String>>(mut self, identities: S) -> Self {
+        self.identities = Some(identities.into());
+        self
+    }
+
+    /// Use a passphrase for passphrase-encrypted files
+    pub fn passphrase<S: Into<String>>(mut self, passphrase: S) -> Self {
+        self.passphrase = Some(passphrase.into().into());
+        self
+    }
+
+    fn is_empty(&self) -> bool {
+        self.identities.is_none() && self.passphrase.is_none()
+    }
+
+    fn decrypt(&self, encrypted: &[u8]) -> io::Result<Vec<u8>> {
+        use std::io::Read;
+
+        let invalid = |e| io::Error::new(io::ErrorKind::InvalidData, e);
+
+        let decryptor = age::Decryptor::new_buffered(encrypted).map_err(invalid)?;
+        let mut identities: Vec<Box<dyn age::Identity>> = Vec::new();
+        if decryptor.is_scrypt() {
+            if let Some(passphrase) = &self.passphrase {
+                identities.push(Box::new(age::scrypt::Identity::new(passphrase.clone())));
+            }
+        } else if let Some(contents) = &self.identities {
+            identities = age::IdentityFile::from_buffer(contents.as_bytes())?
+                .into_identities()
+                .map_err(invalid)?;
+        }
+
+        let mut decrypted = Vec::with_capacity(encrypted.len());
+        decryptor
+            .decrypt(identities.iter().map(|identity| identity.as_ref()))
+            .map_err(invalid)?
+            .read_to_end(&mut decrypted)?;
+        Ok(decrypted)
+    }
+}
+
+#[cfg(feature = "encryption")]
+impl std::fmt::Debug for DecryptionKeys {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Never leak key material into logs
+        f.debug_struct("DecryptionKeys")
+            .field(
+                "identities",
+                &self.identities.as_ref().map(|_| "<redacted>"),
+            )
+            .field(
+                "passphrase",
+                &self.passphrase.as_ref().map(|_| "<redacted>"),
+            )
+            .finish()
+    }
+}
+
+/// How much has been indexed so far, see [`Quotes::index_dir`]
+#[derive(Debug, Default)]
+struct IndexProgress {
+    quotes: usize,
+    /// Files read, whether or not they were indexed
+    files: usize,
+}
+
+/// Options controlling how quote files are indexed
+#[derive(Debug, Clone)]
+pub struct IndexOptions {
+    /// Categories of files to index, or `None` for every category
+    allowed_categories: Option<Vec<QuoteCategory>>,
+    languages: Option<Vec<String>>,
+    max_quotes: Option<usize>,
+    max_files: Option<usize>,
+    max_file_size: Option<u64>,
+    preload_encoded: bool,
+    /// Whether text after a file's last separator is a quote, see [`IndexOptions::trailing_quote`]
+    trailing_quote: bool,
+    storage: StorageMode,
+    charset: Charset,
+    comment_prefix: Option<String>,
+    exclude: Vec<Regex>,
+    /// Lowercase words that get quotes in decorous files reclassified as offensive
+    offensive_words: HashSet<String>,
+    /// Hashes of quotes that are never indexed, see [`Quote::hash`]
+    denied_hashes: HashSet<String>,
+    #[cfg(feature = "encryption")]
+    keys: DecryptionKeys,
+}
+
+impl Default for IndexOptions {
+    fn default() -> Self {
+        Self {
+            allowed_categories: Some(vec![QuoteCategory::DECOROUS]),
+            languages: None,
+            max_quotes: None,
+            max_files: None,
+            max_file_size: None,
+            preload_encoded: false,
+            trailing_quote: true,
+            storage: StorageMode::Lazy,
+            charset: Charset::Utf8,
+            comment_prefix: None,
+            exclude: Vec::new(),
+            offensive_words: HashSet::new(),
+            denied_hashes: HashSet::new(),
+            #[cfg(feature = "encryption")]
+            keys: DecryptionKeys::default(),
+        }
+    }
+}
+
+impl IndexOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only index files in the given categories
+    pub fn categories(mut self, allowed_categories: &[QuoteCategory]) -> Self {
+        self.allowed_categories = Some(allowed_categories.to_vec());
+        self
+    }
+
+    /// Index files in every category, whatever they're named
+    pub fn all_categories(mut self) -> Self {
+        self.allowed_categories = None;
+        self
+    }
+
+    /// Whether files in `category` are indexed
+    fn allows(&self, category: QuoteCategory) -> bool {
+        self.allowed_categories
+            .as_ref()
+            .is_none_or(|categories| categories.contains(&category))
+    }
+
+    /// Only index files in the given languages
+    ///
+    /// Files that don't declare a language are always indexed.
+    pub fn languages<S: AsRef<str>>(mut self, languages: &[S]) -> Self {
+        self.languages = Some(
+            languages
+                .iter()
+                .map(|language| language.as_ref().trim().to_ascii_lowercase())
+                .collect(),
+        );
+        self
+    }
+
+    /// Stop indexing once `max` quotes have been indexed, leaving any further quotes unserved
+    ///
+    /// This bounds the memory used by the index, however many quote files there are.
+    pub fn max_quotes(mut self, max: usize) -> Self {
+        self.max_quotes = Some(max);
+        self
+    }
+
+    /// Hold quote files on disk as `mode` says while serving them (default: [`StorageMode::Lazy`])
+    pub fn storage(mut self, mode: StorageMode) -> Self {
+        self.storage = mode;
+        self
+    }
+
+    /// Read quote files in `charset` unless their front-matter says otherwise (e.g.
+    /// `encoding: latin1`), rather than UTF-8
+    pub fn file_encoding(mut self, charset: Charset) -> Self {
+        self.charset = charset;
+        self
+    }
+
+    /// Also treat lines starting with `prefix` (e.g. "#") as comments, which are never part of a
+    /// quote, as well as those starting with a doubled separator (e.g. "%%")
+    ///
+    /// An empty prefix is ignored, rather than making every line a comment.
+    pub fn comment_prefix<S: Into<String>>(mut self, prefix: S) -> Self {
+        self.comment_prefix = Some(prefix.into()).filter(|prefix| !prefix.is_empty());
+        self
+    }
+
+    /// Skip quotes whose (decoded) text matches `pattern`; may be given repeatedly
+    pub fn exclude_pattern(mut self, pattern: Regex) -> Self {
+        self.exclude.push(pattern);
+        self
+    }
+
+    /// Reclassify quotes in decorous files as offensive if they contain any of the words in `list`
+    /// (e.g. [`BUILTIN_OFFENSIVE_WORDS`]); may be given repeatedly
+    ///
+    /// `list` has one word per line, matched as a whole word regardless of case; empty lines and
+    /// those starting with '#' are ignored. Reclassified quotes are served from a file of their own
+    /// named with the usual `-o` suffix, so they're only served if offensive quotes are allowed.
+    pub fn offensive_words(mut self, list: &str) -> Self {
+        self.offensive_words.extend(
+            list.lines()
+                .map(str::trim)
+                .filter(|word| !word.is_empty() && !word.starts_with('#'))
+                .map(str::to_lowercase),
+        );
+        self
+    }
+
+    /// Skip quotes whose hashes (see [`Quote::hash`]) are in `list`, wherever they're found; may be
+    /// given repeatedly
+    ///
+    /// `list` has one hash per line; empty lines and those starting with '#' are ignored, as is
+    /// anything following the hash on its line (e.g. a note on why it was denied).
+    pub fn deny_hashes(mut self, list: &str) -> Self {
+        self.denied_hashes.extend(
+            list.lines()
+                .filter_map(|line| line.split_whitespace().next())
+                .filter(|hash| !hash.starts_with('#'))
+                .map(str::to_lowercase),
+        );
+        self
+    }
+
+    /// Whether `text` contains any of the words flagged as offensive
+    fn is_offensive(&self, text: &str) -> bool {
+        !self.offensive_words.is_empty()
+            && text
+                .split(|c: char| !c.is_alphanumeric())
+                .any(|word| self.offensive_words.contains(&word.to_lowercase()))
+    }
+
+    /// Stop indexing once `max` files have been read, leaving any further files unserved
+    ///
+    /// Files are counted whether or not they contain quotes that may be served, as each must be
+    /// read all the same.
+    pub fn max_files(mut self, max: usize) -> Self {
+        self.max_files = Some(max);
+        self
+    }
+
+    /// Skip quote files larger than `max` bytes, with a warning, rather than reading them
+    pub fn max_file_size(mut self, max: u64) -> Self {
+        self.max_file_size = Some(max);
+        self
+    }
+
+    /// Decode the quotes of encoded (e.g. ROT13) files once, holding them in memory, rather than
+    /// reading and decoding each quote every time it's served
+    ///
+    /// This trades memory for time: each encoded file is held in memory in its entirety.
+    pub fn preload_encoded(mut self, preload: bool) -> Self {
+        self.preload_encoded = preload;
+        self
+    }
+
+    /// Index any text after a file's last separator as a quote, as fortune does, including the
+    /// whole text of files without any separators (default: enabled)
+    ///
+    /// Otherwise only text followed by a separator is a quote.
+    pub fn trailing_quote(mut self, index: bool) -> Self {
+        self.trailing_quote = index;
+        self
+    }
+
+    /// Describe why the given file must not be served, if it mustn't
+    fn rejection(&self, file: &QuoteFile) -> Option<&'static str> {
+        if file.quotes.is_empty() {
+            Some("contains no quotes")
+        } else if !self.allows(file.category) {
+            Some("is not in allowed categories")
+        } else if let (Some(languages), Some(language)) = (&self.languages, &file.language) {
+            (!languages.contains(language)).then_some("is not in allowed languages")
+        } else {
+            None
+        }
+    }
+
+    /// Decrypt encrypted quote files with the given keys
+    #[cfg(feature = "encryption")]
+    pub fn decryption_keys(mut self, keys: DecryptionKeys) -> Self {
+        self.keys = keys;
+        self
+    }
+}
+
+/// No quotes that may be served were found in a quote directory
+///
+/// This is the inner error of the [`io::Error`] returned when indexing such a directory.
+#[derive(Debug)]
+pub struct NoQuotesError {
+    pub dir: PathBuf,
+}
+
+impl std::fmt::Display for NoQuotesError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "No quotes in allowed categories and languages found in {}",
+            self.dir.display()
+        )
+    }
+}
+
+impl std::error::Error for NoQuotesError {}
+
+/// What was indexed from a quote directory, e.g. to check it before serving from it
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct IndexSummary {
+    pub files: Vec<FileSummary>,
+}
+
+impl IndexSummary {
+    /// Total number of quotes in all files
+    pub fn quotes(&self) -> usize {
+        self.files.iter().map(|file| file.quotes).sum()
+    }
+}
+
+impl std::fmt::Display for IndexSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "Indexed {} quote(s) in {} file(s)",
+            self.quotes(),
+            self.files.len()
+        )?;
+        for file in &self.files {
+            write!(f, "{:>8}  {} ({}", file.quotes, file.name, file.category)?;
+            if let Some(language) = &file.language {
+                write!(f, ", {language}")?;
+            }
+            writeln!(f, ")")?;
+        }
+        Ok(())
+    }
+}
+
+/// What was indexed from a single quote file
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct FileSummary {
+    /// The file's name, as used in [`QuoteId`]s
+    pub name: String,
+    /// Where the file was read from
+    pub path: PathBuf,
+    pub category: QuoteCategory,
+    pub language: Option<String>,
+    /// How the file's quotes are encoded on disk: "plain", "rot13", "rot47", or "keyed"
+    pub encoding: &'static str,
+    /// How the file is held while its quotes are served: "file" (read on demand), "memory",
+    /// "decoded" (in memory, already decoded), or "mapped"; see [`StorageMode`]
+    pub storage: &'static str,
+    /// Number of quotes in the file
+    pub quotes: usize,
+}
+
+/// The source of randomness quotes are chosen with
+struct QuoteRng(Box<dyn RngCore + Send>);
+
+impl std::fmt::Debug for QuoteRng {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("QuoteRng(..)")
+    }
+}
+
+/// What quotes must be to be chosen through a [`FilteredQuotes`]
+#[derive(Debug, Default, Clone)]
+struct FilterCriteria {
+    /// Categories quotes must be in, or empty for any
+    categories: Vec<QuoteCategory>,
+    /// Tags quotes' files must all have, see [`FileMetadata`]
+    tags: Vec<String>,
+    /// Languages quotes must be in, or empty for any; files that don't declare one always match
+    languages: Vec<String>,
+    lengths: QuoteLengths,
+}
+
+impl FilterCriteria {
+    fn matches(&self, file: &QuoteFile) -> bool {
+        (self.categories.is_empty() || self.categories.contains(&file.category))
+            && self.tags.iter().all(|tag| file.metadata.tags.contains(tag))
+            && (self.languages.is_empty()
+                || file
+                    .language
+                    .as_ref()
+                    .is_none_or(|language| self.languages.contains(language)))
+    }
+}
+
+/// Builds a view of only some quotes to choose from, see [`Quotes::filter`]
+#[derive(Debug)]
+pub struct QuoteFilter<'a> {
+    quotes: &'a mut Quotes,
+    criteria: FilterCriteria,
+}
+
+impl<'a> QuoteFilter<'a> {
+    /// Only choose quotes in `category`, or in any of the categories given to calls of this
+    pub fn category(mut self, category: QuoteCategory) -> Self {
+        self.criteria.categories.push(category);
+        self
+    }
+
+    /// Only choose quotes from files tagged `tag` in their front-matter, and any other tags given
+    /// to calls of this
+    pub fn tag<S: AsRef<str>>(mut self, tag: S) -> Self {
+        let tag = tag.as_ref().trim().to_ascii_lowercase();
+        self.criteria.tags.push(tag);
+        self
+    }
+
+    /// Only choose quotes in `language`, or in any of the languages given to calls of this
+    ///
+    /// Quotes from files that don't declare a language are chosen regardless.
+    pub fn language<S: AsRef<str>>(mut self, language: S) -> Self {
+        let language = language.as_ref().trim().to_ascii_lowercase();
+        self.criteria.languages.push(language);
+        self
+    }
+
+    /// Only choose quotes at least `length` bytes long, as they're stored in their files
+    pub fn min_len(mut self, length: usize) -> Self {
+        self.criteria.lengths.shortest = Some(length);
+        self
+    }
+
+    /// Only choose quotes at most `length` bytes long, as they're stored in their files
+    pub fn max_len(mut self, length: usize) -> Self {
+        self.criteria.lengths.longest = Some(length);
+        self
+    }
+
+    /// The view of the quotes that pass the filter
+    pub fn build(self) -> FilteredQuotes<'a> {
+        FilteredQuotes {
+            quotes: self.quotes,
+            criteria: self.criteria,
+        }
+    }
+}
+
+/// A view of only some quotes to choose from, see [`Quotes::filter`]
+#[derive(Debug)]
+pub struct FilteredQuotes<'a> {
+    quotes: &'a mut Quotes,
+    criteria: FilterCriteria,
+}
+
+impl FilteredQuotes<'_> {
+    /// Choose a quote like [`Quotes::random_quote`], but only among those that pass the filter
+    ///
+    /// Quotes are still chosen within the bounds on their lengths set on the quotes themselves
+    /// (see [`Quotes::set_lengths`]), and the schedule still applies.
+    pub async fn random_quote(&mut self) -> io::Result<Quote> {
+        let criteria = &self.criteria;
+        let lengths = self.quotes.lengths;
+        self.quotes.lengths = criteria.lengths.within(lengths);
+        let quote = self
+            .quotes
+            .sample_and_read(|quotes| quotes.sample_matching_file(criteria));
+        self.quotes.lengths = lengths;
+        quote
+    }
+
+    /// How many quotes pass the filter, regardless of the schedule or season
+    pub fn total_quotes(&self) -> usize {
+        let lengths = self.criteria.lengths.within(self.quotes.lengths);
+        self.quotes
+            .files
+            .iter()
+            .filter(|file| self.criteria.matches(file))
+            .flat_map(|file| &file.quotes)
+            .filter(|quote| lengths.contains(quote.length))
+            .count()
+    }
+}
+
+/// The files of one category, weighted for selection among themselves, so that quotes can be chosen
+/// from only some categories without weighing every file anew each time
+#[derive(Debug)]
+struct CategoryIndex {
+    category: QuoteCategory,
+    /// Indices (into [`Quotes::files`]) of the category's files
+    files: Vec<usize>,
+    weights: WeightedAliasIndex<f64>,
+    /// Sum of the files' weights, which categories are chosen between by
+    total_weight: f64,
+    /// Length of the category's shortest quote
+    shortest: usize,
+}
+
+impl CategoryIndex {
+    /// Index the files of each category that has quotes to choose from
+    fn build(files: &[QuoteFile]) -> Vec<Self> {
+        let mut categories: Vec<_> = files.iter().map(|file| file.category).collect();
+        categories.sort();
+        categories.dedup();
+        categories
+            .into_iter()
+            .filter_map(|category| {
+                let (indices, weights): (Vec<_>, Vec<_>) = files
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, file)| file.category == category)
+                    .map(|(i, file)| (i, file.weight()))
+                    .unzip();
+                let total_weight = weights.iter().sum();
+                let shortest = indices
+                    .iter()
+                    .flat_map(|&i| files[i].quotes.iter().map(|quote| quote.length))
+                    .min()?;
+                Some(Self {
+                    category,
+                    files: indices,
+                    weights: WeightedAliasIndex::new(weights).ok()?,
+                    total_weight,
+                    shortest,
+                })
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+pub struct Quotes {
+    files: Vec<QuoteFile>,
+    file_weights: WeightedAliasIndex<f64>,
+    /// Each category's files, weighted on their own
+    category_indexes: Vec<CategoryIndex>,
+    schedule: CategorySchedule,
+    /// Whether any files or quotes are restricted to certain days
+    is_seasonal: bool,
+    strategy: SelectionStrategy,
+    lengths: QuoteLengths,
+    rng: QuoteRng,
+    /// Mixed into the choice of each day's quote, see [`Quotes::daily_quote`]
+    daily_seed: u64,
+    /// Options the quotes were indexed with, which files added later are indexed with too
+    options: IndexOptions,
+    /// The directory the quotes were indexed from, if any, see [`Quotes::reload`]
+    dir: Option<PathBuf>,
+    /// How files are weighed by their recency, if they are, see [`Quotes::with_recency`]
+    recency: Option<Recency>,
+    /// When files were last weighed by their recency, if they have been since they changed
+    reweighed: Option<Instant>,
+}
+
+impl Quotes {
+    pub async fn from_dir<P: AsRef<Path> + Send + std::fmt::Debug + 'static>(
+        dir: P,
+        allowed_categories: &[QuoteCategory],
+    ) -> io::Result<Self> {
+        Self::from_dir_with_options(dir, &IndexOptions::new().categories(allowed_categories)).await
+    }
+
+    /// Index the quote files in `dir` and its subdirectories
+    ///
+    /// Fails with a [`NoQuotesError`] if none of them contain quotes that may be served. The files
+    /// are read on a blocking thread (see [`tokio::task::spawn_blocking`]), so this must be called
+    /// from within a Tokio runtime.
+    #[instrument]
+    pub fn from_dir_with_options<P: AsRef<Path> + Send + std::fmt::Debug + 'static>(
+        dir: P,
+        options: &IndexOptions,
+    ) -> BoxFuture<'_, io::Result<Self>> {
+        let span = tracing::Span::current();
+        async move {
+            let dir = dir.as_ref().to_path_buf();
+            let options = options.clone();
+            // Reading every file may take a while, during which the runtime's threads are left free
+            spawn_blocking(move || span.in_scope(|| Self::index_dir_blocking(dir, options))).await?
+        }
+        .boxed()
+    }
+
+    /// Index the quote files in `dir` and its subdirectories on the calling thread, see
+    /// [`Quotes::from_dir_with_options`]
+    pub(crate) fn index_dir_blocking(dir: PathBuf, options: IndexOptions) -> io::Result<Self> {
+        let files = Self::index_dir(&dir, &options, &mut IndexProgress::default())?;
+        let mut quotes = Self::from_files(files, options).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, NoQuotesError { dir: dir.clone() })
+        })?;
+        quotes.dir = Some(dir);
+        Ok(quotes)
+    }
+
+    /// Index the directory these quotes were indexed from afresh, with the same options, and serve
+    /// its quotes instead
+    ///
+    /// The quotes are only replaced once the directory has been indexed, so that should it fail
+    /// (e.g. with a [`NoQuotesError`]) the quotes already indexed are still served. How quotes are
+    /// chosen is unchanged, as with [`Quotes::swap_index`]. Fails with `Unsupported` for quotes not
+    /// indexed from a directory, e.g. [`Quotes::builtin`].
+    pub async fn reload(&mut self) -> io::Result<IndexSummary> {
+        let Some(dir) = self.dir.clone() else {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Quotes weren't indexed from a directory",
+            ));
+        };
+        let mut reloaded = Self::from_dir_with_options(dir, &self.options).await?;
+        self.swap_index(&mut reloaded);
+        Ok(self.summary())
+    }
+
+    /// Index the quotes read from `reader`, e.g. stdin, as though they were a single file named
+    /// `name`
+    ///
+    /// The quotes are held in memory, so the reader is read to its end at once. Fails with a
+    /// [`NoQuotesError`] if it contains no quotes that may be served.
+    pub fn from_reader<R: Read>(
+        name: &str,
+        mut reader: R,
+        options: &IndexOptions,
+    ) -> io::Result<Self> {
+        let mut contents = Vec::new();
+        reader.read_to_end(&mut contents)?;
+        Self::from_contents(name, contents, options)
+    }
+
+    /// Index the quotes read from an async `reader`, e.g. a network body or a decompressing
+    /// stream, like [`Quotes::from_reader`]
+    ///
+    /// Quotes must be read again whenever they're served, so the reader is read to its end into
+    /// memory, and needn't be seekable.
+    pub async fn from_async_reader<R: futures::AsyncRead + Unpin>(
+        name: &str,
+        mut reader: R,
+        options: &IndexOptions,
+    ) -> io::Result<Self> {
+        use futures::AsyncReadExt;
+
+        let mut contents = Vec::new();
+        reader.read_to_end(&mut contents).await?;
+        Self::from_contents(name, contents, options)
+    }
+
+    /// Index `contents` as though they were a single file named `name`
+    fn from_contents(name: &str, contents: Vec<u8>, options: &IndexOptions) -> io::Result<Self> {
+        let path = PathBuf::from(name);
+        let files = Self::process_contents(&path, Some(contents), options, options.max_quotes)?
+            .into_iter()
+            .filter(|file| match options.rejection(file) {
+                Some(reason) => {
+                    info!("Quotes read from {name} {reason}");
+                    false
+                }
+                None => true,
+            })
+            .collect();
+        Self::from_files(files, options.clone())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, NoQuotesError { dir: path }))
+    }
+
+    /// A small collection of quotes built into the program, for when no quote files are available
+    #[cfg(feature = "builtin-quotes")]
+    pub async fn builtin() -> io::Result<Self> {
+        let contents = BUILTIN_QUOTES.as_bytes().to_vec();
+        let indexed = Self::index_quotes(&mut &contents[..], None, &IndexOptions::default())?;
+        let file = QuoteFile {
+            name: BUILTIN_FILE_NAME.to_string(),
+            path: PathBuf::from(BUILTIN_FILE_NAME),
+            storage: QuoteStorage::Memory(contents),
+            quotes: indexed.quotes,
+            encoding: indexed.encoding,
+            crlf: indexed.crlf,
+            comments: indexed.comments,
+            charset: indexed.charset,
+            category: QuoteCategory::DECOROUS,
+            language: None,
+            dates: None,
+            has_dated_quotes: false,
+            metadata: indexed.metadata,
+            fingerprint: None,
+            last_served: Vec::new(),
+            modified: None,
+            recency_factor: 1.0,
+        };
+        Self::from_files(vec![file], IndexOptions::default()).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                NoQuotesError {
+                    dir: PathBuf::from(BUILTIN_FILE_NAME),
+                },
+            )
+        })
+    }
+
+    /// Serve quotes from the given files, or `None` if they contain no quotes to serve
+    fn from_files(files: Vec<QuoteFile>, options: IndexOptions) -> Option<Self> {
+        // Prepare a weighted distribution to ensure fair selection of every quote, regardless of file sizes
+        let file_weights =
+            WeightedAliasIndex::new(files.iter().map(QuoteFile::weight).collect()).ok()?;
+
+        let category_indexes = CategoryIndex::build(&files);
+        let is_seasonal = files.iter().any(QuoteFile::is_seasonal);
+
+        Some(Self {
+            files,
+            file_weights,
+            category_indexes,
+            schedule: CategorySchedule::default(),
+            strategy: SelectionStrategy::default(),
+            lengths: QuoteLengths::default(),
+            is_seasonal,
+            rng: QuoteRng(Box::new(StdRng::from_entropy())),
+            daily_seed: 0,
+            options,
+            dir: None,
+            recency: None,
+            reweighed: None,
+        })
+    }
+
+    /// Index the quote file at `path` with the options these quotes were indexed with, replacing it
+    /// if it was already indexed, and make its quotes eligible for selection at once
+    ///
+    /// Fails with `InvalidInput` if the file must not be served, e.g. because its category isn't
+    /// allowed. Like [`Quotes::from_dir_with_options`], this must be called from within a Tokio
+    /// runtime.
+    pub async fn add_file<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let max_quotes = self.max_quotes_replacing(path);
+        let (owned, options) = (path.to_path_buf(), self.options.clone());
+        // The file is read on a blocking thread, so as not to hold up the runtime's other tasks
+        let added =
+            spawn_blocking(move || Self::process_file(owned, &options, max_quotes)).await??;
+        self.splice_file(path, added)
+    }
+
+    /// Index the quote file at `path` afresh, splicing its quotes into those already indexed in
+    /// place of its old ones, or stop serving it if it no longer exists
+    ///
+    /// Only this file is read, however many others there are. A relative `path` is taken to be
+    /// within the directory the quotes were indexed from, if any. Fails as [`Quotes::add_file`]
+    /// does, or with `NotFound` if the file neither exists nor was indexed.
+    pub async fn reindex_file<P: AsRef<Path>>(&mut self, path: P) -> io::Result<IndexSummary> {
+        let path = match &self.dir {
+            Some(dir) if path.as_ref().is_relative() => dir.join(path),
+            _ => path.as_ref().to_path_buf(),
+        };
+        if path.exists() {
+            self.add_file(&path).await?;
+        } else if self.positions(&path).is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Quote file \"{}\" doesn't exist", path.display()),
+            ));
+        } else {
+            info!("Quote file \"{}\" was removed", path.display());
+            self.remove_file(&path)?;
+        }
+        Ok(self.summary())
+    }
+
+    /// Index the quote file at `path` on the calling thread, like [`Quotes::add_file`]
+    fn index_file(&mut self, path: &Path) -> io::Result<()> {
+        let max_quotes = self.max_quotes_replacing(path);
+        let added = Self::process_file(path, &self.options, max_quotes)?;
+        self.splice_file(path, added)
+    }
+
+    /// How many quotes may be indexed from the file at `path`, in place of any it was indexed with
+    fn max_quotes_replacing(&self, path: &Path) -> Option<usize> {
+        let existing = self.positions(path);
+        let others = self
+            .files
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| !existing.contains(i))
+            .map(|(_, file)| file.quotes.len())
+            .sum::<usize>();
+        self.options
+            .max_quotes
+            .map(|max| max.saturating_sub(others))
+    }
+
+    /// Serve the files `added` from `path`, in place of any already indexed from it
+    fn splice_file(&mut self, path: &Path, mut added: Vec<QuoteFile>) -> io::Result<()> {
+        let existing = self.positions(path);
+        if added.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Quote file can't be indexed",
+            ));
+        }
+        // Quotes reclassified as offensive may well not be served, but the file as a whole must be
+        if added
+            .iter()
+            .all(|file| self.options.rejection(file).is_some())
+        {
+            let reason = self.options.rejection(&added[0]).unwrap_or_default();
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Quote file \"{}\" {reason}", path.display()),
+            ));
+        }
+        added.retain(|file| self.options.rejection(file).is_none());
+
+        if self.strategy == SelectionStrategy::LeastRecentlyServed {
+            // Quotes are only ever appended, so those already served keep their place
+            for file in &mut added {
+                file.last_served = existing
+                    .iter()
+                    .find(|&&i| self.files[i].name == file.name)
+                    .map(|&i| std::mem::take(&mut self.files[i].last_served))
+                    .unwrap_or_default();
+                file.last_served.resize(file.quotes.len(), None);
+            }
+        }
+        let weights = self
+            .files
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| !existing.contains(i))
+            .map(|(_, other)| other.weight())
+            .chain(added.iter().map(QuoteFile::weight))
+            .collect();
+        self.file_weights = WeightedAliasIndex::new(weights)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        for file in &added {
+            self.is_seasonal |= file.is_seasonal();
+            info!(
+                "Indexed file \"{}\" containing {} entries",
+                file.path.display(),
+                file.quotes.len()
+            );
+        }
+        let mut i = 0;
+        self.files.retain(|_| {
+            i += 1;
+            !existing.contains(&(i - 1))
+        });
+        self.files.append(&mut added);
+        self.category_indexes = CategoryIndex::build(&self.files);
+        // Added files are yet to be weighed by their recency
+        self.reweighed = None;
+
+        Ok(())
+    }
+
+    /// Stop serving the quotes indexed from the file at `path`
+    ///
+    /// The last file is never removed, so that the files and their weights always agree.
+    fn remove_file(&mut self, path: &Path) -> io::Result<()> {
+        let existing = self.positions(path);
+        let weights = self
+            .files
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| !existing.contains(i))
+            .map(|(_, file)| file.weight())
+            .collect();
+        self.file_weights = WeightedAliasIndex::new(weights)
+            .map_err(|_| io::Error::new(io::ErrorKind::NotFound, "No quotes are left to serve"))?;
+        let mut i = 0;
+        self.files.retain(|_| {
+            i += 1;
+            !existing.contains(&(i - 1))
+        });
+        self.category_indexes = CategoryIndex::build(&self.files);
+        Ok(())
+    }
+
+    /// Re-index the file at `file_index` if it changed since it was indexed (e.g. it was rewritten
+    /// by log rotation), or stop serving it if it can't be; returns whether it was changed
+    ///
+    /// Quotes are read by their offsets in the file, so serving from a changed file would send
+    /// whatever now happens to be there.
+    fn reindex_if_changed(&mut self, file_index: usize) -> io::Result<bool> {
+        let file = &self.files[file_index];
+        if !file.is_changed() {
+            return Ok(false);
+        }
+        let path = file.path.clone();
+        warn!(
+            "Quote file \"{}\" changed since it was indexed; re-indexing it",
+            path.display()
+        );
+        if let Err(e) = self.index_file(&path) {
+            warn!("Skipping quote file \"{}\": {e}", path.display());
+            self.remove_file(&path)?;
+        }
+        Ok(true)
+    }
+
+    /// Select a file with `sample`, selecting again whenever the file was changed since it was
+    /// indexed (see [`Quotes::reindex_if_changed`])
+    fn sample_unchanged<F: FnMut(&mut Self) -> io::Result<usize>>(
+        &mut self,
+        mut sample: F,
+    ) -> io::Result<usize> {
+        loop {
+            let file_index = sample(self)?;
+            if !self.reindex_if_changed(file_index)? {
+                return Ok(file_index);
+            }
+        }
+    }
+
+    /// Append a quote to the plain quote file at `path`, creating it if need be, and make it
+    /// eligible for selection at once (see [`Quotes::add_file`])
+    ///
+    /// Fails with `InvalidInput` if the quote is empty or would be misread, e.g. because one of its
+    /// lines begins with the file's separator.
+    pub async fn append_quote<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        text: &str,
+    ) -> io::Result<QuoteId> {
+        let path = path.as_ref();
+        // Any file of reclassified quotes comes after the one they were read from
+        let existing = self.positions(path).first().map(|&i| &self.files[i]);
+        let separator = existing.map_or(SEPARATOR, |file| file.metadata.separator());
+        let invalid =
+            |message: &str| io::Error::new(io::ErrorKind::InvalidInput, message.to_string());
+
+        if existing.is_some_and(|file| file.encoding != FileEncoding::Plain) {
+            return Err(invalid("Quotes can only be appended to unencoded files"));
+        }
+        if self.options.max_quotes.is_some_and(|max| {
+            self.files
+                .iter()
+                .map(|file| file.quotes.len())
+                .sum::<usize>()
+                >= max
+        }) {
+            return Err(invalid("The limit of quotes to index has been reached"));
+        }
+        let text = text.replace("\r\n", "\n");
+        let text = text.trim_end();
+        if text.trim().is_empty() {
+            return Err(invalid("Quote is empty"));
+        }
+        for line in text.lines() {
+            if line.starts_with(separator) {
+                return Err(invalid("Quote has a line beginning with the separator"));
+            }
+            if FileEncoding::detect(line).is_some() {
+                return Err(invalid("Quote contains an encoding token"));
+            }
+        }
+        if self.options.is_offensive(text) && !self.options.allows(QuoteCategory::OFFENSIVE) {
+            return Err(invalid("Quote contains a word flagged as offensive"));
+        }
 
-#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
-pub enum QuoteCategory {
-    #[default]
-    Decorous,
-    Offensive,
-}
+        let (owned, text, separator) =
+            (path.to_path_buf(), text.to_string(), separator.to_string());
+        // Syncing the file waits for the disk
+        spawn_blocking(move || Self::append_to_file(&owned, &text, &separator)).await??;
 
-const SEPARATOR: &str = "%";
-const ROT31_TOKEN: &str = "$SerrOFQ$";
-const PLAIN_TOKEN: &str = "$FreeBSD$";
-const OFFENSIVE_SUFFIX: &str = "-o";
+        self.add_file(path).await?;
+        // The new quote is the last of the file, whether or not it was reclassified
+        let file = self
+            .positions(path)
+            .into_iter()
+            .map(|i| &self.files[i])
+            .max_by_key(|file| file.quotes.last().map(|quote| quote.offset))
+            .expect("file was just added");
+        let index = file.quotes.len() - 1;
+        Ok(QuoteId {
+            file: file.name.clone(),
+            index,
+            hash: file.quotes[index].hash,
+        })
+    }
 
-#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
-enum FileEncoding {
-    #[default]
-    Plain,
-    Rot13,
-}
+    /// Append the validated quote `text` to the file at `path`, whose quotes end with `separator`
+    fn append_to_file(path: &Path, text: &str, separator: &str) -> io::Result<()> {
+        let mut contents = String::new();
+        let mut file = std::fs::OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+        let mut current = String::new();
+        file.read_to_string(&mut current)?;
+        if current.is_empty() {
+            // Declare the new file unencoded, on a separator line of its own
+            contents.push_str(&format!("{separator}{separator}{PLAIN_TOKEN}\n"));
+        } else {
+            if !current.ends_with('\n') {
+                contents.push('\n');
+            }
+            // The file's last quote must be ended before another can follow it
+            let ended = current
+                .lines()
+                .rev()
+                .find(|line| !line.is_empty())
+                .is_some_and(|line| line.starts_with(separator));
+            if !ended {
+                contents.push_str(&format!("{separator}\n"));
+            }
+        }
+        contents.push_str(&format!("{text}\n{separator}\n"));
+        file.write_all(contents.as_bytes())?;
+        file.sync_data()?;
+        Ok(())
+    }
 
-#[derive(Debug, Default, Clone, Copy)]
-struct QuoteIndex {
-    offset: u64,
-    length: usize,
-}
+    /// Indices of the files read from `path`, i.e. the file itself and any reclassified quotes
+    fn positions(&self, path: &Path) -> Vec<usize> {
+        let canonical = std::fs::canonicalize(path).ok();
+        self.files
+            .iter()
+            .enumerate()
+            .filter(|(_, file)| {
+                file.path == path
+                    || canonical.is_some() && std::fs::canonicalize(&file.path).ok() == canonical
+            })
+            .map(|(i, _)| i)
+            .collect()
+    }
 
-#[derive(Debug)]
-struct QuoteFile {
-    file_handle: File,
-    quotes: Vec<QuoteIndex>,
-    encoding: FileEncoding,
-    category: QuoteCategory,
-}
+    /// Index the files in `dir`, recursing into subdirectories
+    ///
+    /// `indexed` counts the quotes indexed and files read so far, across all directories, against
+    /// the limits. Files are indexed in order of their names, rather than the order the filesystem
+    /// lists them in, so that files' indices, and the quotes a seeded RNG (see
+    /// [`Quotes::with_rng`]) chooses, are the same on any machine.
+    fn index_dir(
+        dir: &Path,
+        options: &IndexOptions,
+        indexed: &mut IndexProgress,
+    ) -> io::Result<Vec<QuoteFile>> {
+        let mut files = Vec::new();
 
-#[derive(Debug)]
-pub struct Quotes {
-    files: Vec<QuoteFile>,
-    file_weights: WeightedAliasIndex<usize>,
-}
+        let mut entries = read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            let max_quotes = options
+                .max_quotes
+                .map(|max| max.saturating_sub(indexed.quotes));
+            if max_quotes == Some(0) {
+                warn!(
+                    "Skipping remaining files in \"{}\": the limit of {} quote(s) was reached",
+                    dir.display(),
+                    options.max_quotes.unwrap_or_default()
+                );
+                break;
+            }
 
-impl Quotes {
-    #[instrument]
-    pub fn from_dir<P: AsRef<Path> + Send + std::fmt::Debug + 'static>(
-        dir: P,
-        allowed_categories: &[QuoteCategory],
-    ) -> BoxFuture<'_, io::Result<Self>> {
-        async move {
-            let mut files = Vec::new();
-
-            let mut entries = read_dir(dir).await?;
-            while let Some(entry) = entries.next_entry().await? {
-                if entry.file_type().await?.is_dir() {
-                    files.append(
-                        &mut Self::from_dir(entry.path(), allowed_categories)
-                            .await?
-                            .files,
+            if entry.file_type()?.is_dir() {
+                files.append(&mut Self::index_dir(&entry.path(), options, indexed)?);
+            } else if entry.file_type()?.is_file() {
+                if options.max_files.is_some_and(|max| indexed.files >= max) {
+                    warn!(
+                        "Skipping remaining files in \"{}\": the limit of {} file(s) was reached",
+                        dir.display(),
+                        options.max_files.unwrap_or_default()
                     );
-                } else if entry.file_type().await?.is_file() {
-                    let file = Self::process_file(entry.path()).await?;
-                    if allowed_categories.contains(&file.category) && !file.quotes.is_empty() {
+                    break;
+                }
+                indexed.files += 1;
+                for file in Self::process_file(entry.path(), options, max_quotes)? {
+                    if let Some(reason) = options.rejection(&file) {
+                        info!("File \"{}\" {reason}", file.path.display());
+                    } else {
                         info!(
                             "Indexed file \"{}\" containing {} entries",
-                            entry.path().to_str().unwrap(),
+                            file.path.display(),
                             file.quotes.len()
                         );
+                        indexed.quotes += file.quotes.len();
                         files.push(file);
-                    } else {
-                        info!(
-                            "File \"{}\" is not in allowed categories",
-                            entry.path().to_str().unwrap()
-                        );
                     }
                 }
             }
+        }
+
+        Ok(files)
+    }
+
+    /// Index a single file, up to `max_quotes` of its quotes
+    ///
+    /// Returns no files if it must be skipped, or a second file holding any quotes reclassified as
+    /// offensive (see [`IndexOptions::offensive_words`]).
+    fn process_file<P: AsRef<Path>>(
+        path: P,
+        options: &IndexOptions,
+        max_quotes: Option<usize>,
+    ) -> io::Result<Vec<QuoteFile>> {
+        Self::process_contents(path.as_ref(), None, options, max_quotes)
+    }
+
+    /// Index a single file like [`Quotes::process_file`], from `contents` if given rather than
+    /// from the file at `path`, which then only names it
+    fn process_contents(
+        path: &Path,
+        contents: Option<Vec<u8>>,
+        options: &IndexOptions,
+        max_quotes: Option<usize>,
+    ) -> io::Result<Vec<QuoteFile>> {
+        if let Some(max) = options.max_file_size {
+            let size = match &contents {
+                Some(contents) => contents.len() as u64,
+                None => std::fs::metadata(path)?.len(),
+            };
+            if size > max {
+                warn!(
+                    "Skipping file \"{}\": its size of {size} bytes exceeds the limit of {max} bytes",
+                    path.display()
+                );
+                return Ok(Vec::new());
+            }
+        }
+        let encrypted = path
+            .extension()
+            .is_some_and(|ext| ext == ENCRYPTED_EXTENSION);
+        // Encrypted files are categorized by the name they'd have when decrypted
+        let mut name_path = if encrypted {
+            path.with_extension("")
+        } else {
+            path.to_path_buf()
+        };
+        let language = Self::language_suffix(&mut name_path);
+        let dates = Self::date_suffix(&mut name_path);
+
+        let modified = contents
+            .is_none()
+            .then(|| std::fs::metadata(path).and_then(|metadata| metadata.modified()))
+            .and_then(Result::ok);
+        let (storage, indexed, fingerprint) = if let Some(contents) = contents {
+            let indexed = Self::index_quotes(&mut &contents[..], max_quotes, options)?;
+            (QuoteStorage::Memory(contents), indexed, None)
+        } else if encrypted {
+            #[cfg(feature = "encryption")]
+            {
+                if options.keys.is_empty() {
+                    warn!(
+                        "Skipping encrypted file \"{}\": no decryption keys provided",
+                        path.display()
+                    );
+                    return Ok(Vec::new());
+                }
+                let decrypted = options.keys.decrypt(&std::fs::read(path)?)?;
+                let indexed = Self::index_quotes(&mut &decrypted[..], max_quotes, options)?;
+                (QuoteStorage::Memory(decrypted), indexed, None)
+            }
+            #[cfg(not(feature = "encryption"))]
+            {
+                warn!(
+                    "Skipping encrypted file \"{}\": encryption support is not enabled",
+                    path.display()
+                );
+                return Ok(Vec::new());
+            }
+        } else {
+            let file = File::open(path)?;
+            let metadata = file.metadata()?;
+            let fingerprint = FileFingerprint::of(&metadata);
+            match options.storage {
+                // Empty files can't be mapped, but have no quotes to read either
+                StorageMode::Mmap if metadata.len() > 0 => {
+                    let mapping = Mapping::of(&file)?;
+                    let indexed = Self::index_quotes(&mut &mapping[..], max_quotes, options)?;
+                    (
+                        QuoteStorage::Mapped(Arc::new(mapping)),
+                        indexed,
+                        Some(fingerprint),
+                    )
+                }
+                // Preloaded files are read from memory, so it no longer matters if they change
+                StorageMode::Preload => {
+                    let mut contents = Vec::with_capacity(metadata.len() as usize);
+                    BufReader::new(file).read_to_end(&mut contents)?;
+                    let indexed = Self::index_quotes(&mut &contents[..], max_quotes, options)?;
+                    (QuoteStorage::Memory(contents), indexed, None)
+                }
+                _ => {
+                    let mut buf_read = BufReader::new(file);
+                    let indexed = Self::index_quotes(&mut buf_read, max_quotes, options)?;
+                    (
+                        QuoteStorage::File(buf_read.into_inner()),
+                        indexed,
+                        Some(fingerprint),
+                    )
+                }
+            }
+        };
+
+        // Preloaded quotes are read from memory, so it no longer matters if the file changes
+        let (storage, fingerprint) =
+            if options.preload_encoded && indexed.encoding != FileEncoding::Plain {
+                let decoded = Self::preload(storage, &indexed)?;
+                (QuoteStorage::Decoded(decoded), None)
+            } else {
+                (storage, fingerprint)
+            };
+
+        let category = indexed.metadata.category.unwrap_or_else(|| {
+            // Only the file's own directory counts, lest e.g. a quote directory under "/srv/off"
+            // make every quote offensive
+            let in_offensive_dir = name_path
+                .parent()
+                .and_then(Path::file_name)
+                .and_then(|dir| dir.to_str())
+                .is_some_and(|dir| OFFENSIVE_DIRS.contains(&dir));
+            if in_offensive_dir
+                || name_path
+                    .to_str()
+                    .unwrap_or(OFFENSIVE_SUFFIX)
+                    .ends_with(OFFENSIVE_SUFFIX)
+            {
+                QuoteCategory::OFFENSIVE
+            } else {
+                QuoteCategory::DECOROUS
+            }
+        });
+
+        if indexed.excluded > 0 {
+            info!(
+                "Excluded {} quote(s) of \"{}\" matching an exclusion pattern",
+                indexed.excluded,
+                path.display()
+            );
+        }
+        if indexed.denied > 0 {
+            info!(
+                "Excluded {} quote(s) of \"{}\" on the deny-list",
+                indexed.denied,
+                path.display()
+            );
+        }
+        if indexed.unseparated {
+            warn!(
+                "File \"{}\" contains no separators, so is served as a single quote",
+                path.display()
+            );
+        }
+        if indexed.late_encoding {
+            warn!(
+                "Ignored an encoding token after the first quote of \"{}\"; it must precede them",
+                path.display()
+            );
+        }
+        if indexed.truncated {
+            warn!(
+                "Only indexed the first {} quote(s) of \"{}\": the limit of {} quote(s) was reached",
+                indexed.quotes.len(),
+                path.display(),
+                options.max_quotes.unwrap_or_default()
+            );
+        }
 
-            // Prepare a weighted distribution to ensure fair selection of every quote, regardless of file sizes
-            let file_weights =
-                WeightedAliasIndex::new(files.iter().map(|file| file.quotes.len()).collect())
-                    .unwrap();
+        let name = name_path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let language = indexed.metadata.language.clone().or(language);
+        let dates = indexed.metadata.dates.or(dates);
+        let mut quotes = indexed.quotes;
+        let mut offensive = Vec::new();
+        if category == QuoteCategory::DECOROUS && !indexed.flagged.is_empty() {
+            info!(
+                "Reclassified {} quote(s) of \"{}\" as offensive",
+                indexed.flagged.len(),
+                path.display()
+            );
+            let mut index = 0;
+            quotes.retain(|&quote| {
+                let flagged = indexed.flagged.binary_search(&index).is_ok();
+                if flagged {
+                    offensive.push(quote);
+                }
+                index += 1;
+                !flagged
+            });
+        }
 
-            Ok(Self {
-                files,
-                file_weights,
+        // Reclassified quotes are served from a file of their own, read through its own handle
+        let reclassified = if offensive.is_empty() {
+            None
+        } else {
+            let storage = match &storage {
+                QuoteStorage::File(_) => QuoteStorage::File(File::open(path)?),
+                QuoteStorage::Memory(contents) => QuoteStorage::Memory(contents.clone()),
+                QuoteStorage::Decoded(contents) => QuoteStorage::Decoded(contents.clone()),
+                QuoteStorage::Mapped(mapping) => QuoteStorage::Mapped(Arc::clone(mapping)),
+            };
+            Some(QuoteFile {
+                name: format!("{name}{OFFENSIVE_SUFFIX}"),
+                path: path.to_path_buf(),
+                storage,
+                has_dated_quotes: offensive.iter().any(QuoteIndex::is_dated),
+                quotes: offensive,
+                encoding: indexed.encoding.clone(),
+                crlf: indexed.crlf,
+                comments: indexed.comments.clone(),
+                charset: indexed.charset,
+                category: QuoteCategory::OFFENSIVE,
+                language: language.clone(),
+                dates,
+                metadata: indexed.metadata.clone(),
+                fingerprint,
+                last_served: Vec::new(),
+                modified,
+                recency_factor: 1.0,
             })
+        };
+
+        let mut files = vec![QuoteFile {
+            name,
+            path: path.to_path_buf(),
+            storage,
+            has_dated_quotes: quotes.iter().any(QuoteIndex::is_dated),
+            quotes,
+            encoding: indexed.encoding,
+            crlf: indexed.crlf,
+            comments: indexed.comments,
+            charset: indexed.charset,
+            category,
+            language,
+            dates,
+            metadata: indexed.metadata,
+            fingerprint,
+            last_served: Vec::new(),
+            modified,
+            recency_factor: 1.0,
+        }];
+        files.extend(reclassified);
+        Ok(files)
+    }
+
+    /// Read a file's contents into memory, decoding each of its quotes in place
+    fn preload(storage: QuoteStorage, indexed: &IndexedQuotes) -> io::Result<Vec<u8>> {
+        let mut contents = match storage {
+            QuoteStorage::File(mut file) => {
+                let mut contents = Vec::new();
+                file.rewind()?;
+                file.read_to_end(&mut contents)?;
+                contents
+            }
+            QuoteStorage::Memory(contents) => contents,
+            QuoteStorage::Decoded(contents) => return Ok(contents),
+            QuoteStorage::Mapped(mapping) => mapping.to_vec(),
+        };
+        for quote in &indexed.quotes {
+            let start = quote.offset as usize;
+            let text = contents
+                .get_mut(start..start + quote.length)
+                .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+            // Comments are left as they are, to be found and dropped when the quote is read
+            match &indexed.comments {
+                Some(comments) => comments.decode(&indexed.encoding, text),
+                None => indexed.encoding.decode(text),
+            }
         }
-        .boxed()
+        Ok(contents)
     }
 
-    async fn process_file<P: AsRef<Path>>(path: P) -> io::Result<QuoteFile> {
-        let path = path.as_ref();
+    /// Strip a trailing date window (e.g. `christmas [12-20..12-27]`) from the file name,
+    /// returning it if there was a valid one
+    fn date_suffix(name_path: &mut PathBuf) -> Option<DateWindow> {
+        let name = name_path.file_name()?.to_str()?;
+        let (stem, dates) = name.strip_suffix(']')?.rsplit_once('[')?;
+        match dates.parse() {
+            Ok(dates) => {
+                let stem = stem.trim_end().to_string();
+                name_path.set_file_name(stem);
+                Some(dates)
+            }
+            Err(e) => {
+                warn!("Ignoring dates in file name \"{name}\": {e}");
+                None
+            }
+        }
+    }
 
-        let category = if path
-            .to_str()
-            .unwrap_or(OFFENSIVE_SUFFIX)
-            .ends_with(OFFENSIVE_SUFFIX)
+    /// Strip a `.txt` extension and a preceding two-letter language code (e.g. `quotes.de.txt`)
+    /// from the path, returning the language code if there was one
+    fn language_suffix(name_path: &mut PathBuf) -> Option<String> {
+        if name_path
+            .extension()
+            .is_some_and(|ext| ext == TEXT_EXTENSION)
         {
-            QuoteCategory::Offensive
-        } else {
-            QuoteCategory::Decorous
-        };
+            name_path.set_extension("");
+        }
+
+        let language = name_path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .filter(|ext| ext.len() == 2 && ext.chars().all(|c| c.is_ascii_alphabetic()))
+            .map(|ext| ext.to_ascii_lowercase());
+        if language.is_some() {
+            name_path.set_extension("");
+        }
+        language
+    }
+
+    /// Parse a date window (e.g. `% [12-24..12-26]`) and validity dates (e.g.
+    /// `% [..2026-06-30]`), either or both, following the separator on a separator line, which
+    /// restrict the quote after it to those days
+    fn separator_dates(line: &str) -> (Option<DateWindow>, Option<Validity>) {
+        let mut dates = None;
+        let mut validity = None;
+        let mut rest = line.trim_end();
+        while let Some(bracketed) = rest.strip_suffix(']') {
+            let Some(start) = bracketed.rfind('[') else {
+                break;
+            };
+            let window = &bracketed[start + 1..];
+            if Validity::is_validity(window) {
+                validity = window
+                    .parse()
+                    .inspect_err(|e| warn!("Ignoring quote validity: {e}"))
+                    .ok();
+            } else {
+                dates = window
+                    .parse()
+                    .inspect_err(|e| warn!("Ignoring quote dates: {e}"))
+                    .ok();
+            }
+            rest = bracketed[..start].trim_end();
+        }
+        (dates, validity)
+    }
 
-        let fh = File::open(path).await?;
-        let mut buf_read = BufReader::new(fh);
+    /// Scan up to `max_quotes` quotes from a reader, returning their indexes, the detected encoding,
+    /// and any metadata
+    fn index_quotes<R: BufRead>(
+        buf_read: &mut R,
+        max_quotes: Option<usize>,
+        options: &IndexOptions,
+    ) -> io::Result<IndexedQuotes> {
+        let mut metadata = FileMetadata::default();
+        let mut in_front_matter = false;
 
         let mut offset = 0;
         let mut last_offset = 0;
+        let mut dates = None;
+        let mut validity = None;
 
         // Start with a large capacity to reduce reallocations
-        let mut quotes = Vec::with_capacity(0xFFF);
+        let mut quotes = Vec::with_capacity(max_quotes.unwrap_or(usize::MAX).min(0xFFF));
+        let mut truncated = false;
+        let mut late_encoding = false;
+        // Whether any quote has been seen, after which the encoding can no longer change
+        let mut seen_quote = false;
+        let mut excluded = 0;
+        let mut denied = 0;
+        let mut flagged = Vec::new();
+        // The decoded text of the current quote, as it will be served
+        let mut text = String::new();
+        let mut printable = 0;
         let mut encoding = FileEncoding::Plain;
         let mut encoding_found = false;
+        let mut crlf = false;
+        let mut comments_in_quotes = false;
+        let mut separated = false;
 
         // Initialize a large capacity for the buffer to avoid reallocations
-        let mut line_buf = String::with_capacity(0xFF);
+        let mut raw_line = Vec::with_capacity(0xFF);
+
+        // Where the file's text starts, after any byte-order mark
+        let mut start = 0;
+
+        loop {
+            raw_line.clear();
+            // Text after the last separator is ended by the end of the file, as fortune does
+            let at_end = buf_read.read_until(b'\n', &mut raw_line)? == 0;
+            if at_end && (in_front_matter || !options.trailing_quote || text.trim().is_empty()) {
+                break;
+            }
+            if offset == 0 && raw_line.starts_with(BYTE_ORDER_MARK) {
+                raw_line.drain(..BYTE_ORDER_MARK.len());
+                start = BYTE_ORDER_MARK.len();
+                offset = start;
+                last_offset = start;
+            }
+            // Offsets are of the file as it is, whatever it's transcoded into
+            let line_len = raw_line.len();
+            // The front-matter may yet change the charset, but is ASCII whatever it is
+            let line_buf = metadata
+                .charset
+                .unwrap_or(options.charset)
+                .decode(&raw_line)?;
+            crlf |= line_buf.ends_with("\r\n");
+            let is_delimiter = line_buf.trim_end() == FRONT_MATTER_DELIMITER;
+            if in_front_matter || (offset == start && is_delimiter) {
+                match (offset == start, is_delimiter) {
+                    (true, _) => in_front_matter = true,
+                    (_, true) => in_front_matter = false,
+                    (_, false) => metadata.parse_line(&line_buf),
+                }
+                // The front-matter is never part of a quote
+                offset += line_len;
+                last_offset = offset;
+                continue;
+            }
 
-        while buf_read.read_line(&mut line_buf).await? > 0 {
             if !encoding_found {
-                if line_buf.contains(ROT31_TOKEN) {
-                    encoding = FileEncoding::Rot13;
-                    encoding_found = true;
-                } else if line_buf.contains(PLAIN_TOKEN) {
-                    encoding = FileEncoding::Plain;
+                if let Some(detected) = FileEncoding::detect(&line_buf) {
+                    // Quotes already seen were taken to be plain, so changing now would be
+                    // inconsistent
+                    if seen_quote {
+                        late_encoding = true;
+                    } else {
+                        encoding = detected;
+                    }
                     encoding_found = true;
                 }
             }
 
-            let line_len = line_buf.len();
-            if line_buf.starts_with(SEPARATOR) {
+            let separator = metadata.separator();
+            if !at_end
+                && Comments::is_comment_line(
+                    &raw_line,
+                    separator,
+                    options.comment_prefix.as_deref(),
+                )
+            {
+                // Comments before a quote are simply skipped; any within one are dropped when it's
+                // read
+                if offset == last_offset {
+                    last_offset += line_len;
+                } else {
+                    comments_in_quotes = true;
+                }
+                offset += line_len;
+                continue;
+            }
+
+            if at_end || encoding.is_separator(&line_buf, separator) {
+                separated |= !at_end;
                 let len = offset - last_offset;
-                if len > 0 {
+                seen_quote |= len > 0;
+                if len > 0
+                    && options
+                        .exclude
+                        .iter()
+                        .any(|pattern| pattern.is_match(&text))
+                {
+                    excluded += 1;
+                } else if len > 0
+                    && !options.denied_hashes.is_empty()
+                    && options
+                        .denied_hashes
+                        .contains(&format!("{:016x}", text_hash(&text)))
+                {
+                    denied += 1;
+                } else if len > 0 && max_quotes.is_some_and(|max| quotes.len() >= max) {
+                    truncated = true;
+                    break;
+                } else if len > 0 {
+                    if options.is_offensive(&text) {
+                        flagged.push(quotes.len());
+                    }
                     quotes.push(QuoteIndex {
                         offset: last_offset as u64,
                         length: len,
+                        dates,
+                        validity,
+                        hash: text_hash(&text),
                     });
                 }
                 last_offset = offset + line_len;
+                (dates, validity) = Self::separator_dates(&line_buf);
+                text.clear();
+                printable = 0;
+            } else {
+                let mut line = line_buf.as_bytes().to_vec();
+                printable += encoding.decode_chunk(&mut line, printable);
+                // As served, so that e.g. hashes match those of the same quote with "\n" endings
+                strip_carriage_returns(&mut line);
+                text.push_str(&String::from_utf8_lossy(&line));
             }
             offset += line_len;
-            line_buf.clear();
+        }
+
+        if in_front_matter {
+            warn!("Front-matter was never closed; no quotes were indexed");
         }
 
         // No need to maintain extra capacity after this point, as the data should remain static
         quotes.shrink_to_fit();
 
-        Ok(QuoteFile {
-            file_handle: buf_read.into_inner(),
+        let comments = comments_in_quotes.then(|| Comments {
+            separator: metadata.separator().to_string(),
+            prefix: options.comment_prefix.clone(),
+        });
+
+        let charset = metadata.charset.unwrap_or(options.charset);
+        let unseparated = !separated && !quotes.is_empty();
+
+        Ok(IndexedQuotes {
             quotes,
             encoding,
-            category,
+            crlf,
+            comments,
+            charset,
+            metadata,
+            truncated,
+            late_encoding,
+            excluded,
+            denied,
+            flagged,
+            unseparated,
         })
     }
 
-    pub async fn random_quote(&mut self) -> io::Result<Vec<u8>> {
-        // We have to select an index, rather than using `rand`'s SliceSequence trait, to avoid
-        // holding the non-`Send` RNG across awaits - although I'm sure there's a way around that
-        let i = self.file_weights.sample(&mut thread_rng());
-        self.read_quote(i).await
+    /// Restrict categories to the time windows of the given schedule
+    ///
+    /// Only categories that were indexed can be served, so any scheduled category must also have
+    /// been allowed when loading the quotes.
+    pub fn with_schedule(mut self, schedule: CategorySchedule) -> Self {
+        self.schedule = schedule;
+        self
     }
 
-    pub async fn read_quote(&mut self, file_index: usize) -> io::Result<Vec<u8>> {
+    /// Options the quotes were indexed with
+    pub fn index_options(&self) -> &IndexOptions {
+        &self.options
+    }
+
+    /// Limit the files quotes are read from to reading, in preparation for entering capability mode
+    /// (see [`enter_capability_mode`](crate::enter_capability_mode))
+    ///
+    /// Files are no longer checked for changes from then on, nor are long quotes streamed, as both
+    /// take reaching files by their paths.
+    #[cfg(target_os = "freebsd")]
+    pub fn limit_capabilities(&mut self) -> io::Result<()> {
+        for file in &mut self.files {
+            if let QuoteStorage::File(fh) = &file.storage {
+                crate::capsicum::limit(fh, crate::capsicum::FILE_RIGHTS).map_err(|e| {
+                    io::Error::new(
+                        e.kind(),
+                        format!("Failed to limit \"{}\": {e}", file.path.display()),
+                    )
+                })?;
+            }
+            file.fingerprint = None;
+        }
+        Ok(())
+    }
+
+    /// Serve the quotes indexed in `other` instead, leaving it with the quotes indexed here
+    ///
+    /// How quotes are chosen (e.g. the schedule, strategy, and RNG) is kept by each side, so that
+    /// quotes indexed in the background (e.g. with [`Quotes::index_options`]) can be swapped in, and
+    /// back out again, without otherwise changing what's served.
+    pub fn swap_index(&mut self, other: &mut Quotes) {
+        std::mem::swap(&mut self.files, &mut other.files);
+        std::mem::swap(&mut self.file_weights, &mut other.file_weights);
+        std::mem::swap(&mut self.category_indexes, &mut other.category_indexes);
+        std::mem::swap(&mut self.is_seasonal, &mut other.is_seasonal);
+        std::mem::swap(&mut self.options, &mut other.options);
+        std::mem::swap(&mut self.dir, &mut other.dir);
+        self.fit_history();
+        other.fit_history();
+        self.reweighed = None;
+        other.reweighed = None;
+    }
+
+    /// Serve the quotes indexed in `other` along with these, e.g. to combine the built-in quotes with
+    /// those in a directory, each quote as likely to be chosen as any other
+    ///
+    /// How quotes are chosen (e.g. the schedule, strategy, and RNG), and the options files added
+    /// later are indexed with, are kept from these quotes; `other`'s are dropped. The limit on
+    /// quotes (see [`IndexOptions::max_quotes`]) isn't applied to the merged quotes.
+    pub fn merge(mut self, mut other: Quotes) -> Quotes {
+        self.is_seasonal |= other.is_seasonal;
+        self.files.append(&mut other.files);
+        self.fit_history();
+        if self.recency.is_none() {
+            for file in &mut self.files {
+                file.recency_factor = 1.0;
+            }
+        }
+        self.file_weights =
+            WeightedAliasIndex::new(self.files.iter().map(QuoteFile::weight).collect())
+                .expect("merged quotes are weighed as their parts were");
+        self.category_indexes = CategoryIndex::build(&self.files);
+        // Merged files are yet to be weighed by their recency
+        self.reweighed = None;
+        self.reweigh_if_due();
+        self
+    }
+
+    /// Track when each quote was last served only if the strategy needs it, keeping what's known
+    fn fit_history(&mut self) {
+        for file in &mut self.files {
+            match self.strategy {
+                SelectionStrategy::Random => file.last_served = Vec::new(),
+                SelectionStrategy::LeastRecentlyServed => {
+                    file.last_served.resize(file.quotes.len(), None);
+                }
+            }
+        }
+    }
+
+    /// Length of the shortest quote indexed, as stored in its file
+    ///
+    /// This is kept as quotes are indexed, so is cheap to ask for.
+    pub fn shortest_quote(&self) -> Option<usize> {
+        self.category_indexes
+            .iter()
+            .map(|index| index.shortest)
+            .min()
+    }
+
+    /// IDs of all quotes that may be served
+    pub fn ids(&self) -> impl Iterator<Item = QuoteId> + '_ {
+        self.files.iter().flat_map(|file| {
+            file.quotes
+                .iter()
+                .enumerate()
+                .map(|(index, quote)| QuoteId {
+                    file: file.name.clone(),
+                    index,
+                    hash: quote.hash,
+                })
+        })
+    }
+
+    /// Describe the quote files and quotes that were indexed
+    pub fn summary(&self) -> IndexSummary {
+        IndexSummary {
+            files: self.files().collect(),
+        }
+    }
+
+    /// Describe each quote file that was indexed, in the order they were indexed
+    ///
+    /// Quotes reclassified as offensive (see [`IndexOptions::offensive_words`]) are described as a
+    /// file of their own, as they're served from one.
+    pub fn files(&self) -> impl Iterator<Item = FileSummary> + '_ {
+        self.files.iter().map(|file| FileSummary {
+            name: file.name.clone(),
+            path: file.path.clone(),
+            category: file.category,
+            language: file.language.clone(),
+            encoding: file.encoding.name(),
+            storage: file.storage.name(),
+            quotes: file.quotes.len(),
+        })
+    }
+
+    /// Total number of quotes that may be served, in all categories and seasons
+    pub fn total_quotes(&self) -> usize {
+        self.files.iter().map(|file| file.quotes.len()).sum()
+    }
+
+    /// The categories quotes were indexed in, in order
+    pub fn categories(&self) -> Vec<QuoteCategory> {
+        let mut categories: Vec<_> = self.files.iter().map(|file| file.category).collect();
+        categories.sort();
+        categories.dedup();
+        categories
+    }
+
+    /// Only choose quotes within `lengths`, see [`Quotes::set_lengths`]
+    pub fn with_lengths(mut self, lengths: QuoteLengths) -> Self {
+        self.lengths = lengths;
+        self
+    }
+
+    /// The bounds on the length of quotes chosen, see [`Quotes::set_lengths`]
+    pub fn lengths(&self) -> QuoteLengths {
+        self.lengths
+    }
+
+    /// Only choose quotes within `lengths` from now on, e.g. for each request as it's served
+    ///
+    /// Files are sampled as usual, then again should one have no quotes of an allowed length, so
+    /// bounds that few quotes fall within may leave none to be found.
+    pub fn set_lengths(&mut self, lengths: QuoteLengths) {
+        self.lengths = lengths;
+    }
+
+    /// Choose quotes from the selected file according to the given strategy
+    pub fn with_strategy(mut self, strategy: SelectionStrategy) -> Self {
+        self.strategy = strategy;
+        for file in &mut self.files {
+            file.last_served = match strategy {
+                SelectionStrategy::Random => Vec::new(),
+                SelectionStrategy::LeastRecentlyServed => vec![None; file.quotes.len()],
+            };
+        }
+        self
+    }
+
+    /// Weigh files by how recently they were modified, so that a file's quotes are up to
+    /// `recency.boost` times as likely to be chosen just after it's modified, decaying back to
+    /// their usual weight over time
+    ///
+    /// Files are weighed anew every minute, and whenever quotes are added. Files not read from
+    /// disk, e.g. the built-in quotes, are never boosted. The quote of the day (see
+    /// [`Quotes::daily_quote`]) is chosen regardless, so as not to change during the day.
+    pub fn with_recency(mut self, recency: Recency) -> Self {
+        self.recency = Some(recency);
+        self.reweighed = None;
+        self.reweigh_if_due();
+        self
+    }
+
+    /// Weigh files by their recency anew, if it's been long enough since they last were
+    fn reweigh_if_due(&mut self) {
+        let Some(recency) = self.recency else {
+            return;
+        };
+        if self
+            .reweighed
+            .is_some_and(|reweighed| reweighed.elapsed() < RECENCY_INTERVAL)
+        {
+            return;
+        }
+        let now = SystemTime::now();
+        for file in &mut self.files {
+            file.recency_factor = recency.factor(file.modified, now);
+        }
+        match WeightedAliasIndex::new(self.files.iter().map(QuoteFile::weight).collect()) {
+            Ok(weights) => {
+                self.file_weights = weights;
+                self.category_indexes = CategoryIndex::build(&self.files);
+            }
+            Err(e) => warn!("Failed to weigh quote files by recency: {e}"),
+        }
+        self.reweighed = Some(Instant::now());
+    }
+
+    /// Choose quotes with the given source of randomness, e.g. a seeded RNG for reproducible results
+    ///
+    /// By default, an RNG seeded from the operating system's entropy source is used.
+    pub fn with_rng<R: RngCore + Send + 'static>(mut self, rng: R) -> Self {
+        self.rng = QuoteRng(Box::new(rng));
+        self
+    }
+
+    /// Choose each day's quote (see [`Quotes::daily_quote`]) with the given seed, so that different
+    /// servers with the same quotes can have different quotes of the day
+    pub fn with_daily_seed(mut self, seed: u64) -> Self {
+        self.daily_seed = seed;
+        self
+    }
+
+    /// The quote of the day for `date`, which is the same whenever it's asked for as long as the
+    /// same quotes are indexed
+    ///
+    /// Only files and quotes in season on `date` are chosen from. The category schedule doesn't
+    /// apply, as the quote is for the whole day, and other selection is unaffected.
+    pub async fn daily_quote(&mut self, date: NaiveDate) -> io::Result<Quote> {
+        self.seeded_quote(date.and_time(NaiveTime::MIN), self.daily_seed)
+    }
+
+    /// The quote for the period of `rotation` containing `time`, like [`Quotes::daily_quote`] but
+    /// for periods of any length
+    ///
+    /// Daily rotation has the same quotes as [`Quotes::daily_quote`].
+    pub async fn rotating_quote(
+        &mut self,
+        rotation: &Rotation,
+        time: DateTime<Local>,
+    ) -> io::Result<Quote> {
+        self.seeded_quote(rotation.period_start_at(time), self.daily_seed)
+    }
+
+    /// `client`'s own quote of the day for `date`, like [`Quotes::daily_quote`] but different for
+    /// each client
+    pub async fn client_daily_quote(
+        &mut self,
+        date: NaiveDate,
+        client: IpAddr,
+    ) -> io::Result<Quote> {
+        // FNV-1a, as the hash must be the same across restarts and versions
+        let octets = match client {
+            IpAddr::V4(ip) => ip.octets().to_vec(),
+            IpAddr::V6(ip) => ip.octets().to_vec(),
+        };
+        let client_hash = octets
+            .iter()
+            .fold(0xCBF2_9CE4_8422_2325_u64, |hash, &byte| {
+                (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01B3)
+            });
+        self.seeded_quote(date.and_time(NaiveTime::MIN), self.daily_seed ^ client_hash)
+    }
+
+    /// The quote for the period starting at `start`, in server-local time
+    fn seeded_quote(&mut self, start: NaiveDateTime, seed: u64) -> io::Result<Quote> {
+        let date = start.date();
+        let key = start.and_utc().timestamp() as u64;
+        let no_quotes = || {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No quotes are eligible to be served on {date}"),
+            )
+        };
+
+        // Another file is sampled should one have no quotes of an allowed length, but each attempt
+        // is seeded on its own, so that the quote is still the same for the whole period
+        for attempt in 0..=LENGTH_ATTEMPTS as u64 {
+            let seeded = || {
+                StdRng::seed_from_u64(
+                    (seed ^ key.wrapping_mul(0x9E37_79B9_7F4A_7C15)).wrapping_add(attempt),
+                )
+            };
+            let mut rng = seeded();
+            let file_index = self.sample_unchanged(|quotes| {
+                // Each attempt must choose the same way, so that the quote doesn't depend on it
+                rng = seeded();
+                let weights = quotes.files.iter().map(|file| file.seasonal_weight(date));
+                WeightedIndex::new(weights)
+                    .map(|weights| weights.sample(&mut rng))
+                    .map_err(|_| no_quotes())
+            })?;
+            let lengths = self.lengths;
+            let file = &mut self.files[file_index];
+            let index = (0..file.quotes.len())
+                .filter(|&i| {
+                    file.quotes[i].is_in_season(date) && lengths.contains(file.quotes[i].length)
+                })
+                .choose(&mut rng);
+            if let Some(index) = index {
+                return file.read(index);
+            }
+        }
+        Err(no_quotes())
+    }
+
+    pub async fn random_quote(&mut self) -> io::Result<Quote> {
+        self.sample_and_read(Self::sample_file)
+    }
+
+    /// Choose a quote like [`Quotes::random_quote`], but only from the given categories
+    ///
+    /// Only categories that were indexed can be chosen from, and the schedule still applies.
+    pub async fn random_quote_in(&mut self, categories: &[QuoteCategory]) -> io::Result<Quote> {
+        self.sample_and_read(|quotes| quotes.sample_eligible_file(Some(categories)))
+    }
+
+    /// Filter the quotes to choose from, e.g.
+    /// `quotes.filter().category(QuoteCategory::OFFENSIVE).max_len(500).tag("unix").build()`
+    ///
+    /// Criteria of different kinds must all be met, while a quote need only meet one of several of
+    /// the same kind (other than tags), e.g. be in either of two categories. Only the quotes
+    /// indexed can be filtered, so e.g. only categories that were indexed can be chosen from.
+    pub fn filter(&mut self) -> QuoteFilter<'_> {
+        QuoteFilter {
+            quotes: self,
+            criteria: FilterCriteria::default(),
+        }
+    }
+
+    /// Choose a quote like [`Quotes::random_quote`], but leave it to be streamed from its file if
+    /// it's longer than [`STREAM_CHUNK_SIZE`]
+    pub async fn choose_quote(&mut self) -> io::Result<ChosenQuote> {
+        let (file_index, index) = self.sample_quote(Self::sample_file)?;
         let file = &mut self.files[file_index];
-        // @see RNG note in `Self::random_quote`
-        let i = thread_rng().gen_range(0..file.quotes.len());
+        match file.stream(index) {
+            Some(stream) => Ok(ChosenQuote::Streamed(stream)),
+            None => match file.read(index) {
+                Ok(quote) => Ok(ChosenQuote::Read(quote)),
+                Err(e) => {
+                    warn!("Failed to read quote from \"{}\": {e}", file.path.display());
+                    self.sample_and_read(Self::sample_file)
+                        .map(ChosenQuote::Read)
+                }
+            },
+        }
+    }
+
+    /// Choose a quote like [`Quotes::sample_quote`] and read it, choosing another should reading it
+    /// fail, up to [`READ_ATTEMPTS`] more times
+    fn sample_and_read<F: FnMut(&mut Self) -> io::Result<usize>>(
+        &mut self,
+        mut sample: F,
+    ) -> io::Result<Quote> {
+        let mut attempts = 0;
+        loop {
+            let (file_index, index) = self.sample_quote(&mut sample)?;
+            let file = &mut self.files[file_index];
+            match file.read(index) {
+                Err(e) if attempts < READ_ATTEMPTS => {
+                    warn!(
+                        "Failed to read quote from \"{}\": {e}, choosing another",
+                        file.path.display()
+                    );
+                    attempts += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Select a file with `sample` and choose one of its quotes, returning the indices of both
+    ///
+    /// Should the file have no quotes of an allowed length, another is selected, up to
+    /// [`LENGTH_ATTEMPTS`] more times.
+    fn sample_quote<F: FnMut(&mut Self) -> io::Result<usize>>(
+        &mut self,
+        mut sample: F,
+    ) -> io::Result<(usize, usize)> {
+        self.reweigh_if_due();
+        let mut attempts = 0;
+        loop {
+            let file_index = self.sample_unchanged(&mut sample)?;
+            let file = &mut self.files[file_index];
+            match Self::choose_index(file, self.strategy, self.lengths, &mut self.rng.0) {
+                Err(e) if !self.lengths.is_unbounded() && attempts < LENGTH_ATTEMPTS => {
+                    debug!("{e}, choosing another file");
+                    attempts += 1;
+                }
+                index => return index.map(|index| (file_index, index)),
+            }
+        }
+    }
 
-        let quote_index = file.quotes[i];
-        file.file_handle
-            .seek(io::SeekFrom::Start(quote_index.offset))
-            .await?;
-        let mut quote = vec![0_u8; quote_index.length];
-        file.file_handle.read_exact(&mut quote).await?;
+    /// Select the file to choose a quote from
+    fn sample_file(&mut self) -> io::Result<usize> {
+        if self.schedule.is_empty() && !self.is_seasonal {
+            Ok(self.file_weights.sample(&mut self.rng.0))
+        } else {
+            self.sample_eligible_file(None)
+        }
+    }
 
-        if self.files[file_index].encoding == FileEncoding::Rot13 {
-            Self::rot13(&mut quote);
+    /// Select a file from only those with quotes that are currently eligible, i.e. in categories
+    /// enabled by the schedule (and among `categories`, if given) and in season
+    fn sample_eligible_file(&mut self, categories: Option<&[QuoteCategory]>) -> io::Result<usize> {
+        let now = Local::now();
+        let no_quotes = || {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                "No quotes are currently eligible to be served",
+            )
+        };
+        // Out of season files must be weighed anew, but otherwise each category's index will do
+        if !self.is_seasonal {
+            let eligible: Vec<_> = self
+                .category_indexes
+                .iter()
+                .filter(|index| {
+                    self.schedule.is_active_at(index.category, now.time())
+                        && categories.is_none_or(|categories| categories.contains(&index.category))
+                })
+                .collect();
+            let index = match eligible[..] {
+                [] => return Err(no_quotes()),
+                [index] => index,
+                _ => {
+                    let weights = WeightedIndex::new(eligible.iter().map(|i| i.total_weight))
+                        .map_err(|_| no_quotes())?;
+                    eligible[weights.sample(&mut self.rng.0)]
+                }
+            };
+            return Ok(index.files[index.weights.sample(&mut self.rng.0)]);
         }
 
-        Ok(quote)
+        let weights = self.files.iter().map(|file| {
+            if self.schedule.is_active_at(file.category, now.time())
+                && categories.is_none_or(|categories| categories.contains(&file.category))
+            {
+                file.seasonal_weight(now.date_naive()) * file.recency_factor
+            } else {
+                0.0
+            }
+        });
+
+        WeightedIndex::new(weights)
+            .map(|weights| weights.sample(&mut self.rng.0))
+            .map_err(|_| no_quotes())
+    }
+
+    /// Select a file from only those with quotes that are currently eligible, like
+    /// [`Quotes::sample_eligible_file`], and that match `criteria`
+    fn sample_matching_file(&mut self, criteria: &FilterCriteria) -> io::Result<usize> {
+        let now = Local::now();
+        let weights = self.files.iter().map(|file| {
+            if criteria.matches(file) && self.schedule.is_active_at(file.category, now.time()) {
+                file.seasonal_weight(now.date_naive()) * file.recency_factor
+            } else {
+                0.0
+            }
+        });
+
+        WeightedIndex::new(weights)
+            .map(|weights| weights.sample(&mut self.rng.0))
+            .map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "No quotes that pass the filter are currently eligible to be served",
+                )
+            })
     }
 
-    fn rot13(text: &mut [u8]) {
-        text.iter_mut().for_each(|c| match c {
-            b'A'..=b'M' | b'a'..=b'm' => *c += 13,
-            b'N'..=b'Z' | b'n'..=b'z' => *c -= 13,
-            _ => {}
+    /// Choose which of the file's quotes to serve, among those in season and within `lengths`
+    fn choose_index(
+        file: &mut QuoteFile,
+        strategy: SelectionStrategy,
+        lengths: QuoteLengths,
+        rng: &mut dyn RngCore,
+    ) -> io::Result<usize> {
+        let today = Local::now().date_naive();
+        let eligible = (0..file.quotes.len()).filter(|&i| {
+            (!file.has_dated_quotes || file.quotes[i].is_in_season(today))
+                && lengths.contains(file.quotes[i].length)
         });
+        let no_quotes = || {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No quotes in \"{}\" are eligible to be served", file.name),
+            )
+        };
+
+        let index = match strategy {
+            SelectionStrategy::Random if !file.has_dated_quotes && lengths.is_unbounded() => {
+                rng.gen_range(0..file.quotes.len())
+            }
+            SelectionStrategy::Random => eligible.choose(rng).ok_or_else(no_quotes)?,
+            SelectionStrategy::LeastRecentlyServed => {
+                let eligible: Vec<_> = eligible.collect();
+                let never_served = eligible
+                    .iter()
+                    .copied()
+                    .filter(|&i| file.last_served[i].is_none());
+                match never_served.choose(rng) {
+                    Some(index) => index,
+                    None => {
+                        let now = Instant::now();
+                        let ages = eligible.iter().map(|&i| {
+                            file.last_served[i].map_or(0.0, |at| (now - at).as_secs_f64())
+                        });
+                        match WeightedIndex::new(ages) {
+                            Ok(weights) => eligible[weights.sample(rng)],
+                            // Everything was served just now, so none is any less recent
+                            Err(_) => *eligible.choose(rng).ok_or_else(no_quotes)?,
+                        }
+                    }
+                }
+            }
+        };
+        if let Some(last_served) = file.last_served.get_mut(index) {
+            *last_served = Some(Instant::now());
+        }
+
+        Ok(index)
+    }
+
+    pub async fn read_quote(&mut self, file_index: usize) -> io::Result<Quote> {
+        let file = &mut self.files[file_index];
+        let index = Self::choose_index(file, self.strategy, self.lengths, &mut self.rng.0)?;
+        file.read(index)
+    }
+
+    /// Find every quote that may be served containing `text`, in the order they were indexed
+    pub async fn search(&mut self, text: &str) -> io::Result<Vec<Quote>> {
+        self.search_by(|quote| quote.contains(text))
+    }
+
+    /// Find every quote that may be served matching `pattern`, in the order they were indexed
+    pub async fn search_regex(&mut self, pattern: &Regex) -> io::Result<Vec<Quote>> {
+        self.search_by(|quote| pattern.is_match(quote))
+    }
+
+    /// Read every quote, without affecting their selection, keeping those whose decoded text
+    /// matches
+    fn search_by<F: Fn(&str) -> bool>(&mut self, matches: F) -> io::Result<Vec<Quote>> {
+        self.iter_all()
+            .filter(|quote| {
+                quote
+                    .as_ref()
+                    .map_or(true, |quote| matches(&quote.text_str()))
+            })
+            .collect()
+    }
+
+    /// Read every quote that may be served, one at a time as they're iterated over, in the order
+    /// they were indexed and without affecting their selection
+    ///
+    /// Each quote carries its ID, file, and category; see [`Quotes::files`] for more about its file.
+    /// Quotes are read from disk only as needed, or copied from memory for files held there (e.g.
+    /// with [`IndexOptions::preload_encoded`]), so this suits tools (e.g. exporting, or finding
+    /// duplicates) working through quote collections too large to read all at once.
+    pub fn iter_all(&mut self) -> impl Iterator<Item = io::Result<Quote>> + '_ {
+        self.files
+            .iter_mut()
+            .flat_map(|file| (0..file.quotes.len()).map(move |index| file.read(index)))
+    }
+
+    /// Read every quote that may be served, grouped by file, without affecting their selection
+    pub async fn read_all(&mut self) -> io::Result<Vec<(FileSummary, Vec<Quote>)>> {
+        let summary = self.summary();
+        let mut all = Vec::with_capacity(self.files.len());
+        for (file, summary) in self.files.iter_mut().zip(summary.files) {
+            let mut quotes = Vec::with_capacity(file.quotes.len());
+            for index in 0..file.quotes.len() {
+                quotes.push(file.read(index)?);
+            }
+            all.push((summary, quotes));
+        }
+        Ok(all)
     }
 }
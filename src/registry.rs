@@ -0,0 +1,290 @@
+//! This module registers the server with a service registry, so that clients can find it through
+//! service discovery, and keeps the registration alive for as long as quotes are served
+//!
+//! With Consul, the service is registered with the local agent along with a TTL check, which is
+//! passed periodically. With etcd, the service is a key under a lease, which is kept alive
+//! periodically; the key's value is a JSON object describing the service. Either way, a server that
+//! dies without deregistering drops out of the registry once its TTL lapses.
+#![cfg(feature = "registry")]
+
+use std::{
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
+
+use anyhow::Context;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde_json::json;
+use tracing::{debug, info, warn};
+
+/// Default name the service is registered as
+pub const DEFAULT_SERVICE_NAME: &str = "qotd";
+/// Default time a registration lives for unless refreshed
+pub const DEFAULT_REGISTRATION_TTL: Duration = Duration::from_secs(30);
+/// Prefix of the keys services are registered under in etcd
+const ETCD_PREFIX: &str = "/services";
+
+/// Service registries the server can be registered with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum RegistryKind {
+    /// A Consul agent, through its HTTP API
+    Consul,
+    /// An etcd cluster, through its v3 HTTP gateway
+    Etcd,
+}
+
+impl RegistryKind {
+    /// Where the registry usually listens on the local host
+    pub fn default_url(&self) -> &'static str {
+        match self {
+            RegistryKind::Consul => "http://127.0.0.1:8500",
+            RegistryKind::Etcd => "http://127.0.0.1:2379",
+        }
+    }
+}
+
+/// Registers a server with a service registry (see [`Server::register`](crate::Server::register))
+#[derive(Debug, Clone)]
+pub struct Registration {
+    kind: RegistryKind,
+    url: String,
+    name: String,
+    id: Option<String>,
+    address: Option<IpAddr>,
+    ttl: Duration,
+}
+
+impl Registration {
+    /// Register with the registry of `kind` at `url`, e.g. "http://127.0.0.1:8500"
+    pub fn new<S: Into<String>>(kind: RegistryKind, url: S) -> Self {
+        Self {
+            kind,
+            url: url.into().trim_end_matches('/').to_string(),
+            name: DEFAULT_SERVICE_NAME.to_string(),
+            id: None,
+            address: None,
+            ttl: DEFAULT_REGISTRATION_TTL,
+        }
+    }
+
+    /// Register as the service named `name` (default: "qotd")
+    pub fn name<S: Into<String>>(mut self, name: S) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    /// Register as the instance `id` of the service (default: the name, address, and port)
+    pub fn id<S: Into<String>>(mut self, id: S) -> Self {
+        self.id = Some(id.into());
+        self
+    }
+
+    /// Advertise `address` rather than the one the server is bound to, e.g. when it's bound to the
+    /// unspecified address
+    pub fn address(mut self, address: IpAddr) -> Self {
+        self.address = Some(address);
+        self
+    }
+
+    /// Have the registration lapse if it isn't refreshed within `ttl` (default: 30 seconds); it's
+    /// refreshed three times as often
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl.max(Duration::from_secs(1));
+        self
+    }
+
+    /// Register the server bound to `addr`, refreshing the registration until the task is aborted
+    ///
+    /// Failing to register or refresh is never fatal, as the registry may well come back; the
+    /// server is registered anew once it does.
+    pub(crate) async fn run(self, addr: SocketAddr) -> anyhow::Result<()> {
+        let service = Service::new(&self, addr);
+        // The lease the service is registered under, if it's registered
+        let mut registered = None;
+        let mut ticks = tokio::time::interval(self.ttl / 3);
+        loop {
+            ticks.tick().await;
+            let result = match registered {
+                Some(lease) => self.refresh(&service, lease).await,
+                None => self.register(&service).await,
+            };
+            match result {
+                Ok(lease) => registered = Some(lease),
+                Err(e) => {
+                    warn!("Failed to register with {:?}: {e:?}", self.kind);
+                    registered = None;
+                }
+            }
+        }
+    }
+
+    /// Remove the server bound to `addr` from the registry
+    pub(crate) async fn deregister(&self, addr: SocketAddr) {
+        let service = Service::new(self, addr);
+        let result = match self.kind {
+            RegistryKind::Consul => {
+                let url = format!("{}/v1/agent/service/deregister/{}", self.url, service.id);
+                put(url, None).await.map(drop)
+            }
+            // The key is deleted along with its lease, should it have one
+            RegistryKind::Etcd => {
+                let url = format!("{}/v3/kv/deleterange", self.url);
+                let body = json!({ "key": BASE64.encode(service.key()) });
+                post(url, body).await.map(drop)
+            }
+        };
+        match result {
+            Ok(()) => info!("Deregistered {} from {:?}", service.id, self.kind),
+            Err(e) => warn!("Failed to deregister from {:?}: {e:?}", self.kind),
+        }
+    }
+
+    /// Register the service, returning the lease it's registered under, if any
+    async fn register(&self, service: &Service) -> anyhow::Result<Option<i64>> {
+        let lease = match self.kind {
+            RegistryKind::Consul => {
+                let ttl = self.ttl.as_secs();
+                let mut body = json!({
+                    "ID": service.id,
+                    "Name": self.name,
+                    "Port": service.addr.port(),
+                    "Check": {
+                        "TTL": format!("{ttl}s"),
+                        "DeregisterCriticalServiceAfter": format!("{}s", ttl * 10),
+                    },
+                });
+                // The agent's own address is advertised otherwise
+                if !service.addr.ip().is_unspecified() {
+                    body["Address"] = json!(service.addr.ip().to_string());
+                }
+                put(
+                    format!("{}/v1/agent/service/register", self.url),
+                    Some(body),
+                )
+                .await?;
+                self.pass(service).await?;
+                None
+            }
+            RegistryKind::Etcd => {
+                let url = format!("{}/v3/lease/grant", self.url);
+                let granted = post(url, json!({ "TTL": self.ttl.as_secs() })).await?;
+                let lease = lease_id(&granted["ID"]).context("No lease was granted")?;
+                let value = json!({
+                    "name": self.name,
+                    "id": service.id,
+                    "address": service.addr.ip().to_string(),
+                    "port": service.addr.port(),
+                    "status": "passing",
+                });
+                let body = json!({
+                    "key": BASE64.encode(service.key()),
+                    "value": BASE64.encode(value.to_string()),
+                    "lease": lease.to_string(),
+                });
+                post(format!("{}/v3/kv/put", self.url), body).await?;
+                Some(lease)
+            }
+        };
+        info!("Registered {} with {:?}", service.id, self.kind);
+        Ok(lease)
+    }
+
+    /// Keep the registration alive, registering anew should it have lapsed
+    async fn refresh(&self, service: &Service, lease: Option<i64>) -> anyhow::Result<Option<i64>> {
+        match (self.kind, lease) {
+            (RegistryKind::Etcd, Some(lease)) => {
+                let url = format!("{}/v3/lease/keepalive", self.url);
+                let kept = post(url, json!({ "ID": lease.to_string() })).await?;
+                // A lapsed lease can't be kept alive, and is answered without a TTL
+                if lease_id(&kept["result"]["TTL"]).is_some_and(|ttl| ttl > 0) {
+                    debug!("Kept etcd lease {lease} alive");
+                    Ok(Some(lease))
+                } else {
+                    warn!("etcd lease {lease} lapsed; registering again");
+                    self.register(service).await
+                }
+            }
+            _ => match self.pass(service).await {
+                Ok(()) => Ok(lease),
+                // e.g. the agent restarted, and forgot the service
+                Err(e) => {
+                    debug!("Failed to pass Consul check: {e:?}; registering again");
+                    self.register(service).await
+                }
+            },
+        }
+    }
+
+    /// Report the service healthy to Consul
+    async fn pass(&self, service: &Service) -> anyhow::Result<()> {
+        let url = format!("{}/v1/agent/check/pass/service:{}", self.url, service.id);
+        put(url, None).await?;
+        debug!("Passed Consul check of {}", service.id);
+        Ok(())
+    }
+}
+
+/// The instance of the service being registered
+struct Service {
+    id: String,
+    /// Address advertised to clients
+    addr: SocketAddr,
+    name: String,
+}
+
+impl Service {
+    fn new(registration: &Registration, addr: SocketAddr) -> Self {
+        let addr = SocketAddr::new(registration.address.unwrap_or(addr.ip()), addr.port());
+        let id = registration
+            .id
+            .clone()
+            .unwrap_or_else(|| format!("{}-{}-{}", registration.name, addr.ip(), addr.port()));
+        Self {
+            id,
+            addr,
+            name: registration.name.clone(),
+        }
+    }
+
+    /// The etcd key the service is registered under
+    fn key(&self) -> String {
+        format!("{ETCD_PREFIX}/{}/{}", self.name, self.id)
+    }
+}
+
+/// A lease ID or TTL, which etcd's JSON gateway gives as strings as they're 64-bit integers
+fn lease_id(value: &serde_json::Value) -> Option<i64> {
+    match value {
+        serde_json::Value::String(s) => s.parse().ok(),
+        other => other.as_i64(),
+    }
+}
+
+async fn put(url: String, body: Option<serde_json::Value>) -> anyhow::Result<serde_json::Value> {
+    request("PUT", url, body.unwrap_or(serde_json::Value::Null)).await
+}
+
+async fn post(url: String, body: serde_json::Value) -> anyhow::Result<serde_json::Value> {
+    request("POST", url, body).await
+}
+
+/// Send `body` to `url`, returning the JSON answer, if there is one
+async fn request(
+    method: &'static str,
+    url: String,
+    body: serde_json::Value,
+) -> anyhow::Result<serde_json::Value> {
+    tokio::task::spawn_blocking(move || {
+        let request = ureq::request(method, &url).set("Content-Type", "application/json");
+        let response = if body.is_null() {
+            request.call()
+        } else {
+            request.send_string(&body.to_string())
+        }
+        .with_context(|| format!("Request to {url} failed"))?;
+        let text = response.into_string()?;
+        Ok(serde_json::from_str(&text).unwrap_or(serde_json::Value::Null))
+    })
+    .await?
+}
@@ -0,0 +1,347 @@
+//! This module decides which quote categories are enabled, and when quotes rotate, at any given
+//! time
+
+use std::{fmt, str::FromStr, time::Duration};
+
+use chrono::{DateTime, Datelike, Local, NaiveDate, NaiveDateTime, NaiveTime, Offset};
+
+use crate::QuoteCategory;
+
+/// A daily window of server-local time; windows ending before they start wrap past midnight
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeWindow {
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl TimeWindow {
+    pub fn new(start: NaiveTime, end: NaiveTime) -> Self {
+        Self { start, end }
+    }
+
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+impl FromStr for TimeWindow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) = s
+            .split_once('-')
+            .ok_or_else(|| format!("Expected a time window like 18:00-06:00, got: {s}"))?;
+        let parse = |time: &str| {
+            NaiveTime::parse_from_str(time.trim(), "%H:%M")
+                .map_err(|e| format!("Invalid time \"{time}\": {e}"))
+        };
+        Ok(Self::new(parse(start)?, parse(end)?))
+    }
+}
+
+impl fmt::Display for TimeWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}-{}",
+            self.start.format("%H:%M"),
+            self.end.format("%H:%M")
+        )
+    }
+}
+
+/// A yearly window of calendar days, inclusive; windows ending before they start wrap past New Year
+///
+/// Written as `MM-DD..MM-DD`, e.g. `12-20..12-27`, or `MM-DD` for a single day.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateWindow {
+    start: (u32, u32),
+    end: (u32, u32),
+}
+
+impl DateWindow {
+    pub fn contains(&self, date: NaiveDate) -> bool {
+        let day = (date.month(), date.day());
+        if self.start <= self.end {
+            self.start <= day && day <= self.end
+        } else {
+            day >= self.start || day <= self.end
+        }
+    }
+
+    /// Whether the window contains the current date, in server-local time
+    pub fn contains_today(&self) -> bool {
+        self.contains(Local::now().date_naive())
+    }
+
+    fn parse_day(day: &str) -> Result<(u32, u32), String> {
+        let invalid = || format!("Invalid day \"{day}\", expected MM-DD");
+        let (month, day_of_month) = day.trim().split_once('-').ok_or_else(invalid)?;
+        let month = month.parse().map_err(|_| invalid())?;
+        let day_of_month = day_of_month.parse().map_err(|_| invalid())?;
+        // Validate against a leap year, so that 02-29 is accepted
+        NaiveDate::from_ymd_opt(2000, month, day_of_month).ok_or_else(invalid)?;
+        Ok((month, day_of_month))
+    }
+}
+
+impl FromStr for DateWindow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) = s.split_once("..").unwrap_or((s, s));
+        Ok(Self {
+            start: Self::parse_day(start)?,
+            end: Self::parse_day(end)?,
+        })
+    }
+}
+
+impl fmt::Display for DateWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:02}-{:02}..{:02}-{:02}",
+            self.start.0, self.start.1, self.end.0, self.end.1
+        )
+    }
+}
+
+/// The dates something is valid between, inclusive; either end may be left open
+///
+/// Written as `YYYY-MM-DD..YYYY-MM-DD`, e.g. `2026-05-01..2026-06-30`, with either date left out for
+/// an open end (e.g. `..2026-06-30`), or as `YYYY-MM-DD` for a single day.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Validity {
+    pub not_before: Option<NaiveDate>,
+    pub not_after: Option<NaiveDate>,
+}
+
+impl Validity {
+    pub fn contains(&self, date: NaiveDate) -> bool {
+        self.not_before.is_none_or(|start| start <= date)
+            && self.not_after.is_none_or(|end| date <= end)
+    }
+
+    /// Whether `s` is written as validity dates rather than as a [`DateWindow`], i.e. has a year
+    /// or an open end
+    pub fn is_validity(s: &str) -> bool {
+        s.split("..")
+            .any(|date| date.trim().is_empty() || date.matches('-').count() == 2)
+    }
+
+    pub(crate) fn parse_date(date: &str) -> Result<Option<NaiveDate>, String> {
+        let date = date.trim();
+        if date.is_empty() {
+            return Ok(None);
+        }
+        NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .map(Some)
+            .map_err(|_| format!("Invalid date \"{date}\", expected YYYY-MM-DD"))
+    }
+}
+
+impl FromStr for Validity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) = s.split_once("..").unwrap_or((s, s));
+        let validity = Self {
+            not_before: Self::parse_date(start)?,
+            not_after: Self::parse_date(end)?,
+        };
+        match validity {
+            Validity {
+                not_before: Some(start),
+                not_after: Some(end),
+            } if end < start => Err(format!("Dates \"{s}\" end before they start")),
+            Validity {
+                not_before: None,
+                not_after: None,
+            } => Err(format!(
+                "Expected dates like 2026-05-01..2026-06-30, got: {s}"
+            )),
+            validity => Ok(validity),
+        }
+    }
+}
+
+impl fmt::Display for Validity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(start) = self.not_before {
+            write!(f, "{}", start.format("%Y-%m-%d"))?;
+        }
+        write!(f, "..")?;
+        if let Some(end) = self.not_after {
+            write!(f, "{}", end.format("%Y-%m-%d"))?;
+        }
+        Ok(())
+    }
+}
+
+/// A category restricted to a time window, e.g. `offensive=18:00-06:00`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScheduleEntry {
+    pub category: QuoteCategory,
+    pub window: TimeWindow,
+}
+
+impl FromStr for ScheduleEntry {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (category, window) = s
+            .split_once('=')
+            .ok_or_else(|| format!("Expected a schedule like offensive=18:00-06:00, got: {s}"))?;
+        Ok(Self {
+            category: category.parse()?,
+            window: window.parse()?,
+        })
+    }
+}
+
+/// Restricts categories to time windows
+///
+/// Categories without any entries are always enabled; a category with entries is enabled only
+/// while at least one of its windows contains the current time.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CategorySchedule {
+    entries: Vec<ScheduleEntry>,
+}
+
+impl CategorySchedule {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn entry(mut self, entry: ScheduleEntry) -> Self {
+        self.entries.push(entry);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// All categories that are enabled by this schedule at some point in the day
+    pub fn categories(&self) -> impl Iterator<Item = QuoteCategory> + '_ {
+        self.entries.iter().map(|entry| entry.category)
+    }
+
+    /// Whether the category is enabled at the given time
+    pub fn is_active_at(&self, category: QuoteCategory, time: NaiveTime) -> bool {
+        let mut windows = self
+            .entries
+            .iter()
+            .filter(|entry| entry.category == category)
+            .peekable();
+        windows.peek().is_none() || windows.any(|entry| entry.window.contains(time))
+    }
+
+    /// Whether the category is enabled right now, in server-local time
+    pub fn is_active(&self, category: QuoteCategory) -> bool {
+        self.is_empty() || self.is_active_at(category, Local::now().time())
+    }
+}
+
+impl FromIterator<ScheduleEntry> for CategorySchedule {
+    fn from_iter<T: IntoIterator<Item = ScheduleEntry>>(iter: T) -> Self {
+        Self {
+            entries: iter.into_iter().collect(),
+        }
+    }
+}
+
+/// Divides time into periods of equal length, each of which has a quote of its own
+///
+/// Periods are aligned to server-local midnight, so that e.g. hourly periods start on the hour and
+/// daily periods at midnight. Written as a number of seconds, optionally followed by a unit of `s`,
+/// `m`, `h`, or `d`, e.g. `15m`, `1h`, or `24h`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rotation {
+    period: Duration,
+}
+
+impl Rotation {
+    /// Rotate every `period`, which is rounded up to at least a second
+    pub fn new(period: Duration) -> Self {
+        Self {
+            period: period.max(Duration::from_secs(1)),
+        }
+    }
+
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    /// Time left from `now` until the next rotation
+    pub fn until_next_at(&self, now: DateTime<Local>) -> Duration {
+        let period = self.period.as_millis() as i64;
+        Duration::from_millis((period - local_millis(now).rem_euclid(period)) as u64)
+    }
+
+    /// The start of the period containing `time`, in server-local time
+    pub fn period_start_at(&self, time: DateTime<Local>) -> NaiveDateTime {
+        let period = self.period.as_millis() as i64;
+        let local = local_millis(time);
+        DateTime::from_timestamp_millis(local - local.rem_euclid(period))
+            .unwrap_or_default()
+            .naive_utc()
+    }
+
+    /// Time left until the next rotation
+    pub fn until_next(&self) -> Duration {
+        self.until_next_at(Local::now())
+    }
+}
+
+impl Default for Rotation {
+    /// Rotate daily
+    fn default() -> Self {
+        Self::new(Duration::from_secs(24 * 60 * 60))
+    }
+}
+
+impl FromStr for Rotation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let invalid = || format!("Expected a period like 15m, 1h, or 24h, got: {s}");
+        let (number, unit) = s.split_at(s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()));
+        let number: u64 = number.parse().map_err(|_| invalid())?;
+        let unit = match unit {
+            "" | "s" => 1,
+            "m" => 60,
+            "h" => 60 * 60,
+            "d" => 24 * 60 * 60,
+            _ => return Err(invalid()),
+        };
+        if number == 0 {
+            return Err(invalid());
+        }
+        Ok(Self::new(Duration::from_secs(number.saturating_mul(unit))))
+    }
+}
+
+impl fmt::Display for Rotation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let secs = self.period.as_secs();
+        match [(24 * 60 * 60, "d"), (60 * 60, "h"), (60, "m")]
+            .into_iter()
+            .find(|(unit, _)| secs.is_multiple_of(*unit))
+        {
+            Some((unit, suffix)) => write!(f, "{}{suffix}", secs / unit),
+            None => write!(f, "{secs}s"),
+        }
+    }
+}
+
+/// Milliseconds since the Unix epoch, as if `time`'s local time were UTC
+fn local_millis(time: DateTime<Local>) -> i64 {
+    time.timestamp_millis() + i64::from(time.offset().fix().local_minus_utc()) * 1000
+}
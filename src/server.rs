@@ -1,186 +1,2518 @@
 //! This module contains the actual server code itself
+#![cfg(any(feature = "tcp", feature = "udp"))]
 
-use crate::{QuoteCategory, Quotes};
+#[cfg(feature = "udp")]
+use crate::udp_batch::{self, BATCH_SIZE};
+use crate::{
+    audit::AuditLog,
+    format::{Formatter, Transport},
+    pin::Pin,
+    socket::{ConnectionOptions, SocketOptions},
+    AccessControl, ChosenQuote, ColorScheme, Decoration, IndexSummary, Keepalive, Quote,
+    QuoteCategory, QuoteId, QuoteLengths, Quotes, Rotation, SimpleService, Stats, Template,
+};
 use anyhow::Context;
-#[cfg(feature = "cli")]
-use clap::ValueEnum;
-use std::sync::Arc;
+use chrono::NaiveDate;
+use futures::future::BoxFuture;
+use std::{
+    future::Future,
+    io,
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 use tokio::{
-    io::AsyncWriteExt,
-    net::{TcpListener, ToSocketAddrs, UdpSocket},
+    net::{lookup_host, TcpListener, ToSocketAddrs, UdpSocket},
     sync::{
+        broadcast,
         mpsc::{channel, Sender},
-        oneshot,
+        oneshot, watch,
     },
+    task::{JoinHandle, JoinSet},
 };
-use tracing::{debug, error, info, trace, warn};
+use tracing::{debug, info, info_span, trace, warn, Instrument};
+
+/// Quotes must be shorter than this to be sent over UDP, as RFC 865 recommends
+const MAX_RFC865_LENGTH: usize = 512;
+/// Quotes chosen for a TCP client whose quotes are limited in length (see
+/// [`Server::strict_rfc865`] and [`Server::length_prefix`]) before the last is truncated to fit,
+/// rather than choosing forever
+#[cfg(feature = "tcp")]
+const TCP_QUOTE_ATTEMPTS: usize = 16;
+/// Longest quote sent to UDP clients asking for a short one, as with `fortune -s`
+#[cfg(feature = "udp")]
+const SHORT_QUOTE_LENGTH: usize = 160;
+/// Quotes chosen for a UDP client asking for a short one before it's sent any that fits
+#[cfg(feature = "udp")]
+const SHORT_QUOTE_ATTEMPTS: usize = 16;
+/// Quotes chosen for a UDP client before the last is truncated to fit, rather than choosing forever
+#[cfg(feature = "udp")]
+const UDP_QUOTE_ATTEMPTS: usize = 16;
+/// Largest UDP request answered by default when requests are validated (see
+/// [`Server::max_udp_request`]), enough for e.g. a stray line ending
+pub const DEFAULT_MAX_UDP_REQUEST: usize = 16;
+/// How long requests in flight are given to finish when shutting down by default, before they're
+/// abandoned (see [`Server::drain_timeout`])
+pub const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long to stop accepting connections when out of resources (e.g. file descriptors), giving
+/// requests in flight a chance to finish and free theirs
+const ACCEPT_PAUSE: Duration = Duration::from_millis(250);
+/// How long HTTP clients being shed have to send their requests before they're sent a 503 anyway
+const HTTP_SHED_TIMEOUT: Duration = Duration::from_secs(1);
+/// How long TLS clients have to complete their handshake
+#[cfg(feature = "tls")]
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
 
-struct GetQotd(oneshot::Sender<Vec<u8>>);
+/// Identifies a request in the log, and in HTTP responses' `X-Request-Id` header, e.g. to find what
+/// the server logged of a request a client complains about
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct RequestId(u64);
 
+impl std::fmt::Display for RequestId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+/// What the task serving a request knows of it
+#[derive(Debug, Clone, Copy)]
+struct RequestContext {
+    id: RequestId,
+    /// Whether the request is logged at INFO, see [`Server::log_sample_rate`]
+    sampled: bool,
+}
+
+/// What a UDP client asked for with the first byte of its request, see [`Server::udp_hints`]
+#[cfg(feature = "udp")]
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
-#[cfg_attr(feature = "cli", derive(ValueEnum))]
-pub enum AllowedCategories {
+enum UdpHint {
+    /// Nothing in particular, e.g. an empty request
     #[default]
-    Decorous,
+    Default,
+    /// An offensive quote, 'o'
     Offensive,
+    /// A short quote, 's'
+    Short,
+}
+
+#[cfg(feature = "udp")]
+impl UdpHint {
+    fn parse(request: &[u8]) -> Self {
+        match request.first() {
+            Some(b'o') => UdpHint::Offensive,
+            Some(b's') => UdpHint::Short,
+            _ => UdpHint::Default,
+        }
+    }
+}
+
+/// A UDP request awaiting its quote
+#[cfg(feature = "udp")]
+#[derive(Debug)]
+struct UdpRequest {
+    addr: SocketAddr,
+    hint: UdpHint,
+    context: RequestContext,
+    span: tracing::Span,
+    _in_flight: InFlight,
+}
+
+tokio::task_local! {
+    /// The request being served by the current task
+    static REQUEST: RequestContext;
+}
+
+/// Log a per-request event at INFO if the request is sampled (see [`Server::log_sample_rate`]), or
+/// at DEBUG if not
+///
+/// Outside of a request's task, whether it's sampled must be given, e.g.
+/// `request_info!(sampled = sampled; "...")`.
+macro_rules! request_info {
+    (sampled = $sampled:expr; $($arg:tt)+) => {
+        if $sampled {
+            info!($($arg)+)
+        } else {
+            debug!($($arg)+)
+        }
+    };
+    ($($arg:tt)+) => {
+        request_info!(sampled = REQUEST.try_with(|request| request.sampled).unwrap_or(true); $($arg)+)
+    };
+}
+
+/// The fields of a `tcp_server` or `udp_server` span, most of which are recorded as the request is
+/// served, so that structured logs can be queried by them (e.g. for every request sent a quote)
+macro_rules! request_span {
+    ($name:literal, $protocol:literal, $context:expr, $peer:expr) => {
+        info_span!(
+            $name,
+            request_id = %$context.id,
+            protocol = $protocol,
+            peer = %$peer,
+            quote_id = tracing::field::Empty,
+            quote_hash = tracing::field::Empty,
+            quote_bytes = tracing::field::Empty,
+            duration_us = tracing::field::Empty,
+        )
+    };
+}
+
+/// Record the quote chosen for the current request on its span
+fn record_quote(id: &QuoteId, bytes: usize) {
+    let span = tracing::Span::current();
+    span.record("quote_id", tracing::field::display(id));
+    span.record("quote_hash", id.hash_hex());
+    span.record("quote_bytes", bytes);
+}
+
+/// A request for the task owning the quotes
+pub(crate) enum QuoteRequest {
+    /// Choose a quote to send over the given transport
+    Get(Transport, oneshot::Sender<anyhow::Result<ChosenQuote>>),
+    /// Choose a quote from the given categories, to send over the given transport
+    GetIn(
+        Vec<QuoteCategory>,
+        Transport,
+        oneshot::Sender<anyhow::Result<ChosenQuote>>,
+    ),
+    /// Choose the client's own quote of the day, to send over the given transport
+    ClientDaily(
+        IpAddr,
+        Transport,
+        oneshot::Sender<anyhow::Result<ChosenQuote>>,
+    ),
+    /// Append a quote to the submissions file
+    Submit(String, oneshot::Sender<anyhow::Result<QuoteId>>),
+    /// Choose the quote for the current period of the given rotation, to send over the given
+    /// transport
+    Rotating(Rotation, Transport, oneshot::Sender<anyhow::Result<Quote>>),
+    /// Choose the quote of the day for the given date
+    Daily(NaiveDate, oneshot::Sender<anyhow::Result<Quote>>),
+    /// Describe the quotes being served
+    #[cfg(feature = "dashboard")]
+    Summary(oneshot::Sender<IndexSummary>),
+    /// Keep the given quotes on standby, to be served once activated
+    Stage(Box<Quotes>, oneshot::Sender<IndexSummary>),
+    /// Serve the quotes on standby, keeping those they replace to roll back to
+    Activate(oneshot::Sender<anyhow::Result<IndexSummary>>),
+    /// Serve the quotes replaced by the last activation again
+    Rollback(oneshot::Sender<anyhow::Result<IndexSummary>>),
+    /// Re-index a single quote file, see [`Quotes::reindex_file`]
+    Reindex(PathBuf, oneshot::Sender<anyhow::Result<IndexSummary>>),
+    /// Serve the given quote to everyone until the next rotation, see [`Server::override_file`]
+    Pin(String, oneshot::Sender<anyhow::Result<Quote>>),
+    /// Stop serving the pinned quote, answering whether one was pinned
+    Unpin(oneshot::Sender<anyhow::Result<bool>>),
+}
+
+impl QuoteRequest {
+    /// Answer a request for a quote with `quote`, or give back a request of any other kind
+    fn answer(self, quote: Quote, formatter: &Formatter) -> Result<(), Self> {
+        match self {
+            QuoteRequest::Get(transport, quote_tx)
+            | QuoteRequest::GetIn(_, transport, quote_tx)
+            | QuoteRequest::ClientDaily(_, transport, quote_tx) => {
+                let quote = formatter.format(quote, transport);
+                let _ = quote_tx.send(Ok(ChosenQuote::Read(quote)));
+                Ok(())
+            }
+            QuoteRequest::Rotating(_, transport, quote_tx) => {
+                let _ = quote_tx.send(Ok(formatter.format(quote, transport)));
+                Ok(())
+            }
+            other => Err(other),
+        }
+    }
+}
+
+/// Quotes kept alongside those being served, see [`QuoteRequest::Stage`]
+#[derive(Debug)]
+enum Standby {
+    /// Staged, but not yet activated
+    Staged(Quotes),
+    /// Served until the last activation
+    Previous(Quotes),
+}
+
+/// The categories of quotes to serve, parsed from "all" or from a list of names separated by ','
+/// or '+', e.g. "kids,general"
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AllowedCategories {
+    /// Every category, whatever quote files declare
     All,
+    /// Only the listed categories
+    Only(Vec<QuoteCategory>),
+}
+
+impl Default for AllowedCategories {
+    fn default() -> Self {
+        AllowedCategories::Only(vec![QuoteCategory::DECOROUS])
+    }
+}
+
+impl FromStr for AllowedCategories {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.trim().eq_ignore_ascii_case("all") {
+            return Ok(AllowedCategories::All);
+        }
+        s.split([',', '+'])
+            .map(QuoteCategory::named)
+            .collect::<Result<_, _>>()
+            .map(AllowedCategories::Only)
+    }
 }
 
 impl AllowedCategories {
-    pub fn as_category_vec(&self) -> Vec<QuoteCategory> {
-        match *self {
-            AllowedCategories::Decorous => vec![QuoteCategory::Decorous],
-            AllowedCategories::Offensive => vec![QuoteCategory::Offensive],
-            AllowedCategories::All => vec![QuoteCategory::Decorous, QuoteCategory::Offensive],
+    /// The categories allowed, or `None` if all are
+    pub fn as_category_vec(&self) -> Option<Vec<QuoteCategory>> {
+        match self {
+            AllowedCategories::All => None,
+            AllowedCategories::Only(categories) => Some(categories.clone()),
+        }
+    }
+}
+
+/// What to do should privileges fail to be dropped, see [`Server::drop_privileges`]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum PrivilegeDrop {
+    /// Fail if still running as root, but only warn otherwise, e.g. when started unprivileged
+    #[default]
+    FailClosed,
+    /// Fail whenever privileges can't be dropped, even when started unprivileged
+    Strict,
+    /// Only warn, even if that leaves the server running as root
+    AllowRoot,
+}
+
+/// A TCP and UDP socket pair bound to the same address, either of which may be absent if its
+/// protocol isn't served
+#[derive(Debug, Default)]
+struct Listener {
+    tcp: Option<TcpListener>,
+    udp: Option<UdpSocket>,
+}
+
+impl Listener {
+    fn local_addr(&self) -> Option<SocketAddr> {
+        match (&self.tcp, &self.udp) {
+            (Some(tcp), _) => tcp.local_addr().ok(),
+            (None, Some(udp)) => udp.local_addr().ok(),
+            (None, None) => None,
+        }
+    }
+
+    /// The protocols this listener serves, for logging
+    fn protocols(&self) -> &'static str {
+        match (&self.tcp, &self.udp) {
+            (Some(_), Some(_)) => "TCP/UDP",
+            (Some(_), None) => "TCP",
+            (None, Some(_)) => "UDP",
+            (None, None) => "nothing",
+        }
+    }
+}
+
+/// State shared by all of a server's accept loops and the requests they spawn
+#[derive(Debug, Default)]
+struct Shared {
+    stats: Arc<Stats>,
+    slow_request_threshold: Option<Duration>,
+    access_control: AccessControl,
+    log_denied: bool,
+    /// Fraction of requests logged at INFO, if not all of them
+    log_sample_rate: Option<f64>,
+    connection_options: ConnectionOptions,
+    formatter: Formatter,
+    telnet: bool,
+    length_prefix: bool,
+    /// Hold TCP clients' quotes to the same length limit as UDP clients'
+    strict_rfc865: bool,
+    /// Largest UDP datagram answered, if larger ones are to be ignored
+    max_udp_request: Option<usize>,
+    /// Heed the hint in the first byte of UDP requests
+    udp_hints: bool,
+    /// Bounds on the length of quotes chosen for TCP clients, if not those the quotes were given
+    tcp_lengths: Option<QuoteLengths>,
+    /// Bounds on the length of quotes chosen for UDP (and HTTP) clients, likewise
+    udp_lengths: Option<QuoteLengths>,
+    /// Send each client its own quote of the day, rather than a random quote
+    sticky_daily: bool,
+    /// How long a quote chosen for a UDP client is sent to every other UDP client
+    udp_cache_window: Option<Duration>,
+    /// The quote last chosen for a UDP client, and when
+    #[cfg(feature = "udp")]
+    udp_cache: std::sync::Mutex<Option<(Instant, Arc<Quote>)>>,
+    /// Length of the shortest quote indexed, kept up to date by the task owning the quotes, so UDP
+    /// requests needn't retry when no quote fits in a datagram
+    #[cfg(feature = "udp")]
+    shortest_quote: AtomicUsize,
+    /// Most requests handled at once, beyond which new ones are shed
+    max_in_flight: Option<usize>,
+    /// Requests being handled
+    in_flight: AtomicUsize,
+    /// Tasks serving requests, so that those still in flight when the server's done draining can
+    /// be abandoned
+    requests: std::sync::Mutex<JoinSet<()>>,
+    /// Sent to TCP clients whose connections are shed, if anything
+    busy_message: Option<String>,
+    /// Categories served to clients without a policy of their own, if not all of those indexed
+    categories: Option<Vec<QuoteCategory>>,
+    #[cfg(feature = "tls")]
+    tls: Option<crate::Tls>,
+    /// Where offensive quotes served are recorded, if they are
+    audit_log: Option<Arc<AuditLog>>,
+}
+
+impl Shared {
+    /// Check the client against the access control lists, counting and logging it if denied
+    fn admit(&self, protocol: &str, peer: SocketAddr) -> bool {
+        let allowed = self.access_control.is_allowed(peer.ip());
+        if !allowed {
+            self.stats.denied_requests.fetch_add(1, Ordering::Relaxed);
+            if self.log_denied {
+                warn!("Denied {protocol} request from {peer}");
+            } else {
+                debug!("Denied {protocol} request from {peer}");
+            }
+        }
+        allowed
+    }
+
+    /// Count a new request as in flight until the returned guard is dropped, unless as many as are
+    /// allowed already are
+    fn start_request(self: &Arc<Self>) -> Option<InFlight> {
+        let in_flight = self.in_flight.fetch_add(1, Ordering::Relaxed);
+        let guard = InFlight(Arc::clone(self));
+        self.max_in_flight
+            .is_none_or(|max| in_flight < max)
+            .then_some(guard)
+    }
+
+    /// Serve a request in a task of its own
+    fn spawn_request<F: Future<Output = ()> + Send + 'static>(&self, task: F) {
+        let mut requests = self.requests.lock().unwrap();
+        // Nothing else waits on finished requests, so they're reaped here
+        while requests.try_join_next().is_some() {}
+        requests.spawn(task);
+    }
+
+    /// Abort every request still in flight, returning how many were
+    async fn abandon_requests(&self) -> usize {
+        let mut requests = std::mem::take(&mut *self.requests.lock().unwrap());
+        requests.abort_all();
+        let mut abandoned = 0;
+        while let Some(result) = requests.join_next().await {
+            if result.is_err_and(|e| e.is_cancelled()) {
+                abandoned += 1;
+            }
+        }
+        abandoned
+    }
+
+    /// Identify a new request, and decide whether it's logged at INFO (see
+    /// [`Server::log_sample_rate`])
+    fn new_request(&self) -> RequestContext {
+        RequestContext {
+            // Random, rather than counted, so that IDs aren't reused across restarts
+            id: RequestId(rand::random()),
+            sampled: self
+                .log_sample_rate
+                .is_none_or(|rate| rand::random::<f64>() < rate),
+        }
+    }
+
+    /// The quote last chosen for a UDP client, if it's still within the cache window
+    #[cfg(feature = "udp")]
+    fn cached_udp_quote(&self) -> Option<Arc<Quote>> {
+        let window = self.udp_cache_window?;
+        let cache = self.udp_cache.lock().unwrap();
+        cache
+            .as_ref()
+            .filter(|(chosen, _)| chosen.elapsed() < window)
+            .map(|(_, quote)| Arc::clone(quote))
+    }
+
+    #[cfg(feature = "tcp")]
+    fn record_tcp_serve(
+        &self,
+        quote: &ChosenQuote,
+        start: Instant,
+        peer: SocketAddr,
+        request: RequestId,
+    ) {
+        self.stats.record_serve(quote.id(), quote.category());
+        self.stats.tcp_serves.fetch_add(1, Ordering::Relaxed);
+        if let Some(audit_log) = &self.audit_log {
+            audit_log.record_chosen("TCP", peer, request, quote);
         }
+        self.record_service_time(start, "TCP", peer);
+        request_info!("Done! Closing connection");
+    }
+
+    #[cfg(feature = "udp")]
+    fn record_udp_serve(
+        &self,
+        quote: &Quote,
+        start: Instant,
+        peer: SocketAddr,
+        request: RequestId,
+    ) {
+        self.stats.record_serve(&quote.id, quote.category);
+        self.stats.udp_serves.fetch_add(1, Ordering::Relaxed);
+        if let Some(audit_log) = &self.audit_log {
+            audit_log.record("UDP", peer, request, quote);
+        }
+        self.record_service_time(start, "UDP", peer);
+    }
+
+    fn record_service_time(&self, start: Instant, protocol: &str, peer: SocketAddr) {
+        let elapsed = start.elapsed();
+        self.stats.service_time.record(elapsed);
+        tracing::Span::current().record("duration_us", elapsed.as_micros() as u64);
+        if self
+            .slow_request_threshold
+            .is_some_and(|threshold| elapsed > threshold)
+        {
+            self.stats.slow_requests.fetch_add(1, Ordering::Relaxed);
+            warn!("Slow {protocol} request from {peer} took {elapsed:?}");
+        }
+    }
+}
+
+/// A request in flight, no longer counted once dropped (see [`Server::max_in_flight`])
+#[derive(Debug)]
+struct InFlight(Arc<Shared>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// A server serving in the background, created by [`Server::spawn`]
+#[derive(Debug)]
+pub struct ServerHandle {
+    addr: SocketAddr,
+    shutdown: watch::Sender<bool>,
+    task: JoinHandle<anyhow::Result<()>>,
+}
+
+impl ServerHandle {
+    /// The address the server is bound to, for both TCP and UDP
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Stop accepting requests, and finish those in flight (see [`Server::drain_timeout`]); use
+    /// [`ServerHandle::join`] to wait for the server to finish
+    pub fn shutdown(&self) {
+        self.shutdown.send_replace(true);
+    }
+
+    /// Wait for the server to finish, returning the error that stopped it, if any
+    pub async fn join(self) -> anyhow::Result<()> {
+        self.task.await.context("Server panicked")?
     }
 }
 
 #[derive(Debug, Default)]
 pub struct Server {
-    tcp_socket: Option<TcpListener>,
-    udp_socket: Option<UdpSocket>,
+    listeners: Vec<Listener>,
+    /// Simple services to serve, and the ports to serve them on
+    simple_services: Vec<(SimpleService, u16)>,
+    simple_listeners: Vec<(SimpleService, Listener)>,
+    workers: usize,
+    /// Protocols not to serve, even if built with support for them
+    tcp_disabled: bool,
+    udp_disabled: bool,
+    graceful_upgrade: bool,
+    /// How long requests in flight are given to finish when shutting down
+    drain_timeout: Option<Duration>,
+    privilege_drop: PrivilegeDrop,
+    /// Where statistics are persisted, and how often
+    state_file: Option<(PathBuf, Duration)>,
+    admin_address: Option<SocketAddr>,
+    admin: Option<TcpListener>,
+    /// Token admin requests over TCP must bear
+    admin_token: Option<String>,
+    #[cfg(unix)]
+    admin_socket_path: Option<PathBuf>,
+    #[cfg(unix)]
+    admin_socket: Option<tokio::net::UnixListener>,
+    /// Who may make admin requests over the Unix socket
+    #[cfg(unix)]
+    admin_peers: crate::admin::Peers,
+    http_address: Option<SocketAddr>,
+    http: Option<TcpListener>,
+    /// Number of daily quotes in the HTTP endpoint's feed
+    feed_length: Option<usize>,
+    /// Number of daily quotes in the HTTP endpoint's calendar
+    calendar_length: Option<usize>,
+    /// Origins whose pages may fetch from the HTTP endpoint
+    cors_origins: Vec<String>,
+    /// Where quotes submitted through the admin endpoint are appended
+    submissions_file: Option<PathBuf>,
+    /// Where offensive quotes served are recorded
+    audit_file: Option<PathBuf>,
+    /// Serve a single quote per period, rather than a random quote per request
+    rotation: Option<Rotation>,
+    /// Where a quote to serve in place of all others is pinned
+    override_file: Option<PathBuf>,
+    #[cfg(feature = "webhook")]
+    webhook: Option<crate::Webhook>,
+    #[cfg(feature = "mqtt")]
+    mqtt: Option<crate::MqttPublisher>,
+    #[cfg(feature = "statsd")]
+    statsd: Option<crate::StatsdExporter>,
+    #[cfg(feature = "registry")]
+    registration: Option<crate::Registration>,
+    shared: Shared,
+    socket_options: SocketOptions,
 }
 
-impl Server {
-    pub fn new() -> Self {
-        Self::default()
+impl Server {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Log a warning for every request taking longer than `threshold` to serve
+    pub fn slow_request_threshold(mut self, threshold: Duration) -> Self {
+        self.shared.slow_request_threshold = Some(threshold);
+        self
+    }
+
+    /// Only serve clients allowed by `access_control` (default: all clients)
+    pub fn access_control(mut self, access_control: AccessControl) -> Self {
+        self.shared.access_control = access_control;
+        self
+    }
+
+    /// Ignore UDP datagrams with payloads larger than `bytes`, counting them rather than answering
+    ///
+    /// RFC 865 clients send datagrams whose contents are ignored, so genuine requests are empty or
+    /// close to it; larger ones are most likely scans, or spoofed to amplify traffic towards a
+    /// victim. By default, every datagram is answered.
+    pub fn max_udp_request(mut self, bytes: usize) -> Self {
+        self.shared.max_udp_request = Some(bytes);
+        self
+    }
+
+    /// Serve only quotes in `categories`, of those indexed, to clients without a policy of their own
+    /// (default: all indexed quotes)
+    ///
+    /// This allows indexing more categories than are served by default, for clients whose policy
+    /// allows them (see [`Tls::host`](crate::Tls::host)).
+    pub fn categories(mut self, categories: &[QuoteCategory]) -> Self {
+        self.shared.categories = Some(categories.to_vec());
+        self
+    }
+
+    /// Speak TLS to TCP clients, with quote categories chosen by the hostname they ask for
+    #[cfg(feature = "tls")]
+    pub fn tls(mut self, tls: crate::Tls) -> Self {
+        self.shared.tls = Some(tls);
+        self
+    }
+
+    /// Send each TCP and UDP client its own quote of the day (see [`Quotes::client_daily_quote`]),
+    /// chosen by its IP address and the date, rather than a random quote
+    ///
+    /// Clients see the same quote all day, while different clients see different quotes. Should a
+    /// client's quote be too long to send it (e.g. over UDP), it's sent a random quote instead.
+    pub fn sticky_daily(mut self, sticky: bool) -> Self {
+        self.shared.sticky_daily = sticky;
+        self
+    }
+
+    /// Heed a hint in the first byte of UDP requests (default: disabled)
+    ///
+    /// An 'o' asks for an offensive quote, if offensive quotes are served to the client, and an 's'
+    /// for a short one (at most 160 bytes, if one is found quickly). Anything else, including an
+    /// empty request or a zero byte, gets the usual quote, so that RFC 865 clients are unaffected.
+    pub fn udp_hints(mut self, enabled: bool) -> Self {
+        self.shared.udp_hints = enabled;
+        self
+    }
+
+    /// Only choose quotes within `lengths` for TCP clients (default: those given to
+    /// [`Quotes::with_lengths`])
+    ///
+    /// Quotes are still held to the limits of e.g. [`Server::strict_rfc865`] too.
+    pub fn tcp_quote_lengths(mut self, lengths: QuoteLengths) -> Self {
+        self.shared.tcp_lengths = Some(lengths);
+        self
+    }
+
+    /// Only choose quotes within `lengths` for UDP clients, and for HTTP clients, which are served
+    /// like them (default: those given to [`Quotes::with_lengths`])
+    pub fn udp_quote_lengths(mut self, lengths: QuoteLengths) -> Self {
+        self.shared.udp_lengths = Some(lengths);
+        self
+    }
+
+    /// Shed requests while `max` are being handled (default: unlimited)
+    ///
+    /// New TCP connections are closed at once (see [`Server::busy_message`]), HTTP clients sent a
+    /// 503, and UDP requests dropped, and all counted, so that a flood of requests degrades service predictably rather
+    /// than growing the server's tasks and memory without bound.
+    pub fn max_in_flight(mut self, max: usize) -> Self {
+        self.shared.max_in_flight = Some(max);
+        self
+    }
+
+    /// Send `message`, followed by CRLF, to TCP clients before closing their connections when
+    /// shedding them (default: close them without a word)
+    ///
+    /// HTTP clients shed are sent it as the body of their 503, once they've sent their requests.
+    ///
+    /// This is only sent if it can be without waiting, so that shedding stays cheap.
+    pub fn busy_message<S: Into<String>>(mut self, message: S) -> Self {
+        self.shared.busy_message = Some(format!("{}\r\n", message.into()));
+        self
+    }
+
+    /// Send every UDP client the same quote for `window` after one is chosen, rather than choosing
+    /// one for each (default: disabled)
+    ///
+    /// This absorbs bursts of UDP requests, e.g. floods, cheaply: within the window, requests are
+    /// answered without choosing or reading a quote.
+    pub fn udp_cache_window(mut self, window: Duration) -> Self {
+        self.shared.udp_cache_window = Some(window);
+        self
+    }
+
+    /// Log a warning for every denied request, rather than only at debug level
+    pub fn log_denied(mut self, log: bool) -> Self {
+        self.shared.log_denied = log;
+        self
+    }
+
+    /// Log only this fraction (between 0 and 1) of requests at INFO, and the rest at DEBUG, so that
+    /// floods of requests don't flood the log too
+    ///
+    /// Each request is sampled at random. Warnings and errors are always logged.
+    pub fn log_sample_rate(mut self, rate: f64) -> Self {
+        self.shared.log_sample_rate = Some(rate);
+        self
+    }
+
+    /// Set the maximum number of pending TCP connections (default: [`crate::DEFAULT_BACKLOG`])
+    ///
+    /// Must be set before binding.
+    pub fn backlog(mut self, backlog: u32) -> Self {
+        self.socket_options.backlog = backlog;
+        self
+    }
+
+    /// Set SO_REUSEADDR on the sockets (default: enabled on Unix)
+    ///
+    /// Must be set before binding.
+    pub fn reuse_address(mut self, reuse: bool) -> Self {
+        self.socket_options.reuse_address = reuse;
+        self
+    }
+
+    /// Set SO_REUSEPORT on the sockets, allowing multiple servers to bind the same port (default: disabled)
+    ///
+    /// This is only supported on Unix-like systems. Must be set before binding.
+    pub fn reuse_port(mut self, reuse: bool) -> Self {
+        self.socket_options.reuse_port = reuse;
+        self
+    }
+
+    /// Set SO_RCVBUF on the TCP and UDP sockets (default: the system's default)
+    ///
+    /// A larger buffer lets the UDP socket absorb bursts of requests without dropping them. The
+    /// system may adjust the size, e.g. Linux doubles it and caps it at `net.core.rmem_max`. Must be
+    /// set before binding.
+    pub fn recv_buffer_size(mut self, bytes: usize) -> Self {
+        self.socket_options.recv_buffer_size = Some(bytes);
+        self
+    }
+
+    /// Set SO_SNDBUF on the TCP and UDP sockets (default: the system's default)
+    ///
+    /// The system may adjust the size, e.g. Linux doubles it and caps it at `net.core.wmem_max`.
+    /// Must be set before binding.
+    pub fn send_buffer_size(mut self, bytes: usize) -> Self {
+        self.socket_options.send_buffer_size = Some(bytes);
+        self
+    }
+
+    /// Mark packets sent from the TCP and UDP sockets with the given Differentiated Services
+    /// codepoint (default: the system's default, usually 0)
+    ///
+    /// `dscp` is the six-bit codepoint, e.g. 8 for CS1 (the "scavenger" class), not the whole TOS
+    /// byte. Must be set before binding.
+    pub fn dscp(mut self, dscp: u8) -> Self {
+        self.socket_options.dscp = Some(dscp);
+        self
+    }
+
+    /// Set the TTL (IPv4) or hop limit (IPv6) of packets sent from the TCP and UDP sockets
+    /// (default: the system's default)
+    ///
+    /// Must be set before binding.
+    pub fn ttl(mut self, hops: u32) -> Self {
+        self.socket_options.ttl = Some(hops);
+        self
+    }
+
+    /// Bind the TCP and UDP sockets to the network interface named `device` (e.g. "eth1"), so that
+    /// only packets arriving on it are received, whatever address they're sent to (default: any
+    /// interface)
+    ///
+    /// This is only supported on Linux, and needs `CAP_NET_RAW` on kernels before 5.7. Must be set
+    /// before binding.
+    pub fn bind_device<S: Into<String>>(mut self, device: S) -> Self {
+        self.socket_options.device = Some(device.into());
+        self
+    }
+
+    /// Wrap every quote in `template` before sending it
+    pub fn template(mut self, template: Template) -> Self {
+        self.shared.formatter.template = Some(template);
+        self
+    }
+
+    /// Reflow every quote to fit within `width` columns before sending it (and before decorating it)
+    ///
+    /// See [`rewrap`](crate::rewrap) for how deliberate line breaks, e.g. in verse, are preserved.
+    pub fn wrap(mut self, width: usize) -> Self {
+        self.shared.formatter.wrap = Some(width);
+        self
+    }
+
+    /// Style quotes sent over TCP with ANSI escape codes; quotes sent over UDP are never styled
+    pub fn color(mut self, scheme: ColorScheme) -> Self {
+        self.shared.formatter.color = Some(scheme);
+        self
+    }
+
+    /// Frame every quote in `decoration` before sending it (and before applying any template)
+    pub fn decorate(mut self, decoration: Decoration) -> Self {
+        self.shared.formatter.decoration = Some(decoration);
+        self
+    }
+
+    /// Treat TCP clients as telnet clients, refusing any options they negotiate and sending quotes
+    /// as network virtual terminal text (i.e. with CRLF line endings and IAC bytes escaped)
+    pub fn telnet(mut self, telnet: bool) -> Self {
+        self.shared.telnet = telnet;
+        self
+    }
+
+    /// Prefix each quote sent to TCP clients with its length in bytes, as a 2-byte big-endian
+    /// integer, so that clients can read exactly one quote without waiting for the connection to
+    /// close
+    ///
+    /// Quotes too long for the prefix are truncated to fit. Telnet clients are never sent a prefix
+    /// (see [`Server::telnet`]).
+    pub fn length_prefix(mut self, enabled: bool) -> Self {
+        self.shared.length_prefix = enabled;
+        self
+    }
+
+    /// Comply strictly with RFC 865: quotes are restricted to ASCII printing characters, spaces,
+    /// and line breaks, and TCP clients, like UDP clients, are only sent quotes shorter than 512
+    /// characters
+    ///
+    /// Typographic punctuation is replaced with ASCII equivalents, and other characters with '?'
+    /// (see [`to_ascii`](crate::to_ascii)). Colors are never applied in this mode.
+    pub fn strict_rfc865(mut self, strict: bool) -> Self {
+        self.shared.strict_rfc865 = strict;
+        self.shared.formatter.ascii = strict;
+        self
+    }
+
+    /// Set TCP_NODELAY on accepted TCP connections, disabling Nagle's algorithm
+    pub fn tcp_nodelay(mut self, nodelay: bool) -> Self {
+        self.shared.connection_options.nodelay = Some(nodelay);
+        self
+    }
+
+    /// Enable SO_KEEPALIVE on accepted TCP connections, so that dead peers are eventually noticed
+    pub fn tcp_keepalive(mut self, keepalive: Keepalive) -> Self {
+        self.shared.connection_options.keepalive = Some(keepalive);
+        self
+    }
+
+    /// Set SO_LINGER on accepted TCP connections, so that closing them waits up to `linger` for
+    /// unsent data to be delivered; a zero duration resets connections instead of closing them
+    pub fn tcp_linger(mut self, linger: Duration) -> Self {
+        self.shared.connection_options.linger = Some(linger);
+        self
+    }
+
+    /// Run `workers` independent accept loops, each with its own sockets (default: 1)
+    ///
+    /// With more than one worker SO_REUSEPORT is set on all sockets, so the kernel balances incoming
+    /// requests between them; this requires a Unix-like system. Workers can only run in parallel on a
+    /// multi-threaded runtime. Must be set before binding.
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.workers = workers;
+        self
+    }
+
+    /// Serve quotes over TCP (default: enabled if built with the "tcp" feature)
+    ///
+    /// Must be set before binding.
+    pub fn tcp(mut self, enabled: bool) -> Self {
+        self.tcp_disabled = !enabled;
+        self
+    }
+
+    /// Serve quotes over UDP (default: enabled if built with the "udp" feature)
+    ///
+    /// Must be set before binding.
+    pub fn udp(mut self, enabled: bool) -> Self {
+        self.udp_disabled = !enabled;
+        self
+    }
+
+    /// What to do should privileges fail to be dropped (default: fail if still running as root)
+    pub fn privilege_drop(mut self, policy: PrivilegeDrop) -> Self {
+        self.privilege_drop = policy;
+        self
+    }
+
+    /// Upgrade in place upon SIGUSR2 (default: disabled)
+    ///
+    /// The server re-executes its program with the same arguments, handing over its bound sockets;
+    /// once the new instance is serving, this one stops accepting requests, finishes those in flight,
+    /// and returns from [`Server::serve`]. When enabled, a server started by such an upgrade binds to
+    /// the inherited sockets rather than the given address.
+    ///
+    /// NOTE: The new instance runs with the privileges dropped by this one, so it must be able to read
+    /// its quotes as that user. This is only supported on Unix-like systems.
+    pub fn graceful_upgrade(mut self, enabled: bool) -> Self {
+        self.graceful_upgrade = enabled;
+        self
+    }
+
+    /// Give requests in flight `timeout` to finish when shutting down or upgrading, after which
+    /// they're abandoned (default: [`DEFAULT_DRAIN_TIMEOUT`])
+    pub fn drain_timeout(mut self, timeout: Duration) -> Self {
+        self.drain_timeout = Some(timeout);
+        self
+    }
+
+    /// Persist statistics to `path` every `interval` and when done serving, restoring them from it
+    /// when starting to serve
+    pub fn state_file<P: Into<PathBuf>>(mut self, path: P, interval: Duration) -> Self {
+        self.state_file = Some((path.into(), interval));
+        self
+    }
+
+    /// Serve the admin endpoint over HTTP on `address` (default: disabled)
+    ///
+    /// NOTE: Unless a token is set (see [`Server::admin_token`]), the endpoint is unauthenticated,
+    /// so only bind it to a trusted address, e.g. on the loopback interface. Must be set before
+    /// binding.
+    pub fn admin_address(mut self, address: SocketAddr) -> Self {
+        self.admin_address = Some(address);
+        self
+    }
+
+    /// Refuse admin requests over TCP (see [`Server::admin_address`]) unless they bear `token`, in an
+    /// `Authorization: Bearer` header (default: no token is needed)
+    pub fn admin_token<S: Into<String>>(mut self, token: S) -> Self {
+        self.admin_token = Some(token.into());
+        self
+    }
+
+    /// Also serve the admin endpoint over HTTP on a Unix socket at `path` (default: disabled)
+    ///
+    /// Clients are authenticated by their credentials rather than a token: only root, the user the
+    /// server runs as, and users allowed by [`Server::admin_user`] or [`Server::admin_group`] are
+    /// served. A socket left at `path` is replaced, and the socket is left behind on shutdown, so
+    /// that an upgraded server can take it over. Must be set before binding.
+    ///
+    /// On Linux, a `path` starting with '@' (e.g. "@qotd") names a socket in the abstract namespace
+    /// rather than the filesystem, which vanishes when the server exits. As an abstract socket
+    /// can't be replaced, an upgraded server can't bind it while this one still holds it.
+    #[cfg(unix)]
+    pub fn admin_socket<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.admin_socket_path = Some(path.into());
+        self
+    }
+
+    /// Allow the user `uid` on the admin socket (see [`Server::admin_socket`])
+    #[cfg(unix)]
+    pub fn admin_user(mut self, uid: u32) -> Self {
+        self.admin_peers.uids.push(uid);
+        self
+    }
+
+    /// Allow users whose primary group is `gid` on the admin socket (see [`Server::admin_socket`])
+    #[cfg(unix)]
+    pub fn admin_group(mut self, gid: u32) -> Self {
+        self.admin_peers.gids.push(gid);
+        self
+    }
+
+    /// Serve every client the same quote until `rotation` moves on to the next period, rather than
+    /// a random quote per request (default: disabled)
+    ///
+    /// Quotes are chosen as by [`Quotes::rotating_quote`], and each change of quote is logged. This
+    /// is also when quotes are posted to webhooks and published to MQTT; they're posted daily if
+    /// this isn't set.
+    pub fn rotate(mut self, rotation: Rotation) -> Self {
+        self.rotation = Some(rotation);
+        self
+    }
+
+    /// Serve the quote in the file at `path`, whenever there is one, to every client in place of
+    /// the quotes otherwise chosen (default: none), e.g. to pin an announcement
+    ///
+    /// The quote expires at the next rotation (see [`Server::rotate`]; daily if that isn't set)
+    /// after the file was last modified, and the file is then removed. Quotes may also be pinned
+    /// through the admin endpoint, which writes them to this file if it's set. The file must be
+    /// readable and writable by the user the server runs as.
+    pub fn override_file<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.override_file = Some(path.into());
+        self
+    }
+
+    /// Post a quote to `webhook` on every rotation (see [`Server::rotate`]), for as long as quotes
+    /// are served
+    #[cfg(feature = "webhook")]
+    pub fn webhook(mut self, webhook: crate::Webhook) -> Self {
+        self.webhook = Some(webhook);
+        self
+    }
+
+    /// Publish a quote with `publisher` on every rotation (see [`Server::rotate`]), for as long as
+    /// quotes are served
+    #[cfg(feature = "mqtt")]
+    pub fn mqtt(mut self, publisher: crate::MqttPublisher) -> Self {
+        self.mqtt = Some(publisher);
+        self
+    }
+
+    /// Push statistics with `exporter` at its interval, for as long as quotes are served
+    #[cfg(feature = "statsd")]
+    pub fn statsd(mut self, exporter: crate::StatsdExporter) -> Self {
+        self.statsd = Some(exporter);
+        self
+    }
+
+    /// Register the server with a service registry (e.g. Consul or etcd) once it starts serving,
+    /// keeping the registration alive while it serves, and deregister it once it shuts down
+    ///
+    /// The server is registered with the address of its first listener.
+    #[cfg(feature = "registry")]
+    pub fn register(mut self, registration: crate::Registration) -> Self {
+        self.registration = Some(registration);
+        self
+    }
+
+    /// Serve quotes over HTTP on `address` (default: disabled)
+    ///
+    /// `GET /` is sent a quote as plain text or JSON, as its Accept header prefers, `GET /feed.xml`
+    /// an Atom feed of the most recent quotes of the day (see [`Quotes::daily_quote`]), and
+    /// `GET /calendar.ics` an iCalendar feed of the upcoming ones. Quotes are sent with an ETag, and
+    /// may be cached until the next rotation (see [`Server::rotate`]). Clients are subject to the
+    /// same access control as TCP and UDP clients. Must be set before binding.
+    pub fn http_address(mut self, address: SocketAddr) -> Self {
+        self.http_address = Some(address);
+        self
+    }
+
+    /// Include this many days' quotes in the HTTP endpoint's feed (default:
+    /// [`DEFAULT_FEED_LENGTH`](crate::DEFAULT_FEED_LENGTH))
+    pub fn feed_length(mut self, days: usize) -> Self {
+        self.feed_length = Some(days);
+        self
+    }
+
+    /// Include this many days' quotes in the HTTP endpoint's calendar (default:
+    /// [`DEFAULT_CALENDAR_LENGTH`](crate::DEFAULT_CALENDAR_LENGTH))
+    pub fn calendar_length(mut self, days: usize) -> Self {
+        self.calendar_length = Some(days);
+        self
+    }
+
+    /// Allow pages on `origin` (e.g. "https://example.com") to fetch from the HTTP endpoint, or
+    /// pages on any origin if it's "*" (default: none)
+    ///
+    /// May be called more than once, to allow several origins.
+    pub fn cors_origin<S: Into<String>>(mut self, origin: S) -> Self {
+        self.cors_origins.push(origin.into());
+        self
+    }
+
+    /// Accept quotes submitted to the admin endpoint (`POST /admin/quotes`), appending them to the
+    /// quote file at `path` and serving them at once (default: disabled)
+    ///
+    /// The file is created if it doesn't exist yet, and must be writable after dropping
+    /// privileges. It should be in the quote directory, or added with [`Quotes::add_file`], so that
+    /// submissions are still served after a restart.
+    pub fn submissions_file<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.submissions_file = Some(path.into());
+        self
+    }
+
+    /// Append a record of every offensive quote served to the file at `path`, e.g. to answer for
+    /// exactly what was sent to whom
+    ///
+    /// The file is opened on binding. Each record is a line of tab-separated fields: an RFC 3339
+    /// timestamp, the protocol, the client's address, the quote's ID and hash (see
+    /// [`Quote::hash`]), and the request's ID, as logged.
+    pub fn audit_file<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.audit_file = Some(path.into());
+        self
+    }
+
+    /// Also serve `service` on `port` (e.g. [`SimpleService::default_port`]) of the address the
+    /// server is bound to, over TCP and UDP as they're served
+    ///
+    /// Simple services share the server's access control and limit on requests in flight, but
+    /// not its statistics. They're bound anew, rather than handed over, by an upgraded server (see
+    /// [`Server::graceful_upgrade`]).
+    pub fn simple_service(mut self, service: SimpleService, port: u16) -> Self {
+        self.simple_services.push((service, port));
+        self
+    }
+
+    /// The address the admin endpoint is bound to, if it is
+    pub fn admin_local_addr(&self) -> Option<SocketAddr> {
+        self.admin
+            .as_ref()
+            .and_then(|admin| admin.local_addr().ok())
+    }
+
+    /// The address the HTTP endpoint is bound to, if it's enabled (see [`Server::http_address`])
+    pub fn http_local_addr(&self) -> Option<SocketAddr> {
+        self.http.as_ref().and_then(|http| http.local_addr().ok())
+    }
+
+    /// The address `service` is bound to, if it's served (see [`Server::simple_service`])
+    pub fn simple_local_addr(&self, service: SimpleService) -> Option<SocketAddr> {
+        self.simple_listeners
+            .iter()
+            .find(|(served, _)| *served == service)
+            .and_then(|(_, listener)| listener.local_addr())
+    }
+
+    /// The address the server is bound to, if it is, for both TCP and UDP
+    ///
+    /// When binding to port 0 this reveals the port the operating system chose.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.listeners.first().and_then(Listener::local_addr)
+    }
+
+    /// The address the server's TCP socket is bound to, if it serves TCP
+    pub fn tcp_local_addr(&self) -> Option<SocketAddr> {
+        self.listeners
+            .first()
+            .and_then(|listener| listener.tcp.as_ref())
+            .and_then(|tcp| tcp.local_addr().ok())
+    }
+
+    /// The address the server's UDP socket is bound to, if it serves UDP
+    pub fn udp_local_addr(&self) -> Option<SocketAddr> {
+        self.listeners
+            .first()
+            .and_then(|listener| listener.udp.as_ref())
+            .and_then(|udp| udp.local_addr().ok())
+    }
+
+    /// Statistics collected by this server, which remain available while it's serving
+    pub fn stats(&self) -> Arc<Stats> {
+        Arc::clone(&self.shared.stats)
     }
 
     pub async fn bind<A: ToSocketAddrs + std::fmt::Debug>(
         mut self,
         address: A,
     ) -> anyhow::Result<Self> {
-        trace!("Binding TCP socket");
-        let tcp_socket = TcpListener::bind(address)
-            .await
-            .context("Failed to bind TCP port")?;
-        debug!("Bound to TCP {}", tcp_socket.local_addr()?);
-
-        // If user specifies e.g. port 0, meaning "choose one for me", reading TCP socket's address
-        // ensures that we open the same port number for the UDP socket
-        trace!("Binding UDP socket");
-        self.udp_socket = Some(
-            UdpSocket::bind(
-                tcp_socket
-                    .local_addr()
-                    .context("Could not read local address")?,
-            )
+        if let Some(path) = &self.audit_file {
+            let audit_log = AuditLog::open(path)
+                .with_context(|| format!("Unable to open audit log {}", path.display()))?;
+            self.shared.audit_log = Some(Arc::new(audit_log));
+        }
+        if let Some(admin_address) = self.admin_address {
+            // An upgraded server binds its own admin socket while the old one still listens
+            let options = SocketOptions {
+                reuse_port: cfg!(unix) && self.graceful_upgrade,
+                ..SocketOptions::default()
+            };
+            let admin = options
+                .bind_tcp(admin_address)
+                .context("Failed to bind admin port")?;
+            debug!("Bound admin endpoint to {}", admin.local_addr()?);
+            if self.admin_token.is_none() && !admin_address.ip().is_loopback() {
+                warn!("The admin endpoint is unauthenticated, so anyone who can reach {admin_address} can manage the server");
+            }
+            self.admin = Some(admin);
+        }
+        #[cfg(unix)]
+        if let Some(path) = &self.admin_socket_path {
+            let admin_socket = crate::admin::bind_socket(path)?;
+            debug!("Bound admin endpoint to {}", path.display());
+            self.admin_socket = Some(admin_socket);
+        }
+        if let Some(http_address) = self.http_address {
+            let options = SocketOptions {
+                reuse_port: cfg!(unix) && self.graceful_upgrade,
+                ..SocketOptions::default()
+            };
+            let http = options
+                .bind_tcp(http_address)
+                .context("Failed to bind HTTP port")?;
+            debug!("Bound HTTP endpoint to {}", http.local_addr()?);
+            self.http = Some(http);
+        }
+
+        #[cfg(unix)]
+        if self.graceful_upgrade {
+            if let Some(sockets) =
+                crate::upgrade::inherited_sockets().context("Failed to inherit sockets")?
+            {
+                debug!("Ignoring {address:?} in favor of inherited sockets");
+                for (tcp, udp) in sockets {
+                    self.listeners.push(Listener {
+                        tcp: tcp.map(TcpListener::from_std).transpose()?,
+                        udp: udp.map(UdpSocket::from_std).transpose()?,
+                    });
+                }
+                let ip = self.local_addr().context("No sockets inherited")?.ip();
+                self.bind_simple_services(ip)?;
+                return Ok(self);
+            }
+        }
+
+        let serves_tcp = cfg!(feature = "tcp") && !self.tcp_disabled;
+        let serves_udp = cfg!(feature = "udp") && !self.udp_disabled;
+        if !serves_tcp && !serves_udp {
+            anyhow::bail!("Neither TCP nor UDP is enabled");
+        }
+        if self.workers > 1 {
+            self.socket_options.reuse_port = true;
+        }
+
+        let mut listener = Err(anyhow::anyhow!("No addresses to bind to: {address:?}"));
+        for addr in lookup_host(address)
             .await
-            .context("Failed to bind UDP port")?,
-        );
-        debug!(
-            "Bound to UDP {}",
-            self.udp_socket.as_ref().unwrap().local_addr()?
-        );
-        self.tcp_socket = Some(tcp_socket);
+            .context("Failed to resolve bind address")?
+        {
+            listener = self.bind_listener(&self.socket_options, addr);
+            if listener.is_ok() {
+                break;
+            }
+        }
+        let listener = listener?;
+
+        // If user specifies e.g. port 0, meaning "choose one for me", reading the first socket's
+        // address ensures that we open the same port number for all other workers
+        let local_addr = listener
+            .local_addr()
+            .context("Could not read local address")?;
+        self.listeners.push(listener);
+
+        for worker in 1..self.workers {
+            trace!("Binding sockets for worker {worker}");
+            let listener = self
+                .bind_listener(&self.socket_options, local_addr)
+                .context("Failed to bind sockets for worker")?;
+            self.listeners.push(listener);
+        }
+        self.bind_simple_services(local_addr.ip())?;
 
         Ok(self)
     }
 
-    /// Drop elevated privileges
+    /// Bind the simple services (see [`Server::simple_service`]) to `ip`, each on its own port
+    fn bind_simple_services(&mut self, ip: IpAddr) -> anyhow::Result<()> {
+        // An upgraded server binds them while the old one still listens, as it does its admin socket
+        let options = SocketOptions {
+            reuse_port: self.socket_options.reuse_port || (cfg!(unix) && self.graceful_upgrade),
+            ..self.socket_options.clone()
+        };
+        for &(service, port) in &self.simple_services {
+            let mut listener = self
+                .bind_listener(&options, SocketAddr::new(ip, port))
+                .with_context(|| format!("Failed to bind {} port", service.name()))?;
+            if !service.serves_udp() {
+                listener.udp = None;
+            }
+            self.simple_listeners.push((service, listener));
+        }
+        Ok(())
+    }
+
+    /// Bind a socket for each enabled protocol to `address`
+    ///
+    /// The UDP socket is bound to the TCP socket's port, which differs from `address` if its port is
+    /// 0.
+    #[allow(unused_mut, unused_variables)]
+    fn bind_listener(
+        &self,
+        options: &SocketOptions,
+        mut address: SocketAddr,
+    ) -> anyhow::Result<Listener> {
+        let mut listener = Listener::default();
+
+        #[cfg(feature = "tcp")]
+        if !self.tcp_disabled {
+            trace!("Binding TCP socket");
+            let tcp_socket = options
+                .bind_tcp(address)
+                .context("Failed to bind TCP port")?;
+            address = tcp_socket.local_addr()?;
+            debug!("Bound to TCP {address}");
+            listener.tcp = Some(tcp_socket);
+        }
+
+        #[cfg(feature = "udp")]
+        if !self.udp_disabled {
+            trace!("Binding UDP socket");
+            let udp_socket = options
+                .bind_udp(address)
+                .context("Failed to bind UDP port")?;
+            debug!("Bound to UDP {}", udp_socket.local_addr()?);
+            listener.udp = Some(udp_socket);
+        }
+
+        Ok(listener)
+    }
+
+    /// Drop elevated privileges to those of the user `name`
+    ///
+    /// Failing to drop them, e.g. because there's no such user, is an error if still running as
+    /// root, unless allowed by [`Server::privilege_drop`]; otherwise it's only logged.
     ///
     /// This is currently a no-op on non-Unix/non-Unix-like systems (e.g. Windows)
     pub fn drop_privileges<S: AsRef<str>>(self, name: S) -> anyhow::Result<Self> {
         #[cfg(unix)]
         {
-            use nix::unistd::{setgid, setuid, User};
+            use nix::unistd::{setgid, setuid, Uid, User};
 
-            if let Some(user) = User::from_name(name.as_ref())
-                .context(format!("Failed to get user: {}", name.as_ref()))?
-            {
-                // Must drop gid first: dropping uid first robs us of our permissions to change our gid!
-                setgid(user.gid)
-                    .context(format!("Failed to set gid: {}", user.gid))
-                    .and_then(|_| {
-                        setuid(user.uid).context(format!("Failed to set uid: {}", user.uid))
-                    })
-                    .unwrap_or_else(|e| {
-                        warn!("Failed to drop user privileges: {e:?}");
-                    });
+            let user = User::from_name(name.as_ref())
+                .context(format!("Failed to get user: {}", name.as_ref()))?;
+            let dropped = user
+                .with_context(|| format!("No such user: {}", name.as_ref()))
+                .and_then(|user| {
+                    // Like the gid, supplementary groups must be set while we still may
+                    crate::privsep::init_groups(&user)?;
+                    // Must drop gid first: dropping uid first robs us of our permissions to change
+                    // our gid!
+                    setgid(user.gid).context(format!("Failed to set gid: {}", user.gid))?;
+                    setuid(user.uid).context(format!("Failed to set uid: {}", user.uid))
+                });
+            if let Err(e) = dropped {
+                let fatal = match self.privilege_drop {
+                    PrivilegeDrop::FailClosed => Uid::effective().is_root(),
+                    PrivilegeDrop::Strict => true,
+                    PrivilegeDrop::AllowRoot => false,
+                };
+                if fatal {
+                    return Err(e.context("Failed to drop user privileges"));
+                }
+                warn!("Failed to drop user privileges: {e:?}");
             }
         }
 
         Ok(self)
     }
 
-    pub async fn serve(self, mut quotes: Quotes) -> anyhow::Result<()> {
-        // Get our bound ports
-        let tcp = self.tcp_socket.context("Not bound to TCP socket")?;
-        let udp = Arc::new(self.udp_socket.context("Not bound to UDP socket")?);
+    /// Serve quotes from unprivileged child processes rather than this one, restarting them should
+    /// they crash, until SIGTERM or SIGINT
+    ///
+    /// Each child is this program run again with the same arguments, which should confine itself
+    /// (see [`confine`](crate::confine)) if [`is_supervised`](crate::is_supervised), then bind and
+    /// serve with graceful upgrades enabled (see [`Server::graceful_upgrade`]), whereupon it's
+    /// handed the sockets bound here. Sockets it binds itself, e.g. for the admin endpoint, are
+    /// bound without privileges. SIGUSR2 replaces the child with a new one, once that is serving.
+    #[cfg(unix)]
+    pub async fn supervise(self) -> anyhow::Result<()> {
+        // The sockets stay open here for as long as children are served from
+        crate::privsep::supervise(self.listener_fds()).await
+    }
+
+    /// Limit the sockets bound to accepting connections and talking over them, in preparation for
+    /// entering capability mode (see [`enter_capability_mode`](crate::enter_capability_mode))
+    ///
+    /// Fails if UDP is served, as datagrams can't be sent to an address in capability mode. Files
+    /// the server writes by their paths (e.g. [`Server::state_file`]) can't be reached in
+    /// capability mode either, so should not be set.
+    #[cfg(target_os = "freebsd")]
+    pub fn limit_capabilities(&self) -> anyhow::Result<()> {
+        use crate::capsicum::{limit, LISTENER_RIGHTS};
+
+        let listeners = self
+            .listeners
+            .iter()
+            .chain(self.simple_listeners.iter().map(|(_, listener)| listener));
+        if listeners.clone().any(|listener| listener.udp.is_some()) {
+            anyhow::bail!("UDP can't be served in capability mode");
+        }
+        let sockets = listeners
+            .filter_map(|listener| listener.tcp.as_ref())
+            .chain(&self.admin)
+            .chain(&self.http);
+        for socket in sockets {
+            limit(socket, LISTENER_RIGHTS).context("Failed to limit socket")?;
+        }
+        if let Some(socket) = &self.admin_socket {
+            limit(socket, LISTENER_RIGHTS).context("Failed to limit admin socket")?;
+        }
+        Ok(())
+    }
+
+    /// Serve quotes in a background task, returning a handle to control it with
+    ///
+    /// Must be called from within a Tokio runtime.
+    pub fn spawn(self, quotes: Quotes) -> anyhow::Result<ServerHandle> {
+        let addr = self.local_addr().context("Not bound to any sockets")?;
+        let (shutdown_tx, mut shutdown_rx) = watch::channel(false);
+        let task = tokio::spawn(self.serve_with_shutdown(quotes, async move {
+            // If the handle is dropped, keep serving until the runtime shuts down
+            if shutdown_rx.wait_for(|&shutdown| shutdown).await.is_err() {
+                std::future::pending::<()>().await;
+            }
+        }));
+
+        Ok(ServerHandle {
+            addr,
+            shutdown: shutdown_tx,
+            task,
+        })
+    }
+
+    /// Serve quotes until a fatal error occurs (or the server is upgraded, if enabled)
+    pub async fn serve(self, quotes: Quotes) -> anyhow::Result<()> {
+        self.serve_with_shutdown(quotes, std::future::pending())
+            .await
+    }
 
-        let local_addr = tcp.local_addr()?;
+    /// Serve quotes until `signal` completes, then stop accepting requests, finish those in flight,
+    /// and return
+    ///
+    /// A [`CancellationToken`](https://docs.rs/tokio-util/latest/tokio_util/sync/struct.CancellationToken.html)
+    /// can be used by passing its `cancelled_owned()` future.
+    pub async fn serve_with_shutdown<F>(self, mut quotes: Quotes, signal: F) -> anyhow::Result<()>
+    where
+        F: Future<Output = ()> + Send,
+    {
+        // Get our bound ports
+        let local_addr = self.local_addr().context("Not bound to any sockets")?;
         info!(
-            "Now listening on TCP/UDP {}:{}",
+            "Now listening on {} {}:{} with {} worker(s)",
+            self.listeners[0].protocols(),
             local_addr.ip(),
-            local_addr.port()
+            local_addr.port(),
+            self.listeners.len()
+        );
+        #[cfg(unix)]
+        if self.graceful_upgrade {
+            crate::upgrade::notify_ready().context("Failed to notify upgraded server")?;
+        }
+        let handover = self.handover();
+
+        let (getqotd_tx, mut getqotd_rx) = channel::<QuoteRequest>(32);
+
+        self.shared.stats.register_quotes(quotes.ids());
+        let saver = self.state_file.clone().map(|(path, interval)| {
+            match self.shared.stats.restore(&path) {
+                Ok(()) => info!("Restored statistics from {}", path.display()),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => warn!("Failed to restore statistics from {}: {e}", path.display()),
+            }
+            tokio::spawn(Self::save_periodically(self.stats(), path, interval))
+        });
+        let stats = self.stats();
+        let admin = Arc::new(crate::admin::Admin {
+            stats,
+            submissions: self.submissions_file.is_some(),
+            index_options: quotes.index_options().clone(),
+            token: self.admin_token,
+            #[cfg(unix)]
+            peers: self.admin_peers,
+        });
+        let admin_listener = self.admin;
+        #[cfg(unix)]
+        let admin_socket = self.admin_socket;
+        let http = self.http.map(|http| {
+            let state = Arc::new(crate::web::Web {
+                stats: self.shared.stats.clone(),
+                audit_log: self.shared.audit_log.clone(),
+                feed_length: self.feed_length.unwrap_or(crate::web::DEFAULT_FEED_LENGTH),
+                calendar_length: self
+                    .calendar_length
+                    .unwrap_or(crate::web::DEFAULT_CALENDAR_LENGTH),
+                rotation: self.rotation,
+                cors_origins: self.cors_origins,
+            });
+            (http, state)
+        });
+        let submissions_file = self.submissions_file;
+        let rotation = self.rotation;
+        let mut pin = Pin::new(self.override_file, rotation.unwrap_or_default());
+        #[cfg(feature = "udp")]
+        if self.listeners.iter().any(|listener| listener.udp.is_some())
+            && quotes
+                .shortest_quote()
+                .is_some_and(|length| length >= MAX_RFC865_LENGTH)
+        {
+            warn!("No quote is short enough to send over UDP, so UDP clients will be sent truncated quotes");
+        }
+        #[cfg(feature = "udp")]
+        self.shared.shortest_quote.store(
+            quotes.shortest_quote().unwrap_or_default(),
+            Ordering::Relaxed,
         );
+        let shared = Arc::new(self.shared);
+        let quote_shared = Arc::clone(&shared);
+        let mut quote_task = tokio::spawn(async move {
+            let mut standby = None;
+            // Quotes are chosen only once requested, as the eligible categories may change over time
+            let base_lengths = quotes.lengths();
+            while let Some(request) = getqotd_rx.recv().await {
+                // A pinned quote is served in place of any other
+                let request = match pin.quote(chrono::Local::now()) {
+                    Some(quote) => match request.answer(quote, &quote_shared.formatter) {
+                        Ok(()) => continue,
+                        Err(request) => request,
+                    },
+                    None => request,
+                };
+                // Each request is held to the lengths allowed over its transport
+                let lengths = match &request {
+                    QuoteRequest::Get(transport, _)
+                    | QuoteRequest::GetIn(_, transport, _)
+                    | QuoteRequest::ClientDaily(_, transport, _)
+                    | QuoteRequest::Rotating(_, transport, _) => match transport {
+                        Transport::Tcp => quote_shared.tcp_lengths,
+                        Transport::Udp => quote_shared.udp_lengths,
+                    },
+                    _ => None,
+                };
+                quotes.set_lengths(lengths.unwrap_or(base_lengths));
+                match request {
+                    QuoteRequest::Get(transport, quote_tx) => {
+                        let start = Instant::now();
+                        // Long quotes are streamed to TCP clients, unless they must be transformed
+                        let quote = if let Some(rotation) = &rotation {
+                            quotes
+                                .rotating_quote(rotation, chrono::Local::now())
+                                .await
+                                .map(|quote| {
+                                    ChosenQuote::Read(
+                                        quote_shared.formatter.format(quote, transport),
+                                    )
+                                })
+                        } else if let Some(categories) = &quote_shared.categories {
+                            quotes.random_quote_in(categories).await.map(|quote| {
+                                ChosenQuote::Read(quote_shared.formatter.format(quote, transport))
+                            })
+                        } else if transport == Transport::Tcp
+                            && quote_shared.formatter.is_identity(transport)
+                            && !quote_shared.telnet
+                        {
+                            quotes.choose_quote().await
+                        } else {
+                            quotes.random_quote().await.map(|quote| {
+                                ChosenQuote::Read(quote_shared.formatter.format(quote, transport))
+                            })
+                        }
+                        .context("Failed to choose quote");
+                        quote_shared.stats.selection_time.record(start.elapsed());
+                        debug!("Chose quote");
+                        debug!("Sending quote to requesting task");
+                        let _ = quote_tx.send(quote);
+                    }
+                    QuoteRequest::Submit(text, id_tx) => {
+                        let id = match &submissions_file {
+                            Some(path) => {
+                                quotes.append_quote(path, &text).await.map_err(Into::into)
+                            }
+                            None => Err(anyhow::anyhow!("Quote submissions are not enabled")),
+                        };
+                        let _ = id_tx.send(id);
+                    }
+                    QuoteRequest::GetIn(categories, transport, quote_tx) => {
+                        let start = Instant::now();
+                        let quote = quotes
+                            .random_quote_in(&categories)
+                            .await
+                            .map(|quote| {
+                                ChosenQuote::Read(quote_shared.formatter.format(quote, transport))
+                            })
+                            .context("Failed to choose quote");
+                        quote_shared.stats.selection_time.record(start.elapsed());
+                        let _ = quote_tx.send(quote);
+                    }
+                    QuoteRequest::ClientDaily(client, transport, quote_tx) => {
+                        let start = Instant::now();
+                        let date = chrono::Local::now().date_naive();
+                        let quote = quotes
+                            .client_daily_quote(date, client)
+                            .await
+                            .map(|quote| {
+                                ChosenQuote::Read(quote_shared.formatter.format(quote, transport))
+                            })
+                            .context("Failed to choose quote");
+                        quote_shared.stats.selection_time.record(start.elapsed());
+                        let _ = quote_tx.send(quote);
+                    }
+                    QuoteRequest::Rotating(rotation, transport, quote_tx) => {
+                        let quote = quotes
+                            .rotating_quote(&rotation, chrono::Local::now())
+                            .await
+                            .map(|quote| quote_shared.formatter.format(quote, transport))
+                            .map_err(Into::into);
+                        let _ = quote_tx.send(quote);
+                    }
+                    QuoteRequest::Daily(date, quote_tx) => {
+                        let _ = quote_tx.send(quotes.daily_quote(date).await.map_err(Into::into));
+                    }
+                    #[cfg(feature = "dashboard")]
+                    QuoteRequest::Summary(summary_tx) => {
+                        let _ = summary_tx.send(quotes.summary());
+                    }
+                    QuoteRequest::Stage(staged, summary_tx) => {
+                        info!("Staged quotes on standby");
+                        let _ = summary_tx.send(staged.summary());
+                        standby = Some(Standby::Staged(*staged));
+                    }
+                    QuoteRequest::Activate(summary_tx) => {
+                        let summary = match standby.take() {
+                            Some(Standby::Staged(mut staged)) => {
+                                quotes.swap_index(&mut staged);
+                                quote_shared.stats.register_quotes(quotes.ids());
+                                standby = Some(Standby::Previous(staged));
+                                info!("Activated staged quotes");
+                                Ok(quotes.summary())
+                            }
+                            other => {
+                                standby = other;
+                                Err(anyhow::anyhow!("No quotes are staged"))
+                            }
+                        };
+                        let _ = summary_tx.send(summary);
+                    }
+                    QuoteRequest::Rollback(summary_tx) => {
+                        let summary = match standby.take() {
+                            Some(Standby::Previous(mut previous)) => {
+                                quotes.swap_index(&mut previous);
+                                // The rolled back quotes may be activated again
+                                standby = Some(Standby::Staged(previous));
+                                info!("Rolled back to previous quotes");
+                                Ok(quotes.summary())
+                            }
+                            other => {
+                                standby = other;
+                                Err(anyhow::anyhow!("No quotes were activated to roll back"))
+                            }
+                        };
+                        let _ = summary_tx.send(summary);
+                    }
+                    QuoteRequest::Reindex(path, summary_tx) => {
+                        let summary = quotes.reindex_file(&path).await;
+                        if summary.is_ok() {
+                            quote_shared.stats.register_quotes(quotes.ids());
+                        }
+                        let _ = summary_tx.send(summary.map_err(Into::into));
+                    }
+                    QuoteRequest::Pin(text, quote_tx) => {
+                        let quote = pin.pin(&text, chrono::Local::now());
+                        let _ = quote_tx.send(quote.map_err(Into::into));
+                    }
+                    QuoteRequest::Unpin(unpinned_tx) => {
+                        let _ = unpinned_tx.send(pin.unpin().map_err(Into::into));
+                    }
+                }
+                // Any request may have changed the quotes, e.g. by re-indexing a changed file
+                #[cfg(feature = "udp")]
+                quote_shared.shortest_quote.store(
+                    quotes.shortest_quote().unwrap_or_default(),
+                    Ordering::Relaxed,
+                );
+            }
+            // Every accept loop and request holds a sender, so they're all finished
+            debug!("Quote channel closed");
+        });
+
+        let mut workers = Vec::new();
+        #[cfg(feature = "registry")]
+        let registration = self.registration;
+        #[cfg(feature = "registry")]
+        if let Some(registration) = &registration {
+            workers.push(tokio::spawn(registration.clone().run(local_addr)));
+        }
+        for listener in self.listeners {
+            #[cfg(feature = "tcp")]
+            if let Some(tcp) = listener.tcp {
+                workers.push(tokio::spawn(Self::tcp_accept_loop(
+                    tcp,
+                    getqotd_tx.clone(),
+                    Arc::clone(&shared),
+                )));
+            }
+            #[cfg(feature = "udp")]
+            if let Some(udp) = listener.udp {
+                workers.push(tokio::spawn(Self::udp_accept_loop(
+                    Arc::new(udp),
+                    getqotd_tx.clone(),
+                    Arc::clone(&shared),
+                )));
+            }
+        }
+        for (service, listener) in self.simple_listeners {
+            if let Some(addr) = listener.local_addr() {
+                info!(
+                    "Also serving {} on {} {addr}",
+                    service.name(),
+                    listener.protocols()
+                );
+            }
+            #[cfg(feature = "tcp")]
+            if let Some(tcp) = listener.tcp {
+                workers.push(tokio::spawn(Self::simple_tcp_loop(
+                    service,
+                    tcp,
+                    Arc::clone(&shared),
+                )));
+            }
+            #[cfg(feature = "udp")]
+            if let Some(udp) = listener.udp {
+                workers.push(tokio::spawn(Self::simple_udp_loop(
+                    service,
+                    udp,
+                    Arc::clone(&shared),
+                )));
+            }
+        }
+        if let Some(listener) = admin_listener {
+            workers.push(tokio::spawn(crate::admin::accept_loop(
+                listener,
+                getqotd_tx.clone(),
+                Arc::clone(&admin),
+            )));
+        }
+        #[cfg(unix)]
+        if let Some(listener) = admin_socket {
+            workers.push(tokio::spawn(crate::admin::unix_accept_loop(
+                listener,
+                getqotd_tx.clone(),
+                Arc::clone(&admin),
+            )));
+        }
+        if let Some((http, state)) = http {
+            workers.push(tokio::spawn(Self::http_accept_loop(
+                http,
+                getqotd_tx.clone(),
+                Arc::clone(&shared),
+                state,
+            )));
+        }
+        let (rotation_tx, _) = broadcast::channel::<Quote>(1);
+        #[cfg(feature = "webhook")]
+        if let Some(webhook) = self.webhook {
+            workers.push(tokio::spawn(webhook.run(rotation_tx.subscribe())));
+        }
+        #[cfg(feature = "mqtt")]
+        if let Some(publisher) = self.mqtt {
+            workers.push(tokio::spawn(publisher.run(rotation_tx.subscribe())));
+        }
+        #[cfg(feature = "statsd")]
+        if let Some(exporter) = self.statsd {
+            workers.push(tokio::spawn(exporter.run(Arc::clone(&shared.stats))));
+        }
+        // Anything announcing quotes rotates daily, even if quotes aren't otherwise served by rotation
+        if rotation.is_some() || rotation_tx.receiver_count() > 0 {
+            workers.push(tokio::spawn(Self::rotate_loop(
+                rotation.unwrap_or_default(),
+                getqotd_tx.clone(),
+                rotation_tx,
+            )));
+        }
+        drop(getqotd_tx);
+
+        tokio::select! {
+            // Accept loops only ever return on failure, which is fatal to the whole server
+            (result, _, _) = futures::future::select_all(workers.iter_mut()) => {
+                return result.context("Worker panicked")?;
+            }
+            result = handover => {
+                result?;
+                info!("Upgraded server is now serving; finishing requests in flight");
+            }
+            () = signal => info!("Shutting down; finishing requests in flight"),
+        }
+
+        for worker in &workers {
+            worker.abort();
+        }
+        // Clients should look elsewhere while requests in flight are finished
+        #[cfg(feature = "registry")]
+        if let Some(registration) = &registration {
+            registration.deregister(local_addr).await;
+        }
+        let drain_timeout = self.drain_timeout.unwrap_or(DEFAULT_DRAIN_TIMEOUT);
+        match tokio::time::timeout(drain_timeout, &mut quote_task).await {
+            Ok(result) => {
+                result.context("Quote task panicked")?;
+                info!("All requests finished");
+            }
+            Err(_) => {
+                quote_task.abort();
+                let abandoned = shared.abandon_requests().await;
+                warn!("Abandoned {abandoned} request(s) still in flight after {drain_timeout:?}");
+            }
+        }
+        if let (Some(saver), Some((path, _))) = (saver, &self.state_file) {
+            saver.abort();
+            Self::save_stats(Arc::clone(&shared.stats), path.clone()).await;
+        }
+
+        Ok(())
+    }
+
+    /// Announce each period's quote to `rotation_tx`, as the period begins
+    async fn rotate_loop(
+        rotation: Rotation,
+        getqotd_tx: Sender<QuoteRequest>,
+        rotation_tx: broadcast::Sender<Quote>,
+    ) -> anyhow::Result<()> {
+        loop {
+            tokio::time::sleep(rotation.until_next()).await;
+            let (quote_tx, quote_rx) = oneshot::channel();
+            // Quotes are announced as UDP clients get them, i.e. never colored
+            getqotd_tx
+                .send(QuoteRequest::Rotating(rotation, Transport::Udp, quote_tx))
+                .await
+                .map_err(|_| anyhow::anyhow!("Quote channel closed"))?;
+            match quote_rx.await? {
+                Ok(quote) => {
+                    info!("Rotated to quote {} ({})", quote.id, quote.hash());
+                    // There may be nobody listening, which is fine
+                    let _ = rotation_tx.send(quote);
+                }
+                Err(e) => warn!("Failed to choose quote for rotation: {e:?}"),
+            }
+        }
+    }
 
-        let (getqotd_tx, mut getqotd_rx) = channel::<GetQotd>(32);
+    async fn save_periodically(stats: Arc<Stats>, path: PathBuf, interval: Duration) {
+        let mut ticks = tokio::time::interval(interval);
+        // The first tick completes immediately, when there's nothing new to save
+        ticks.tick().await;
+        loop {
+            ticks.tick().await;
+            Self::save_stats(Arc::clone(&stats), path.clone()).await;
+        }
+    }
+
+    async fn save_stats(stats: Arc<Stats>, path: PathBuf) {
+        let saved = tokio::task::spawn_blocking(move || {
+            stats
+                .save(&path)
+                .with_context(|| format!("Failed to save statistics to {}", path.display()))
+        })
+        .await;
+        match saved {
+            Ok(Ok(())) => debug!("Saved statistics"),
+            Ok(Err(e)) => warn!("{e:?}"),
+            Err(e) => warn!("Saving statistics panicked: {e}"),
+        }
+    }
 
-        tokio::spawn(async move {
-            loop {
-                let quote = quotes
-                    .random_quote()
+    /// Wait until an upgraded server has taken over our sockets; never completes if not enabled
+    fn handover(&self) -> BoxFuture<'static, anyhow::Result<()>> {
+        #[cfg(unix)]
+        if self.graceful_upgrade {
+            let sockets = self.listener_fds();
+            return Box::pin(async move {
+                crate::upgrade::handover_on_signal(&sockets)
                     .await
-                    .context("Failed to choose quote")?;
-                debug!("Chose quote, waiting");
-                if let Some(getter) = getqotd_rx.recv().await {
-                    info!("Sending quote to requesting task");
-                    let _ = getter.0.send(quote);
+                    .context("Failed to hand over sockets")
+            });
+        }
+
+        Box::pin(std::future::pending())
+    }
+
+    /// The fds of our bound TCP and UDP sockets, to hand over to another process
+    #[cfg(unix)]
+    fn listener_fds(&self) -> Vec<crate::upgrade::FdPair> {
+        use std::os::fd::AsRawFd;
+
+        self.listeners
+            .iter()
+            .map(|listener| {
+                (
+                    listener.tcp.as_ref().map(AsRawFd::as_raw_fd),
+                    listener.udp.as_ref().map(AsRawFd::as_raw_fd),
+                )
+            })
+            .collect()
+    }
+
+    #[cfg(feature = "tcp")]
+    async fn tcp_accept_loop(
+        tcp: TcpListener,
+        getqotd_tx: Sender<QuoteRequest>,
+        shared: Arc<Shared>,
+    ) -> anyhow::Result<()> {
+        loop {
+            if getqotd_tx.is_closed() {
+                panic!("Quote channel closed!");
+            }
+
+            let client = accept(&tcp, "TCP", &shared.stats).await;
+            let start = Instant::now();
+            let (mut conn, peer) = client.context("Failed to connect TCP client")?;
+            if !shared.admit("TCP", peer) {
+                continue;
+            }
+            let Some(in_flight) = shared.start_request() else {
+                shared
+                    .stats
+                    .shed_connections
+                    .fetch_add(1, Ordering::Relaxed);
+                if let Some(message) = &shared.busy_message {
+                    // Straight to the socket, as tokio doesn't yet know it's writable
+                    let _ = socket2::SockRef::from(&conn).send(message.as_bytes());
+                }
+                debug!("Shed TCP connection from {peer}, too many requests in flight");
+                continue;
+            };
+            if let Err(e) = shared.connection_options.apply(&conn) {
+                warn!("Failed to set TCP options for {peer}: {e}");
+            }
+            let context = shared.new_request();
+            let span = request_span!("tcp_server", "TCP", context, peer);
+            span.in_scope(|| request_info!(sampled = context.sampled; "Client connected"));
+            let get_tx = getqotd_tx.clone();
+            let stats = Arc::clone(&shared.stats);
+            let task = REQUEST.scope(context, {
+                let shared = Arc::clone(&shared);
+                async move {
+                    let _in_flight = in_flight;
+                    #[cfg(feature = "tls")]
+                    if let Some(tls) = &shared.tls {
+                        let mut conn = tokio::time::timeout(
+                            TLS_HANDSHAKE_TIMEOUT,
+                            tls.acceptor().accept(conn),
+                        )
+                        .await
+                        .context("TLS handshake timed out")?
+                        .context("TLS handshake failed")?;
+                        let server_name = conn.get_ref().1.server_name();
+                        debug!("TLS client {peer} asked for {server_name:?}");
+                        let categories = tls.categories_for(server_name).map(<[_]>::to_vec);
+                        let quote =
+                            Self::choose_tcp_quote(&get_tx, &shared, peer, categories.as_deref())
+                                .await?;
+                        Self::send_tcp_quote(&mut conn, &quote, &shared).await?;
+                        shared.record_tcp_serve(&quote, start, peer, context.id);
+                        quote.recycle();
+                        return anyhow::Ok(());
+                    }
+                    let quote = Self::choose_tcp_quote(&get_tx, &shared, peer, None).await?;
+                    Self::send_tcp_quote(&mut conn, &quote, &shared).await?;
+                    shared.record_tcp_serve(&quote, start, peer, context.id);
+                    quote.recycle();
+                    anyhow::Ok(())
+                }
+            });
+            let task = async move {
+                if let Err(e) = task.await {
+                    stats.failed_requests.fetch_add(1, Ordering::Relaxed);
+                    debug!("Failed to serve TCP client {peer}: {e:#}");
+                }
+            };
+            shared.spawn_request(task.instrument(span));
+        }
+    }
+
+    /// Serve a simple service's TCP clients
+    #[cfg(feature = "tcp")]
+    async fn simple_tcp_loop(
+        service: SimpleService,
+        tcp: TcpListener,
+        shared: Arc<Shared>,
+    ) -> anyhow::Result<()> {
+        let name = service.name();
+        // Echo and chargen clients may never leave, so they're disconnected once this loop ends
+        let mut connections = tokio::task::JoinSet::new();
+        loop {
+            while connections.try_join_next().is_some() {}
+            let client = accept(&tcp, name, &shared.stats).await;
+            let (conn, peer) =
+                client.with_context(|| format!("Failed to connect {name} client"))?;
+            if !shared.admit(name, peer) {
+                continue;
+            }
+            let Some(in_flight) = shared.start_request() else {
+                debug!("Shed {name} connection from {peer}, too many requests in flight");
+                continue;
+            };
+            if let Err(e) = shared.connection_options.apply(&conn) {
+                warn!("Failed to set TCP options for {peer}: {e}");
+            }
+            let span = info_span!("simple_server", service = name, peer = %peer);
+            let task = async move {
+                let _in_flight = in_flight;
+                debug!("Client connected");
+                match service.serve_tcp(conn).await {
+                    Ok(()) => debug!("Done! Closing connection"),
+                    Err(e) => debug!("Failed to serve {name} client {peer}: {e}"),
+                }
+            };
+            connections.spawn(task.instrument(span));
+        }
+    }
+
+    /// Answer a simple service's UDP requests
+    #[cfg(feature = "udp")]
+    async fn simple_udp_loop(
+        service: SimpleService,
+        udp: UdpSocket,
+        shared: Arc<Shared>,
+    ) -> anyhow::Result<()> {
+        let name = service.name();
+        // Large enough for any datagram, as echo clients get back all they send
+        let mut buf = vec![0; u16::MAX as usize];
+        loop {
+            let (len, addr) = match udp.recv_from(&mut buf).await {
+                Ok(received) => received,
+                Err(e) if is_transient(&e) => {
+                    debug!("Failed to receive {name} request: {e}");
+                    continue;
+                }
+                Err(e) => {
+                    return Err(e).with_context(|| format!("Failed to connect {name} client"))
+                }
+            };
+            if !shared.admit(name, addr) {
+                continue;
+            }
+            let Some(reply) = service.reply_udp(&buf[..len], addr.port()) else {
+                debug!("Ignored {name} request from {addr}");
+                continue;
+            };
+            if let Err(e) = udp.send_to(&reply, addr).await {
+                debug!("Failed to send {name} reply to {addr}: {e}");
+            }
+        }
+    }
+
+    /// Choose a quote for a TCP client, from `categories` if given, that can be sent to it
+    #[cfg(feature = "tcp")]
+    async fn choose_tcp_quote(
+        get_tx: &Sender<QuoteRequest>,
+        shared: &Shared,
+        peer: SocketAddr,
+        categories: Option<&[QuoteCategory]>,
+    ) -> anyhow::Result<ChosenQuote> {
+        let prefixed = shared.length_prefix && !shared.telnet;
+        // The most a quote may be sent as, if it's limited at all
+        let max = [
+            shared.strict_rfc865.then_some(MAX_RFC865_LENGTH - 1),
+            prefixed.then_some(u16::MAX as usize),
+        ]
+        .into_iter()
+        .flatten()
+        .min();
+        // Only the first quote is the client's own, so that retries can find one that fits
+        let mut client = shared
+            .sticky_daily
+            .then_some(peer.ip())
+            .filter(|_| categories.is_none());
+        let mut attempts = 0;
+        loop {
+            request_info!("Getting quote");
+            // Clients are better off with a quote apologising for the lack of one than with none
+            let quote = Self::get_quote(get_tx, Transport::Tcp, client.take(), categories)
+                .await
+                .unwrap_or_else(|e| {
+                    warn!("Failed to get quote for {peer}, sending the fallback: {e:#}");
+                    ChosenQuote::Read(Quote::fallback())
+                });
+            attempts += 1;
+            // Telnet clients are sent more than the quote, e.g. a CR for every LF
+            let length = match &quote {
+                ChosenQuote::Read(quote) if shared.telnet => {
+                    crate::telnet::escaped_length(&quote.text)
+                }
+                quote => quote.length(),
+            };
+            let Some(max) = max.filter(|&max| length > max) else {
+                record_quote(quote.id(), quote.length());
+                return Ok(quote);
+            };
+            if attempts >= TCP_QUOTE_ATTEMPTS {
+                request_info!("No quote short enough for TCP client, truncating one ({length})");
+                let mut quote = quote.into_quote()?;
+                let fitting = if shared.telnet {
+                    crate::telnet::fitting_length(&quote.text, max)
                 } else {
-                    error!("Quote channel closed!");
-                    break Err::<(), _>(anyhow::Error::msg("Quote channel closed"));
+                    max
+                };
+                quote.truncate(fitting);
+                record_quote(&quote.id, quote.text.len());
+                return Ok(ChosenQuote::Read(quote));
+            }
+            request_info!("Quote too long for TCP client ({length}), retrying");
+        }
+    }
+
+    /// Send a quote to a TCP client, over TLS or not, and close the connection
+    ///
+    /// Telnet clients are spoken telnet to, and never sent a length prefix; other clients are sent
+    /// one if needed.
+    #[cfg(feature = "tcp")]
+    async fn send_tcp_quote<S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin>(
+        conn: &mut S,
+        quote: &ChosenQuote,
+        shared: &Shared,
+    ) -> anyhow::Result<()> {
+        use tokio::io::AsyncWriteExt;
+
+        request_info!("Sending quote");
+        if let ChosenQuote::Read(quote) = quote {
+            if shared.telnet {
+                crate::telnet::send(conn, &quote.text).await?;
+                return Ok(());
+            }
+        }
+        if shared.length_prefix && !shared.telnet {
+            let length = u16::try_from(quote.length()).context("Quote too long to prefix")?;
+            conn.write_all(&length.to_be_bytes()).await?;
+        }
+        match quote {
+            ChosenQuote::Read(quote) => conn.write_all(&quote.text).await?,
+            ChosenQuote::Streamed(stream) => {
+                for chunk in stream.chunks()? {
+                    let chunk = chunk?;
+                    conn.write_all(&chunk).await?;
+                    crate::buffer::recycle(chunk);
                 }
             }
-        });
+        }
+        conn.shutdown().await?;
+        Ok(())
+    }
 
-        let mut buf = [0_u8; 0];
+    #[cfg(feature = "udp")]
+    async fn udp_accept_loop(
+        udp: Arc<UdpSocket>,
+        getqotd_tx: Sender<QuoteRequest>,
+        shared: Arc<Shared>,
+    ) -> anyhow::Result<()> {
+        // One byte more than is allowed reveals a datagram that's too large, as it's truncated
+        let len = shared
+            .max_udp_request
+            .map_or(0, |max| max + 1)
+            .max(usize::from(shared.udp_hints));
+        let mut bufs = vec![vec![0_u8; len]; BATCH_SIZE];
+        let mut received = Vec::with_capacity(BATCH_SIZE);
         loop {
             if getqotd_tx.is_closed() {
                 panic!("Quote channel closed!");
             }
 
-            tokio::select! {
-                client = tcp.accept() => {
-                    let (mut conn, _) = client.context("Failed to connect TCP client")?;
-                    info!("TCP client connected: {}", conn.peer_addr()?);
-                    let get_tx = getqotd_tx.clone();
-                    tokio::spawn(async move {
-                        info!("Getting quote");
-                        let quote = Self::get_quote(&get_tx).await?;
-                        info!("Sending quote to client");
-                        conn.write_all(&quote).await?;
-                        info!("Done! Closing connection");
-                        anyhow::Ok(())
-                    });
-                },
-                client = udp.recv_from(&mut buf) => {
-                    let (_, addr) = client.context("Failed to connect UDP client")?;
-                    info!("UDP client connected: {}", addr);
-                    let get_tx = getqotd_tx.clone();
-                    let udp = udp.clone();
-                    tokio::spawn(async move {
-                        loop {
-                            info!("Getting quote");
-                            let quote = Self::get_quote(&get_tx).await?;
-                            if quote.len() < 512 {
-                                info!("Sending quote to client");
-                                udp.send_to(&quote, addr).await?;
-                                info!("Done! Closing connection");
-                                break anyhow::Ok(());
-                            }
-                            info!("Quote too long for UDP client ({}), retrying", quote.len());
+            received.clear();
+            match udp_batch::recv_batch(&udp, &mut bufs, &mut received).await {
+                Ok(()) => {}
+                // e.g. an ICMP error for an earlier reply, reported on some platforms
+                Err(e) if is_transient(&e) => {
+                    debug!("Failed to receive UDP request: {e}");
+                    continue;
+                }
+                Err(e) => return Err(e).context("Failed to connect UDP client"),
+            }
+            let start = Instant::now();
+
+            // Requests answered from the cache are sent together straight away, the rest together
+            // once quotes have been chosen for them
+            let mut cached = Vec::new();
+            let mut uncached = Vec::new();
+            for (&(len, addr), buf) in received.iter().zip(&bufs) {
+                if !shared.admit("UDP", addr) {
+                    continue;
+                }
+                if shared.max_udp_request.is_some_and(|max| len > max) {
+                    shared
+                        .stats
+                        .ignored_datagrams
+                        .fetch_add(1, Ordering::Relaxed);
+                    debug!("Ignored oversized UDP request from {addr}");
+                    continue;
+                }
+                let Some(in_flight) = shared.start_request() else {
+                    shared.stats.shed_datagrams.fetch_add(1, Ordering::Relaxed);
+                    debug!("Shed UDP request from {addr}, too many requests in flight");
+                    continue;
+                };
+                let hint = if shared.udp_hints {
+                    UdpHint::parse(&buf[..len.min(buf.len())])
+                } else {
+                    UdpHint::Default
+                };
+                let context = shared.new_request();
+                let span = request_span!("udp_server", "UDP", context, addr);
+                span.in_scope(|| {
+                    request_info!(sampled = context.sampled; "Client connected");
+                    if hint != UdpHint::Default {
+                        request_info!(sampled = context.sampled; "UDP client hinted {:?}", hint);
+                    }
+                });
+                let request = UdpRequest {
+                    addr,
+                    hint,
+                    context,
+                    span,
+                    _in_flight: in_flight,
+                };
+                // Only clients that didn't ask for anything in particular get the cached quote
+                match shared
+                    .cached_udp_quote()
+                    .filter(|_| hint == UdpHint::Default)
+                {
+                    Some(quote) => {
+                        request.span.in_scope(|| {
+                            record_quote(&quote.id, quote.text.len());
+                            debug!("Sending cached quote");
+                        });
+                        cached.push((quote, request));
+                    }
+                    None => uncached.push(request),
+                }
+            }
+
+            if !cached.is_empty() {
+                let replies: Vec<_> = cached
+                    .iter()
+                    .map(|(quote, request)| (quote.text.as_slice(), request.addr))
+                    .collect();
+                let results = udp_batch::send_batch(&udp, &replies).await;
+                for ((quote, request), result) in cached.iter().zip(results) {
+                    let addr = request.addr;
+                    // Failing to reply to one client is no reason to stop serving the others
+                    let _entered = request.span.enter();
+                    match result {
+                        Ok(()) => shared.record_udp_serve(quote, start, addr, request.context.id),
+                        Err(e) => {
+                            shared.stats.failed_requests.fetch_add(1, Ordering::Relaxed);
+                            warn!("Failed to send quote to {addr}: {e}");
                         }
-                    });
-                },
+                    }
+                }
+            }
+
+            if !uncached.is_empty() {
+                let get_tx = getqotd_tx.clone();
+                let udp = udp.clone();
+                let batch =
+                    Self::udp_serve_batch(udp, get_tx, Arc::clone(&shared), uncached, start);
+                shared.spawn_request(batch);
+            }
+        }
+    }
+
+    /// Choose quotes for a batch of UDP requests, then send them all at once
+    #[cfg(feature = "udp")]
+    async fn udp_serve_batch(
+        udp: Arc<UdpSocket>,
+        get_tx: Sender<QuoteRequest>,
+        shared: Arc<Shared>,
+        requests: Vec<UdpRequest>,
+        start: Instant,
+    ) {
+        let mut chosen = Vec::with_capacity(requests.len());
+        for request in requests {
+            let addr = request.addr;
+            let quote = REQUEST
+                .scope(
+                    request.context,
+                    Self::udp_quote(&get_tx, &shared, addr, request.hint),
+                )
+                .instrument(request.span.clone())
+                .await;
+            match quote {
+                Ok(quote) => chosen.push((quote, request)),
+                Err(e) => {
+                    shared.stats.failed_requests.fetch_add(1, Ordering::Relaxed);
+                    request
+                        .span
+                        .in_scope(|| warn!("Failed to get quote for {addr}: {e}"));
+                }
+            }
+        }
+
+        let replies: Vec<_> = chosen
+            .iter()
+            .map(|(quote, request)| (quote.text.as_slice(), request.addr))
+            .collect();
+        let results = udp_batch::send_batch(&udp, &replies).await;
+        for ((quote, request), result) in chosen.iter().zip(results) {
+            let _entered = request.span.enter();
+            let (addr, context) = (request.addr, request.context);
+            match result {
+                Ok(()) => {
+                    shared.record_udp_serve(quote, start, addr, context.id);
+                    request_info!(sampled = context.sampled; "Done! Closing connection");
+                }
+                Err(e) => {
+                    shared.stats.failed_requests.fetch_add(1, Ordering::Relaxed);
+                    warn!("Failed to send quote to {addr}: {e}");
+                }
+            }
+        }
+        if shared.udp_cache_window.is_some() {
+            let last = chosen
+                .iter()
+                .rposition(|(_, request)| request.hint == UdpHint::Default);
+            if let Some(i) = last {
+                let (quote, _) = chosen.swap_remove(i);
+                *shared.udp_cache.lock().unwrap() = Some((Instant::now(), Arc::new(quote)));
+            }
+        }
+        for (quote, _) in chosen {
+            crate::buffer::recycle(quote.text);
+        }
+    }
+
+    /// Choose a quote short enough to send to a UDP client, heeding its hint if it can be
+    #[cfg(feature = "udp")]
+    async fn udp_quote(
+        get_tx: &Sender<QuoteRequest>,
+        shared: &Shared,
+        addr: SocketAddr,
+        hint: UdpHint,
+    ) -> anyhow::Result<Quote> {
+        let offensive_allowed = shared
+            .categories
+            .as_ref()
+            .is_none_or(|categories| categories.contains(&QuoteCategory::OFFENSIVE));
+        let mut categories = (hint == UdpHint::Offensive && offensive_allowed)
+            .then_some(&[QuoteCategory::OFFENSIVE][..]);
+        let mut client = shared
+            .sticky_daily
+            .then_some(addr.ip())
+            .filter(|_| categories.is_none());
+        let mut attempts = 0;
+        loop {
+            request_info!("Getting quote");
+            let mut quote =
+                match Self::get_quote(get_tx, Transport::Udp, client.take(), categories).await {
+                    // e.g. no offensive quotes are indexed, so the client gets the usual quote instead
+                    Err(e) if categories.is_some() => {
+                        request_info!("No quote for UDP client's hint ({e}), ignoring it");
+                        categories = None;
+                        continue;
+                    }
+                    quote => quote
+                        .and_then(|quote| Ok(quote.into_quote()?))
+                        .unwrap_or_else(|e| {
+                            warn!("Failed to get quote for {addr}, sending the fallback: {e:#}");
+                            Quote::fallback()
+                        }),
+                };
+            attempts += 1;
+            // Short quotes are asked for, not promised, so give up on them rather than retry forever
+            let short_enough = hint != UdpHint::Short
+                || attempts > SHORT_QUOTE_ATTEMPTS
+                || quote.text.len() <= SHORT_QUOTE_LENGTH;
+            if quote.text.len() < MAX_RFC865_LENGTH && short_enough {
+                record_quote(&quote.id, quote.text.len());
+                request_info!("Sending quote");
+                break Ok(quote);
+            }
+            // With no quote short enough, retrying would be in vain
+            let none_fit = shared.shortest_quote.load(Ordering::Relaxed) >= MAX_RFC865_LENGTH;
+            if (attempts >= UDP_QUOTE_ATTEMPTS || none_fit) && short_enough {
+                request_info!(
+                    "No quote short enough for UDP client, truncating one ({})",
+                    quote.text.len()
+                );
+                quote.truncate(MAX_RFC865_LENGTH - 1);
+                record_quote(&quote.id, quote.text.len());
+                break Ok(quote);
+            }
+            request_info!(
+                "Quote too long for UDP client ({}), retrying",
+                quote.text.len()
+            );
+        }
+    }
+
+    async fn http_accept_loop(
+        http: TcpListener,
+        getqotd_tx: Sender<QuoteRequest>,
+        shared: Arc<Shared>,
+        web: Arc<crate::web::Web>,
+    ) -> anyhow::Result<()> {
+        loop {
+            let client = accept(&http, "HTTP", &shared.stats).await;
+            let start = Instant::now();
+            let (mut conn, peer) = client.context("Failed to connect HTTP client")?;
+            if !shared.admit("HTTP", peer) {
+                continue;
+            }
+            let Some(in_flight) = shared.start_request() else {
+                shared
+                    .stats
+                    .shed_connections
+                    .fetch_add(1, Ordering::Relaxed);
+                let message = shared
+                    .busy_message
+                    .as_deref()
+                    .map_or("Too many requests in flight", str::trim_end);
+                let response = crate::http::Response::text(503, message);
+                debug!("Shed HTTP connection from {peer}, too many requests in flight");
+                shared.spawn_request(async move {
+                    // Closing the connection with the request unread would reset it, perhaps
+                    // before the client has read the response
+                    let read = crate::http::Request::read(&mut conn);
+                    let _ = tokio::time::timeout(HTTP_SHED_TIMEOUT, read).await;
+                    let _ = response.write(&mut conn).await;
+                });
+                continue;
             };
+            let get_tx = getqotd_tx.clone();
+            let web = Arc::clone(&web);
+            let context = shared.new_request();
+            let span = info_span!("http_server", request_id = %context.id);
+            let task = REQUEST.scope(context, {
+                let shared = Arc::clone(&shared);
+                async move {
+                    let _in_flight = in_flight;
+                    let response = match crate::http::Request::read(&mut conn).await {
+                        Ok(request) => {
+                            request_info!(
+                                "HTTP request from {peer}: {} {}",
+                                request.method,
+                                request.path
+                            );
+                            crate::web::route(&request, peer, context.id, &get_tx, &web).await
+                        }
+                        Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                            crate::http::Response::text(400, e)
+                        }
+                        Err(e) => return Err(e.into()),
+                    };
+                    response
+                        .header("X-Request-Id", context.id.to_string())
+                        .write(&mut conn)
+                        .await?;
+                    shared.record_service_time(start, "HTTP", peer);
+                    anyhow::Ok(())
+                }
+            });
+            let task = async move {
+                if let Err(e) = task.await {
+                    debug!("Failed to serve HTTP client {peer}: {e:#}");
+                }
+            };
+            shared.spawn_request(task.instrument(span));
         }
     }
 
-    async fn get_quote(tx: &Sender<GetQotd>) -> anyhow::Result<Vec<u8>> {
+    /// Get a quote from the quote task, `client`'s own quote of the day if given
+    async fn get_quote(
+        tx: &Sender<QuoteRequest>,
+        transport: Transport,
+        client: Option<IpAddr>,
+        categories: Option<&[QuoteCategory]>,
+    ) -> anyhow::Result<ChosenQuote> {
         let (quote_tx, quote_rx) = oneshot::channel();
-        tx.send(GetQotd(quote_tx)).await?;
-        Ok(quote_rx.await?)
+        let request = match (client, categories) {
+            (Some(client), _) => QuoteRequest::ClientDaily(client, transport, quote_tx),
+            (None, Some(categories)) => {
+                QuoteRequest::GetIn(categories.to_vec(), transport, quote_tx)
+            }
+            (None, None) => QuoteRequest::Get(transport, quote_tx),
+        };
+        // Requests may carry quotes, which can't be shared between threads as errors must be
+        tx.send(request)
+            .await
+            .map_err(|_| anyhow::anyhow!("Quote channel closed"))?;
+        quote_rx.await?
+    }
+}
+
+/// Accept a connection, pausing whenever the process or system runs out of resources to accept one
+/// with (e.g. file descriptors) rather than failing
+///
+/// Connections waiting to be accepted meanwhile stay in the listen backlog, or are refused once it's
+/// full, so the server sheds load until requests in flight have finished.
+pub(crate) async fn accept(
+    listener: &TcpListener,
+    protocol: &str,
+    stats: &Stats,
+) -> io::Result<(tokio::net::TcpStream, SocketAddr)> {
+    loop {
+        match listener.accept().await {
+            Err(e) if is_transient(&e) => debug!("Failed to accept {protocol} connection: {e}"),
+            Err(e) if is_resource_exhaustion(&e) => {
+                stats.accept_pauses.fetch_add(1, Ordering::Relaxed);
+                warn!("Out of resources to accept {protocol} connections, pausing: {e}");
+                tokio::time::sleep(ACCEPT_PAUSE).await;
+            }
+            accepted => return accepted,
+        }
+    }
+}
+
+/// Whether `e` concerns only a single client (e.g. one that went away before it was accepted), so
+/// that serving other clients can carry on
+fn is_transient(e: &io::Error) -> bool {
+    use io::ErrorKind;
+
+    if matches!(
+        e.kind(),
+        ErrorKind::ConnectionAborted
+            | ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionRefused
+            | ErrorKind::Interrupted
+            | ErrorKind::WouldBlock
+            | ErrorKind::TimedOut
+    ) {
+        return true;
+    }
+    #[cfg(unix)]
+    if let Some(errno) = e.raw_os_error() {
+        use nix::errno::Errno;
+
+        // Linux reports network errors pending on a new connection from accept, and firewalls may
+        // refuse them with EPERM
+        return matches!(
+            Errno::from_raw(errno),
+            Errno::EPROTO
+                | Errno::EPERM
+                | Errno::ENETDOWN
+                | Errno::ENETUNREACH
+                | Errno::EHOSTDOWN
+                | Errno::EHOSTUNREACH
+                | Errno::EOPNOTSUPP
+        );
+    }
+    false
+}
+
+/// Whether `e` means the process or system is out of resources, which waiting may relieve
+fn is_resource_exhaustion(e: &io::Error) -> bool {
+    #[cfg(unix)]
+    if let Some(errno) = e.raw_os_error() {
+        use nix::errno::Errno;
+
+        return matches!(
+            Errno::from_raw(errno),
+            Errno::EMFILE | Errno::ENFILE | Errno::ENOBUFS | Errno::ENOMEM
+        );
     }
+    false
 }
@@ -0,0 +1,127 @@
+//! This module answers the other simple services inetd used to offer alongside QOTD: Echo
+//! (RFC 862), Character Generator (RFC 864), and Daytime (RFC 867)
+//!
+//! They're served by [`Server`](crate::Server) on their own ports (see
+//! [`Server::simple_service`](crate::Server::simple_service)). Chargen is only served over TCP, as
+//! over UDP it's the textbook amplifier for reflection attacks; for the same reason, UDP requests
+//! from well-known ports (i.e. other servers) are never answered.
+#![cfg(any(feature = "tcp", feature = "udp"))]
+#[cfg(feature = "tcp")]
+use std::time::Duration;
+
+use chrono::Local;
+#[cfg(feature = "tcp")]
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// How long an echo client may go without sending anything before it's disconnected
+#[cfg(feature = "tcp")]
+const ECHO_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+/// Characters per line generated, not counting the CRLF, as suggested by RFC 864
+#[cfg(feature = "tcp")]
+const CHARGEN_LINE_LENGTH: usize = 72;
+/// Lowest port UDP requests are answered from; lower ones belong to servers, perhaps other simple
+/// services, that would answer back
+#[cfg(feature = "udp")]
+const MIN_UDP_CLIENT_PORT: u16 = 1024;
+
+/// A simple service served alongside QOTD
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum SimpleService {
+    /// Echo (RFC 862): sends back whatever it's sent
+    Echo,
+    /// Daytime (RFC 867): sends the local date and time
+    Daytime,
+    /// Character Generator (RFC 864): sends characters until the client leaves; TCP only
+    Chargen,
+}
+
+impl SimpleService {
+    /// The port the service is usually served on
+    pub fn default_port(self) -> u16 {
+        match self {
+            SimpleService::Echo => 7,
+            SimpleService::Daytime => 13,
+            SimpleService::Chargen => 19,
+        }
+    }
+
+    /// The service's name, for logging
+    pub fn name(self) -> &'static str {
+        match self {
+            SimpleService::Echo => "echo",
+            SimpleService::Daytime => "daytime",
+            SimpleService::Chargen => "chargen",
+        }
+    }
+
+    /// Whether the service is served over UDP, if UDP is served at all
+    pub(crate) fn serves_udp(self) -> bool {
+        self != SimpleService::Chargen
+    }
+
+    /// Serve a TCP client until it's served, or leaves
+    #[cfg(feature = "tcp")]
+    pub(crate) async fn serve_tcp<S: AsyncRead + AsyncWrite + Unpin>(
+        self,
+        mut conn: S,
+    ) -> std::io::Result<()> {
+        match self {
+            SimpleService::Echo => {
+                let mut buf = [0; 4096];
+                loop {
+                    let read = tokio::time::timeout(ECHO_IDLE_TIMEOUT, conn.read(&mut buf))
+                        .await
+                        .map_err(|_| std::io::ErrorKind::TimedOut)??;
+                    if read == 0 {
+                        break;
+                    }
+                    conn.write_all(&buf[..read]).await?;
+                }
+            }
+            SimpleService::Daytime => conn.write_all(daytime().as_bytes()).await?,
+            SimpleService::Chargen => {
+                // Whatever the client sends is ignored, as RFC 864 allows
+                for line in (0..).map(chargen_line) {
+                    conn.write_all(&line).await?;
+                }
+            }
+        }
+        conn.shutdown().await
+    }
+
+    /// The reply to a UDP request from `port`, if it's to be answered
+    #[cfg(feature = "udp")]
+    pub(crate) fn reply_udp(self, request: &[u8], port: u16) -> Option<Vec<u8>> {
+        if port < MIN_UDP_CLIENT_PORT {
+            return None;
+        }
+        match self {
+            SimpleService::Echo => Some(request.to_vec()),
+            SimpleService::Daytime => Some(daytime().into_bytes()),
+            SimpleService::Chargen => None,
+        }
+    }
+}
+
+/// The local date and time, as RFC 867 suggests, e.g. "Monday, February 22, 1982 17:37:43-08:00"
+fn daytime() -> String {
+    Local::now()
+        .format("%A, %B %-d, %Y %H:%M:%S%:z\r\n")
+        .to_string()
+}
+
+/// The `n`th line generated: the printable ASCII characters, starting one further along on each
+/// line, wrapping around
+#[cfg(feature = "tcp")]
+fn chargen_line(n: usize) -> Vec<u8> {
+    const PRINTABLE: std::ops::RangeInclusive<u8> = b' '..=b'~';
+    let count = PRINTABLE.len();
+    let mut line: Vec<u8> = PRINTABLE
+        .cycle()
+        .skip(n % count)
+        .take(CHARGEN_LINE_LENGTH)
+        .collect();
+    line.extend_from_slice(b"\r\n");
+    line
+}
@@ -0,0 +1,207 @@
+//! This module creates and configures the server's sockets
+#![cfg(any(feature = "tcp", feature = "udp"))]
+
+use std::{io, net::SocketAddr, time::Duration};
+
+use socket2::{Domain, Protocol, Socket, Type};
+#[cfg(feature = "tcp")]
+use socket2::{SockRef, TcpKeepalive};
+use tokio::net::TcpListener;
+#[cfg(feature = "tcp")]
+use tokio::net::TcpStream;
+#[cfg(feature = "udp")]
+use tokio::net::UdpSocket;
+
+/// The listen backlog used unless otherwise configured, matching Tokio's own default
+pub const DEFAULT_BACKLOG: u32 = 1024;
+
+/// Options applied to the server's sockets when binding them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SocketOptions {
+    pub backlog: u32,
+    pub reuse_address: bool,
+    pub reuse_port: bool,
+    pub recv_buffer_size: Option<usize>,
+    pub send_buffer_size: Option<usize>,
+    /// Differentiated Services codepoint to mark outgoing packets with
+    pub dscp: Option<u8>,
+    /// Hop limit of outgoing packets
+    pub ttl: Option<u32>,
+    /// Network interface to only send and receive through, e.g. "eth1"
+    pub device: Option<String>,
+}
+
+impl Default for SocketOptions {
+    fn default() -> Self {
+        Self {
+            backlog: DEFAULT_BACKLOG,
+            // Tokio sets SO_REUSEADDR on its listeners on Unix, so we do too
+            reuse_address: cfg!(unix),
+            reuse_port: false,
+            recv_buffer_size: None,
+            send_buffer_size: None,
+            dscp: None,
+            ttl: None,
+            device: None,
+        }
+    }
+}
+
+impl SocketOptions {
+    /// Bind a TCP listener; this is also used for the admin endpoint, even if quotes aren't served
+    /// over TCP
+    pub fn bind_tcp(&self, address: SocketAddr) -> io::Result<TcpListener> {
+        let socket = self.socket(address, Type::STREAM, Protocol::TCP)?;
+        socket.listen(self.backlog.try_into().unwrap_or(i32::MAX))?;
+        TcpListener::from_std(socket.into())
+    }
+
+    #[cfg(feature = "udp")]
+    pub fn bind_udp(&self, address: SocketAddr) -> io::Result<UdpSocket> {
+        let socket = self.socket(address, Type::DGRAM, Protocol::UDP)?;
+        UdpSocket::from_std(socket.into())
+    }
+
+    /// Create a non-blocking socket with our options applied, and bind it
+    fn socket(&self, address: SocketAddr, ty: Type, protocol: Protocol) -> io::Result<Socket> {
+        let socket = Socket::new(Domain::for_address(address), ty, Some(protocol))?;
+        socket.set_nonblocking(true)?;
+        socket.set_reuse_address(self.reuse_address)?;
+        if self.reuse_port {
+            #[cfg(unix)]
+            socket.set_reuse_port(true)?;
+            #[cfg(not(unix))]
+            tracing::warn!("SO_REUSEPORT is not supported on this platform");
+        }
+        // Connections accepted by a listener inherit its buffer sizes
+        if let Some(size) = self.recv_buffer_size {
+            socket.set_recv_buffer_size(size)?;
+            tracing::debug!("SO_RCVBUF is {} bytes", socket.recv_buffer_size()?);
+        }
+        if let Some(size) = self.send_buffer_size {
+            socket.set_send_buffer_size(size)?;
+            tracing::debug!("SO_SNDBUF is {} bytes", socket.send_buffer_size()?);
+        }
+        // As are their DSCP and TTL; the DSCP is the upper six bits of the TOS/traffic class
+        if let Some(dscp) = self.dscp {
+            let class = u32::from(dscp) << 2;
+            match address {
+                #[cfg(not(any(
+                    target_os = "fuchsia",
+                    target_os = "redox",
+                    target_os = "solaris",
+                    target_os = "illumos",
+                    target_os = "haiku",
+                )))]
+                SocketAddr::V4(_) => socket.set_tos(class)?,
+                #[cfg(any(
+                    target_os = "android",
+                    target_os = "dragonfly",
+                    target_os = "freebsd",
+                    target_os = "fuchsia",
+                    target_os = "linux",
+                    target_os = "macos",
+                    target_os = "netbsd",
+                    target_os = "openbsd",
+                ))]
+                SocketAddr::V6(_) => socket.set_tclass_v6(class)?,
+                #[allow(unreachable_patterns)]
+                _ => tracing::warn!("Setting the DSCP is not supported on this platform"),
+            }
+        }
+        if let Some(ttl) = self.ttl {
+            match address {
+                SocketAddr::V4(_) => socket.set_ttl(ttl)?,
+                SocketAddr::V6(_) => socket.set_unicast_hops_v6(ttl)?,
+            }
+        }
+        if let Some(device) = &self.device {
+            #[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+            socket.bind_device(Some(device.as_bytes()))?;
+            #[cfg(not(any(target_os = "android", target_os = "fuchsia", target_os = "linux")))]
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("Binding to device {device} is not supported on this platform"),
+            ));
+        }
+        socket.bind(&address.into())?;
+        Ok(socket)
+    }
+}
+
+/// TCP keepalive probing of idle connections
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Keepalive {
+    /// How long a connection must be idle before the first probe is sent
+    pub idle: Duration,
+    /// Time between unanswered probes, if not the system default
+    pub interval: Option<Duration>,
+    /// Unanswered probes after which the connection is dropped, if not the system default
+    pub retries: Option<u32>,
+}
+
+impl Keepalive {
+    pub fn new(idle: Duration) -> Self {
+        Self {
+            idle,
+            interval: None,
+            retries: None,
+        }
+    }
+
+    #[cfg(feature = "tcp")]
+    fn to_socket2(self) -> TcpKeepalive {
+        let keepalive = TcpKeepalive::new().with_time(self.idle);
+        #[cfg(any(
+            target_os = "android",
+            target_os = "freebsd",
+            target_os = "ios",
+            target_os = "linux",
+            target_os = "macos",
+            target_os = "netbsd",
+            windows
+        ))]
+        let keepalive = match self.interval {
+            Some(interval) => keepalive.with_interval(interval),
+            None => keepalive,
+        };
+        #[cfg(any(
+            target_os = "android",
+            target_os = "freebsd",
+            target_os = "ios",
+            target_os = "linux",
+            target_os = "macos",
+            target_os = "netbsd",
+        ))]
+        let keepalive = match self.retries {
+            Some(retries) => keepalive.with_retries(retries),
+            None => keepalive,
+        };
+        keepalive
+    }
+}
+
+/// Options applied to each accepted TCP connection; unset options keep the system defaults
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct ConnectionOptions {
+    pub nodelay: Option<bool>,
+    pub keepalive: Option<Keepalive>,
+    pub linger: Option<Duration>,
+}
+
+#[cfg(feature = "tcp")]
+impl ConnectionOptions {
+    pub fn apply(&self, conn: &TcpStream) -> io::Result<()> {
+        if let Some(nodelay) = self.nodelay {
+            conn.set_nodelay(nodelay)?;
+        }
+        let socket = SockRef::from(conn);
+        if let Some(keepalive) = self.keepalive {
+            socket.set_tcp_keepalive(&keepalive.to_socket2())?;
+        }
+        if let Some(linger) = self.linger {
+            socket.set_linger(Some(linger))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,532 @@
+//! This module collects statistics about the running server
+
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{QuoteCategory, QuoteId};
+
+/// Upper bounds of the latency histogram buckets, in microseconds
+///
+/// Durations exceeding the last bound are counted only in the implicit "+Inf" bucket, i.e. the total.
+pub const LATENCY_BUCKETS_US: [u64; 12] = [
+    100, 250, 500, 1_000, 2_500, 5_000, 10_000, 25_000, 50_000, 100_000, 250_000, 1_000_000,
+];
+
+/// First line of every state file written by [`Stats::save`]
+const STATE_FILE_HEADER: &str = "# qotd-server state v1";
+
+/// What the server was built from, so that what's deployed where can be told apart
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct BuildInfo {
+    /// The crate's version
+    pub version: &'static str,
+    /// The git commit built, if it was built from a git checkout
+    pub git_hash: Option<&'static str>,
+    /// The crate's features enabled in the build
+    pub features: Vec<&'static str>,
+}
+
+impl BuildInfo {
+    /// What this program was built from
+    pub fn current() -> Self {
+        let features = [
+            ("blocking", cfg!(feature = "blocking")),
+            ("builtin-quotes", cfg!(feature = "builtin-quotes")),
+            ("cli", cfg!(feature = "cli")),
+            ("dashboard", cfg!(feature = "dashboard")),
+            ("encryption", cfg!(feature = "encryption")),
+            ("export", cfg!(feature = "export")),
+            ("fetch", cfg!(feature = "fetch")),
+            ("ffi", cfg!(feature = "ffi")),
+            ("import", cfg!(feature = "import")),
+            ("irc", cfg!(feature = "irc")),
+            ("mqtt", cfg!(feature = "mqtt")),
+            ("registry", cfg!(feature = "registry")),
+            ("statsd", cfg!(feature = "statsd")),
+            ("tcp", cfg!(feature = "tcp")),
+            ("test-util", cfg!(feature = "test-util")),
+            ("tls", cfg!(feature = "tls")),
+            ("udp", cfg!(feature = "udp")),
+            ("webhook", cfg!(feature = "webhook")),
+        ];
+        Self {
+            version: env!("CARGO_PKG_VERSION"),
+            git_hash: option_env!("QOTD_GIT_HASH"),
+            features: features
+                .into_iter()
+                .filter_map(|(name, enabled)| enabled.then_some(name))
+                .collect(),
+        }
+    }
+}
+
+impl fmt::Display for BuildInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "version: {}", self.version)?;
+        writeln!(f, "git: {}", self.git_hash.unwrap_or("unknown"))?;
+        writeln!(f, "features: {}", self.features.join(", "))
+    }
+}
+
+/// A lock-free histogram of durations with fixed buckets
+#[derive(Debug, Default)]
+pub struct Histogram {
+    buckets: [AtomicU64; LATENCY_BUCKETS_US.len()],
+    count: AtomicU64,
+    sum_us: AtomicU64,
+}
+
+impl Histogram {
+    pub fn record(&self, duration: Duration) {
+        let us = u64::try_from(duration.as_micros()).unwrap_or(u64::MAX);
+        if let Some(i) = LATENCY_BUCKETS_US.iter().position(|&bound| us <= bound) {
+            self.buckets[i].fetch_add(1, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_us.fetch_add(us, Ordering::Relaxed);
+    }
+
+    /// Add the counts of a previously taken snapshot, e.g. one restored from disk
+    fn restore(&self, snapshot: &HistogramSnapshot) {
+        for (bucket, &count) in self.buckets.iter().zip(&snapshot.buckets) {
+            bucket.fetch_add(count, Ordering::Relaxed);
+        }
+        self.count.fetch_add(snapshot.count, Ordering::Relaxed);
+        self.sum_us.fetch_add(snapshot.sum_us, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> HistogramSnapshot {
+        HistogramSnapshot {
+            buckets: std::array::from_fn(|i| self.buckets[i].load(Ordering::Relaxed)),
+            count: self.count.load(Ordering::Relaxed),
+            sum_us: self.sum_us.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// A point-in-time copy of a [`Histogram`]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct HistogramSnapshot {
+    /// Counts per bucket of [`LATENCY_BUCKETS_US`]; these are not cumulative
+    pub buckets: [u64; LATENCY_BUCKETS_US.len()],
+    pub count: u64,
+    pub sum_us: u64,
+}
+
+impl HistogramSnapshot {
+    /// Parse the space-separated count, sum, and buckets written by the Display impl
+    fn parse(s: &str) -> Option<Self> {
+        let mut values = s.split_whitespace().map(|value| value.parse().ok());
+        let count = values.next()??;
+        let sum_us = values.next()??;
+        let mut buckets = [0; LATENCY_BUCKETS_US.len()];
+        for bucket in &mut buckets {
+            *bucket = values.next()??;
+        }
+        Some(Self {
+            buckets,
+            count,
+            sum_us,
+        })
+    }
+
+    pub fn mean(&self) -> Option<Duration> {
+        (self.count > 0).then(|| Duration::from_micros(self.sum_us / self.count))
+    }
+
+    /// Estimate a quantile (0.0 to 1.0) as the upper bound of the bucket it falls into
+    ///
+    /// Returns `None` if nothing has been recorded, or the quantile falls beyond the last bucket.
+    pub fn quantile(&self, quantile: f64) -> Option<Duration> {
+        let target = (self.count as f64 * quantile).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        self.buckets
+            .iter()
+            .zip(LATENCY_BUCKETS_US)
+            .find(|(&count, _)| {
+                seen += count;
+                seen >= target
+            })
+            .map(|(_, bound)| Duration::from_micros(bound))
+    }
+}
+
+impl fmt::Display for HistogramSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.count, self.sum_us)?;
+        for bucket in &self.buckets {
+            write!(f, " {bucket}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Statistics shared by all of a server's tasks
+#[derive(Debug, Default)]
+pub struct Stats {
+    /// Time from accepting a request until its last byte was sent
+    pub service_time: Histogram,
+    /// Time taken to choose and read a quote
+    pub selection_time: Histogram,
+    /// Requests whose service time exceeded the slow request threshold
+    pub slow_requests: AtomicU64,
+    /// Requests refused by the access control lists
+    pub denied_requests: AtomicU64,
+    /// UDP datagrams ignored as too large to be genuine requests, e.g. scans
+    pub ignored_datagrams: AtomicU64,
+    /// Times accepting connections was paused for lack of resources, e.g. file descriptors
+    pub accept_pauses: AtomicU64,
+    /// TCP connections closed at once as too many requests were in flight
+    pub shed_connections: AtomicU64,
+    /// UDP requests dropped as too many requests were in flight
+    pub shed_datagrams: AtomicU64,
+    /// Requests that failed, e.g. as no quote could be chosen or it couldn't be sent
+    pub failed_requests: AtomicU64,
+    /// Quotes served over TCP
+    pub tcp_serves: AtomicU64,
+    /// Quotes served over UDP
+    pub udp_serves: AtomicU64,
+    /// Times each quote has been served
+    quote_serves: Mutex<HashMap<QuoteId, u64>>,
+    /// Times quotes of each category have been served
+    category_serves: Mutex<HashMap<QuoteCategory, u64>>,
+    started: Started,
+}
+
+/// When statistics started to be collected, i.e. when the server was set up
+#[derive(Debug)]
+struct Started(Instant);
+
+impl Default for Started {
+    fn default() -> Self {
+        Self(Instant::now())
+    }
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Make quotes known, so that they are reported as never served until they are
+    pub fn register_quotes<I: IntoIterator<Item = QuoteId>>(&self, ids: I) {
+        let mut serves = self.quote_serves.lock().unwrap();
+        for id in ids {
+            serves.entry(id).or_insert(0);
+        }
+    }
+
+    /// Count a quote as served; the protocol it was served over is counted separately
+    pub fn record_serve(&self, id: &QuoteId, category: QuoteCategory) {
+        let mut serves = self.quote_serves.lock().unwrap();
+        match serves.get_mut(id) {
+            Some(count) => *count += 1,
+            None => {
+                serves.insert(id.clone(), 1);
+            }
+        }
+        *self
+            .category_serves
+            .lock()
+            .unwrap()
+            .entry(category)
+            .or_insert(0) += 1;
+    }
+
+    /// Report on how often quotes have been served, listing up to `top` of the most served
+    pub fn quote_report(&self, top: usize) -> QuoteReport {
+        let serves = self.quote_serves.lock().unwrap();
+        let mut served: Vec<_> = serves
+            .iter()
+            .filter(|(_, &count)| count > 0)
+            .map(|(id, &count)| (id.clone(), count))
+            .collect();
+        served.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        let mut never_served: Vec<_> = serves
+            .iter()
+            .filter(|(_, &count)| count == 0)
+            .map(|(id, _)| id.clone())
+            .collect();
+        never_served.sort_unstable();
+
+        let mut file_serves: HashMap<&str, u64> = HashMap::new();
+        for (id, &count) in serves.iter().filter(|(_, &count)| count > 0) {
+            *file_serves.entry(&id.file).or_insert(0) += count;
+        }
+        let mut by_file: Vec<_> = file_serves
+            .into_iter()
+            .map(|(file, count)| (file.to_string(), count))
+            .collect();
+        by_file.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        let mut by_category: Vec<_> = self
+            .category_serves
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(&category, &count)| (category, count))
+            .collect();
+        by_category.sort_unstable();
+
+        QuoteReport {
+            quotes: serves.len(),
+            serves: served.iter().map(|(_, count)| count).sum(),
+            served: served.len(),
+            tcp_serves: self.tcp_serves.load(Ordering::Relaxed),
+            udp_serves: self.udp_serves.load(Ordering::Relaxed),
+            by_category,
+            by_file,
+            most_served: served.into_iter().take(top).collect(),
+            never_served,
+        }
+    }
+
+    /// Write these statistics to a state file, replacing it atomically
+    ///
+    /// The file is a simple line-based text format, one "key value" pair per line, with a line per
+    /// served quote ("serve COUNT ID HASH") and per served category ("category_serve COUNT
+    /// CATEGORY").
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+
+        let mut out = BufWriter::new(std::fs::File::create(&tmp_path)?);
+        let snapshot = self.snapshot();
+        writeln!(out, "{STATE_FILE_HEADER}")?;
+        writeln!(out, "service_time {}", snapshot.service_time)?;
+        writeln!(out, "selection_time {}", snapshot.selection_time)?;
+        writeln!(out, "slow_requests {}", snapshot.slow_requests)?;
+        writeln!(out, "denied_requests {}", snapshot.denied_requests)?;
+        writeln!(out, "ignored_datagrams {}", snapshot.ignored_datagrams)?;
+        writeln!(out, "accept_pauses {}", snapshot.accept_pauses)?;
+        writeln!(out, "shed_connections {}", snapshot.shed_connections)?;
+        writeln!(out, "shed_datagrams {}", snapshot.shed_datagrams)?;
+        writeln!(out, "failed_requests {}", snapshot.failed_requests)?;
+        writeln!(out, "tcp_serves {}", snapshot.tcp_serves)?;
+        writeln!(out, "udp_serves {}", snapshot.udp_serves)?;
+        for (category, count) in self.category_serves.lock().unwrap().iter() {
+            writeln!(out, "category_serve {count} {category}")?;
+        }
+        for (id, count) in self.quote_serves.lock().unwrap().iter() {
+            if *count > 0 {
+                writeln!(out, "serve {count} {id} {}", id.hash_hex())?;
+            }
+        }
+        out.into_inner()?.sync_all()?;
+
+        std::fs::rename(&tmp_path, path)
+    }
+
+    /// Add the statistics saved in a state file to these
+    ///
+    /// Quotes already registered (see [`Stats::register_quotes`]) are matched by their hashes, so
+    /// that their counts follow them should their files have been edited since.
+    pub fn restore<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let file = BufReader::new(std::fs::File::open(path)?);
+        let invalid = |line: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid line in state file: {line}"),
+            )
+        };
+        let mut lines = file.lines();
+        if lines.next().transpose()?.as_deref() != Some(STATE_FILE_HEADER) {
+            return Err(invalid("not a state file"));
+        }
+
+        let known: Vec<QuoteId> = self.quote_serves.lock().unwrap().keys().cloned().collect();
+        let by_hash: HashMap<u64, &QuoteId> = known.iter().map(|id| (id.hash, id)).collect();
+        let by_position: HashMap<(&str, usize), &QuoteId> = known
+            .iter()
+            .map(|id| ((id.file.as_str(), id.index), id))
+            .collect();
+
+        for line in lines {
+            let line = line?;
+            let (key, value) = line.split_once(' ').ok_or_else(|| invalid(&line))?;
+            match key {
+                "service_time" | "selection_time" => {
+                    let snapshot = HistogramSnapshot::parse(value).ok_or_else(|| invalid(&line))?;
+                    let histogram = if key == "service_time" {
+                        &self.service_time
+                    } else {
+                        &self.selection_time
+                    };
+                    histogram.restore(&snapshot);
+                }
+                "slow_requests" | "denied_requests" | "ignored_datagrams" | "accept_pauses"
+                | "shed_connections" | "shed_datagrams" | "failed_requests" | "tcp_serves"
+                | "udp_serves" => {
+                    let count: u64 = value.parse().map_err(|_| invalid(&line))?;
+                    let counter = match key {
+                        "slow_requests" => &self.slow_requests,
+                        "denied_requests" => &self.denied_requests,
+                        "ignored_datagrams" => &self.ignored_datagrams,
+                        "accept_pauses" => &self.accept_pauses,
+                        "shed_connections" => &self.shed_connections,
+                        "shed_datagrams" => &self.shed_datagrams,
+                        "failed_requests" => &self.failed_requests,
+                        "tcp_serves" => &self.tcp_serves,
+                        _ => &self.udp_serves,
+                    };
+                    counter.fetch_add(count, Ordering::Relaxed);
+                }
+                "category_serve" => {
+                    let (count, category) = value.split_once(' ').ok_or_else(|| invalid(&line))?;
+                    let count: u64 = count.parse().map_err(|_| invalid(&line))?;
+                    let category: QuoteCategory = category.parse().map_err(|_| invalid(&line))?;
+                    *self
+                        .category_serves
+                        .lock()
+                        .unwrap()
+                        .entry(category)
+                        .or_insert(0) += count;
+                }
+                "serve" => {
+                    let (count, id) = value.split_once(' ').ok_or_else(|| invalid(&line))?;
+                    let count: u64 = count.parse().map_err(|_| invalid(&line))?;
+                    // Lines written before quotes had hashes have none
+                    let (id, hash) = match id.split_once(' ') {
+                        Some((id, hash)) => {
+                            let hash = u64::from_str_radix(hash, 16).map_err(|_| invalid(&line))?;
+                            (id, Some(hash))
+                        }
+                        None => (id, None),
+                    };
+                    let (file, index) = id.rsplit_once('#').ok_or_else(|| invalid(&line))?;
+                    let index: usize = index.parse().map_err(|_| invalid(&line))?;
+                    // The quote may have moved since, e.g. if its file was edited, so it's found by
+                    // its hash if possible
+                    let known = match hash {
+                        Some(hash) => by_hash.get(&hash),
+                        None => by_position.get(&(file, index)),
+                    };
+                    let id = known.map_or_else(
+                        || QuoteId {
+                            file: file.to_string(),
+                            index,
+                            hash: hash.unwrap_or_default(),
+                        },
+                        |&id| id.clone(),
+                    );
+                    *self.quote_serves.lock().unwrap().entry(id).or_insert(0) += count;
+                }
+                // Written by a newer version, perhaps
+                _ => tracing::debug!("Ignoring unknown line in state file: {line}"),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// How long the server has been up, which isn't restored from state files
+    pub fn uptime(&self) -> Duration {
+        self.started.0.elapsed()
+    }
+
+    pub fn snapshot(&self) -> StatsSnapshot {
+        StatsSnapshot {
+            uptime: self.uptime(),
+            service_time: self.service_time.snapshot(),
+            selection_time: self.selection_time.snapshot(),
+            slow_requests: self.slow_requests.load(Ordering::Relaxed),
+            denied_requests: self.denied_requests.load(Ordering::Relaxed),
+            ignored_datagrams: self.ignored_datagrams.load(Ordering::Relaxed),
+            accept_pauses: self.accept_pauses.load(Ordering::Relaxed),
+            shed_connections: self.shed_connections.load(Ordering::Relaxed),
+            shed_datagrams: self.shed_datagrams.load(Ordering::Relaxed),
+            failed_requests: self.failed_requests.load(Ordering::Relaxed),
+            tcp_serves: self.tcp_serves.load(Ordering::Relaxed),
+            udp_serves: self.udp_serves.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// A point-in-time copy of [`Stats`]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct StatsSnapshot {
+    pub uptime: Duration,
+    pub service_time: HistogramSnapshot,
+    pub selection_time: HistogramSnapshot,
+    pub slow_requests: u64,
+    pub denied_requests: u64,
+    pub ignored_datagrams: u64,
+    pub accept_pauses: u64,
+    pub shed_connections: u64,
+    pub shed_datagrams: u64,
+    pub failed_requests: u64,
+    pub tcp_serves: u64,
+    pub udp_serves: u64,
+}
+
+/// How often quotes have been served
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct QuoteReport {
+    /// Number of quotes known, whether served or not
+    pub quotes: usize,
+    /// Total number of times any quote has been served
+    pub serves: u64,
+    /// Number of distinct quotes that have been served
+    pub served: usize,
+    /// Times quotes have been served over TCP
+    pub tcp_serves: u64,
+    /// Times quotes have been served over UDP
+    pub udp_serves: u64,
+    /// Times quotes of each category have been served, for categories served at all
+    pub by_category: Vec<(QuoteCategory, u64)>,
+    /// Times quotes from each file have been served, in descending order, for files served at all
+    pub by_file: Vec<(String, u64)>,
+    /// The most served quotes with their serve counts, in descending order
+    pub most_served: Vec<(QuoteId, u64)>,
+    pub never_served: Vec<QuoteId>,
+}
+
+impl fmt::Display for QuoteReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Served {} quote(s) {} time(s); {} quote(s) never served",
+            self.served,
+            self.serves,
+            self.never_served.len()
+        )?;
+        writeln!(
+            f,
+            "By protocol: TCP {}, UDP {}",
+            self.tcp_serves, self.udp_serves
+        )?;
+        if !self.by_category.is_empty() {
+            let categories: Vec<_> = self
+                .by_category
+                .iter()
+                .map(|(category, count)| format!("{category} {count}"))
+                .collect();
+            writeln!(f, "By category: {}", categories.join(", "))?;
+        }
+        if !self.by_file.is_empty() {
+            writeln!(f, "By file:")?;
+            for (file, count) in &self.by_file {
+                writeln!(f, "{count:>8}  {file}")?;
+            }
+        }
+        if !self.most_served.is_empty() {
+            writeln!(f, "Most served:")?;
+            for (id, count) in &self.most_served {
+                writeln!(f, "{count:>8}  {}  {id}", id.hash_hex())?;
+            }
+        }
+        Ok(())
+    }
+}
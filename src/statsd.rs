@@ -0,0 +1,144 @@
+//! This module pushes statistics to a StatsD server (or relay) at a fixed interval, for hosts with
+//! nothing to pull them
+//!
+//! Counters are sent as how much they've grown since the last flush, so that StatsD can sum them
+//! across servers; mean service and selection times over the interval, and uptime, are sent as
+//! gauges. Metrics are sent as a single datagram per flush, and a lost datagram is simply lost.
+#![cfg(feature = "statsd")]
+
+use std::{fmt::Write, sync::Arc, time::Duration};
+
+use anyhow::Context;
+use tokio::net::{lookup_host, UdpSocket};
+use tracing::{debug, warn};
+
+use crate::{HistogramSnapshot, Stats, StatsSnapshot};
+
+/// How often metrics are flushed unless told otherwise
+pub const DEFAULT_STATSD_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Pushes statistics to a StatsD server for as long as quotes are served (see
+/// [`Server::statsd`](crate::Server::statsd))
+#[derive(Debug, Clone)]
+pub struct StatsdExporter {
+    address: String,
+    prefix: String,
+    interval: Duration,
+}
+
+impl StatsdExporter {
+    /// Push to the StatsD server at `address` (e.g. "localhost:8125")
+    pub fn new<A: Into<String>>(address: A) -> Self {
+        Self {
+            address: address.into(),
+            prefix: "qotd".to_string(),
+            interval: DEFAULT_STATSD_INTERVAL,
+        }
+    }
+
+    /// Prefix every metric's name with `prefix` and a dot, e.g. "qotd.tcp_serves"; an empty prefix
+    /// leaves names as they are
+    pub fn prefix<S: Into<String>>(mut self, prefix: S) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    /// Flush metrics every `interval`
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Push metrics every interval, for good
+    ///
+    /// Failing to push is never fatal, as the StatsD server may well be back by the next flush.
+    pub(crate) async fn run(self, stats: Arc<Stats>) -> anyhow::Result<()> {
+        let mut ticks = tokio::time::interval(self.interval);
+        // The first tick completes immediately, when there's nothing new to push
+        ticks.tick().await;
+        // Statistics restored from a state file were pushed by whoever served them
+        let mut last = stats.snapshot();
+        loop {
+            ticks.tick().await;
+            let snapshot = stats.snapshot();
+            let metrics = self.metrics(&last, &snapshot);
+            if let Err(e) = self.push(&metrics).await {
+                warn!("Failed to push metrics to StatsD {}: {e:?}", self.address);
+            }
+            last = snapshot;
+        }
+    }
+
+    async fn push(&self, metrics: &str) -> anyhow::Result<()> {
+        // The address is looked up anew each time, in case the relay has moved
+        let address = lookup_host(&self.address)
+            .await?
+            .next()
+            .context("Address not found")?;
+        let local: std::net::SocketAddr = if address.is_ipv4() {
+            ([0, 0, 0, 0], 0).into()
+        } else {
+            ([0_u16; 8], 0).into()
+        };
+        let socket = UdpSocket::bind(local).await?;
+        socket.send_to(metrics.as_bytes(), address).await?;
+        debug!("Pushed metrics to StatsD {}", self.address);
+        Ok(())
+    }
+
+    /// The metrics for the interval between the `last` and `current` snapshots, one per line
+    fn metrics(&self, last: &StatsSnapshot, current: &StatsSnapshot) -> String {
+        let (a, b) = (last, current);
+        let counters = [
+            ("tcp_serves", a.tcp_serves, b.tcp_serves),
+            ("udp_serves", a.udp_serves, b.udp_serves),
+            ("failed_requests", a.failed_requests, b.failed_requests),
+            ("slow_requests", a.slow_requests, b.slow_requests),
+            ("denied_requests", a.denied_requests, b.denied_requests),
+            (
+                "ignored_datagrams",
+                a.ignored_datagrams,
+                b.ignored_datagrams,
+            ),
+            ("shed_connections", a.shed_connections, b.shed_connections),
+            ("shed_datagrams", a.shed_datagrams, b.shed_datagrams),
+            ("accept_pauses", a.accept_pauses, b.accept_pauses),
+        ];
+        let mut metrics = String::new();
+        for (name, last, current) in counters {
+            let count = current.saturating_sub(last);
+            let _ = writeln!(metrics, "{}:{count}|c", self.name(name));
+        }
+        let timings = [
+            ("service_time", &a.service_time, &b.service_time),
+            ("selection_time", &a.selection_time, &b.selection_time),
+        ];
+        for (name, last, current) in timings {
+            // Nothing was timed, so there's no mean to speak of
+            if let Some(mean) = mean_us(last, current) {
+                let _ = writeln!(metrics, "{}_mean_us:{mean}|g", self.name(name));
+            }
+        }
+        let _ = writeln!(
+            metrics,
+            "{}:{}|g",
+            self.name("uptime_seconds"),
+            b.uptime.as_secs()
+        );
+        metrics
+    }
+
+    fn name(&self, metric: &str) -> String {
+        if self.prefix.is_empty() {
+            metric.to_string()
+        } else {
+            format!("{}.{metric}", self.prefix)
+        }
+    }
+}
+
+/// The mean of the durations recorded between the `last` and `current` snapshots, in microseconds
+fn mean_us(last: &HistogramSnapshot, current: &HistogramSnapshot) -> Option<u64> {
+    let count = current.count.checked_sub(last.count).filter(|&n| n > 0)?;
+    Some(current.sum_us.saturating_sub(last.sum_us) / count)
+}
@@ -0,0 +1,139 @@
+//! This module speaks just enough of the Telnet protocol (RFC 854) for telnet clients to render
+//! quotes correctly, while refusing every option they ask for
+#![cfg(feature = "tcp")]
+
+use std::{io, time::Duration};
+
+use futures::FutureExt;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// "Interpret As Command", which introduces every command and must be doubled in data
+const IAC: u8 = 255;
+const DONT: u8 = 254;
+const DO: u8 = 253;
+const WONT: u8 = 252;
+const WILL: u8 = 251;
+/// Begins a subnegotiation, which is ended by IAC SE
+const SB: u8 = 250;
+const SE: u8 = 240;
+
+/// How long to keep reading (and discarding) input after sending a quote
+///
+/// Closing a connection with unread input resets it, which may cost the client the quote it was
+/// just sent, so we wait for the client to hang up or for this long, whichever is sooner.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum State {
+    #[default]
+    Data,
+    Iac,
+    /// Awaiting the option of a DO, DONT, WILL, or WONT
+    Option(u8),
+    Subnegotiation,
+    SubnegotiationIac,
+}
+
+/// Parses the client's input, refusing any options it asks us to enable or offers to enable
+#[derive(Debug, Default)]
+struct Negotiation {
+    state: State,
+}
+
+impl Negotiation {
+    /// Consume client input, appending our replies to `replies`; all data is discarded
+    fn receive(&mut self, input: &[u8], replies: &mut Vec<u8>) {
+        for &byte in input {
+            self.state = match (self.state, byte) {
+                (State::Data, IAC) => State::Iac,
+                (State::Data, _) => State::Data,
+                (State::Iac, DO | DONT | WILL | WONT) => State::Option(byte),
+                (State::Iac, SB) => State::Subnegotiation,
+                // Escaped data, or a command without an option
+                (State::Iac, _) => State::Data,
+                (State::Option(command), option) => {
+                    match command {
+                        DO => replies.extend_from_slice(&[IAC, WONT, option]),
+                        WILL => replies.extend_from_slice(&[IAC, DONT, option]),
+                        // Refusals to disable options, which are all already disabled
+                        _ => {}
+                    }
+                    State::Data
+                }
+                (State::Subnegotiation, IAC) => State::SubnegotiationIac,
+                (State::Subnegotiation, _) => State::Subnegotiation,
+                (State::SubnegotiationIac, SE) => State::Data,
+                (State::SubnegotiationIac, _) => State::Subnegotiation,
+            };
+        }
+    }
+}
+
+/// Escape text for a network virtual terminal: IAC bytes are doubled and line feeds become CRLF
+fn escape(text: &[u8]) -> Vec<u8> {
+    let mut escaped = Vec::with_capacity(text.len() + text.len() / 32);
+    let mut previous = None;
+    for &byte in text {
+        match byte {
+            IAC => escaped.extend_from_slice(&[IAC, IAC]),
+            b'\n' if previous != Some(b'\r') => escaped.extend_from_slice(b"\r\n"),
+            _ => escaped.push(byte),
+        }
+        previous = Some(byte);
+    }
+    escaped
+}
+
+/// The number of bytes `text` is sent to a telnet client as
+pub(crate) fn escaped_length(text: &[u8]) -> usize {
+    escape(text).len()
+}
+
+/// The number of bytes of `text` that are sent to a telnet client as at most `max` bytes
+pub(crate) fn fitting_length(text: &[u8], max: usize) -> usize {
+    let mut escaped = 0;
+    let mut previous = None;
+    for (i, &byte) in text.iter().enumerate() {
+        escaped += match byte {
+            IAC => 2,
+            b'\n' if previous != Some(b'\r') => 2,
+            _ => 1,
+        };
+        if escaped > max {
+            return i;
+        }
+        previous = Some(byte);
+    }
+    text.len()
+}
+
+/// Send a quote to a telnet client, refusing any negotiation it has already begun
+pub(crate) async fn send<S: AsyncRead + AsyncWrite + Unpin>(
+    conn: &mut S,
+    quote: &[u8],
+) -> io::Result<()> {
+    let mut negotiation = Negotiation::default();
+    let mut replies = Vec::new();
+    let mut buf = [0_u8; 256];
+    loop {
+        // Only what the client has already sent; we don't wait for more
+        match conn.read(&mut buf).now_or_never() {
+            None | Some(Ok(0)) => break,
+            Some(Ok(n)) => negotiation.receive(&buf[..n], &mut replies),
+            Some(Err(e)) => return Err(e),
+        }
+    }
+
+    replies.extend_from_slice(&escape(quote));
+    conn.write_all(&replies).await?;
+    conn.shutdown().await?;
+
+    // Whatever else the client has to say goes unanswered, as we're done talking
+    let _ = tokio::time::timeout(DRAIN_TIMEOUT, async {
+        while conn.read(&mut buf).await? > 0 {}
+        io::Result::Ok(())
+    })
+    .await;
+
+    Ok(())
+}
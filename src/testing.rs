@@ -0,0 +1,74 @@
+//! This module helps test code that talks to a QOTD server, by serving known quotes on an
+//! ephemeral port
+//!
+//! Quotes are chosen with a fixed seed, so a test that makes the same requests in the same order is
+//! served the same quotes every time it runs.
+#![cfg(feature = "test-util")]
+
+use std::{io, net::SocketAddr};
+
+use rand::{rngs::StdRng, SeedableRng};
+
+use crate::{IndexOptions, Quotes, Server, ServerHandle};
+
+/// A few short quotes, each of which fits in a single UDP datagram
+pub const TEST_QUOTES: [&str; 3] = [
+    "The quick brown fox jumps over the lazy dog.",
+    "A journey of a thousand miles begins with a single step.",
+    "To be, or not to be, that is the question.",
+];
+
+/// Seed quotes are chosen with, see [`Quotes::with_rng`]
+const TEST_SEED: u64 = 865;
+
+/// Quotes consisting of `texts`, in a single file named "test", chosen from with a fixed seed
+pub fn test_quotes<S: AsRef<str>>(texts: &[S]) -> io::Result<Quotes> {
+    let mut contents = String::from("%\n");
+    for text in texts {
+        contents.push_str(text.as_ref().trim());
+        contents.push_str("\n%\n");
+    }
+    let quotes = Quotes::from_reader("test", contents.as_bytes(), &IndexOptions::new())?;
+    Ok(quotes.with_rng(StdRng::seed_from_u64(TEST_SEED)))
+}
+
+/// Serve `quotes` over TCP and UDP on an ephemeral port of the loopback address, in a background
+/// task
+///
+/// The server's address is given by [`ServerHandle::addr`]; shut it down with
+/// [`ServerHandle::shutdown`]. Must be called from within a Tokio runtime.
+pub async fn spawn_test_server(quotes: Quotes) -> anyhow::Result<ServerHandle> {
+    Server::new()
+        .bind((std::net::Ipv4Addr::LOCALHOST, 0))
+        .await?
+        .spawn(quotes)
+}
+
+/// Fetch a quote from the server at `addr` over TCP
+#[cfg(feature = "tcp")]
+pub async fn fetch_tcp(addr: SocketAddr) -> io::Result<String> {
+    use tokio::io::AsyncReadExt;
+
+    let mut conn = tokio::net::TcpStream::connect(addr).await?;
+    let mut quote = String::new();
+    conn.read_to_string(&mut quote).await?;
+    Ok(quote)
+}
+
+/// Fetch a quote from the server at `addr` over UDP
+///
+/// This waits for as long as it takes to be answered, so should be wrapped in a timeout if the
+/// server may not answer at all.
+#[cfg(feature = "udp")]
+pub async fn fetch_udp(addr: SocketAddr) -> io::Result<String> {
+    let local = match addr {
+        SocketAddr::V4(_) => SocketAddr::from((std::net::Ipv4Addr::LOCALHOST, 0)),
+        SocketAddr::V6(_) => SocketAddr::from((std::net::Ipv6Addr::LOCALHOST, 0)),
+    };
+    let socket = tokio::net::UdpSocket::bind(local).await?;
+    socket.send_to(b"\n", addr).await?;
+    let mut buf = vec![0; u16::MAX.into()];
+    let len = socket.recv(&mut buf).await?;
+    buf.truncate(len);
+    String::from_utf8(buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
@@ -0,0 +1,68 @@
+//! This module speaks TLS to TCP clients, choosing which quotes they're served by the hostname they
+//! ask for (i.e. their SNI), e.g. "clean.example.com" vs "spicy.example.com"
+#![cfg(feature = "tls")]
+
+use std::{path::Path, sync::Arc};
+
+use anyhow::Context;
+use tokio_rustls::{
+    rustls::{
+        crypto::ring,
+        pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+        ServerConfig,
+    },
+    TlsAcceptor,
+};
+
+use crate::QuoteCategory;
+
+/// A certificate to serve TCP clients over TLS with, and the categories served for each hostname
+#[derive(Debug, Clone)]
+pub struct Tls {
+    config: Arc<ServerConfig>,
+    hosts: Vec<(String, Vec<QuoteCategory>)>,
+}
+
+impl Tls {
+    /// Read a PEM certificate chain and private key
+    ///
+    /// The certificate should be valid for every hostname given to [`Tls::host`].
+    pub fn from_pem_files<C: AsRef<Path>, K: AsRef<Path>>(cert: C, key: K) -> anyhow::Result<Self> {
+        let (cert, key) = (cert.as_ref(), key.as_ref());
+        let certs = CertificateDer::pem_file_iter(cert)
+            .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+            .with_context(|| format!("Unable to read certificates from {}", cert.display()))?;
+        let key = PrivateKeyDer::from_pem_file(key)
+            .with_context(|| format!("Unable to read private key from {}", key.display()))?;
+        let config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()?
+            .with_no_client_auth()
+            .with_single_cert(certs, key)
+            .context("Invalid certificate or private key")?;
+        Ok(Self {
+            config: Arc::new(config),
+            hosts: Vec::new(),
+        })
+    }
+
+    /// Serve only quotes in `categories` to clients asking for `hostname`
+    ///
+    /// Clients asking for other hostnames, or none at all, are served as any other client.
+    pub fn host<S: Into<String>>(mut self, hostname: S, categories: &[QuoteCategory]) -> Self {
+        self.hosts.push((hostname.into(), categories.to_vec()));
+        self
+    }
+
+    pub(crate) fn acceptor(&self) -> TlsAcceptor {
+        TlsAcceptor::from(Arc::clone(&self.config))
+    }
+
+    /// The categories served to clients asking for `server_name`, if it has any of its own
+    pub(crate) fn categories_for(&self, server_name: Option<&str>) -> Option<&[QuoteCategory]> {
+        let server_name = server_name?;
+        self.hosts
+            .iter()
+            .find(|(hostname, _)| hostname.eq_ignore_ascii_case(server_name))
+            .map(|(_, categories)| categories.as_slice())
+    }
+}
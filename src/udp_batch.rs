@@ -0,0 +1,198 @@
+//! This module receives and answers UDP requests in batches, so that a busy server isn't limited to
+//! one datagram per syscall
+//!
+//! On Linux this uses `recvmmsg(2)` and `sendmmsg(2)`; elsewhere, datagrams already waiting are
+//! drained one at a time, which still saves waking up for each of them.
+#![cfg(feature = "udp")]
+
+use std::{io, net::SocketAddr};
+
+use tokio::net::UdpSocket;
+
+/// Most datagrams received, or sent, at once
+pub(crate) const BATCH_SIZE: usize = 32;
+
+/// Wait for at least one datagram, then receive as many as are waiting (up to one per buffer)
+///
+/// The length and sender of each datagram received is appended to `received`, in the order of
+/// `bufs`. As with [`UdpSocket::recv_from`], datagrams longer than their buffer are truncated.
+pub(crate) async fn recv_batch(
+    udp: &UdpSocket,
+    bufs: &mut [Vec<u8>],
+    received: &mut Vec<(usize, SocketAddr)>,
+) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::fd::AsRawFd;
+        use tokio::io::Interest;
+
+        udp.async_io(Interest::READABLE, || {
+            linux::recvmmsg(udp.as_raw_fd(), bufs, received)
+        })
+        .await
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let (first, rest) = bufs.split_first_mut().expect("no buffers to receive into");
+        received.push(udp.recv_from(first).await?);
+        for buf in rest {
+            // Errors are left for the next call to find, along with whatever follows them
+            match udp.try_recv_from(buf) {
+                Ok(datagram) => received.push(datagram),
+                Err(_) => break,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Send each of `replies` to its address
+///
+/// Returns the result of sending each reply, in order; one failing doesn't stop the rest being
+/// sent.
+pub(crate) async fn send_batch(
+    udp: &UdpSocket,
+    replies: &[(&[u8], SocketAddr)],
+) -> Vec<io::Result<()>> {
+    let mut results = Vec::with_capacity(replies.len());
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::fd::AsRawFd;
+        use tokio::io::Interest;
+
+        for chunk in replies.chunks(BATCH_SIZE) {
+            let mut sent = 0;
+            while sent < chunk.len() {
+                let rest = &chunk[sent..];
+                match udp
+                    .async_io(Interest::WRITABLE, || {
+                        linux::sendmmsg(udp.as_raw_fd(), rest)
+                    })
+                    .await
+                {
+                    Ok(count) => {
+                        results.extend((0..count).map(|_| Ok(())));
+                        sent += count;
+                    }
+                    // The error is that of the first datagram not sent; carry on after it
+                    Err(e) => {
+                        results.push(Err(e));
+                        sent += 1;
+                    }
+                }
+            }
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    for &(reply, addr) in replies {
+        results.push(udp.send_to(reply, addr).await.map(|_| ()));
+    }
+    results
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::{io, mem, net::SocketAddr, os::fd::RawFd, ptr};
+
+    use socket2::SockAddr;
+
+    use super::BATCH_SIZE;
+
+    pub fn recvmmsg(
+        fd: RawFd,
+        bufs: &mut [Vec<u8>],
+        received: &mut Vec<(usize, SocketAddr)>,
+    ) -> io::Result<()> {
+        let len = bufs.len().min(BATCH_SIZE);
+        let bufs = &mut bufs[..len];
+        // SAFETY: An all-zero sockaddr_storage is a valid (unspecified) address
+        let mut names: Vec<libc::sockaddr_storage> =
+            bufs.iter().map(|_| unsafe { mem::zeroed() }).collect();
+        let mut iovecs: Vec<libc::iovec> = bufs
+            .iter_mut()
+            .map(|buf| libc::iovec {
+                iov_base: buf.as_mut_ptr().cast(),
+                iov_len: buf.len(),
+            })
+            .collect();
+        let mut msgs: Vec<libc::mmsghdr> = names
+            .iter_mut()
+            .zip(iovecs.iter_mut())
+            .map(|(name, iovec)| {
+                // SAFETY: An all-zero mmsghdr is valid, having no name, buffers, or control data
+                let mut msg: libc::mmsghdr = unsafe { mem::zeroed() };
+                msg.msg_hdr.msg_name = ptr::from_mut(name).cast();
+                msg.msg_hdr.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as _;
+                msg.msg_hdr.msg_iov = iovec;
+                msg.msg_hdr.msg_iovlen = 1;
+                msg
+            })
+            .collect();
+
+        // SAFETY: Every message points to a name and buffer that outlive the call, and holds their
+        // true sizes
+        let count = unsafe {
+            libc::recvmmsg(
+                fd,
+                msgs.as_mut_ptr(),
+                msgs.len() as _,
+                libc::MSG_DONTWAIT as _,
+                ptr::null_mut(),
+            )
+        };
+        if count < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        for (msg, name) in msgs.iter().zip(&names).take(count as usize) {
+            // SAFETY: The kernel filled in the name, and its length, of each datagram received
+            let addr = unsafe { SockAddr::new(*name, msg.msg_hdr.msg_namelen) };
+            // UDP datagrams always come from an IP address
+            if let Some(addr) = addr.as_socket() {
+                received.push((msg.msg_len as usize, addr));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the number of datagrams sent, which may be fewer than given
+    pub fn sendmmsg(fd: RawFd, replies: &[(&[u8], SocketAddr)]) -> io::Result<usize> {
+        let names: Vec<SockAddr> = replies.iter().map(|&(_, addr)| addr.into()).collect();
+        let mut iovecs: Vec<libc::iovec> = replies
+            .iter()
+            .map(|&(reply, _)| libc::iovec {
+                // sendmmsg() only reads from the buffers, despite the pointer's mutability
+                iov_base: reply.as_ptr().cast_mut().cast(),
+                iov_len: reply.len(),
+            })
+            .collect();
+        let mut msgs: Vec<libc::mmsghdr> = names
+            .iter()
+            .zip(iovecs.iter_mut())
+            .map(|(name, iovec)| {
+                // SAFETY: An all-zero mmsghdr is valid, having no name, buffers, or control data
+                let mut msg: libc::mmsghdr = unsafe { mem::zeroed() };
+                msg.msg_hdr.msg_name = name.as_ptr().cast_mut().cast();
+                msg.msg_hdr.msg_namelen = name.len();
+                msg.msg_hdr.msg_iov = iovec;
+                msg.msg_hdr.msg_iovlen = 1;
+                msg
+            })
+            .collect();
+
+        // SAFETY: Every message points to a name and buffer that outlive the call, and holds their
+        // true sizes
+        let count = unsafe {
+            libc::sendmmsg(
+                fd,
+                msgs.as_mut_ptr(),
+                msgs.len() as _,
+                libc::MSG_DONTWAIT as _,
+            )
+        };
+        if count < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(count as usize)
+    }
+}
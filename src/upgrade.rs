@@ -0,0 +1,183 @@
+//! This module upgrades a running server in place by re-executing its binary
+//!
+//! On SIGUSR2 the server spawns a new instance of itself, handing over its bound sockets. Once the
+//! new instance signals that it is serving, the old one stops accepting requests, finishes those
+//! in flight, and exits; the sockets are never closed, so no client is ever refused.
+#![cfg(all(unix, any(feature = "tcp", feature = "udp")))]
+
+use std::{
+    ffi::OsString,
+    io::{self, Read, Write},
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+        unix::process::CommandExt,
+    },
+    process::{Child, Command},
+};
+
+use nix::fcntl::{fcntl, FcntlArg, FdFlag};
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::{debug, error, info};
+
+/// Environment variable listing inherited sockets, as comma-separated pairs of TCP and UDP fds, with
+/// "-" in place of a socket that isn't served
+const LISTEN_FDS_VAR: &str = "QOTD_LISTEN_FDS";
+/// Environment variable naming the pipe the new instance writes to once it is serving
+const READY_FD_VAR: &str = "QOTD_READY_FD";
+
+/// A pair of TCP and UDP fds, either of which may be absent
+pub(crate) type FdPair = (Option<RawFd>, Option<RawFd>);
+
+/// Sockets handed over by the instance being upgraded, if this is the new instance
+#[allow(clippy::type_complexity)]
+pub(crate) fn inherited_sockets(
+) -> io::Result<Option<Vec<(Option<std::net::TcpListener>, Option<std::net::UdpSocket>)>>> {
+    let Some(fds) = std::env::var_os(LISTEN_FDS_VAR) else {
+        return Ok(None);
+    };
+    let invalid = || {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid {LISTEN_FDS_VAR}: {fds:?}"),
+        )
+    };
+    let fds = fds
+        .to_str()
+        .ok_or_else(invalid)?
+        .split(',')
+        .map(|fd| match fd {
+            "-" => Ok(None),
+            fd => fd.parse::<RawFd>().map(Some).map_err(|_| invalid()),
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+    if fds.is_empty() || fds.len() % 2 != 0 {
+        return Err(invalid());
+    }
+
+    let sockets = fds
+        .chunks_exact(2)
+        .map(|pair| {
+            debug!("Inheriting TCP fd {:?} and UDP fd {:?}", pair[0], pair[1]);
+            // Don't pass them on to any other program we might run
+            for &fd in pair.iter().flatten() {
+                fcntl(fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))?;
+            }
+            // SAFETY: These fds were handed to us by our parent for exactly this purpose, and nothing
+            // else in this process knows about them
+            Ok(unsafe {
+                (
+                    pair[0].map(|fd| std::net::TcpListener::from_raw_fd(fd)),
+                    pair[1].map(|fd| std::net::UdpSocket::from_raw_fd(fd)),
+                )
+            })
+        })
+        .collect::<io::Result<_>>()?;
+
+    Ok(Some(sockets))
+}
+
+/// Tell the instance being upgraded, if any, that we are now serving
+pub(crate) fn notify_ready() -> io::Result<()> {
+    let Some(fd) = std::env::var_os(READY_FD_VAR) else {
+        return Ok(());
+    };
+    let fd = fd
+        .to_str()
+        .and_then(|fd| fd.parse::<RawFd>().ok())
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid {READY_FD_VAR}: {fd:?}"),
+            )
+        })?;
+    // SAFETY: This fd was handed to us by our parent for exactly this purpose
+    let mut pipe = std::fs::File::from(unsafe { OwnedFd::from_raw_fd(fd) });
+    pipe.write_all(b"!")
+}
+
+/// Hand our sockets over to an upgraded instance once SIGUSR2 is received
+///
+/// This returns once the new instance is serving; failed upgrades are logged, and another may then
+/// be requested.
+pub(crate) async fn handover_on_signal(sockets: &[FdPair]) -> io::Result<()> {
+    // A supervised server is upgraded by its supervisor, which holds the sockets too
+    if crate::privsep::is_supervised() {
+        return std::future::pending().await;
+    }
+    let mut upgrade_requested = signal(SignalKind::user_defined2())?;
+    loop {
+        upgrade_requested.recv().await;
+        info!("Upgrade requested");
+        match spawn_successor(sockets).await {
+            Ok(()) => break Ok(()),
+            Err(e) => error!("Upgrade failed, continuing to serve: {e}"),
+        }
+    }
+}
+
+/// Spawn a new instance of this program with the given sockets, and wait until it is serving
+async fn spawn_successor(sockets: &[FdPair]) -> io::Result<()> {
+    let child = spawn_serving(sockets, &[]).await?;
+    info!("Upgraded server is serving as process {}", child.id());
+    Ok(())
+}
+
+/// Spawn a new instance of this program with the given sockets and additional environment
+/// variables, and wait until it is serving
+pub(crate) async fn spawn_serving(sockets: &[FdPair], envs: &[(&str, &str)]) -> io::Result<Child> {
+    let (mut ready_rx, ready_tx) = io::pipe()?;
+    let ready_fd = ready_tx.as_raw_fd();
+    let fds: Vec<Option<RawFd>> = sockets.iter().flat_map(|&(tcp, udp)| [tcp, udp]).collect();
+    let listen_fds = fds
+        .iter()
+        .map(|fd| fd.map_or_else(|| "-".to_string(), |fd| fd.to_string()))
+        .collect::<Vec<_>>()
+        .join(",");
+    let mut inherited: Vec<RawFd> = fds.into_iter().flatten().collect();
+    inherited.push(ready_fd);
+
+    let mut child = successor_command(listen_fds, ready_fd, inherited)
+        .envs(envs.iter().copied())
+        .spawn()?;
+    debug!("Spawned server as process {}", child.id());
+    // Close our copy of the pipe's write end, so that we see EOF if the child exits early
+    drop(ready_tx);
+
+    let ready = tokio::task::spawn_blocking(move || {
+        let mut buf = [0_u8; 1];
+        ready_rx.read(&mut buf)
+    })
+    .await??;
+    if ready == 0 {
+        // Reap it, rather than leave a zombie behind
+        let status = tokio::task::spawn_blocking(move || child.wait()).await??;
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("New server exited before it began serving ({status})"),
+        ));
+    }
+
+    Ok(child)
+}
+
+/// Command running this program by the same name with the same arguments, so that a binary replaced
+/// on disk is picked up, and `inherited` fds left open
+fn successor_command(listen_fds: String, ready_fd: RawFd, inherited: Vec<RawFd>) -> Command {
+    let mut args = std::env::args_os();
+    let program = args.next().unwrap_or_else(|| OsString::from("qotd-server"));
+    let mut command = Command::new(program);
+    command
+        .args(args)
+        .env(LISTEN_FDS_VAR, listen_fds)
+        .env(READY_FD_VAR, ready_fd.to_string());
+    // SAFETY: Only async-signal-safe functions are called between fork and exec
+    unsafe {
+        command.pre_exec(move || {
+            for &fd in &inherited {
+                fcntl(fd, FcntlArg::F_SETFD(FdFlag::empty()))?;
+            }
+            Ok(())
+        });
+    }
+    command
+}
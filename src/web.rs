@@ -0,0 +1,506 @@
+//! This module serves quotes over HTTP, for clients that can't speak RFC 865 (e.g. browsers and
+//! feed readers)
+//!
+//! Unlike the admin endpoint, this is meant to be public: it's subject to the same access control
+//! as TCP and UDP clients. Quotes are sent as plain text or JSON, as the client's Accept header
+//! prefers, with an ETag that lasts as long as the quote is served (see [`Server::rotate`]), and
+//! pages on the origins allowed by [`Server::cors_origin`] may fetch them.
+//!
+//! [`Server::rotate`]: crate::Server::rotate
+//! [`Server::cors_origin`]: crate::Server::cors_origin
+#![cfg(any(feature = "tcp", feature = "udp"))]
+
+use std::{fmt::Write, net::SocketAddr, sync::Arc};
+
+use chrono::{DateTime, Days, Local, NaiveDate, TimeZone};
+use tokio::sync::{mpsc::Sender, oneshot};
+use tracing::warn;
+
+use crate::{
+    audit::AuditLog,
+    format::Transport,
+    http::Request,
+    http::Response,
+    server::{QuoteRequest, RequestId},
+    Quote, Rotation, Stats,
+};
+
+/// Number of daily quotes in the feed by default
+pub const DEFAULT_FEED_LENGTH: usize = 10;
+/// Number of daily quotes in the calendar by default
+pub const DEFAULT_CALENDAR_LENGTH: usize = 30;
+/// Longest line in a calendar, in bytes, before it must be folded
+const MAX_CALENDAR_LINE: usize = 75;
+/// How long browsers may cache the answer to a preflight request, in seconds
+const PREFLIGHT_MAX_AGE: u32 = 24 * 60 * 60;
+
+/// State shared by the HTTP endpoint's requests
+#[derive(Debug)]
+pub(crate) struct Web {
+    pub stats: Arc<Stats>,
+    /// Where offensive quotes served are recorded, if they are
+    pub audit_log: Option<Arc<AuditLog>>,
+    /// Number of daily quotes in the feed, ending with today's
+    pub feed_length: usize,
+    /// Number of daily quotes in the calendar, starting with today's
+    pub calendar_length: usize,
+    /// Rotation quotes are served by, if they aren't chosen anew for every request
+    pub rotation: Option<Rotation>,
+    /// Origins whose pages may fetch from the endpoint; "*" allows any
+    pub cors_origins: Vec<String>,
+}
+
+impl Web {
+    /// Identifies `quote` sent as `representation`, for as long as it's the quote being served
+    fn etag(&self, quote: &Quote, representation: Representation, now: DateTime<Local>) -> String {
+        let suffix = match representation {
+            Representation::Text => "",
+            Representation::Json => "-json",
+        };
+        match &self.rotation {
+            Some(rotation) => format!(
+                "\"{}-{}{suffix}\"",
+                rotation.period_start_at(now).format("%Y%m%dT%H%M%S"),
+                quote.hash()
+            ),
+            None => format!("\"{}{suffix}\"", quote.hash()),
+        }
+    }
+
+    /// How long a quote may be cached for at `now`, i.e. until the next rotation
+    fn cache_control(&self, now: DateTime<Local>) -> String {
+        match &self.rotation {
+            Some(rotation) => format!("public, max-age={}", rotation.until_next_at(now).as_secs()),
+            // Every request may be sent a different quote, but revalidating is cheap
+            None => "no-cache".to_string(),
+        }
+    }
+
+    /// Allow the page that made `request` to read `response`, if its origin is allowed
+    fn allow_origin(&self, request: &Request, mut response: Response) -> Response {
+        let any = self.cors_origins.iter().any(|origin| origin == "*");
+        if !any && !self.cors_origins.is_empty() {
+            response = response.header("Vary", "Origin");
+        }
+        let Some(origin) = request.header("origin") else {
+            return response;
+        };
+        let allowed = if any {
+            "*"
+        } else if self
+            .cors_origins
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(origin))
+        {
+            origin
+        } else {
+            return response;
+        };
+        response
+            .header("Access-Control-Allow-Origin", allowed)
+            .header(
+                "Access-Control-Expose-Headers",
+                "ETag, X-Quote-Hash, X-Request-Id",
+            )
+    }
+}
+
+/// How a quote is sent, as negotiated by the client's Accept header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Representation {
+    Text,
+    Json,
+}
+
+impl Representation {
+    /// In order of preference, should a client accept more than one equally
+    const ALL: [Representation; 2] = [Representation::Text, Representation::Json];
+
+    fn media_type(&self) -> &'static str {
+        match self {
+            Representation::Text => "text/plain",
+            Representation::Json => "application/json",
+        }
+    }
+
+    /// The representation preferred by a client sending `accept`, or `None` if it accepts none
+    fn negotiate(accept: Option<&str>) -> Option<Self> {
+        let Some(accept) = accept else {
+            return Some(Representation::Text);
+        };
+        let mut best: Option<(Self, f32)> = None;
+        for representation in Self::ALL {
+            let quality = quality(accept, representation.media_type());
+            if quality > 0.0 && best.is_none_or(|(_, best)| quality > best) {
+                best = Some((representation, quality));
+            }
+        }
+        best.map(|(representation, _)| representation)
+    }
+
+    fn content_type(&self) -> &'static str {
+        match self {
+            Representation::Text => "text/plain; charset=utf-8",
+            Representation::Json => "application/json",
+        }
+    }
+
+    fn render(&self, quote: &Quote) -> Vec<u8> {
+        match self {
+            Representation::Text => quote.text.clone(),
+            Representation::Json => {
+                let author = quote
+                    .author()
+                    .map_or_else(|| "null".to_string(), |author| json_string(&author));
+                format!(
+                    "{{\"text\":{},\"author\":{author},\"file\":{},\"category\":{},\"hash\":{}}}\n",
+                    json_string(&quote.text_str()),
+                    json_string(&quote.file),
+                    json_string(&quote.category.to_string()),
+                    json_string(&quote.hash()),
+                )
+                .into_bytes()
+            }
+        }
+    }
+}
+
+/// How much a client sending `accept` wants `media_type`, from 0 (not at all) to 1, going by the
+/// most specific media range that matches it
+fn quality(accept: &str, media_type: &str) -> f32 {
+    let wildcard = media_type
+        .split_once('/')
+        .map(|(ty, _)| format!("{ty}/*"))
+        .unwrap_or_default();
+    // Of the most specific range matched so far: how specific it is, and its quality
+    let mut best: Option<(u8, f32)> = None;
+    for range in accept.split(',') {
+        let mut params = range.split(';');
+        let range = params.next().unwrap_or_default().trim();
+        let specificity = if range.eq_ignore_ascii_case(media_type) {
+            2
+        } else if range.eq_ignore_ascii_case(&wildcard) {
+            1
+        } else if range == "*/*" {
+            0
+        } else {
+            continue;
+        };
+        let quality = params
+            .filter_map(|param| param.trim().strip_prefix("q="))
+            .find_map(|quality| quality.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+        if best.is_none_or(|(best, _)| specificity > best) {
+            best = Some((specificity, quality.clamp(0.0, 1.0)));
+        }
+    }
+    best.map_or(0.0, |(_, quality)| quality)
+}
+
+/// Whether an If-None-Match header of `tags` matches `etag`, so it needn't be sent again
+fn matches(tags: Option<&str>, etag: &str) -> bool {
+    tags.is_some_and(|tags| {
+        tags.split(',')
+            .map(|tag| tag.trim().trim_start_matches("W/"))
+            .any(|tag| tag == "*" || tag == etag)
+    })
+}
+
+pub(crate) async fn route(
+    request: &Request,
+    peer: SocketAddr,
+    id: RequestId,
+    quote_tx: &Sender<QuoteRequest>,
+    web: &Web,
+) -> Response {
+    let response = match request.method.as_str() {
+        "GET" => match request.path.as_str() {
+            "/" => quote(request, peer, id, quote_tx, web).await,
+            "/feed.xml" => feed(quote_tx, web).await,
+            "/calendar.ics" => calendar(quote_tx, web).await,
+            _ => Response::text(404, "Not found"),
+        },
+        "OPTIONS" => preflight(request, web),
+        _ => Response::text(405, "Method not allowed").header("Allow", "GET, OPTIONS"),
+    };
+    web.allow_origin(request, response)
+}
+
+/// The methods and headers pages on allowed origins may fetch with
+fn preflight(request: &Request, web: &Web) -> Response {
+    let mut response = Response::empty(204).header("Allow", "GET, OPTIONS");
+    if web.cors_origins.is_empty() || request.header("access-control-request-method").is_none() {
+        return response;
+    }
+    response = response
+        .header("Access-Control-Allow-Methods", "GET")
+        .header("Access-Control-Max-Age", PREFLIGHT_MAX_AGE.to_string());
+    // e.g. If-None-Match, which browsers don't send cross-origin unless allowed
+    if let Some(headers) = request.header("access-control-request-headers") {
+        response = response.header("Access-Control-Allow-Headers", headers);
+    }
+    response
+}
+
+/// A quote, as UDP clients would be sent it (i.e. never colored), as plain text or JSON
+async fn quote(
+    request: &Request,
+    peer: SocketAddr,
+    id: RequestId,
+    quote_tx: &Sender<QuoteRequest>,
+    web: &Web,
+) -> Response {
+    let Some(representation) = Representation::negotiate(request.header("accept")) else {
+        return Response::text(406, "Quotes are sent as text/plain or application/json")
+            .header("Vary", "Accept");
+    };
+    let (reply_tx, quote_rx) = oneshot::channel();
+    if quote_tx
+        .send(QuoteRequest::Get(Transport::Udp, reply_tx))
+        .await
+        .is_err()
+    {
+        return Response::text(503, "Server is shutting down");
+    }
+    let quote = match quote_rx.await {
+        Ok(Ok(quote)) => quote.into_quote(),
+        Ok(Err(e)) => Err(std::io::Error::other(e)),
+        Err(_) => return Response::text(503, "Server is shutting down"),
+    };
+    match quote {
+        Ok(quote) => {
+            let now = Local::now();
+            let etag = web.etag(&quote, representation, now);
+            let response = if matches(request.header("if-none-match"), &etag) {
+                Response::empty(304)
+            } else {
+                web.stats.record_serve(&quote.id, quote.category);
+                if let Some(audit_log) = &web.audit_log {
+                    audit_log.record("HTTP", peer, id, &quote);
+                }
+                Response::new(
+                    200,
+                    representation.content_type(),
+                    representation.render(&quote),
+                )
+                .header("X-Quote-Hash", quote.hash())
+            };
+            response
+                .header("ETag", etag)
+                .header("Cache-Control", web.cache_control(now))
+                .header("Vary", "Accept")
+        }
+        Err(e) => {
+            warn!("Failed to serve quote over HTTP: {e:?}");
+            Response::text(500, "Failed to choose quote")
+        }
+    }
+}
+
+/// An Atom feed of the most recent daily quotes
+async fn feed(quote_tx: &Sender<QuoteRequest>, web: &Web) -> Response {
+    let today = Local::now().date_naive();
+    let dates =
+        (0..web.feed_length as u64).map_while(|days| today.checked_sub_days(Days::new(days)));
+    match daily_quotes(quote_tx, dates).await {
+        Some(entries) => Response::new(
+            200,
+            "application/atom+xml; charset=utf-8",
+            render_feed(&entries),
+        ),
+        None => Response::text(503, "Server is shutting down"),
+    }
+}
+
+/// An iCalendar feed of the upcoming daily quotes, each an all-day event
+async fn calendar(quote_tx: &Sender<QuoteRequest>, web: &Web) -> Response {
+    let today = Local::now().date_naive();
+    let dates =
+        (0..web.calendar_length as u64).map_while(|days| today.checked_add_days(Days::new(days)));
+    match daily_quotes(quote_tx, dates).await {
+        Some(events) => Response::new(
+            200,
+            "text/calendar; charset=utf-8",
+            render_calendar(&events),
+        ),
+        None => Response::text(503, "Server is shutting down"),
+    }
+}
+
+/// The quotes of the day for `dates`, or `None` if the server is shutting down
+///
+/// Days without a quote, e.g. because none are in season, are left out.
+async fn daily_quotes<I: Iterator<Item = NaiveDate>>(
+    quote_tx: &Sender<QuoteRequest>,
+    dates: I,
+) -> Option<Vec<(NaiveDate, Quote)>> {
+    let mut quotes = Vec::new();
+    for date in dates {
+        let (reply_tx, quote_rx) = oneshot::channel();
+        quote_tx
+            .send(QuoteRequest::Daily(date, reply_tx))
+            .await
+            .ok()?;
+        match quote_rx.await.ok()? {
+            Ok(quote) => quotes.push((date, quote)),
+            Err(e) => warn!("No quote of the day for {date}: {e:?}"),
+        }
+    }
+    Some(quotes)
+}
+
+fn render_feed(entries: &[(NaiveDate, Quote)]) -> String {
+    let mut xml = String::new();
+    // Writing to a String can't fail
+    let _ = write_feed(&mut xml, entries);
+    xml
+}
+
+fn write_feed(xml: &mut String, entries: &[(NaiveDate, Quote)]) -> std::fmt::Result {
+    writeln!(xml, r#"<?xml version="1.0" encoding="utf-8"?>"#)?;
+    writeln!(xml, r#"<feed xmlns="http://www.w3.org/2005/Atom">"#)?;
+    writeln!(xml, "<title>Quote of the Day</title>")?;
+    writeln!(xml, "<id>urn:qotd:feed</id>")?;
+    let updated = entries.first().map_or_else(
+        || timestamp(Local::now().date_naive()),
+        |(date, _)| timestamp(*date),
+    );
+    writeln!(xml, "<updated>{updated}</updated>")?;
+    writeln!(
+        xml,
+        "<generator version=\"{}\">qotd-rs</generator>",
+        env!("CARGO_PKG_VERSION")
+    )?;
+    for (date, quote) in entries {
+        writeln!(xml, "<entry>")?;
+        writeln!(xml, "<title>Quote of the Day for {date}</title>")?;
+        // The same quote on the same day is the same entry, however often the feed is fetched
+        writeln!(xml, "<id>urn:qotd:{date}:{}</id>", quote.hash())?;
+        writeln!(xml, "<updated>{}</updated>", timestamp(*date))?;
+        writeln!(xml, "<author><name>{}</name></author>", escape(&quote.file))?;
+        writeln!(
+            xml,
+            "<content type=\"text\">{}</content>",
+            escape(quote.text_str().trim_end())
+        )?;
+        writeln!(xml, "</entry>")?;
+    }
+    writeln!(xml, "</feed>")
+}
+
+fn render_calendar(events: &[(NaiveDate, Quote)]) -> String {
+    let mut ics = String::new();
+    let mut line = |line: &str| fold(&mut ics, line);
+    line("BEGIN:VCALENDAR");
+    line("VERSION:2.0");
+    line(&format!(
+        "PRODID:-//qotd-rs//qotd-rs {}//EN",
+        env!("CARGO_PKG_VERSION")
+    ));
+    line("CALSCALE:GREGORIAN");
+    line("X-WR-CALNAME:Quote of the Day");
+    let stamp = chrono::Utc::now().format("%Y%m%dT%H%M%SZ");
+    for (date, quote) in events {
+        let day = date.format("%Y%m%d");
+        line("BEGIN:VEVENT");
+        // Each day is the same event, even should its quote change
+        line(&format!("UID:{day}@qotd"));
+        line(&format!("DTSTAMP:{stamp}"));
+        line(&format!("DTSTART;VALUE=DATE:{day}"));
+        if let Some(next) = date.succ_opt() {
+            line(&format!("DTEND;VALUE=DATE:{}", next.format("%Y%m%d")));
+        }
+        line("SUMMARY:Quote of the Day");
+        line(&format!(
+            "DESCRIPTION:{}",
+            escape_text(quote.text_str().trim_end())
+        ));
+        line("END:VEVENT");
+    }
+    line("END:VCALENDAR");
+    ics
+}
+
+/// Append a content line, folded so that no line is longer than allowed
+fn fold(ics: &mut String, line: &str) {
+    let mut rest = line;
+    let mut limit = MAX_CALENDAR_LINE;
+    while rest.len() > limit {
+        let mut end = limit;
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        ics.push_str(&rest[..end]);
+        // Continuation lines begin with a space, which counts against their length
+        ics.push_str("\r\n ");
+        rest = &rest[end..];
+        limit = MAX_CALENDAR_LINE - 1;
+    }
+    ics.push_str(rest);
+    ics.push_str("\r\n");
+}
+
+/// Escape text for an iCalendar TEXT value
+fn escape_text(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            ';' => escaped.push_str("\\;"),
+            ',' => escaped.push_str("\\,"),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push(c),
+            c if c.is_control() => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// The start of `date` in server-local time, as an RFC 3339 timestamp
+fn timestamp(date: NaiveDate) -> String {
+    let midnight = date.and_time(chrono::NaiveTime::MIN);
+    Local
+        .from_local_datetime(&midnight)
+        .earliest()
+        .map_or_else(|| midnight.and_utc().to_rfc3339(), |time| time.to_rfc3339())
+}
+
+/// Escape text for inclusion in XML, dropping characters XML can't represent
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if c.is_control() => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Quote `text` as a JSON string
+fn json_string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(quoted, "\\u{:04x}", c as u32);
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
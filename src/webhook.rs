@@ -0,0 +1,98 @@
+//! This module posts a quote to a webhook on every rotation, e.g. to have a quote of the day appear
+//! in a team's chat channel
+#![cfg(feature = "webhook")]
+
+use anyhow::Context;
+use serde_json::json;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{info, warn};
+
+use crate::Quote;
+
+/// Shapes of the JSON payload posted to a webhook
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum WebhookFormat {
+    /// The quote's text, ID, file, and category
+    #[default]
+    Json,
+    /// A Slack incoming webhook's `text` (also accepted by e.g. Mattermost)
+    Slack,
+    /// A Discord webhook's `content`
+    Discord,
+    /// A Matrix `m.text` message's content, e.g. for a Matrix hookshot webhook
+    Matrix,
+}
+
+impl WebhookFormat {
+    /// The payload announcing `quote`
+    pub fn payload(&self, quote: &Quote) -> serde_json::Value {
+        let text = quote.text_str();
+        let text = text.trim_end();
+        match self {
+            WebhookFormat::Json => json!({
+                "id": quote.id.to_string(),
+                "hash": quote.hash(),
+                "file": quote.file,
+                "category": quote.category.to_string(),
+                "text": text,
+                "author": quote.author(),
+            }),
+            WebhookFormat::Slack => json!({ "text": text }),
+            WebhookFormat::Discord => json!({ "content": text }),
+            WebhookFormat::Matrix => json!({ "msgtype": "m.text", "body": text }),
+        }
+    }
+}
+
+/// Posts a quote to a URL on every rotation (see [`Server::rotate`](crate::Server::rotate))
+#[derive(Debug, Clone)]
+pub struct Webhook {
+    url: String,
+    format: WebhookFormat,
+}
+
+impl Webhook {
+    pub fn new<S: Into<String>>(url: S) -> Self {
+        Self {
+            url: url.into(),
+            format: WebhookFormat::default(),
+        }
+    }
+
+    pub fn format(mut self, format: WebhookFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Post each quote rotated to, until rotation ends
+    ///
+    /// Failing to post is never fatal, as the next rotation may well succeed.
+    pub(crate) async fn run(self, mut rotations: broadcast::Receiver<Quote>) -> anyhow::Result<()> {
+        loop {
+            let quote = match rotations.recv().await {
+                Ok(quote) => quote,
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return Ok(()),
+            };
+            if let Err(e) = self.post(&quote).await {
+                warn!("Failed to post quote to webhook: {e:?}");
+            }
+        }
+    }
+
+    async fn post(&self, quote: &Quote) -> anyhow::Result<()> {
+        let body = self.format.payload(quote).to_string();
+        let url = self.url.clone();
+        tokio::task::spawn_blocking(move || {
+            ureq::post(&url)
+                .set("Content-Type", "application/json")
+                .send_string(&body)
+                .map(drop)
+                .context("Webhook request failed")
+        })
+        .await??;
+        info!("Posted quote {} to webhook", quote.id);
+        Ok(())
+    }
+}
@@ -0,0 +1,109 @@
+use std::{
+    io::{Read, Write},
+    net::{Ipv4Addr, TcpStream},
+    time::Duration,
+};
+
+use qotd::{IndexOptions, Quotes, Server};
+
+fn quotes() -> Quotes {
+    let contents = "%\nThe quick brown fox jumps over the lazy dog.\n%\n";
+    Quotes::from_reader("test", contents.as_bytes(), &IndexOptions::new()).unwrap()
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn admin_socket_binds_abstract_addresses() {
+    use std::os::{
+        linux::net::SocketAddrExt,
+        unix::net::{SocketAddr, UnixStream},
+    };
+
+    let name = format!("qotd-test-{}", std::process::id());
+    let server = Server::new()
+        .admin_socket(format!("@{name}"))
+        .bind((Ipv4Addr::LOCALHOST, 0))
+        .await
+        .unwrap()
+        .spawn(quotes())
+        .unwrap();
+    // Nothing was created in the filesystem for it
+    assert!(!std::path::Path::new(&format!("@{name}")).exists());
+
+    let version = tokio::task::spawn_blocking(move || {
+        let address = SocketAddr::from_abstract_name(&name)?;
+        let mut conn = UnixStream::connect_addr(&address)?;
+        conn.write_all(b"GET /admin/version HTTP/1.1\r\nConnection: close\r\n\r\n")?;
+        let mut response = String::new();
+        conn.read_to_string(&mut response)?;
+        std::io::Result::Ok(response)
+    })
+    .await
+    .unwrap()
+    .unwrap();
+    server.shutdown();
+
+    assert!(version.starts_with("HTTP/1.1 200"), "{version}");
+    assert!(
+        version.trim_end().ends_with(env!("CARGO_PKG_VERSION")),
+        "{version}"
+    );
+}
+
+#[tokio::test]
+async fn quotes_are_served_while_loading() {
+    // Enough quotes that indexing them takes a while
+    let dir = std::env::temp_dir().join(format!("qotd-test-{}-load", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let contents =
+        "%\nA quote among very many others, which all take a while to index.\n".repeat(10_000);
+    for i in 0..10 {
+        std::fs::write(dir.join(format!("file{i:02}")), &contents).unwrap();
+    }
+
+    let server = Server::new()
+        .admin_address((Ipv4Addr::LOCALHOST, 0).into())
+        .bind((Ipv4Addr::LOCALHOST, 0))
+        .await
+        .unwrap();
+    let admin = server.admin_local_addr().unwrap();
+    let server = server.spawn(quotes()).unwrap();
+    let addr = server.addr();
+
+    let body = dir.display().to_string();
+    let load = tokio::task::spawn_blocking(move || {
+        let mut conn = TcpStream::connect(admin)?;
+        write!(
+            conn,
+            "POST /admin/load HTTP/1.1\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        )?;
+        let mut response = String::new();
+        conn.read_to_string(&mut response)?;
+        std::io::Result::Ok(response)
+    });
+    // Give the server time to begin loading
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let quote = tokio::task::spawn_blocking(move || {
+        let mut conn = TcpStream::connect(addr)?;
+        conn.set_read_timeout(Some(Duration::from_secs(10)))?;
+        let mut quote = String::new();
+        conn.read_to_string(&mut quote)?;
+        std::io::Result::Ok(quote)
+    })
+    .await
+    .unwrap()
+    .unwrap();
+    let loaded_first = load.is_finished();
+    let loaded = load.await.unwrap().unwrap();
+    server.shutdown();
+    std::fs::remove_dir_all(dir).unwrap();
+
+    assert!(quote.starts_with("The quick brown fox"), "{quote}");
+    assert!(
+        !loaded_first,
+        "The quote was only sent once loading finished"
+    );
+    assert!(loaded.starts_with("HTTP/1.1 200"), "{loaded}");
+}
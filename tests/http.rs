@@ -0,0 +1,40 @@
+use std::{
+    io::{Read, Write},
+    net::{Ipv4Addr, TcpStream},
+    time::Duration,
+};
+
+use qotd::{IndexOptions, Quotes, Server};
+
+#[tokio::test]
+async fn http_requests_are_shed_with_busy_message() {
+    let contents = "%\nThe quick brown fox jumps over the lazy dog.\n%\n";
+    let quotes = Quotes::from_reader("test", contents.as_bytes(), &IndexOptions::new()).unwrap();
+    let server = Server::new()
+        .http_address((Ipv4Addr::LOCALHOST, 0).into())
+        .max_in_flight(1)
+        .busy_message("Try again later")
+        .bind((Ipv4Addr::LOCALHOST, 0))
+        .await
+        .unwrap();
+    let http = server.http_local_addr().unwrap();
+    let server = server.spawn(quotes).unwrap();
+
+    let shed = tokio::task::spawn_blocking(move || {
+        // A client that never sends its request keeps it in flight
+        let _idle = TcpStream::connect(http)?;
+        std::thread::sleep(Duration::from_millis(100));
+        let mut conn = TcpStream::connect(http)?;
+        conn.write_all(b"GET /quote HTTP/1.1\r\nConnection: close\r\n\r\n")?;
+        let mut response = String::new();
+        conn.read_to_string(&mut response)?;
+        std::io::Result::Ok(response)
+    })
+    .await
+    .unwrap()
+    .unwrap();
+    server.shutdown();
+
+    assert!(shed.starts_with("HTTP/1.1 503"), "{shed}");
+    assert!(shed.ends_with("\r\n\r\nTry again later\n"), "{shed}");
+}
@@ -0,0 +1,48 @@
+use std::{fs, path::PathBuf};
+
+use qotd::{QuoteCategory, Quotes};
+use rand::{rngs::StdRng, SeedableRng};
+
+const FILES: usize = 32;
+
+/// A fresh directory of quote files, written in the order `order` gives
+fn quote_dir(name: &str, order: impl Iterator<Item = usize>) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("qotd-test-{}-{name}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    for i in order {
+        fs::write(
+            dir.join(format!("file{i:02}")),
+            format!("%\nThe first quote of file {i}.\n%\nThe second quote of file {i}.\n%\n"),
+        )
+        .unwrap();
+    }
+    dir
+}
+
+#[tokio::test]
+async fn file_indices_ignore_directory_order() {
+    let forwards = quote_dir("forwards", 0..FILES);
+    let backwards = quote_dir("backwards", (0..FILES).rev());
+
+    let mut indexed = Vec::new();
+    for dir in [&forwards, &backwards] {
+        let quotes = Quotes::from_dir(dir.clone(), &[QuoteCategory::DECOROUS])
+            .await
+            .unwrap();
+        let names: Vec<_> = quotes.files().map(|file| file.name).collect();
+        let mut quotes = quotes.with_rng(StdRng::seed_from_u64(865));
+        let mut chosen = Vec::new();
+        for _ in 0..16 {
+            chosen.push(quotes.random_quote().await.unwrap().text);
+        }
+        indexed.push((names, chosen));
+    }
+    fs::remove_dir_all(forwards).unwrap();
+    fs::remove_dir_all(backwards).unwrap();
+
+    let names = &indexed[0].0;
+    assert_eq!(names.len(), FILES);
+    assert!(names.is_sorted());
+    assert_eq!(indexed[0], indexed[1]);
+}
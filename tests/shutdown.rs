@@ -0,0 +1,33 @@
+use std::{
+    net::{Ipv4Addr, TcpStream},
+    time::Duration,
+};
+
+use qotd::{IndexOptions, Quotes, Server};
+
+#[tokio::test]
+async fn shutdown_abandons_requests_after_drain_timeout() {
+    let contents = "%\nThe quick brown fox jumps over the lazy dog.\n%\n";
+    let quotes = Quotes::from_reader("test", contents.as_bytes(), &IndexOptions::new()).unwrap();
+    let server = Server::new()
+        .http_address((Ipv4Addr::LOCALHOST, 0).into())
+        .drain_timeout(Duration::from_millis(100))
+        .bind((Ipv4Addr::LOCALHOST, 0))
+        .await
+        .unwrap();
+    let http = server.http_local_addr().unwrap();
+    let server = server.spawn(quotes).unwrap();
+
+    // A client that never sends its request keeps it in flight until the request times out
+    let conn = tokio::task::spawn_blocking(move || TcpStream::connect(http))
+        .await
+        .unwrap()
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    server.shutdown();
+    let joined = tokio::time::timeout(Duration::from_secs(5), server.join()).await;
+    drop(conn);
+
+    assert!(joined.is_ok(), "The request in flight wasn't abandoned");
+    joined.unwrap().unwrap();
+}